@@ -18,12 +18,20 @@ pub fn get_random_nonce() -> ring::aead::Nonce {
 /// Encrypt `plaintext` using `key`.
 ///
 /// Prefixes the ciphertext with a nonce.
-pub fn encrypt(mut plaintext: Vec<u8>, key: &LessSafeKey) -> Result<Vec<u8>> {
+pub fn encrypt(plaintext: Vec<u8>, key: &LessSafeKey) -> Result<Vec<u8>> {
+    encrypt_with_aad(plaintext, key, &[])
+}
+
+/// Encrypt `plaintext` using `key`, authenticating the additional data `aad`
+/// along with it. The same `aad` has to be passed to [`decrypt_with_aad`].
+///
+/// Prefixes the ciphertext with a nonce.
+pub fn encrypt_with_aad(mut plaintext: Vec<u8>, key: &LessSafeKey, aad: &[u8]) -> Result<Vec<u8>> {
     let nonce = get_random_nonce();
     // prefix ciphertext with nonce
     let mut ciphertext: Vec<u8> = nonce.as_ref().to_vec();
 
-    key.seal_in_place_append_tag(nonce, Aad::empty(), &mut plaintext)
+    key.seal_in_place_append_tag(nonce, Aad::from(aad), &mut plaintext)
         .map_err(|_| anyhow::format_err!("Encryption failed due to unspecified aead error"))?;
 
     ciphertext.append(&mut plaintext);
@@ -35,6 +43,17 @@ pub fn encrypt(mut plaintext: Vec<u8>, key: &LessSafeKey) -> Result<Vec<u8>> {
 ///
 /// Expect nonce in the prefix, like [`encrypt`] produces.
 pub fn decrypt<'c>(ciphertext: &'c mut [u8], key: &LessSafeKey) -> Result<&'c [u8]> {
+    decrypt_with_aad(ciphertext, key, &[])
+}
+
+/// Decrypts a `ciphertext` using `key` that was encrypted with
+/// [`encrypt_with_aad`], failing if `aad` differs from the additional data
+/// given when encrypting.
+pub fn decrypt_with_aad<'c>(
+    ciphertext: &'c mut [u8],
+    key: &LessSafeKey,
+    aad: &[u8],
+) -> Result<&'c [u8]> {
     if ciphertext.len() < NONCE_LEN {
         bail!("Ciphertext too short: {}", ciphertext.len());
     }
@@ -43,7 +62,7 @@ pub fn decrypt<'c>(ciphertext: &'c mut [u8], key: &LessSafeKey) -> Result<&'c [u
 
     key.open_in_place(
         Nonce::assume_unique_for_key(nonce_bytes.try_into().expect("nonce size known")),
-        Aad::empty(),
+        Aad::from(aad),
         encrypted_bytes,
     )
     .map_err(|_| format_err!("Decryption failed due to unspecified aead error"))?;
@@ -88,12 +107,27 @@ pub fn encrypted_read(key: &LessSafeKey, file: PathBuf) -> Result<Vec<u8>> {
 /// * `password` - Strong user-created password
 /// * `salt` - Nonce >8 bytes to discourage rainbow attacks
 pub fn get_encryption_key(password: &str, salt: &str) -> Result<LessSafeKey> {
+    encryption_key_from_bytes(&get_encryption_key_bytes(password, salt)?)
+}
+
+/// Stretches a user password into raw key material the same way
+/// [`get_encryption_key`] does, for callers that need to derive more than one
+/// key from it.
+pub fn get_encryption_key_bytes(
+    password: &str,
+    salt: &str,
+) -> Result<[u8; ring::digest::SHA256_OUTPUT_LEN]> {
     let mut key = [0u8; ring::digest::SHA256_OUTPUT_LEN];
 
     argon2()
         .hash_password_into(password.as_bytes(), salt.as_bytes(), &mut key)
         .map_err(|e| format_err!("could not hash password").context(e))?;
-    let key = UnboundKey::new(&ring::aead::CHACHA20_POLY1305, &key)
+    Ok(key)
+}
+
+/// Constructs a ChaCha20-Poly1305 key from 32 bytes of raw key material
+pub fn encryption_key_from_bytes(key: &[u8]) -> Result<LessSafeKey> {
+    let key = UnboundKey::new(&ring::aead::CHACHA20_POLY1305, key)
         .map_err(|_| anyhow::Error::msg("Unable to create key"))?;
     Ok(LessSafeKey::new(key))
 }
@@ -115,7 +149,7 @@ fn argon2() -> Argon2<'static> {
 
 #[cfg(test)]
 mod tests {
-    use crate::{decrypt, encrypt, get_encryption_key};
+    use crate::{decrypt, decrypt_with_aad, encrypt, encrypt_with_aad, get_encryption_key};
 
     #[test]
     fn encrypts_and_decrypts() {
@@ -129,4 +163,16 @@ mod tests {
 
         assert_eq!(decrypted, message.as_bytes());
     }
+
+    #[test]
+    fn rejects_wrong_aad() {
+        let key = get_encryption_key("test123", "salt1235").unwrap();
+        let cipher_text = encrypt_with_aad(b"hello world".to_vec(), &key, b"aad").unwrap();
+
+        let mut correct_aad = cipher_text.clone();
+        let decrypted = decrypt_with_aad(&mut correct_aad, &key, b"aad").unwrap();
+        assert_eq!(decrypted, b"hello world");
+        assert!(decrypt_with_aad(&mut cipher_text.clone(), &key, b"other").is_err());
+        assert!(decrypt(&mut cipher_text.clone(), &key).is_err());
+    }
 }
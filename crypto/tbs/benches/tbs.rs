@@ -6,10 +6,33 @@ mod bench {
 
     use tbs::{
         blind_message, combine_valid_shares, dealer_keygen, sign_blinded_msg, unblind_signature,
-        verify, BlindingKey, Message,
+        verify, verify_batch, AggregatePublicKey, BlindingKey, Message, Signature,
     };
     use test::Bencher;
 
+    const BATCH_SIZE: usize = 100;
+
+    /// Signs `BATCH_SIZE` messages under a handful of keys, like the notes of a
+    /// reissuance spread over a few denominations
+    fn signed_batch() -> Vec<(Message, Signature, AggregatePublicKey)> {
+        let keys = (0..8).map(|_| dealer_keygen(4, 5)).collect::<Vec<_>>();
+        (0..BATCH_SIZE)
+            .map(|idx| {
+                let (pk, _pks, sks) = &keys[idx % keys.len()];
+                let msg = Message::from_bytes(&idx.to_le_bytes());
+                let bkey = BlindingKey::random();
+                let bmsg = blind_message(msg, bkey);
+                let shares = sks
+                    .iter()
+                    .map(|sk| sign_blinded_msg(bmsg, *sk))
+                    .enumerate()
+                    .collect::<Vec<_>>();
+                let bsig = combine_valid_shares(shares, 4);
+                (msg, unblind_signature(bkey, bsig), *pk)
+            })
+            .collect()
+    }
+
     #[bench]
     fn bench_blinding(bencher: &mut Bencher) {
         bencher.iter(|| {
@@ -76,4 +99,18 @@ mod bench {
 
         bencher.iter(|| verify(msg, sig, pk));
     }
+
+    #[bench]
+    fn bench_verify_individually(bencher: &mut Bencher) {
+        let batch = signed_batch();
+
+        bencher.iter(|| batch.iter().all(|(msg, sig, pk)| verify(*msg, *sig, *pk)));
+    }
+
+    #[bench]
+    fn bench_verify_batch(bencher: &mut Bencher) {
+        let batch = signed_batch();
+
+        bencher.iter(|| verify_batch(batch.iter().copied()));
+    }
 }
@@ -3,9 +3,13 @@
 //! This library implements an ad-hoc threshold blind signature scheme based on
 //! BLS signatures using the (unrelated) BLS12-381 curve.
 
+use std::collections::HashMap;
 use std::hash::Hasher;
+use std::iter::once;
 
-use bls12_381::{pairing, G1Affine, G1Projective, G2Affine, G2Projective};
+use bls12_381::{
+    multi_miller_loop, pairing, G1Affine, G1Projective, G2Affine, G2Prepared, G2Projective, Gt,
+};
 pub use bls12_381::{G1Affine as MessagePoint, G2Affine as PubKeyPoint, Scalar};
 use ff::Field;
 use group::Curve;
@@ -219,6 +223,53 @@ pub fn verify(msg: Message, sig: Signature, pk: AggregatePublicKey) -> bool {
     pairing(&msg.0, &pk.0) == pairing(&sig.0, &G2Affine::generator())
 }
 
+/// Verifies many signatures at once, which is considerably faster than calling
+/// [`verify`] for each of them. Instead of two pairings per signature only one
+/// Miller loop per distinct public key plus one for the signatures and a
+/// single final exponentiation are needed.
+///
+/// Each signature is weighted by a random scalar before being combined, so
+/// invalid signatures can't be crafted to cancel each other out. If the result
+/// is `false` at least one signature is invalid, finding out which ones
+/// requires verifying them one by one.
+pub fn verify_batch<I>(items: I) -> bool
+where
+    I: IntoIterator<Item = (Message, Signature, AggregatePublicKey)>,
+{
+    let mut rng = OsRng; // FIXME: pass rng
+    let mut msg_sums = HashMap::<AggregatePublicKey, G1Projective>::new();
+    let mut sig_sum = G1Projective::identity();
+
+    for (msg, sig, pk) in items {
+        let weight = Scalar::random(&mut rng);
+        *msg_sums.entry(pk).or_insert_with(G1Projective::identity) += msg.0 * weight;
+        sig_sum += sig.0 * weight;
+    }
+
+    if msg_sums.is_empty() {
+        return true;
+    }
+
+    // Checks prod(e(sum(r_i * msg_i), pk)) == e(sum(r_i * sig_i), g2) by moving
+    // the right hand side to the left
+    let (msg_points, pk_points): (Vec<_>, Vec<_>) = msg_sums
+        .into_iter()
+        .map(|(pk, msg_sum)| (msg_sum, G2Prepared::from(pk.0)))
+        .unzip();
+    let mut g1_points = msg_points;
+    g1_points.push(-sig_sum);
+    let mut g1_affine = vec![G1Affine::identity(); g1_points.len()];
+    G1Projective::batch_normalize(&g1_points, &mut g1_affine);
+    let generator = G2Prepared::from(G2Affine::generator());
+
+    let terms = g1_affine
+        .iter()
+        .zip(pk_points.iter().chain(once(&generator)))
+        .collect::<Vec<_>>();
+
+    multi_miller_loop(&terms).final_exponentiation() == Gt::identity()
+}
+
 pub fn verify_blind_share(
     msg: BlindedMessage,
     sig: BlindedSignatureShare,
@@ -255,9 +306,27 @@ impl Aggregatable for Vec<PublicKeyShare> {
 mod tests {
     use crate::{
         blind_message, combine_valid_shares, dealer_keygen, sign_blinded_msg, unblind_signature,
-        verify, Aggregatable, BlindingKey, Message,
+        verify, verify_batch, Aggregatable, AggregatePublicKey, BlindingKey, Message,
+        SecretKeyShare, Signature,
     };
 
+    fn sign(msg: Message, sk: SecretKeyShare) -> Signature {
+        let bkey = BlindingKey::random();
+        let bsig = combine_valid_shares([(0, sign_blinded_msg(blind_message(msg, bkey), sk))], 1);
+        unblind_signature(bkey, bsig)
+    }
+
+    fn signed_messages(num: usize) -> Vec<(Message, Signature, AggregatePublicKey)> {
+        let keys = (0..3).map(|_| dealer_keygen(1, 1)).collect::<Vec<_>>();
+        (0..num)
+            .map(|idx| {
+                let (pk, _pks, sks) = &keys[idx % keys.len()];
+                let msg = Message::from_bytes(&idx.to_le_bytes());
+                (msg, sign(msg, sks[0]), *pk)
+            })
+            .collect()
+    }
+
     #[test]
     fn test_keygen() {
         let (pk, pks, _sks) = dealer_keygen(5, 15);
@@ -304,6 +373,32 @@ mod tests {
         assert!(verify(msg, sig, pk));
     }
 
+    #[test]
+    fn test_verify_batch() {
+        assert!(verify_batch([]));
+
+        let items = signed_messages(10);
+        assert!(items.iter().all(|(msg, sig, pk)| verify(*msg, *sig, *pk)));
+        assert!(verify_batch(items.clone()));
+
+        // Signature of another message
+        let mut invalid_sig = items.clone();
+        invalid_sig[3].1 = invalid_sig[4].1;
+        assert!(!verify_batch(invalid_sig));
+
+        // Valid signature under the wrong key
+        let mut wrong_key = items.clone();
+        wrong_key[5].2 = wrong_key[6].2;
+        assert!(!verify_batch(wrong_key));
+
+        // Two invalid signatures whose errors would cancel out without weights
+        let mut swapped = items;
+        let sig = swapped[0].1;
+        swapped[0].1 = swapped[3].1;
+        swapped[3].1 = sig;
+        assert!(!verify_batch(swapped));
+    }
+
     #[test]
     #[should_panic(expected = "Not enough signature shares")]
     fn test_insufficient_shares() {
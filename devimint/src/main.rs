@@ -371,6 +371,12 @@ async fn cli_tests(dev_fed: DevFed) -> Result<()> {
         .as_str()
         .map(|s| s.to_owned())
         .unwrap();
+    let client_ng_validate_amt = cmd!(fed, "validate", &reissue_notes, "--check-spent")
+        .out_json()
+        .await?["amount_msat"]
+        .as_u64()
+        .unwrap();
+    assert_eq!(client_ng_validate_amt, reissue_amount);
     let client_ng_reissue_amt = cmd!(fed, "reissue", reissue_notes)
         .out_json()
         .await?
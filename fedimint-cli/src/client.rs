@@ -1,5 +1,7 @@
+use std::ops::Bound;
+use std::path::PathBuf;
 use std::str::FromStr;
-use std::time::Duration;
+use std::time::{Duration, UNIX_EPOCH};
 
 use anyhow::{anyhow, bail};
 use bitcoin::secp256k1;
@@ -7,6 +9,8 @@ use bitcoin_hashes::hex;
 use bitcoin_hashes::hex::ToHex;
 use clap::Subcommand;
 use fedimint_client::backup::Metadata;
+use fedimint_client::ledger::LedgerFormat;
+use fedimint_client::oplog::{OperationLogQuery, OperationStatus};
 use fedimint_client::secret::PlainRootSecretStrategy;
 use fedimint_client::sm::OperationId;
 use fedimint_client::Client;
@@ -56,6 +60,15 @@ pub enum ClientCmd {
         #[clap(value_parser = parse_ecash)]
         notes: TieredMulti<SpendableNote>,
     },
+    /// Verify the signatures of notes received from a third party without
+    /// reissuing them
+    Validate {
+        #[clap(value_parser = parse_ecash)]
+        notes: TieredMulti<SpendableNote>,
+        /// Also ask the federation whether the notes were spent already
+        #[clap(long)]
+        check_spent: bool,
+    },
     /// Prepare notes to send to a third party as a payment
     Spend {
         #[clap(value_parser = parse_fedimint_amount)]
@@ -118,6 +131,44 @@ pub enum ClientCmd {
     },
     /// Print the secret key of the client
     PrintSecret,
+    /// List operations matching all given filters, newest first
+    ListOperations {
+        /// Only list operations of this type, usually the module kind
+        #[clap(long)]
+        operation_type: Option<String>,
+        /// Only list operations created at or after this unix timestamp
+        #[clap(long)]
+        since: Option<u64>,
+        /// Only list operations created before this unix timestamp
+        #[clap(long)]
+        until: Option<u64>,
+        /// Only list `pending` or `completed` operations
+        #[clap(long)]
+        status: Option<OperationStatus>,
+        /// Only list operations of at least this amount
+        #[clap(long, value_parser = parse_fedimint_amount)]
+        min_amount: Option<Amount>,
+        /// Only list operations of at most this amount
+        #[clap(long, value_parser = parse_fedimint_amount)]
+        max_amount: Option<Amount>,
+        #[clap(long, default_value = "10")]
+        limit: usize,
+    },
+    /// Export the history of operations as a ledger for accounting
+    Export {
+        /// `csv` or `json`
+        #[clap(long, default_value = "csv")]
+        format: LedgerFormat,
+        /// Only export operations created at or after this unix timestamp
+        #[clap(long)]
+        since: Option<u64>,
+        /// Only export operations created before this unix timestamp
+        #[clap(long)]
+        until: Option<u64>,
+        /// File to write the ledger to
+        #[clap(long)]
+        output: PathBuf,
+    },
 }
 
 pub fn parse_gateway_id(s: &str) -> Result<secp256k1::PublicKey, secp256k1::Error> {
@@ -157,6 +208,13 @@ pub async fn handle_ng_command(
 
             Ok(serde_json::to_value(amount).unwrap())
         }
+        ClientCmd::Validate { notes, check_spent } => {
+            let amount = client.validate_notes(&notes, check_spent).await?;
+
+            Ok(json!({
+                "amount_msat": amount,
+            }))
+        }
         ClientCmd::Spend { amount } => {
             let (operation, notes) = client
                 .spend_notes(amount, Duration::from_secs(3600), ())
@@ -382,6 +440,65 @@ pub async fn handle_ng_command(
         ClientCmd::DiscoverVersion => {
             Ok(json!({ "versions": client.discover_common_api_version().await? }))
         }
+        ClientCmd::ListOperations {
+            operation_type,
+            since,
+            until,
+            status,
+            min_amount,
+            max_amount,
+            limit,
+        } => {
+            let query = OperationLogQuery {
+                operation_type,
+                start_time: since.map(|secs| UNIX_EPOCH + Duration::from_secs(secs)),
+                end_time: until.map(|secs| UNIX_EPOCH + Duration::from_secs(secs)),
+                status,
+                min_amount,
+                max_amount,
+                limit: Some(limit),
+            };
+            let mut operations = vec![];
+            for (key, entry) in client.query_operations(&query).await {
+                operations.push(json!({
+                    "id": key.operation_id,
+                    "creation_time": key
+                        .creation_time
+                        .duration_since(UNIX_EPOCH)
+                        .expect("Creation time is after the unix epoch")
+                        .as_secs(),
+                    "operation_type": entry.operation_type(),
+                    "status": client
+                        .operation_log()
+                        .get_operation_status(key.operation_id)
+                        .await,
+                    "amount": client.operation_amount(key.operation_id, &entry).await,
+                    "meta": entry.meta::<serde_json::Value>(),
+                    "outcome": entry.outcome::<serde_json::Value>(),
+                }));
+            }
+
+            Ok(json!({ "operations": operations }))
+        }
+        ClientCmd::Export {
+            format,
+            since,
+            until,
+            output,
+        } => {
+            let time_range = (
+                since.map_or(Bound::Unbounded, |secs| {
+                    Bound::Included(UNIX_EPOCH + Duration::from_secs(secs))
+                }),
+                until.map_or(Bound::Unbounded, |secs| {
+                    Bound::Excluded(UNIX_EPOCH + Duration::from_secs(secs))
+                }),
+            );
+            let ledger = client.export_ledger(format, time_range).await?;
+            std::fs::write(&output, ledger)?;
+
+            Ok(json!({ "output": output }))
+        }
     }
 }
 
@@ -390,9 +507,17 @@ async fn get_note_summary(client: &Client) -> anyhow::Result<serde_json::Value>
     let summary = mint_client
         .get_wallet_summary(&mut client.db().begin_transaction().await.with_module_prefix(1))
         .await;
+    let last_backup = client.last_backup().await.map(|last_backup| {
+        last_backup
+            .time
+            .duration_since(UNIX_EPOCH)
+            .expect("Backup time is after the unix epoch")
+            .as_secs()
+    });
     Ok(serde_json::to_value(InfoResponse {
         total_msat: summary.total_amount(),
         denominations_msat: summary,
+        last_backup,
     })
     .unwrap())
 }
@@ -401,6 +526,9 @@ async fn get_note_summary(client: &Client) -> anyhow::Result<serde_json::Value>
 struct InfoResponse {
     total_msat: Amount,
     denominations_msat: TieredSummary,
+    /// Time of the last backup uploaded to the federation in seconds since
+    /// the unix epoch
+    last_backup: Option<u64>,
 }
 
 pub fn parse_fedimint_amount(s: &str) -> Result<fedimint_core::Amount, ParseAmountError> {
@@ -14,6 +14,7 @@ use std::{fs, result};
 
 use clap::{CommandFactory, Parser, Subcommand};
 use fedimint_aead::{encrypted_read, encrypted_write, get_encryption_key};
+use fedimint_client::inspect::{StateId, StateInfo};
 use fedimint_client::module::gen::{ClientModuleGen, ClientModuleGenRegistry, IClientModuleGen};
 use fedimint_client::secret::PlainRootSecretStrategy;
 use fedimint_client::sm::OperationId;
@@ -455,6 +456,33 @@ enum DevCmd {
 
     /// Decode a transaction hex string and print it to stdout
     DecodeTransaction { hex_string: String },
+
+    /// Inspect and repair the client's state machines
+    #[clap(subcommand)]
+    States(StatesCmd),
+}
+
+#[derive(Debug, Clone, Subcommand)]
+enum StatesCmd {
+    /// List active, inactive and dead-lettered states, optionally only of one
+    /// operation
+    List {
+        #[clap(long = "operation-id")]
+        operation_id: Option<OperationId>,
+    },
+
+    /// Show a single state including its decoded representation
+    Show { state_id: StateId },
+
+    /// Stop an active state machine from making further progress, funds held
+    /// by it may be lost
+    Abort { state_id: StateId },
+
+    /// Re-evaluate the triggers of an active state machine immediately
+    Retry { state_id: StateId },
+
+    /// Make a state machine the executor gave up on active again
+    Requeue { state_id: StateId },
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -625,7 +653,7 @@ impl FedimintCli {
                     .map_err_cli_msg(CliErrorKind::InvalidValue, "Invalid JSON-RPC parameters")?;
                 let params = ApiRequestErased::new(params);
                 let ws_api: Arc<_> = WsFederationApi::from_config(
-                    cli.build_client_ng(&self.module_gens).await?.get_config(),
+                    &cli.build_client_ng(&self.module_gens).await?.get_config(),
                 )
                 .into();
                 let response: Value = match peer_id {
@@ -767,6 +795,53 @@ impl FedimintCli {
                     transaction: (format!("{tx:?}")),
                 })
             }
+            Command::Dev(DevCmd::States(command)) => {
+                let client = cli
+                    .build_client_ng(&self.module_gens)
+                    .await
+                    .map_err_cli_msg(CliErrorKind::GeneralFailure, "failure")?;
+                match command {
+                    StatesCmd::List { operation_id } => {
+                        let states = client
+                            .list_states(operation_id)
+                            .await
+                            .iter()
+                            .map(|info| state_info_to_json(info, false))
+                            .collect();
+                        Ok(CliOutput::Raw(Value::Array(states)))
+                    }
+                    StatesCmd::Show { state_id } => {
+                        let info = client
+                            .list_states(None)
+                            .await
+                            .into_iter()
+                            .find(|info| info.id == state_id)
+                            .ok_or_cli_msg(CliErrorKind::InvalidValue, "no such state")?;
+                        Ok(CliOutput::Raw(state_info_to_json(&info, true)))
+                    }
+                    StatesCmd::Abort { state_id } => {
+                        client
+                            .abort_state(state_id)
+                            .await
+                            .map_err_cli_msg(CliErrorKind::InvalidValue, "failed to abort state")?;
+                        Ok(CliOutput::Raw(json!({ "aborted": state_id.to_string() })))
+                    }
+                    StatesCmd::Retry { state_id } => {
+                        client
+                            .retry_state(state_id)
+                            .await
+                            .map_err_cli_msg(CliErrorKind::InvalidValue, "failed to retry state")?;
+                        Ok(CliOutput::Raw(json!({ "retried": state_id.to_string() })))
+                    }
+                    StatesCmd::Requeue { state_id } => {
+                        client.requeue_dead_letter(state_id).await.map_err_cli_msg(
+                            CliErrorKind::InvalidValue,
+                            "failed to requeue state",
+                        )?;
+                        Ok(CliOutput::Raw(json!({ "requeued": state_id.to_string() })))
+                    }
+                }
+            }
             Command::Completion { shell } => {
                 clap_complete::generate(
                     shell,
@@ -815,6 +890,30 @@ fn metadata_from_clap_cli(metadata: Vec<String>) -> Result<BTreeMap<String, Stri
     Ok(metadata)
 }
 
+/// JSON representation of a state for `dev states`, the decoded state itself
+/// is only included if `with_state` is set since it can be large
+fn state_info_to_json(info: &StateInfo, with_state: bool) -> Value {
+    let mut json = json!({
+        "id": info.id.to_string(),
+        "operation_id": info.operation_id,
+        "module_instance_id": info.module_instance_id,
+        "module_kind": info.module_kind,
+        "active": info.is_active,
+        "age_secs": info.age().as_secs(),
+        "summary": info.summary,
+    });
+    if let Some(dead_letter) = &info.dead_letter {
+        json["dead_letter"] = json!({
+            "attempts": dead_letter.attempts,
+            "last_error": dead_letter.last_error,
+        });
+    }
+    if with_state {
+        json["state"] = Value::String(info.state.clone());
+    }
+    json
+}
+
 #[test]
 fn metadata_from_clap_cli_test() {
     for (args, expected) in [
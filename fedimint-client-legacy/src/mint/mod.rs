@@ -166,14 +166,23 @@ impl ClientModule for MintClient {
     fn input_amount(&self, input: &MintInput) -> TransactionItemAmount {
         TransactionItemAmount {
             amount: input.total_amount(),
-            fee: self.config.fee_consensus.note_spend_abs * (input.count_items() as u64),
+            // The federation rejects notes whose fee overflows anyway
+            fee: self
+                .config
+                .fee_consensus
+                .spend_fee(&input.summary())
+                .unwrap_or(Amount::from_msats(u64::MAX)),
         }
     }
 
     fn output_amount(&self, output: &MintOutput) -> TransactionItemAmount {
         TransactionItemAmount {
             amount: output.total_amount(),
-            fee: self.config.fee_consensus.note_issuance_abs * (output.count_items() as u64),
+            fee: self
+                .config
+                .fee_consensus
+                .issuance_fee(&output.summary())
+                .unwrap_or(Amount::from_msats(u64::MAX)),
         }
     }
 }
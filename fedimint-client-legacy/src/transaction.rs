@@ -187,6 +187,7 @@ pub mod legacy {
                         }
                     })
                     .collect(),
+                expiry: None,
                 signature: self.signature,
             }
         }
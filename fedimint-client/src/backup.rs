@@ -1,13 +1,18 @@
 use std::cmp::Reverse;
 use std::collections::BTreeMap;
+use std::time::{Duration, SystemTime};
 
 use anyhow::Result;
 use bitcoin::secp256k1;
 use fedimint_core::api::GlobalFederationApi;
 use fedimint_core::core::backup::{BackupRequest, SignedBackupRequest};
 use fedimint_core::core::ModuleInstanceId;
+use fedimint_core::db::DatabaseTransaction;
 use fedimint_core::encoding::{Decodable, Encodable};
 use fedimint_core::module::registry::ModuleDecoderRegistry;
+use fedimint_core::task::{sleep, timeout};
+use fedimint_core::time::now;
+use fedimint_core::util::NextOrPending;
 use fedimint_derive_secret::DerivableSecret;
 use fedimint_logging::{LOG_CLIENT, LOG_CLIENT_BACKUP, LOG_CLIENT_RECOVERY};
 use secp256k1_zkp::{KeyPair, Secp256k1};
@@ -15,8 +20,11 @@ use serde::{Deserialize, Serialize};
 use tracing::{debug, info, warn};
 
 use super::Client;
-use crate::get_client_root_secret_encoding;
+use crate::db::LastBackupKey;
+use crate::oplog::OperationLog;
 use crate::secret::{DeriveableSecretClientExt, RootSecretStrategy};
+use crate::sm::{DynState, Executor, IState, OperationId};
+use crate::{get_client_root_secret_encoding, DynGlobalClientContext};
 
 /// Backup metadata
 ///
@@ -106,6 +114,41 @@ impl ClientBackup {
     }
 }
 
+/// Configures automatic backups uploaded in the background, see
+/// [`ClientBuilder::with_backup_schedule`](crate::ClientBuilder::with_backup_schedule)
+#[derive(Debug, Clone)]
+pub struct BackupSchedule {
+    /// Upload a backup at least this often, even if the balance didn't change
+    pub interval: Duration,
+    /// After a balance change wait until the balance didn't change for this
+    /// long, so a burst of changes only results in one backup
+    pub debounce: Duration,
+    /// Delay before retrying a failed upload, doubled after every failure
+    pub min_retry_delay: Duration,
+    /// Upper bound for the retry delay
+    pub max_retry_delay: Duration,
+}
+
+impl Default for BackupSchedule {
+    fn default() -> Self {
+        BackupSchedule {
+            interval: Duration::from_secs(60 * 60),
+            debounce: Duration::from_secs(10),
+            min_retry_delay: Duration::from_secs(5),
+            max_retry_delay: Duration::from_secs(10 * 60),
+        }
+    }
+}
+
+/// Last backup successfully uploaded to the federation, stored in the client
+/// database
+#[derive(Debug, Clone, PartialEq, Eq, Encodable, Decodable, Serialize)]
+pub struct LastBackup {
+    pub time: SystemTime,
+    /// Metadata included in the backup, automatic backups keep it unchanged
+    pub metadata: Metadata,
+}
+
 /// Encrypted version of [`ClientBackup`].
 pub struct EncryptedClientBackup(Vec<u8>);
 
@@ -135,6 +178,40 @@ impl EncryptedClientBackup {
     }
 }
 
+/// Resumes operations a module re-created from its backup, each given as its
+/// id, the meta of its log entry and the state machine continuing it.
+///
+/// The operations are added to `operation_log` and their state machines are
+/// started, both within `dbtx`. Operations already in the log, e.g. because the
+/// client is restored again after a wipe, keep their existing entries.
+pub async fn restore_operations<M, S>(
+    dbtx: &mut DatabaseTransaction<'_>,
+    module_instance_id: ModuleInstanceId,
+    operation_type: &str,
+    executor: &Executor<DynGlobalClientContext>,
+    operation_log: &OperationLog,
+    operations: Vec<(OperationId, M, S)>,
+) -> Result<()>
+where
+    M: Serialize,
+    S: IState<DynGlobalClientContext> + 'static,
+{
+    let mut states = vec![];
+    for (operation_id, meta, state) in operations {
+        if OperationLog::get_operation_inner(dbtx, operation_id)
+            .await
+            .is_none()
+        {
+            operation_log
+                .add_operation_log_entry(dbtx, operation_id, operation_type, meta)
+                .await;
+        }
+        states.push(DynState::from_typed(module_instance_id, state));
+    }
+
+    executor.add_state_machines_dbtx(dbtx, states).await
+}
+
 impl Client {
     /// Create a backup, include provided `metadata`
     pub async fn create_backup(&self, metadata: Metadata) -> anyhow::Result<ClientBackup> {
@@ -181,13 +258,80 @@ impl Client {
 
     /// Prepare an encrypted backup and send it to federation for storing
     pub async fn backup_to_federation(&self, metadata: Metadata) -> Result<()> {
-        let backup = self.create_encrypted_backup(metadata).await?;
+        let backup = self.create_encrypted_backup(metadata.clone()).await?;
 
         self.upload_backup(backup).await?;
 
+        let mut dbtx = self.db().begin_transaction().await;
+        dbtx.insert_entry(
+            &LastBackupKey,
+            &LastBackup {
+                time: now(),
+                metadata,
+            },
+        )
+        .await;
+        dbtx.commit_tx_result().await?;
+
         Ok(())
     }
 
+    /// Returns the last backup successfully uploaded by
+    /// [`Client::backup_to_federation`], manually or by the
+    /// [`BackupSchedule`]
+    pub async fn last_backup(&self) -> Option<LastBackup> {
+        self.db()
+            .begin_transaction()
+            .await
+            .get_value(&LastBackupKey)
+            .await
+    }
+
+    /// Uploads backups according to `schedule`, never returns
+    pub(crate) async fn run_backup_schedule(&self, schedule: BackupSchedule) {
+        let mut balance_changes = self.subscribe_balance_changes().await;
+        loop {
+            let next_backup = self
+                .last_backup()
+                .await
+                .map_or_else(now, |last_backup| last_backup.time + schedule.interval);
+            let until_next_backup = next_backup.duration_since(now()).unwrap_or_default();
+
+            tokio::select! {
+                _ = balance_changes.next_or_pending() => {
+                    while timeout(schedule.debounce, balance_changes.next_or_pending())
+                        .await
+                        .is_ok()
+                    {}
+                    debug!(target: LOG_CLIENT_BACKUP, "Balance changed, uploading backup");
+                }
+                _ = sleep(until_next_backup) => {
+                    debug!(target: LOG_CLIENT_BACKUP, "Backup interval elapsed, uploading backup");
+                }
+            }
+
+            let mut retry_delay = schedule.min_retry_delay;
+            loop {
+                let metadata = self
+                    .last_backup()
+                    .await
+                    .map_or_else(Metadata::empty, |last_backup| last_backup.metadata);
+                match self.backup_to_federation(metadata).await {
+                    Ok(()) => break,
+                    Err(e) => {
+                        warn!(
+                            target: LOG_CLIENT_BACKUP,
+                            ?retry_delay,
+                            "Automatic backup failed: {e}"
+                        );
+                        sleep(retry_delay).await;
+                        retry_delay = (retry_delay * 2).min(schedule.max_retry_delay);
+                    }
+                }
+            }
+        }
+    }
+
     /// Wipe the client state (including module state)
     pub async fn wipe_state(&self) -> Result<()> {
         let mut dbtx = self.db().begin_transaction().await;
@@ -280,6 +424,7 @@ impl Client {
                     &mut dbtx,
                     id,
                     self.inner.executor.clone(),
+                    &self.inner.operation_log,
                     self.inner.api.clone(),
                     module_backup.map(Vec::as_slice),
                 )
@@ -0,0 +1,360 @@
+use std::collections::HashSet;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use anyhow::anyhow;
+use fedimint_core::db::AutocommitError;
+use fedimint_core::task::{sleep, MaybeSend, MaybeSync};
+use fedimint_core::{maybe_add_send_sync, OutPoint, TransactionId};
+use futures::channel::{mpsc, oneshot};
+use futures::StreamExt;
+use rand::thread_rng;
+use tracing::{debug, warn};
+
+use crate::sm::OperationId;
+use crate::transaction::TransactionBuilder;
+use crate::{Client, ClientInner};
+
+/// Settings for submitting the transactions of several operations as one
+/// combined transaction, see
+/// [`ClientBuilder::with_transaction_batching`](crate::ClientBuilder::with_transaction_batching).
+#[derive(Debug, Clone)]
+pub struct BatchingConfig {
+    /// How long to wait for further operations once the first operation of a
+    /// batch was queued
+    pub window: Duration,
+    /// Submit a batch before its window elapsed once it contains this many
+    /// operations
+    pub max_operations: usize,
+}
+
+impl Default for BatchingConfig {
+    fn default() -> Self {
+        BatchingConfig {
+            window: Duration::from_millis(250),
+            max_operations: 50,
+        }
+    }
+}
+
+/// Where the inputs and outputs of an operation ended up in the combined
+/// transaction submitted by [`Client::submit_batched_transaction`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BatchedTransaction {
+    /// Id of the combined transaction
+    pub txid: TransactionId,
+    /// Index of the first input of the operation, followed by the other inputs
+    /// of its transaction builder and then its funding inputs
+    pub first_input_idx: u64,
+    /// Index of the first output of the operation, followed by the other
+    /// outputs of its transaction builder and then its change output
+    pub first_output_idx: u64,
+    /// Change output of the operation, if one was needed
+    pub change_outpoint: Option<OutPoint>,
+}
+
+impl BatchedTransaction {
+    /// Returns the out point of the output at index `idx` of the operation's
+    /// transaction builder
+    pub fn out_point(&self, idx: u64) -> OutPoint {
+        OutPoint {
+            txid: self.txid,
+            out_idx: self.first_output_idx + idx,
+        }
+    }
+}
+
+type OperationMetaGen = Box<maybe_add_send_sync!(dyn Fn(BatchedTransaction) -> serde_json::Value)>;
+
+/// Operation waiting to be submitted as part of the next batch
+pub(crate) struct QueuedOperation {
+    operation_id: OperationId,
+    operation_type: String,
+    operation_meta: OperationMetaGen,
+    tx_builder: TransactionBuilder,
+}
+
+pub(crate) type BatchQueue = mpsc::UnboundedReceiver<(
+    QueuedOperation,
+    oneshot::Sender<anyhow::Result<BatchedTransaction>>,
+)>;
+
+pub(crate) struct TransactionBatcher {
+    config: BatchingConfig,
+    sender: mpsc::UnboundedSender<(
+        QueuedOperation,
+        oneshot::Sender<anyhow::Result<BatchedTransaction>>,
+    )>,
+    /// Taken by the background task once the executor is started
+    queue: Mutex<Option<BatchQueue>>,
+}
+
+impl TransactionBatcher {
+    pub(crate) fn new(config: BatchingConfig) -> Self {
+        let (sender, queue) = mpsc::unbounded();
+        TransactionBatcher {
+            config,
+            sender,
+            queue: Mutex::new(Some(queue)),
+        }
+    }
+
+    /// Queues `operation` for the next batch and waits until the combined
+    /// transaction was committed to the database
+    async fn submit(&self, operation: QueuedOperation) -> anyhow::Result<BatchedTransaction> {
+        let (result_sender, result_receiver) = oneshot::channel();
+        self.sender
+            .unbounded_send((operation, result_sender))
+            .map_err(|_| anyhow!("Transaction batching was shut down"))?;
+        result_receiver
+            .await
+            .map_err(|_| anyhow!("Transaction batching was shut down"))?
+    }
+
+    /// Returns the queue of operations the first time it's called
+    pub(crate) fn take_queue(&self) -> Option<BatchQueue> {
+        self.queue.lock().expect("poisoned").take()
+    }
+}
+
+impl Client {
+    /// Funds the transaction builder like
+    /// [`Client::finalize_and_submit_transaction`] and submits it to the
+    /// federation, but if [transaction
+    /// batching](crate::ClientBuilder::with_transaction_batching) is enabled
+    /// waits for other operations to be submitted within the batching window
+    /// and combines them all into a single transaction.
+    ///
+    /// Every operation is still funded and given change separately and gets
+    /// its own state machines and operation log entry, so it resolves
+    /// independently of the other operations in the batch. Operations that fail
+    /// to be funded or violate a [`SpendingPolicy`](crate::policy::SpendingPolicy)
+    /// fail on their own without affecting the rest of the batch.
+    ///
+    /// Since the outputs of the operation don't necessarily start at index 0 of
+    /// the combined transaction, out points have to be derived from the
+    /// returned [`BatchedTransaction`] instead of only the transaction id.
+    /// Without batching the transaction is submitted right away.
+    ///
+    /// **Attention**: if the federation rejects the combined transaction, e.g.
+    /// because one operation spends already spent e-cash, all operations in
+    /// the batch fail. Their inputs are reclaimed by their state machines just
+    /// like for a rejected transaction of a single operation, but the
+    /// operations aren't retried. Transactions with an
+    /// [expiry](TransactionBuilder::with_expiry) are never batched, so they
+    /// neither get delayed by the batching window nor cut short by the expiry
+    /// of other operations.
+    pub async fn submit_batched_transaction<F, M>(
+        &self,
+        operation_id: OperationId,
+        operation_type: &str,
+        operation_meta: F,
+        tx_builder: TransactionBuilder,
+    ) -> anyhow::Result<BatchedTransaction>
+    where
+        F: Fn(BatchedTransaction) -> M + MaybeSend + MaybeSync + 'static,
+        M: serde::Serialize,
+    {
+        let operation = QueuedOperation {
+            operation_id,
+            operation_type: operation_type.to_owned(),
+            operation_meta: Box::new(move |batched_tx| {
+                serde_json::to_value(operation_meta(batched_tx))
+                    .expect("Can only fail if meta is not serializable")
+            }),
+            tx_builder,
+        };
+
+        match &self.inner.batcher {
+            Some(batcher) if operation.tx_builder.expiry.is_none() => {
+                batcher.submit(operation).await
+            }
+            _ => self
+                .submit_batch(&[operation])
+                .await
+                .pop()
+                .expect("One result per operation"),
+        }
+    }
+
+    /// Submits queued operations in batches, never returns
+    pub(crate) async fn run_transaction_batcher(&self, mut queue: BatchQueue) {
+        let config = self
+            .inner
+            .batcher
+            .as_ref()
+            .expect("Only run with batching enabled")
+            .config
+            .clone();
+
+        while let Some(first_operation) = queue.next().await {
+            let mut batch = vec![first_operation];
+            let window = sleep(config.window);
+            futures::pin_mut!(window);
+            while batch.len() < config.max_operations {
+                tokio::select! {
+                    () = &mut window => break,
+                    operation = queue.next() => match operation {
+                        Some(operation) => batch.push(operation),
+                        None => break,
+                    },
+                }
+            }
+
+            debug!(operations = batch.len(), "Submitting transaction batch");
+            let (operations, result_senders): (Vec<_>, Vec<_>) = batch.into_iter().unzip();
+            let results = self.submit_batch(&operations).await;
+            for (result, result_sender) in results.into_iter().zip(result_senders) {
+                // The caller may have given up waiting, which is fine
+                let _ = result_sender.send(result);
+            }
+        }
+    }
+
+    /// Funds all `operations` separately, combines them into one transaction
+    /// and submits it. Returns the result for each operation in the same order.
+    async fn submit_batch(
+        &self,
+        operations: &[QueuedOperation],
+    ) -> Vec<anyhow::Result<BatchedTransaction>> {
+        let autocommit_res = self
+            .inner
+            .db
+            .autocommit(
+                |dbtx| {
+                    Box::pin(async move {
+                        let mut combined_tx_builder = TransactionBuilder::new();
+                        // Indices of the first input, first output and change output of each
+                        // operation included in the combined transaction
+                        let mut placements = Vec::with_capacity(operations.len());
+                        // Amounts spent by the included operations, recorded once the txid is
+                        // known
+                        let mut spends = Vec::with_capacity(operations.len());
+                        let mut seen_operation_ids = HashSet::new();
+
+                        for operation in operations {
+                            let operation_id = operation.operation_id;
+                            if !seen_operation_ids.insert(operation_id)
+                                || ClientInner::operation_exists(dbtx, operation_id).await
+                            {
+                                placements.push(Err(anyhow!(
+                                    "There already exists an operation with id {operation_id:?}"
+                                )));
+                                continue;
+                            }
+
+                            // Operations that fail must not leave spent funds or spending records
+                            // behind, so each one is rolled back on its own
+                            dbtx.set_tx_savepoint().await?;
+                            match self
+                                .inner
+                                .balance_operation(
+                                    dbtx,
+                                    operation_id,
+                                    operation.tx_builder.clone(),
+                                    true,
+                                )
+                                .await
+                            {
+                                Ok((tx_builder, change_idx, spend)) => {
+                                    let first_input_idx = combined_tx_builder.inputs.len() as u64;
+                                    let first_output_idx = combined_tx_builder.outputs.len() as u64;
+                                    placements.push(Ok((
+                                        first_input_idx,
+                                        first_output_idx,
+                                        change_idx.map(|idx| first_output_idx + idx),
+                                    )));
+                                    spends.push((operation_id, spend));
+                                    combined_tx_builder.extend(tx_builder);
+                                }
+                                Err(error) => {
+                                    dbtx.rollback_tx_to_savepoint().await?;
+                                    placements.push(Err(error));
+                                }
+                            }
+                        }
+
+                        if placements.iter().all(Result::is_err) {
+                            return Ok::<_, anyhow::Error>(
+                                placements
+                                    .into_iter()
+                                    .map(|placement| Err(placement.expect_err("All failed")))
+                                    .collect::<Vec<_>>(),
+                            );
+                        }
+
+                        let (transaction, mut states) =
+                            combined_tx_builder.build(&self.inner.secp_ctx, thread_rng());
+                        let txid = transaction.tx_hash();
+                        for (operation_id, spend) in spends {
+                            ClientInner::record_transaction_spend(dbtx, operation_id, txid, spend)
+                                .await;
+                        }
+                        let results = placements
+                            .into_iter()
+                            .map(|placement| {
+                                let (first_input_idx, first_output_idx, change_idx) = placement?;
+                                Ok(BatchedTransaction {
+                                    txid,
+                                    first_input_idx,
+                                    first_output_idx,
+                                    change_outpoint: change_idx
+                                        .map(|out_idx| OutPoint { txid, out_idx }),
+                                })
+                            })
+                            .collect::<Vec<_>>();
+
+                        for (operation, result) in operations.iter().zip(&results) {
+                            if result.is_ok() {
+                                states.push(ClientInner::tx_submission_state(
+                                    operation.operation_id,
+                                    transaction.clone(),
+                                ));
+                            }
+                        }
+                        self.inner
+                            .executor
+                            .add_state_machines_dbtx(dbtx, states)
+                            .await?;
+
+                        for (operation, result) in operations.iter().zip(&results) {
+                            if let Ok(batched_tx) = result {
+                                self.operation_log()
+                                    .add_operation_log_entry(
+                                        dbtx,
+                                        operation.operation_id,
+                                        &operation.operation_type,
+                                        (operation.operation_meta)(*batched_tx),
+                                    )
+                                    .await;
+                            }
+                        }
+
+                        Ok(results)
+                    })
+                },
+                Some(100),
+            )
+            .await;
+
+        match autocommit_res {
+            Ok(results) => results,
+            Err(error) => {
+                let error = match error {
+                    AutocommitError::ClosureError { error, .. } => error,
+                    AutocommitError::CommitFailed {
+                        attempts,
+                        last_error,
+                    } => last_error.context(format!(
+                        "Failed to commit batch submission dbtx after {attempts} attempts"
+                    )),
+                };
+                warn!("Failed to submit transaction batch: {error:#}");
+                operations
+                    .iter()
+                    .map(|_| Err(anyhow!("Failed to submit transaction batch: {error:#}")))
+                    .collect()
+            }
+        }
+    }
+}
@@ -0,0 +1,227 @@
+use std::collections::BTreeSet;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use anyhow::ensure;
+use fedimint_core::api::{
+    DynGlobalApi, DynModuleApi, GlobalFederationApi, IFederationApi, IGlobalFederationApi,
+    IModuleFederationApi, JsonRpcResult, WsClientConnectInfo, WsFederationApi,
+};
+use fedimint_core::config::ClientConfig;
+use fedimint_core::core::ModuleInstanceId;
+use fedimint_core::db::DatabaseTransaction;
+use fedimint_core::task::sleep;
+use fedimint_core::{apply, async_trait_maybe_send, PeerId};
+use serde_json::Value;
+use tracing::{debug, info, warn};
+
+use crate::db::{ClientConfigKey, ConsensusConfigHashKey};
+use crate::event::ConfigChangedEvent;
+use crate::{Client, DatabaseSource};
+
+/// Settings for periodically checking the federation for config changes, see
+/// [`ClientBuilder::with_config_refresh`](crate::ClientBuilder::with_config_refresh)
+#[derive(Debug, Clone)]
+pub struct ConfigRefresh {
+    /// Connect info used to download changed configs. Every download counts
+    /// against the download limit of its token, if the federation set one.
+    pub connect_info: WsClientConnectInfo,
+    /// How often to ask the federation for the hash of its current config
+    pub interval: Duration,
+}
+
+impl ConfigRefresh {
+    pub fn new(connect_info: WsClientConnectInfo) -> Self {
+        ConfigRefresh {
+            connect_info,
+            interval: Duration::from_secs(10 * 60),
+        }
+    }
+}
+
+impl Client {
+    /// Asks the federation for the hash of its config and, if it changed since
+    /// the last check, downloads and verifies the new config. Returns `true`
+    /// if the config differs from the [latest one](Client::get_config).
+    ///
+    /// A changed config is persisted and used instead of the one given to the
+    /// [`ClientBuilder`](crate::ClientBuilder) from then on. Changed API
+    /// endpoints and meta fields take effect right away, while added or
+    /// changed module instances are only initialized once the client is built
+    /// again. Subscribers of [`Client::subscribe_events`] receive a
+    /// [`ClientEvent::ConfigChanged`](crate::event::ClientEvent::ConfigChanged).
+    pub async fn refresh_config(&self, connect_info: &WsClientConnectInfo) -> anyhow::Result<bool> {
+        ensure!(
+            connect_info.id == self.federation_id(),
+            "Connect info belongs to another federation"
+        );
+
+        let config_hash = self.api().consensus_config_hash().await?;
+        let last_config_hash = self
+            .db()
+            .begin_transaction()
+            .await
+            .get_value(&ConsensusConfigHashKey)
+            .await;
+        if last_config_hash == Some(config_hash) {
+            return Ok(false);
+        }
+
+        // The download verifies the config against the federation id
+        let new_config = self
+            .api()
+            .download_client_config(connect_info)
+            .await?
+            .redecode_raw(self.decoders())?;
+        let old_config = self.get_config();
+        let changed = new_config != old_config;
+
+        let mut dbtx = self.db().begin_transaction().await;
+        if changed {
+            dbtx.insert_entry(&ClientConfigKey, &new_config).await;
+        }
+        dbtx.insert_entry(&ConsensusConfigHashKey, &config_hash)
+            .await;
+        dbtx.commit_tx_result().await?;
+
+        if !changed {
+            return Ok(false);
+        }
+
+        let event = ConfigChangedEvent::new(&old_config, &new_config);
+        if event.api_endpoints_changed {
+            self.inner
+                .refreshable_api
+                .replace(WsFederationApi::from_config(&new_config).into());
+        }
+        *self.inner.latest_config.write().expect("poisoned") = new_config;
+        // Nobody listening for events is fine
+        let _ = self.inner.config_changes.send(event);
+
+        Ok(true)
+    }
+
+    /// Refreshes the config according to `refresh`, never returns
+    pub(crate) async fn run_config_refresh(&self, refresh: ConfigRefresh) {
+        loop {
+            match self.refresh_config(&refresh.connect_info).await {
+                Ok(true) => info!("Federation config changed"),
+                Ok(false) => debug!("Federation config unchanged"),
+                Err(e) => warn!("Failed to refresh federation config: {e:?}"),
+            }
+            sleep(refresh.interval).await;
+        }
+    }
+}
+
+/// Reads the config persisted by a previous config refresh, before the module
+/// decoders are known. Configs of modules stay undecoded until
+/// [`ClientConfig::redecode_raw`] is called.
+pub(crate) async fn load_stored_config(db_source: &DatabaseSource) -> Option<ClientConfig> {
+    match db_source {
+        DatabaseSource::Fresh(db) => {
+            let fake_notifications = Default::default();
+            let mut dbtx = DatabaseTransaction::new(
+                db.begin_transaction().await,
+                Default::default(),
+                &fake_notifications,
+            );
+            dbtx.get_value(&ClientConfigKey).await
+        }
+        DatabaseSource::Reuse(client) => {
+            client
+                .db()
+                .begin_transaction()
+                .await
+                .get_value(&ClientConfigKey)
+                .await
+        }
+    }
+}
+
+impl ConfigChangedEvent {
+    fn new(old_config: &ClientConfig, new_config: &ClientConfig) -> Self {
+        let module_instances = old_config
+            .modules
+            .keys()
+            .chain(new_config.modules.keys())
+            .copied()
+            .collect::<BTreeSet<_>>();
+
+        ConfigChangedEvent {
+            api_endpoints_changed: old_config.api_endpoints != new_config.api_endpoints,
+            meta_changed: old_config.meta != new_config.meta,
+            modules_changed: module_instances
+                .into_iter()
+                .filter(|id| old_config.modules.get(id) != new_config.modules.get(id))
+                .collect(),
+        }
+    }
+}
+
+/// Federation API whose underlying API can be replaced when the API endpoints
+/// of the federation change, so modules and state machines holding on to it
+/// use the new endpoints too
+#[derive(Debug, Clone)]
+pub(crate) struct RefreshableFederationApi {
+    peers: BTreeSet<PeerId>,
+    current: Arc<RwLock<DynGlobalApi>>,
+    module_id: Option<ModuleInstanceId>,
+}
+
+impl RefreshableFederationApi {
+    pub(crate) fn new(api: DynGlobalApi) -> Self {
+        RefreshableFederationApi {
+            peers: api.all_members().clone(),
+            current: Arc::new(RwLock::new(api)),
+            module_id: None,
+        }
+    }
+
+    /// Makes all requests go to `api` from now on. Since the set of peers
+    /// can't change without restarting the client, APIs with different peers
+    /// are ignored.
+    pub(crate) fn replace(&self, api: DynGlobalApi) {
+        if api.all_members() != &self.peers {
+            warn!("Guardians of the federation changed, restart the client to use the new ones");
+            return;
+        }
+        *self.current.write().expect("poisoned") = api;
+    }
+}
+
+impl IGlobalFederationApi for RefreshableFederationApi {}
+
+impl IModuleFederationApi for RefreshableFederationApi {}
+
+#[apply(async_trait_maybe_send!)]
+impl IFederationApi for RefreshableFederationApi {
+    fn all_members(&self) -> &BTreeSet<PeerId> {
+        &self.peers
+    }
+
+    fn with_module(&self, id: ModuleInstanceId) -> DynModuleApi {
+        RefreshableFederationApi {
+            module_id: Some(id),
+            ..self.clone()
+        }
+        .into()
+    }
+
+    async fn request_raw(
+        &self,
+        peer_id: PeerId,
+        method: &str,
+        params: &[Value],
+    ) -> JsonRpcResult<Value> {
+        let api = self.current.read().expect("poisoned").clone();
+        match self.module_id {
+            None => api.request_raw(peer_id, method, params).await,
+            Some(id) => {
+                api.with_module(id)
+                    .request_raw(peer_id, method, params)
+                    .await
+            }
+        }
+    }
+}
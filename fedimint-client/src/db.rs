@@ -1,14 +1,18 @@
 use std::io::{Error, Read, Write};
 use std::marker::PhantomData;
 
+use bitcoin_hashes::sha256;
 use fedimint_core::api::ApiVersionSet;
+use fedimint_core::config::{ClientConfig, FederationId};
 use fedimint_core::encoding::{Decodable, DecodeError, Encodable};
 use fedimint_core::module::registry::ModuleDecoderRegistry;
-use fedimint_core::{impl_db_lookup, impl_db_record};
+use fedimint_core::{impl_db_lookup, impl_db_record, Amount, TransactionId};
 use serde::Serialize;
 use strum_macros::EnumIter;
 
+use crate::backup::LastBackup;
 use crate::oplog::OperationLogEntry;
+use crate::policy::SpendingRules;
 use crate::secret::RootSecretStrategy;
 use crate::sm::OperationId;
 use crate::ClientSecret;
@@ -20,6 +24,18 @@ pub enum DbKeyPrefix {
     OperationLog = 0x2c,
     ChronologicalOperationLog = 0x2d,
     CommonApiVersionCache = 0x2e,
+    OperationTypeIndex = 0x2f,
+    PendingOperation = 0x30,
+    OperationLogIndexesBuilt = 0x31,
+    JoinedFederation = 0x32,
+    SpendingRules = 0x33,
+    OperationSpend = 0x34,
+    LastBackup = 0x35,
+    ClientConfig = 0x36,
+    ConsensusConfigHash = 0x37,
+    OperationFee = 0x38,
+    SpendCounter = 0x39,
+    TransactionSpend = 0x3a,
 }
 
 impl std::fmt::Display for DbKeyPrefix {
@@ -94,6 +110,197 @@ impl_db_lookup!(
     query_prefix = ChronologicalOperationLogKeyPrefix
 );
 
+/// Key used to lookup operation log entries of one operation type in
+/// chronological order
+#[derive(Debug, Clone, Encodable, Decodable, Serialize)]
+pub struct OperationTypeIndexKey {
+    pub operation_type: String,
+    pub creation_time: std::time::SystemTime,
+    pub operation_id: OperationId,
+}
+
+#[derive(Debug, Encodable)]
+pub struct OperationTypeIndexKeyPrefix {
+    pub operation_type: String,
+}
+
+impl_db_record!(
+    key = OperationTypeIndexKey,
+    value = (),
+    db_prefix = DbKeyPrefix::OperationTypeIndex
+);
+
+impl_db_lookup!(
+    key = OperationTypeIndexKey,
+    query_prefix = OperationTypeIndexKeyPrefix
+);
+
+impl From<OperationTypeIndexKey> for ChronologicalOperationLogKey {
+    fn from(key: OperationTypeIndexKey) -> Self {
+        ChronologicalOperationLogKey {
+            creation_time: key.creation_time,
+            operation_id: key.operation_id,
+        }
+    }
+}
+
+/// Marks operations that still have active state machines, see
+/// [`crate::oplog::OperationStatus`]. The value is the creation time of the
+/// operation.
+#[derive(Debug, Clone, Copy, Encodable, Decodable, Serialize)]
+pub struct PendingOperationKey {
+    pub operation_id: OperationId,
+}
+
+#[derive(Debug, Encodable)]
+pub struct PendingOperationKeyPrefix;
+
+impl_db_record!(
+    key = PendingOperationKey,
+    value = std::time::SystemTime,
+    db_prefix = DbKeyPrefix::PendingOperation
+);
+
+impl_db_lookup!(
+    key = PendingOperationKey,
+    query_prefix = PendingOperationKeyPrefix
+);
+
+/// Present once the operation log indexes were built for operations created
+/// before they were introduced
+#[derive(Debug, Encodable, Decodable, Serialize)]
+pub struct OperationLogIndexesBuiltKey;
+
+impl_db_record!(
+    key = OperationLogIndexesBuiltKey,
+    value = (),
+    db_prefix = DbKeyPrefix::OperationLogIndexesBuilt
+);
+
+/// Federations joined by a [`ClientManager`](crate::manager::ClientManager),
+/// only used in the manager's own database
+#[derive(Debug, Clone, Copy, Encodable, Decodable, Serialize)]
+pub struct JoinedFederationKey {
+    pub federation_id: FederationId,
+}
+
+#[derive(Debug, Encodable)]
+pub struct JoinedFederationKeyPrefix;
+
+impl_db_record!(
+    key = JoinedFederationKey,
+    value = ClientConfig,
+    db_prefix = DbKeyPrefix::JoinedFederation
+);
+
+impl_db_lookup!(
+    key = JoinedFederationKey,
+    query_prefix = JoinedFederationKeyPrefix
+);
+
+/// The [`SpendingRules`] enforced when submitting transactions
+#[derive(Debug, Encodable, Decodable, Serialize)]
+pub struct SpendingRulesKey;
+
+impl_db_record!(
+    key = SpendingRulesKey,
+    value = SpendingRules,
+    db_prefix = DbKeyPrefix::SpendingRules
+);
+
+/// Amount spent by an operation's transactions and out of band, used to
+/// enforce rolling spending limits
+#[derive(Debug, Clone, Copy, Encodable, Decodable, Serialize)]
+pub struct OperationSpendKey {
+    pub operation_id: OperationId,
+}
+
+impl_db_record!(
+    key = OperationSpendKey,
+    value = Amount,
+    db_prefix = DbKeyPrefix::OperationSpend
+);
+
+/// Number of spends recorded so far. Every spend increments it, so concurrent
+/// database transactions recording spends conflict on databases detecting
+/// write conflicts, e.g. RocksDB, and the loser re-checks the spending policies
+/// against the winner's spend when it is retried.
+#[derive(Debug, Encodable, Decodable, Serialize)]
+pub struct SpendCounterKey;
+
+impl_db_record!(
+    key = SpendCounterKey,
+    value = u64,
+    db_prefix = DbKeyPrefix::SpendCounter
+);
+
+/// Part of an operation's [`OperationSpendKey`] spent by a transaction, which
+/// is released again if the transaction is rejected or expires
+#[derive(Debug, Clone, Copy, Encodable, Decodable, Serialize)]
+pub struct TransactionSpendKey {
+    pub txid: TransactionId,
+    pub operation_id: OperationId,
+}
+
+#[derive(Debug, Clone, Copy, Encodable, Decodable, Serialize)]
+pub struct TransactionSpendKeyPrefix {
+    pub txid: TransactionId,
+}
+
+impl_db_record!(
+    key = TransactionSpendKey,
+    value = Amount,
+    db_prefix = DbKeyPrefix::TransactionSpend
+);
+
+impl_db_lookup!(
+    key = TransactionSpendKey,
+    query_prefix = TransactionSpendKeyPrefix
+);
+
+/// Federation fees paid by all transactions submitted for an operation
+#[derive(Debug, Clone, Copy, Encodable, Decodable, Serialize)]
+pub struct OperationFeeKey {
+    pub operation_id: OperationId,
+}
+
+impl_db_record!(
+    key = OperationFeeKey,
+    value = Amount,
+    db_prefix = DbKeyPrefix::OperationFee
+);
+
+/// The last backup uploaded to the federation
+#[derive(Debug, Encodable, Decodable, Serialize)]
+pub struct LastBackupKey;
+
+impl_db_record!(
+    key = LastBackupKey,
+    value = LastBackup,
+    db_prefix = DbKeyPrefix::LastBackup
+);
+
+/// Latest config downloaded from the federation, takes precedence over the
+/// config given to the [`ClientBuilder`](crate::ClientBuilder)
+#[derive(Debug, Encodable, Decodable, Serialize)]
+pub struct ClientConfigKey;
+
+impl_db_record!(
+    key = ClientConfigKey,
+    value = ClientConfig,
+    db_prefix = DbKeyPrefix::ClientConfig
+);
+
+/// Hash of the federation's consensus config at the last config refresh
+#[derive(Debug, Encodable, Decodable, Serialize)]
+pub struct ConsensusConfigHashKey;
+
+impl_db_record!(
+    key = ConsensusConfigHashKey,
+    value = sha256::Hash,
+    db_prefix = DbKeyPrefix::ConsensusConfigHash
+);
+
 #[derive(Debug, Encodable, Decodable)]
 pub struct CachedApiVersionSetKey;
 
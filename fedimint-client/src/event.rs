@@ -0,0 +1,97 @@
+use fedimint_core::core::{ModuleInstanceId, ModuleKind};
+use fedimint_core::Amount;
+use serde::{Deserialize, Serialize};
+
+use crate::sm::OperationId;
+
+/// Event emitted by [`Client::subscribe_events`](crate::Client::subscribe_events)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ClientEvent {
+    /// A state machine of an operation made progress
+    StateTransition(StateTransitionEvent),
+    /// The balance available for spending changed, contains the new balance
+    BalanceChanged(Amount),
+    /// The executor gave up on a state machine after its transitions failed
+    /// too often, see [`RetryPolicy`](crate::sm::RetryPolicy)
+    DeadLetter(DeadLetterEvent),
+    /// A config refresh found that the federation's config changed, see
+    /// [`Client::refresh_config`](crate::Client::refresh_config)
+    ConfigChanged(ConfigChangedEvent),
+}
+
+/// A state transition processed by the executor
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StateTransitionEvent {
+    pub operation_id: OperationId,
+    pub module_instance_id: ModuleInstanceId,
+    /// Kind of the module owning the state machine, `None` for the transaction
+    /// submission state machines run by the client itself
+    pub module_kind: Option<ModuleKind>,
+    /// Operation type as recorded in the operation log, `None` if the
+    /// operation wasn't logged
+    pub operation_type: Option<String>,
+    pub old_state: StateSummary,
+    pub new_state: StateSummary,
+}
+
+/// A state moved to the dead-letter table by the executor
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeadLetterEvent {
+    pub operation_id: OperationId,
+    pub module_instance_id: ModuleInstanceId,
+    /// Kind of the module owning the state machine, `None` for the transaction
+    /// submission state machines run by the client itself
+    pub module_kind: Option<ModuleKind>,
+    pub state: StateSummary,
+    /// Number of failed attempts before giving up
+    pub attempts: u32,
+    /// Error of the last failed attempt
+    pub last_error: String,
+}
+
+/// Parts of the federation's config that changed
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConfigChangedEvent {
+    /// The client already uses the new API endpoints
+    pub api_endpoints_changed: bool,
+    /// The new meta fields are already returned by
+    /// [`Client::get_meta`](crate::Client::get_meta)
+    pub meta_changed: bool,
+    /// Module instances that were added, removed or changed, they only take
+    /// effect once the client is built again
+    pub modules_changed: Vec<ModuleInstanceId>,
+}
+
+/// Module-independent summary of a state machine state, see
+/// [`ClientModule::summarize_state`](crate::module::ClientModule::summarize_state)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StateSummary {
+    /// The state machine is waiting for something to happen, e.g. a
+    /// transaction being accepted, contains a short human-readable description
+    /// of what it's waiting for
+    InProgress(String),
+    /// **This state is final**
+    Succeeded,
+    /// The state machine failed, contains a human-readable reason. Funds that
+    /// could be recovered were refunded.
+    ///
+    /// **This state is final**
+    Failed(String),
+}
+
+impl StateSummary {
+    pub fn in_progress(description: impl Into<String>) -> Self {
+        StateSummary::InProgress(description.into())
+    }
+
+    pub fn failed(reason: impl ToString) -> Self {
+        StateSummary::Failed(reason.to_string())
+    }
+
+    /// Returns `true` if the state machine can't make further progress
+    pub fn is_final(&self) -> bool {
+        !matches!(self, StateSummary::InProgress(_))
+    }
+}
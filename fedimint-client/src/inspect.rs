@@ -0,0 +1,170 @@
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+use std::time::{Duration, SystemTime};
+
+use anyhow::anyhow;
+use bitcoin_hashes::{sha256, Hash};
+use fedimint_core::core::{ModuleInstanceId, ModuleKind};
+use fedimint_core::encoding::Encodable;
+use fedimint_core::time::now;
+
+use crate::event::StateSummary;
+use crate::sm::{DeadLetterState, DynState, OperationId};
+use crate::{Client, DynGlobalClientContext};
+
+/// Identifies a single state machine state, derived from its encoding.
+///
+/// Equal states of the same module instance share the same id, which is fine
+/// since the executor doesn't allow running them twice anyway.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct StateId(pub sha256::Hash);
+
+impl StateId {
+    pub fn from_state(state: &DynState<DynGlobalClientContext>) -> Self {
+        StateId(sha256::Hash::hash(
+            &state
+                .consensus_encode_to_vec()
+                .expect("Encoding to vec can't fail"),
+        ))
+    }
+}
+
+impl Display for StateId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(&self.0, f)
+    }
+}
+
+impl FromStr for StateId {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(StateId(
+            sha256::Hash::from_str(s).map_err(|e| anyhow!("Invalid state id: {e}"))?,
+        ))
+    }
+}
+
+/// Information about a state known to the executor, returned by
+/// [`Client::list_states`]
+#[derive(Debug, Clone)]
+pub struct StateInfo {
+    pub id: StateId,
+    pub operation_id: OperationId,
+    pub module_instance_id: ModuleInstanceId,
+    /// Kind of the module owning the state machine, `None` for the transaction
+    /// submission state machines run by the client itself
+    pub module_kind: Option<ModuleKind>,
+    /// Active states are still being driven forward by the executor
+    pub is_active: bool,
+    /// Set if the executor gave up on the state after it failed too often, it
+    /// can be made active again using [`Client::requeue_dead_letter`]
+    pub dead_letter: Option<DeadLetterState>,
+    pub created_at: SystemTime,
+    /// When the state machine left this state, `None` for active states
+    pub exited_at: Option<SystemTime>,
+    pub summary: StateSummary,
+    /// Decoded state, formatted using its `Debug` implementation
+    pub state: String,
+}
+
+impl StateInfo {
+    /// Time the state machine has spent in this state so far
+    pub fn age(&self) -> Duration {
+        self.exited_at
+            .unwrap_or_else(now)
+            .duration_since(self.created_at)
+            .unwrap_or_default()
+    }
+}
+
+impl Client {
+    /// Lists the active, inactive and dead-lettered states of all state
+    /// machines, optionally restricted to the ones belonging to `operation_id`,
+    /// ordered by creation time
+    pub async fn list_states(&self, operation_id: Option<OperationId>) -> Vec<StateInfo> {
+        let executor = &self.inner.executor;
+        let active = executor
+            .get_active_states()
+            .await
+            .into_iter()
+            .map(|(state, meta)| (state, true, meta.created_at, None, None));
+        let inactive = executor
+            .get_inactive_states()
+            .await
+            .into_iter()
+            .map(|(state, meta)| (state, false, meta.created_at, Some(meta.exited_at), None));
+        let dead_letters = executor.get_dead_letter_states().await;
+
+        let mut states = active
+            .chain(inactive)
+            .chain(dead_letters.into_iter().map(|(state, meta)| {
+                let exited_at = Some(meta.dead_lettered_at);
+                (state, false, meta.created_at, exited_at, Some(meta))
+            }))
+            .filter(|(state, ..)| {
+                operation_id.map_or(true, |operation_id| state.operation_id() == operation_id)
+            })
+            .map(|(state, is_active, created_at, exited_at, dead_letter)| {
+                let (module_kind, summary) = self.summarize_state(&state);
+                StateInfo {
+                    id: StateId::from_state(&state),
+                    operation_id: state.operation_id(),
+                    module_instance_id: state.module_instance_id(),
+                    module_kind,
+                    is_active,
+                    dead_letter,
+                    created_at,
+                    exited_at,
+                    summary,
+                    state: format!("{state:?}"),
+                }
+            })
+            .collect::<Vec<_>>();
+        states.sort_by_key(|info| info.created_at);
+        states
+    }
+
+    /// Stops the active state `state_id` from making further progress, see
+    /// [`Executor::abort_state`](crate::sm::Executor::abort_state)
+    pub async fn abort_state(&self, state_id: StateId) -> anyhow::Result<()> {
+        let state = self.find_active_state(state_id).await?;
+        self.inner.executor.abort_state(state).await
+    }
+
+    /// Re-evaluates the triggers of the active state `state_id`, see
+    /// [`Executor::retry_state`](crate::sm::Executor::retry_state)
+    pub async fn retry_state(&self, state_id: StateId) -> anyhow::Result<()> {
+        let state = self.find_active_state(state_id).await?;
+        self.inner.executor.retry_state(state).await
+    }
+
+    /// Makes the dead-lettered state `state_id` active again, see
+    /// [`Executor::requeue_dead_letter`](crate::sm::Executor::requeue_dead_letter)
+    pub async fn requeue_dead_letter(&self, state_id: StateId) -> anyhow::Result<()> {
+        let state = self
+            .inner
+            .executor
+            .get_dead_letter_states()
+            .await
+            .into_iter()
+            .map(|(state, _)| state)
+            .find(|state| StateId::from_state(state) == state_id)
+            .ok_or_else(|| anyhow!("No dead-lettered state with id {state_id}"))?;
+        self.inner.executor.requeue_dead_letter(state).await
+    }
+
+    async fn find_active_state(
+        &self,
+        state_id: StateId,
+    ) -> anyhow::Result<DynState<DynGlobalClientContext>> {
+        self.inner
+            .executor
+            .get_active_states()
+            .await
+            .into_iter()
+            .map(|(state, _)| state)
+            .find(|state| StateId::from_state(state) == state_id)
+            .ok_or_else(|| anyhow!("No active state with id {state_id}"))
+    }
+}
@@ -0,0 +1,361 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::ops::{Bound, RangeBounds};
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{bail, Result};
+use fedimint_core::core::ModuleKind;
+use fedimint_core::db::DatabaseTransaction;
+use fedimint_core::Amount;
+use serde::{Deserialize, Serialize};
+
+use super::Client;
+use crate::db::{ChronologicalOperationLogKey, OperationFeeKey};
+use crate::event::StateSummary;
+use crate::oplog::{OperationLogEntry, OperationLogQuery};
+use crate::sm::OperationId;
+
+/// Serialization formats supported by [`Client::export_ledger`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LedgerFormat {
+    /// One header line followed by one line per [`LedgerRow`]
+    Csv,
+    /// A JSON array of [`LedgerRow`]s
+    Json,
+}
+
+impl FromStr for LedgerFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(LedgerFormat::Csv),
+            "json" => Ok(LedgerFormat::Json),
+            _ => bail!("Unknown ledger format {s}, expected csv or json"),
+        }
+    }
+}
+
+/// Whether funds were received or sent by the client
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LedgerDirection {
+    Incoming,
+    Outgoing,
+}
+
+/// Final outcome of an operation as far as the client knows it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LedgerStatus {
+    /// No final outcome was recorded in the operation log yet
+    Pending,
+    Succeeded,
+    Failed,
+}
+
+/// Module-specific part of a [`LedgerRow`], returned by
+/// [`ClientModule::ledger_entry`](crate::module::ClientModule::ledger_entry)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LedgerEntry {
+    pub direction: LedgerDirection,
+    /// Amount sent or received excluding fees, `None` if the module doesn't
+    /// know it
+    pub amount: Option<Amount>,
+    /// Fees paid to third parties on top of `amount`, e.g. on-chain or gateway
+    /// fees, `None` if there are none or the module doesn't know them. Fees
+    /// paid to the federation are added by the client.
+    pub fee: Option<Amount>,
+    /// Invoice, address or other identifier of the other party, if any
+    pub counterparty: Option<String>,
+    pub status: LedgerStatus,
+}
+
+/// One normalized line of an exported ledger
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LedgerRow {
+    /// Creation time of the operation in seconds since the unix epoch
+    pub timestamp: u64,
+    pub operation_id: OperationId,
+    /// Kind of the module that created the operation
+    pub module: String,
+    pub direction: LedgerDirection,
+    pub amount_msat: Option<Amount>,
+    pub fee_msat: Option<Amount>,
+    pub counterparty: Option<String>,
+    pub status: LedgerStatus,
+}
+
+const CSV_HEADER: &str =
+    "timestamp,operation_id,module,direction,amount_msat,fee_msat,counterparty,status";
+
+impl LedgerRow {
+    fn new(key: ChronologicalOperationLogKey, module: &ModuleKind, entry: LedgerEntry) -> Self {
+        LedgerRow {
+            timestamp: key
+                .creation_time
+                .duration_since(UNIX_EPOCH)
+                .expect("Creation time is after the unix epoch")
+                .as_secs(),
+            operation_id: key.operation_id,
+            module: module.to_string(),
+            direction: entry.direction,
+            amount_msat: entry.amount,
+            fee_msat: entry.fee,
+            counterparty: entry.counterparty,
+            status: entry.status,
+        }
+    }
+
+    fn write_csv_line(&self, out: &mut String) {
+        let optional_amount = |amount: Option<Amount>| {
+            amount
+                .map(|amount| amount.msats.to_string())
+                .unwrap_or_default()
+        };
+        let fields = [
+            self.timestamp.to_string(),
+            self.operation_id.to_string(),
+            self.module.clone(),
+            serde_plain_name(&self.direction),
+            optional_amount(self.amount_msat),
+            optional_amount(self.fee_msat),
+            self.counterparty.clone().unwrap_or_default(),
+            serde_plain_name(&self.status),
+        ];
+
+        let line = fields
+            .iter()
+            .map(|field| csv_escape(field))
+            .collect::<Vec<_>>()
+            .join(",");
+        writeln!(out, "{line}").expect("Writing to a string can't fail");
+    }
+}
+
+/// Returns the name serde uses for a unit enum variant
+fn serde_plain_name(value: &impl Serialize) -> String {
+    match serde_json::to_value(value).expect("Serialization can't fail") {
+        serde_json::Value::String(name) => name,
+        other => other.to_string(),
+    }
+}
+
+/// Quotes a CSV field if it contains a separator, quote or line break
+fn csv_escape(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_owned()
+    }
+}
+
+/// Serializes ledger rows in the given format
+pub fn format_ledger(rows: &[LedgerRow], format: LedgerFormat) -> String {
+    match format {
+        LedgerFormat::Csv => {
+            let mut out = format!("{CSV_HEADER}\n");
+            for row in rows {
+                row.write_csv_line(&mut out);
+            }
+            out
+        }
+        LedgerFormat::Json => serde_json::to_string_pretty(rows).expect("Serialization can't fail"),
+    }
+}
+
+impl Client {
+    /// Exports all operations created in `time_range` that moved funds as a
+    /// ledger, oldest first. Each operation is described by the module that
+    /// created it, operations of unknown modules or ones not moving funds are
+    /// left out.
+    ///
+    /// Operations whose outcome wasn't recorded yet, because nobody followed
+    /// their updates to the end, get their status from their state machines.
+    /// The fees include all federation fees paid by the operation's
+    /// transactions.
+    pub async fn export_ledger(
+        &self,
+        format: LedgerFormat,
+        time_range: impl RangeBounds<SystemTime>,
+    ) -> Result<String> {
+        let rows = self.ledger_rows(time_range).await;
+        Ok(format_ledger(&rows, format))
+    }
+
+    async fn ledger_rows(&self, time_range: impl RangeBounds<SystemTime>) -> Vec<LedgerRow> {
+        // The operation log query covers creation times in [start, end)
+        let smallest_step = Duration::from_nanos(1);
+        let query = OperationLogQuery {
+            start_time: match time_range.start_bound() {
+                Bound::Included(start) => Some(*start),
+                Bound::Excluded(start) => Some(*start + smallest_step),
+                Bound::Unbounded => None,
+            },
+            end_time: match time_range.end_bound() {
+                Bound::Included(end) => Some(*end + smallest_step),
+                Bound::Excluded(end) => Some(*end),
+                Bound::Unbounded => None,
+            },
+            ..Default::default()
+        };
+
+        let operations = self.operation_log().query_operations(&query).await;
+        let state_summaries = self.operation_state_summaries().await;
+
+        let mut dbtx = self.db().begin_transaction().await;
+        let mut rows = vec![];
+        for (key, operation) in operations {
+            let Some(mut entry) = self
+                .ledger_entry(&mut dbtx, key.operation_id, &operation)
+                .await
+            else {
+                continue;
+            };
+
+            if entry.status == LedgerStatus::Pending {
+                entry.status = status_from_states(
+                    state_summaries
+                        .get(&key.operation_id)
+                        .map(Vec::as_slice)
+                        .unwrap_or_default(),
+                );
+            }
+
+            let federation_fee = dbtx
+                .get_value(&OperationFeeKey {
+                    operation_id: key.operation_id,
+                })
+                .await;
+            entry.fee = match (entry.fee, federation_fee) {
+                (None, None) => None,
+                (fee, federation_fee) => {
+                    Some(fee.unwrap_or(Amount::ZERO) + federation_fee.unwrap_or(Amount::ZERO))
+                }
+            };
+
+            let module_kind = ModuleKind::clone_from_str(operation.operation_type());
+            rows.push(LedgerRow::new(key, &module_kind, entry));
+        }
+        rows.reverse();
+        rows
+    }
+
+    /// Summaries of the states of all state machines, grouped by operation.
+    /// The flag is set for states that are still active.
+    async fn operation_state_summaries(&self) -> HashMap<OperationId, Vec<(bool, StateSummary)>> {
+        let executor = &self.inner.executor;
+        let active_states = executor
+            .get_active_states()
+            .await
+            .into_iter()
+            .map(|(state, _)| (true, state));
+        let inactive_states = executor
+            .get_inactive_states()
+            .await
+            .into_iter()
+            .map(|(state, _)| (false, state));
+
+        let mut summaries = HashMap::<_, Vec<_>>::new();
+        for (active, state) in active_states.chain(inactive_states) {
+            let (_, summary) = self.summarize_state(&state);
+            summaries
+                .entry(state.operation_id())
+                .or_default()
+                .push((active, summary));
+        }
+        summaries
+    }
+
+    /// Describes an operation using the module that created it, `None` if
+    /// the module is unknown or the operation didn't move funds
+    pub(crate) async fn ledger_entry(
+        &self,
+        dbtx: &mut DatabaseTransaction<'_>,
+        operation_id: OperationId,
+        operation: &OperationLogEntry,
+    ) -> Option<LedgerEntry> {
+        let module_kind = ModuleKind::clone_from_str(operation.operation_type());
+        let instance = self.get_first_instance(&module_kind)?;
+        self.get_module_client_dyn(instance)
+            .ok()?
+            .ledger_entry(instance, dbtx, operation_id, operation)
+            .await
+    }
+}
+
+/// Determines the status of an operation whose outcome wasn't recorded yet
+/// from the summaries of its states, see [`Client::operation_state_summaries`].
+/// It is pending while any of its state machines is active, otherwise it
+/// failed if any state machine failed.
+fn status_from_states(summaries: &[(bool, StateSummary)]) -> LedgerStatus {
+    if summaries.iter().any(|(active, _)| *active) {
+        return LedgerStatus::Pending;
+    }
+
+    let mut status = LedgerStatus::Pending;
+    for (_, summary) in summaries {
+        match summary {
+            StateSummary::Failed(_) => return LedgerStatus::Failed,
+            StateSummary::Succeeded => status = LedgerStatus::Succeeded,
+            StateSummary::InProgress(_) => {}
+        }
+    }
+    status
+}
+
+#[cfg(test)]
+mod tests {
+    use fedimint_core::Amount;
+
+    use super::{format_ledger, LedgerDirection, LedgerFormat, LedgerRow, LedgerStatus};
+    use crate::sm::OperationId;
+
+    #[test]
+    fn test_format_ledger() {
+        let rows = vec![
+            LedgerRow {
+                timestamp: 1_700_000_000,
+                operation_id: OperationId([0x11; 32]),
+                module: "ln".to_string(),
+                direction: LedgerDirection::Outgoing,
+                amount_msat: Some(Amount::from_msats(1000)),
+                fee_msat: None,
+                counterparty: Some("lnbc1,\"quoted\"".to_string()),
+                status: LedgerStatus::Succeeded,
+            },
+            LedgerRow {
+                timestamp: 1_700_000_001,
+                operation_id: OperationId([0x22; 32]),
+                module: "wallet".to_string(),
+                direction: LedgerDirection::Incoming,
+                amount_msat: None,
+                fee_msat: Some(Amount::from_msats(2000)),
+                counterparty: None,
+                status: LedgerStatus::Pending,
+            },
+        ];
+
+        let csv = format_ledger(&rows, LedgerFormat::Csv);
+        let lines = csv.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(
+            lines[1],
+            format!(
+                "1700000000,{},ln,outgoing,1000,,\"lnbc1,\"\"quoted\"\"\",succeeded",
+                OperationId([0x11; 32])
+            )
+        );
+        assert_eq!(
+            lines[2],
+            format!(
+                "1700000001,{},wallet,incoming,,2000,,pending",
+                OperationId([0x22; 32])
+            )
+        );
+
+        let json = format_ledger(&rows, LedgerFormat::Json);
+        assert_eq!(serde_json::from_str::<Vec<LedgerRow>>(&json).unwrap(), rows);
+    }
+}
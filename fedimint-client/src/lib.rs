@@ -69,8 +69,10 @@
 use std::cmp::Ordering;
 use std::collections::{BTreeMap, HashSet};
 use std::fmt::{Debug, Formatter};
+use std::future::Future;
 use std::io::{Error, Read, Write};
 use std::sync::Arc;
+use std::time::Duration;
 
 use anyhow::{anyhow, bail};
 use async_stream::stream;
@@ -91,7 +93,8 @@ use fedimint_core::module::{
 use fedimint_core::task::{MaybeSend, MaybeSync, TaskGroup};
 use fedimint_core::time::now;
 use fedimint_core::transaction::Transaction;
-use fedimint_core::util::{BoxStream, NextOrPending};
+use fedimint_core::util::broadcaststream::BroadcastStream;
+use fedimint_core::util::{BoxFuture, BoxStream, NextOrPending};
 use fedimint_core::{
     apply, async_trait_maybe_send, dyn_newtype_define, maybe_add_send_sync, Amount, OutPoint,
     TransactionId,
@@ -106,13 +109,22 @@ use secret::DeriveableSecretClientExt;
 use serde::Serialize;
 use tracing::{debug, info, warn};
 
-use crate::backup::Metadata;
-use crate::db::ClientSecretKey;
+use crate::backup::{BackupSchedule, Metadata};
+use crate::batch::{BatchingConfig, TransactionBatcher};
+use crate::config_refresh::{load_stored_config, ConfigRefresh, RefreshableFederationApi};
+use crate::db::{
+    ChronologicalOperationLogKey, ClientSecretKey, OperationFeeKey, OperationSpendKey,
+    SpendCounterKey, SpendingRulesKey, TransactionSpendKey,
+};
+use crate::event::{
+    ClientEvent, ConfigChangedEvent, DeadLetterEvent, StateSummary, StateTransitionEvent,
+};
 use crate::module::gen::{
     ClientModuleGen, ClientModuleGenRegistry, DynClientModuleGen, IClientModuleGen,
 };
 use crate::module::{ClientModule, ClientModuleRegistry, IClientModule, StateGenerator};
-use crate::oplog::OperationLog;
+use crate::oplog::{OperationLog, OperationLogEntry, OperationLogQuery};
+use crate::policy::{SpendRequest, SpendingPolicy, SpendingRules};
 use crate::secret::RootSecretStrategy;
 use crate::sm::executor::{
     ActiveOperationStateKeyPrefix, ContextGen, InactiveOperationStateKeyPrefix,
@@ -122,19 +134,33 @@ use crate::sm::{
     OperationState, State,
 };
 use crate::transaction::{
-    tx_submission_sm_decoder, ClientInput, ClientOutput, TransactionBuilder,
-    TransactionBuilderBalance, TxSubmissionContext, TxSubmissionError, TxSubmissionStates,
-    TRANSACTION_SUBMISSION_MODULE_INSTANCE,
+    tx_submission_sm_decoder, ClientInput, ClientOutput, InputPreview, OutputPreview,
+    TransactionBuilder, TransactionBuilderBalance, TransactionPreview, TxSubmissionContext,
+    TxSubmissionError, TxSubmissionStates, TRANSACTION_SUBMISSION_MODULE_INSTANCE,
 };
 
 /// Client backup
 pub mod backup;
+/// Batching of operations into combined transactions
+pub mod batch;
+/// Periodic refresh of the federation config
+pub mod config_refresh;
 /// Database keys used by the client
 pub mod db;
+/// Typed events about operations and balance changes
+pub mod event;
+/// Inspection and repair of state machines run by the executor
+pub mod inspect;
+/// Accounting export of the operation log
+pub mod ledger;
+/// Running clients of multiple federations in one process
+pub mod manager;
 /// Module client interface definitions
 pub mod module;
 /// Operation log subsystem of the client
 pub mod oplog;
+/// Client-side limits on spending funds
+pub mod policy;
 /// Secret handling & derivation
 pub mod secret;
 /// Client state machine interfaces and executor implementation
@@ -196,6 +222,16 @@ pub trait IGlobalClientContext: Debug + MaybeSend + MaybeSync + 'static {
         sm: Box<maybe_add_send_sync!(dyn IState<DynGlobalClientContext>)>,
     ) -> anyhow::Result<()>;
 
+    /// Checks `amount` handed out by the operation without a transaction
+    /// against the spending policies and records it, see
+    /// [`Client::enforce_out_of_band_spend`]. Fails if a policy doesn't allow
+    /// the spend, in which case the funds must not be handed out.
+    async fn enforce_out_of_band_spend(
+        &self,
+        dbtx: &mut ClientSMDatabaseTransaction<'_, '_>,
+        amount: Amount,
+    ) -> anyhow::Result<()>;
+
     async fn transaction_update_stream(
         &self,
         operation_id: OperationId,
@@ -225,6 +261,9 @@ impl DynGlobalClientContext {
                     TxSubmissionStates::Rejected { txid, error } if txid == query_txid => {
                         Some(Err(error))
                     }
+                    TxSubmissionStates::Expired { txid, expiry } if txid == query_txid => {
+                        Some(Err(TxSubmissionError::Expired(expiry)))
+                    }
                     _ => None,
                 })
             })
@@ -386,6 +425,7 @@ impl IGlobalClientContext for ModuleGlobalClientContext {
                 dbtx.global_tx(),
                 self.operation,
                 TransactionBuilder::new().with_input(instance_input),
+                false,
             )
             .await
             .expect("Can obly fail if additional funding is needed")
@@ -406,6 +446,7 @@ impl IGlobalClientContext for ModuleGlobalClientContext {
                 dbtx.global_tx(),
                 self.operation,
                 TransactionBuilder::new().with_output(instance_output),
+                false,
             )
             .await
     }
@@ -423,6 +464,20 @@ impl IGlobalClientContext for ModuleGlobalClientContext {
             .await
     }
 
+    async fn enforce_out_of_band_spend(
+        &self,
+        dbtx: &mut ClientSMDatabaseTransaction<'_, '_>,
+        amount: Amount,
+    ) -> anyhow::Result<()> {
+        let request = SpendRequest {
+            amount,
+            destinations: vec![],
+        };
+        self.client.check_spend(dbtx.global_tx(), &request).await?;
+        ClientInner::record_spend(dbtx.global_tx(), self.operation, amount).await;
+        Ok(())
+    }
+
     async fn transaction_update_stream(
         &self,
         operation_id: OperationId,
@@ -467,11 +522,81 @@ impl Client {
         ClientBuilder::default()
     }
 
+    /// Starts the state machine executor and, if configured, the
+    /// [transaction batching](ClientBuilder::with_transaction_batching), the
+    /// [config refresh](ClientBuilder::with_config_refresh), the automatic
+    /// [`BackupSchedule`] and the
+    /// [background tasks](ClientBuilder::with_background_task)
     pub async fn start_executor(&self, tg: &mut TaskGroup) {
         self.inner
             .executor
             .start_executor(tg, self.inner.context_gen())
-            .await
+            .await;
+
+        if let Some(queue) = self.inner.batcher.as_ref().and_then(|b| b.take_queue()) {
+            let client = self.clone();
+            let _handle = tg
+                .spawn("client_transaction_batcher", move |handle| async move {
+                    let shutdown_future = handle.make_shutdown_rx().await;
+                    tokio::select! {
+                        _ = shutdown_future => {
+                            info!("Shutting down transaction batching");
+                        },
+                        _ = client.run_transaction_batcher(queue) => {},
+                    }
+                })
+                .await;
+        }
+
+        if let Some(refresh) = self.inner.config_refresh.clone() {
+            let client = self.clone();
+            let _handle = tg
+                .spawn("client_config_refresh", move |handle| async move {
+                    let shutdown_future = handle.make_shutdown_rx().await;
+                    tokio::select! {
+                        _ = shutdown_future => {
+                            info!("Shutting down config refresh");
+                        },
+                        _ = client.run_config_refresh(refresh) => {},
+                    }
+                })
+                .await;
+        }
+
+        if let Some(schedule) = self.inner.backup_schedule.clone() {
+            let client = self.clone();
+            let _handle = tg
+                .spawn("client_backup_schedule", move |handle| async move {
+                    let shutdown_future = handle.make_shutdown_rx().await;
+                    tokio::select! {
+                        _ = shutdown_future => {
+                            info!("Shutting down automatic backups");
+                        },
+                        _ = client.run_backup_schedule(schedule) => {},
+                    }
+                })
+                .await;
+        }
+
+        for (name, task) in self.inner.background_tasks.clone() {
+            let client = self.clone();
+            let _handle = tg
+                .spawn(name, move |handle| async move {
+                    let shutdown_future = handle.make_shutdown_rx().await;
+                    tokio::select! {
+                        _ = shutdown_future => {
+                            info!("Shutting down background task {name}");
+                        },
+                        _ = task(client) => {},
+                    }
+                })
+                .await;
+        }
+    }
+
+    /// Returns the schedule of automatic backups, if they are enabled
+    pub fn backup_schedule(&self) -> Option<&BackupSchedule> {
+        self.inner.backup_schedule.as_ref()
     }
 
     pub fn api(&self) -> &(dyn IGlobalFederationApi + 'static) {
@@ -491,7 +616,13 @@ impl Client {
     }
 
     pub fn get_meta(&self, key: &str) -> Option<String> {
-        self.inner.federation_meta.get(key).cloned()
+        self.inner
+            .latest_config
+            .read()
+            .expect("poisoned")
+            .meta
+            .get(key)
+            .cloned()
     }
 
     pub fn decoders(&self) -> &ModuleDecoderRegistry {
@@ -502,8 +633,27 @@ impl Client {
         self.inner.root_secret.clone()
     }
 
+    /// Adds funding and/or change to the transaction builder like
+    /// [`Client::finalize_and_submit_transaction`] would and returns the
+    /// resulting inputs, outputs and fees without submitting anything or
+    /// modifying the database. The funding inputs and change may differ from
+    /// the ones used by a later submission if the client's funds change in
+    /// between.
+    pub async fn preview_transaction(
+        &self,
+        tx_builder: TransactionBuilder,
+    ) -> anyhow::Result<TransactionPreview> {
+        self.inner.preview_transaction(tx_builder).await
+    }
+
     /// Add funding and/or change to the transaction builder as needed, finalize
     /// the transaction and submit it to the federation.
+    ///
+    /// Fails if the transaction could not be committed to the database after
+    /// 100 attempts, in which case nothing was submitted. Fails with the
+    /// error of the first [`SpendingPolicy`] the transaction doesn't comply
+    /// with, e.g. a [`SpendingPolicyViolation`](policy::SpendingPolicyViolation),
+    /// in which case nothing was signed or submitted.
     pub async fn finalize_and_submit_transaction<F, M>(
         &self,
         operation_id: OperationId,
@@ -526,28 +676,17 @@ impl Client {
                     let tx_builder = tx_builder.clone();
                     let operation_meta = operation_meta.clone();
                     Box::pin(async move {
-                        if ClientInner::operation_exists(dbtx, operation_id).await {
-                            bail!("There already exists an operation with id {operation_id:?}")
-                        }
-
-                        let (txid, change_outpoint) = self
-                            .inner
-                            .finalize_and_submit_transaction(dbtx, operation_id, tx_builder)
-                            .await?;
-
-                        self.operation_log()
-                            .add_operation_log_entry(
-                                dbtx,
-                                operation_id,
-                                &operation_type,
-                                operation_meta(txid, change_outpoint),
-                            )
-                            .await;
-
-                        Ok(txid)
+                        self.finalize_and_submit_transaction_dbtx(
+                            dbtx,
+                            operation_id,
+                            &operation_type,
+                            operation_meta,
+                            tx_builder,
+                        )
+                        .await
                     })
                 },
-                Some(100), // TODO: handle what happens after 100 retries
+                Some(100),
             )
             .await;
 
@@ -557,10 +696,95 @@ impl Client {
             Err(AutocommitError::CommitFailed {
                 attempts,
                 last_error,
-            }) => panic!(
-                "Failed to commit tx submission dbtx after {attempts} attempts: {last_error}"
-            ),
+            }) => Err(last_error.context(format!(
+                "Failed to commit tx submission dbtx after {attempts} attempts"
+            ))),
+        }
+    }
+
+    /// Like [`Client::finalize_and_submit_transaction`], but as part of `dbtx`
+    /// so callers can atomically update their own state with the submission,
+    /// e.g. remove the notes spent by the transaction from their wallet.
+    /// Nothing is submitted unless `dbtx` is committed.
+    pub async fn finalize_and_submit_transaction_dbtx<F, M>(
+        &self,
+        dbtx: &mut DatabaseTransaction<'_>,
+        operation_id: OperationId,
+        operation_type: &str,
+        operation_meta: F,
+        tx_builder: TransactionBuilder,
+    ) -> anyhow::Result<TransactionId>
+    where
+        F: FnOnce(TransactionId, Option<OutPoint>) -> M,
+        M: serde::Serialize,
+    {
+        if ClientInner::operation_exists(dbtx, operation_id).await {
+            bail!("There already exists an operation with id {operation_id:?}")
         }
+
+        let (txid, change_outpoint) = self
+            .inner
+            .finalize_and_submit_transaction(dbtx, operation_id, tx_builder, true)
+            .await?;
+
+        self.operation_log()
+            .add_operation_log_entry(
+                dbtx,
+                operation_id,
+                operation_type,
+                operation_meta(txid, change_outpoint),
+            )
+            .await;
+
+        Ok(txid)
+    }
+
+    /// Checks `amount` handed out by an operation without a transaction, e.g.
+    /// e-cash notes spent out of band, against the [`SpendingPolicy`]s and
+    /// records it for enforcing rolling limits. Has to be called in the
+    /// database transaction handing out the funds. Fails if a policy doesn't
+    /// allow the spend, in which case `dbtx` must not be committed.
+    pub async fn enforce_out_of_band_spend(
+        &self,
+        dbtx: &mut DatabaseTransaction<'_>,
+        operation_id: OperationId,
+        amount: Amount,
+    ) -> anyhow::Result<()> {
+        let request = SpendRequest {
+            amount,
+            destinations: vec![],
+        };
+        self.inner.check_spend(dbtx, &request).await?;
+        ClientInner::record_spend(dbtx, operation_id, amount).await;
+        Ok(())
+    }
+
+    /// Returns the built-in [`SpendingRules`] enforced by
+    /// [`Client::finalize_and_submit_transaction`], which are empty unless
+    /// set
+    pub async fn spending_rules(&self) -> SpendingRules {
+        self.inner
+            .db
+            .begin_transaction()
+            .await
+            .get_value(&SpendingRulesKey)
+            .await
+            .unwrap_or_default()
+    }
+
+    /// Replaces the [`SpendingRules`], they apply to all transactions
+    /// submitted afterwards
+    pub async fn set_spending_rules(&self, rules: SpendingRules) -> anyhow::Result<()> {
+        let mut dbtx = self.inner.db.begin_transaction().await;
+        dbtx.insert_entry(&SpendingRulesKey, &rules).await;
+        dbtx.commit_tx_result().await
+    }
+
+    /// Returns the amount spent by operations created within the last
+    /// `period`, as counted by [`RollingLimit`](policy::RollingLimit)s
+    pub async fn spent_within(&self, period: Duration) -> Amount {
+        let mut dbtx = self.inner.db.begin_transaction().await;
+        policy::spent_within(&mut dbtx, period).await
     }
 
     pub async fn add_state_machines(
@@ -583,6 +807,57 @@ impl Client {
         &self.inner.operation_log
     }
 
+    /// Returns the amount moved by an operation as reported by the
+    /// [`ClientModule::ledger_entry`] of the module that created it. Falls
+    /// back to [`OperationLogEntry::amount`] for modules that don't describe
+    /// their operations.
+    pub async fn operation_amount(
+        &self,
+        operation_id: OperationId,
+        operation: &OperationLogEntry,
+    ) -> Option<Amount> {
+        let mut dbtx = self.db().begin_transaction().await;
+        self.ledger_entry(&mut dbtx, operation_id, operation)
+            .await
+            .and_then(|entry| entry.amount)
+            .or_else(|| operation.amount())
+    }
+
+    /// Returns the operations matching all filters of `query`, newest first.
+    /// Amount filters are evaluated against [`Client::operation_amount`].
+    pub async fn query_operations(
+        &self,
+        query: &OperationLogQuery,
+    ) -> Vec<(ChronologicalOperationLogKey, OperationLogEntry)> {
+        if query.min_amount.is_none() && query.max_amount.is_none() {
+            return self.operation_log().query_operations(query).await;
+        }
+
+        // The limit has to be applied after filtering by amount
+        let candidates = self
+            .operation_log()
+            .query_operations(&OperationLogQuery {
+                min_amount: None,
+                max_amount: None,
+                limit: None,
+                ..query.clone()
+            })
+            .await;
+
+        let mut operations = vec![];
+        for (key, operation) in candidates {
+            if query.limit.map_or(false, |limit| operations.len() >= limit) {
+                break;
+            }
+
+            let amount = self.operation_amount(key.operation_id, &operation).await;
+            if query.contains_amount(amount) {
+                operations.push((key, operation));
+            }
+        }
+        operations
+    }
+
     /// Returns a reference to a typed module client instance by kind
     pub fn get_first_module<M: ClientModule>(
         &self,
@@ -670,9 +945,13 @@ impl Client {
             .await
     }
 
-    /// Returns the config with which the client was initialized.
-    pub fn get_config(&self) -> &ClientConfig {
-        &self.inner.config
+    /// Returns the latest config of the federation. If a [config
+    /// refresh](Client::refresh_config) changed it since the client was built,
+    /// this is the refreshed config, which is also used for the meta fields and
+    /// API endpoints. Module instances keep using their config from when the
+    /// client was built until it's built again.
+    pub fn get_config(&self) -> ClientConfig {
+        self.inner.latest_config.read().expect("poisoned").clone()
     }
 
     /// Get the primary module
@@ -709,12 +988,103 @@ impl Client {
         })
     }
 
+    /// Returns a stream of all state transitions processed by the executor,
+    /// summarized by the modules owning the state machines, of all states the
+    /// executor gave up on and of all balance changes. Only events happening
+    /// after subscribing are returned.
+    pub async fn subscribe_events(&self) -> BoxStream<'_, ClientEvent> {
+        let transitions = self.inner.executor.notifier().subscribe_transitions().then(
+            move |(old_state, new_state)| async move {
+                ClientEvent::StateTransition(
+                    self.state_transition_event(&old_state, &new_state).await,
+                )
+            },
+        );
+        let dead_letters = self.inner.executor.notifier().subscribe_dead_letters().map(
+            move |(state, dead_letter)| {
+                let (module_kind, summary) = self.summarize_state(&state);
+                ClientEvent::DeadLetter(DeadLetterEvent {
+                    operation_id: state.operation_id(),
+                    module_instance_id: state.module_instance_id(),
+                    module_kind,
+                    state: summary,
+                    attempts: dead_letter.attempts,
+                    last_error: dead_letter.last_error,
+                })
+            },
+        );
+        let balance_changes = self
+            .subscribe_balance_changes()
+            .await
+            .map(ClientEvent::BalanceChanged);
+
+        let config_changes = BroadcastStream::new(self.inner.config_changes.subscribe())
+            .filter_map(|res| async move { res.ok().map(ClientEvent::ConfigChanged) });
+
+        Box::pin(futures::stream::select(
+            futures::stream::select(transitions, config_changes),
+            futures::stream::select(dead_letters, balance_changes),
+        ))
+    }
+
+    async fn state_transition_event(
+        &self,
+        old_state: &DynState<DynGlobalClientContext>,
+        new_state: &DynState<DynGlobalClientContext>,
+    ) -> StateTransitionEvent {
+        let module_instance_id = new_state.module_instance_id();
+        let operation_id = new_state.operation_id();
+
+        let (module_kind, old_summary) = self.summarize_state(old_state);
+        let (_, new_summary) = self.summarize_state(new_state);
+
+        let operation_type = self
+            .operation_log()
+            .get_operation(operation_id)
+            .await
+            .map(|operation| operation.operation_type().to_owned());
+
+        StateTransitionEvent {
+            operation_id,
+            module_instance_id,
+            module_kind,
+            operation_type,
+            old_state: old_summary,
+            new_state: new_summary,
+        }
+    }
+
+    /// Summarizes `state` using the module owning it, also returns the kind of
+    /// that module
+    fn summarize_state(
+        &self,
+        state: &DynState<DynGlobalClientContext>,
+    ) -> (Option<ModuleKind>, StateSummary) {
+        let module_instance_id = state.module_instance_id();
+        if module_instance_id == TRANSACTION_SUBMISSION_MODULE_INSTANCE {
+            let summary = state
+                .as_any()
+                .downcast_ref::<OperationState<TxSubmissionStates>>()
+                .expect("Transaction submission state has the right type")
+                .state
+                .summary();
+            (None, summary)
+        } else {
+            let (kind, module) = self
+                .inner
+                .modules
+                .get_with_kind(module_instance_id)
+                .expect("Executor only runs states of registered modules");
+            (Some(kind.clone()), module.summarize_state(state))
+        }
+    }
+
     pub async fn discover_common_api_version(&self) -> anyhow::Result<ApiVersionSet> {
         Ok(self
             .api()
             .discover_api_version_set(
                 &Self::supported_api_versions_summary_static(
-                    self.get_config(),
+                    &self.get_config(),
                     &self.inner.module_gens,
                 )
                 .await,
@@ -851,7 +1221,6 @@ struct ClientInner {
     decoders: ModuleDecoderRegistry,
     db: Database,
     federation_id: FederationId,
-    federation_meta: BTreeMap<String, String>,
     primary_module_instance: ModuleInstanceId,
     modules: ClientModuleRegistry,
     module_gens: ClientModuleGenRegistry,
@@ -860,8 +1229,23 @@ struct ClientInner {
     root_secret: DerivableSecret,
     operation_log: OperationLog,
     secp_ctx: Secp256k1<secp256k1_zkp::All>,
+    backup_schedule: Option<BackupSchedule>,
+    batcher: Option<TransactionBatcher>,
+    config_refresh: Option<ConfigRefresh>,
+    /// Latest config of the federation, see [`Client::get_config`]
+    latest_config: std::sync::RwLock<ClientConfig>,
+    /// Handle to replace the API used by the client and its modules
+    refreshable_api: RefreshableFederationApi,
+    config_changes: tokio::sync::broadcast::Sender<ConfigChangedEvent>,
+    /// Checked in addition to the stored [`SpendingRules`]
+    spending_policies: Vec<Arc<dyn SpendingPolicy>>,
+    background_tasks: Vec<(&'static str, BackgroundTask)>,
 }
 
+/// Long running task started together with the executor, see
+/// [`ClientBuilder::with_background_task`]
+type BackgroundTask = Arc<maybe_add_send_sync!(dyn Fn(Client) -> BoxFuture<'static, ()>)>;
+
 impl ClientInner {
     fn primary_module(&self) -> &DynClientModule {
         self.modules
@@ -940,19 +1324,32 @@ impl ClientInner {
         }
     }
 
+    /// Sums up the fees the federation charges for all inputs and outputs of
+    /// a transaction
+    fn transaction_fee(&self, builder: &TransactionBuilder) -> Amount {
+        let input_fees = builder.inputs.iter().map(|input| {
+            self.get_module(input.input.module_instance_id())
+                .input_amount(&input.input)
+                .fee
+        });
+        let output_fees = builder.outputs.iter().map(|output| {
+            self.get_module(output.output.module_instance_id())
+                .output_amount(&output.output)
+                .fee
+        });
+        input_fees.chain(output_fees).sum()
+    }
+
     /// Adds funding to a transaction or removes overfunding via change.
-    async fn finalize_transaction(
+    /// Returns the index of the change output, if one was added.
+    async fn balance_transaction(
         &self,
         dbtx: &mut DatabaseTransaction<'_>,
         operation_id: OperationId,
-        mut partial_transaction: TransactionBuilder,
-    ) -> anyhow::Result<(
-        Transaction,
-        Vec<DynState<DynGlobalClientContext>>,
-        Option<u64>,
-    )> {
+        partial_transaction: &mut TransactionBuilder,
+    ) -> anyhow::Result<Option<u64>> {
         if let TransactionBuilderBalance::Underfunded(missing_amount) =
-            self.transaction_builder_balance(&partial_transaction)
+            self.transaction_builder_balance(partial_transaction)
         {
             let input = self
                 .primary_module()
@@ -968,7 +1365,7 @@ impl ClientInner {
 
         let mut change_idx: Option<u64> = None;
         if let TransactionBuilderBalance::Overfunded(excess_amount) =
-            self.transaction_builder_balance(&partial_transaction)
+            self.transaction_builder_balance(partial_transaction)
         {
             let output = self
                 .primary_module()
@@ -985,15 +1382,232 @@ impl ClientInner {
 
         assert!(
             matches!(
-                self.transaction_builder_balance(&partial_transaction),
+                self.transaction_builder_balance(partial_transaction),
                 TransactionBuilderBalance::Balanced
             ),
             "Transaction is balanced after the previous two operations"
         );
 
+        Ok(change_idx)
+    }
+
+    /// Balances the transaction builder of a single operation and, if
+    /// `enforce_spending_policy` is set, checks it against the
+    /// [`SpendingPolicy`]s. The fees of the balanced transaction are added to
+    /// the ones the operation paid so far. Returns the balanced builder, the
+    /// index of the change output, if one was added, and the amount the
+    /// transaction spends, which has to be recorded with
+    /// [`Self::record_transaction_spend`] once the transaction is submitted.
+    async fn balance_operation(
+        &self,
+        dbtx: &mut DatabaseTransaction<'_>,
+        operation_id: OperationId,
+        mut partial_transaction: TransactionBuilder,
+        enforce_spending_policy: bool,
+    ) -> anyhow::Result<(TransactionBuilder, Option<u64>, Amount)> {
+        let change_idx = self
+            .balance_transaction(dbtx, operation_id, &mut partial_transaction)
+            .await?;
+
+        let spend = if enforce_spending_policy {
+            self.enforce_spending_policy(dbtx, &partial_transaction)
+                .await?
+        } else {
+            Amount::ZERO
+        };
+
+        let fee_key = OperationFeeKey { operation_id };
+        let previous_fee = dbtx.get_value(&fee_key).await.unwrap_or(Amount::ZERO);
+        let fee = self.transaction_fee(&partial_transaction);
+        dbtx.insert_entry(&fee_key, &(previous_fee + fee)).await;
+
+        Ok((partial_transaction, change_idx, spend))
+    }
+
+    /// Adds funding to a transaction or removes overfunding via change.
+    ///
+    /// If `enforce_spending_policy` is set the balanced transaction is checked
+    /// against the [`SpendingPolicy`]s before it is signed.
+    async fn finalize_transaction(
+        &self,
+        dbtx: &mut DatabaseTransaction<'_>,
+        operation_id: OperationId,
+        partial_transaction: TransactionBuilder,
+        enforce_spending_policy: bool,
+    ) -> anyhow::Result<(
+        Transaction,
+        Vec<DynState<DynGlobalClientContext>>,
+        Option<u64>,
+        Amount,
+    )> {
+        let (partial_transaction, change_idx, spend) = self
+            .balance_operation(
+                dbtx,
+                operation_id,
+                partial_transaction,
+                enforce_spending_policy,
+            )
+            .await?;
+
         let (tx, states) = partial_transaction.build(&self.secp_ctx, thread_rng());
 
-        Ok((tx, states, change_idx))
+        Ok((tx, states, change_idx, spend))
+    }
+
+    /// Balances a transaction like [`Self::finalize_transaction`] would, but
+    /// in a database transaction that is never committed, so neither funds
+    /// are spent nor state machines created.
+    async fn preview_transaction(
+        &self,
+        mut partial_transaction: TransactionBuilder,
+    ) -> anyhow::Result<TransactionPreview> {
+        let mut dbtx = self.db.begin_transaction().await;
+        dbtx.ignore_uncommitted();
+
+        let num_original_inputs = partial_transaction.inputs.len();
+        let change_idx = self
+            .balance_transaction(
+                &mut dbtx,
+                OperationId::new_random(),
+                &mut partial_transaction,
+            )
+            .await?;
+
+        let inputs = partial_transaction
+            .inputs
+            .into_iter()
+            .enumerate()
+            .map(|(idx, input)| InputPreview {
+                amount: self
+                    .get_module(input.input.module_instance_id())
+                    .input_amount(&input.input),
+                input: input.input,
+                is_funding: idx >= num_original_inputs,
+            })
+            .collect();
+        let outputs = partial_transaction
+            .outputs
+            .into_iter()
+            .map(|output| OutputPreview {
+                amount: self
+                    .get_module(output.output.module_instance_id())
+                    .output_amount(&output.output),
+                output: output.output,
+            })
+            .collect();
+
+        Ok(TransactionPreview {
+            inputs,
+            outputs,
+            change_idx: change_idx.map(|idx| idx as usize),
+        })
+    }
+
+    /// Checks what a balanced transaction spends against the
+    /// [`SpendingPolicy`]s and returns the amount.
+    ///
+    /// A transaction spends everything its inputs take from the client's
+    /// modules minus the outputs paying back to the client, i.e. including
+    /// fees. Transactions only paying back to the client, e.g. reissuing
+    /// notes or claiming incoming funds, don't spend anything.
+    async fn enforce_spending_policy(
+        &self,
+        dbtx: &mut DatabaseTransaction<'_>,
+        transaction: &TransactionBuilder,
+    ) -> anyhow::Result<Amount> {
+        let mut pays_others = false;
+        let mut own_outputs = Amount::ZERO;
+        for output in &transaction.outputs {
+            let module = self.get_module(output.output.module_instance_id());
+            if module.output_is_own(&output.output) {
+                own_outputs += module.output_amount(&output.output).amount;
+            } else {
+                pays_others = true;
+            }
+        }
+
+        let amount = if pays_others {
+            let inputs: Amount = transaction
+                .inputs
+                .iter()
+                .map(|input| {
+                    self.get_module(input.input.module_instance_id())
+                        .input_amount(&input.input)
+                        .amount
+                })
+                .sum();
+            inputs.saturating_sub(own_outputs)
+        } else {
+            Amount::ZERO
+        };
+        let request = SpendRequest {
+            amount,
+            destinations: transaction
+                .outputs
+                .iter()
+                .filter_map(|output| {
+                    self.get_module(output.output.module_instance_id())
+                        .output_destination(&output.output)
+                })
+                .collect(),
+        };
+
+        self.check_spend(dbtx, &request).await?;
+        Ok(request.amount)
+    }
+
+    /// Checks `request` against the stored [`SpendingRules`] and the
+    /// registered [`SpendingPolicy`]s
+    async fn check_spend(
+        &self,
+        dbtx: &mut DatabaseTransaction<'_>,
+        request: &SpendRequest,
+    ) -> anyhow::Result<()> {
+        let rules = dbtx.get_value(&SpendingRulesKey).await.unwrap_or_default();
+        rules.check(dbtx, request).await?;
+        for policy in &self.spending_policies {
+            policy.check(dbtx, request).await?;
+        }
+        Ok(())
+    }
+
+    /// Adds `amount` to the one spent by the operation so far. Bumps the
+    /// [`SpendCounterKey`] so that a concurrent spend checked against the
+    /// same previous spends fails to commit and is checked again.
+    async fn record_spend(
+        dbtx: &mut DatabaseTransaction<'_>,
+        operation_id: OperationId,
+        amount: Amount,
+    ) {
+        if amount == Amount::ZERO {
+            return;
+        }
+
+        let spend_count = dbtx.get_value(&SpendCounterKey).await.unwrap_or(0);
+        dbtx.insert_entry(&SpendCounterKey, &(spend_count + 1))
+            .await;
+
+        let spend_key = OperationSpendKey { operation_id };
+        let previous_spend = dbtx.get_value(&spend_key).await.unwrap_or(Amount::ZERO);
+        dbtx.insert_entry(&spend_key, &(previous_spend + amount))
+            .await;
+    }
+
+    /// Records `amount` spent by the submitted transaction `txid` of the
+    /// operation, so it can be released if the transaction is rejected
+    async fn record_transaction_spend(
+        dbtx: &mut DatabaseTransaction<'_>,
+        operation_id: OperationId,
+        txid: TransactionId,
+        amount: Amount,
+    ) {
+        if amount == Amount::ZERO {
+            return;
+        }
+
+        Self::record_spend(dbtx, operation_id, amount).await;
+        dbtx.insert_entry(&TransactionSpendKey { txid, operation_id }, &amount)
+            .await;
     }
 
     async fn finalize_and_submit_transaction(
@@ -1001,29 +1615,38 @@ impl ClientInner {
         dbtx: &mut DatabaseTransaction<'_>,
         operation_id: OperationId,
         tx_builder: TransactionBuilder,
+        enforce_spending_policy: bool,
     ) -> anyhow::Result<(TransactionId, Option<OutPoint>)> {
-        let (transaction, mut states, change_idx) = self
-            .finalize_transaction(dbtx, operation_id, tx_builder)
+        let (transaction, mut states, change_idx, spend) = self
+            .finalize_transaction(dbtx, operation_id, tx_builder, enforce_spending_policy)
             .await?;
         let txid = transaction.tx_hash();
         let change_outpoint = change_idx.map(|out_idx| OutPoint { txid, out_idx });
+        Self::record_transaction_spend(dbtx, operation_id, txid, spend).await;
+
+        states.push(Self::tx_submission_state(operation_id, transaction));
+
+        self.executor.add_state_machines_dbtx(dbtx, states).await?;
 
-        let tx_submission_sm = DynState::from_typed(
+        Ok((txid, change_outpoint))
+    }
+
+    /// State machine submitting `transaction` on behalf of `operation_id`
+    fn tx_submission_state(
+        operation_id: OperationId,
+        transaction: Transaction,
+    ) -> DynState<DynGlobalClientContext> {
+        DynState::from_typed(
             TRANSACTION_SUBMISSION_MODULE_INSTANCE,
             OperationState {
                 operation_id,
                 state: TxSubmissionStates::Created {
-                    txid,
+                    txid: transaction.tx_hash(),
                     tx: transaction,
                     next_submission: now(),
                 },
             },
-        );
-        states.push(tx_submission_sm);
-
-        self.executor.add_state_machines_dbtx(dbtx, states).await?;
-
-        Ok((txid, change_outpoint))
+        )
     }
 
     async fn transaction_update_stream(
@@ -1096,6 +1719,9 @@ impl TransactionUpdates {
                     TxSubmissionStates::Rejected { txid, error } if txid == await_txid => {
                         Some(Err(error))
                     }
+                    TxSubmissionStates::Expired { txid, expiry } if txid == await_txid => {
+                        Some(Err(TxSubmissionError::Expired(expiry)))
+                    }
                     _ => None,
                 })
             })
@@ -1110,6 +1736,12 @@ pub struct ClientBuilder {
     primary_module_instance: Option<ModuleInstanceId>,
     config: Option<ClientConfig>,
     db: Option<DatabaseSource>,
+    root_secret: Option<DerivableSecret>,
+    backup_schedule: Option<BackupSchedule>,
+    batching: Option<BatchingConfig>,
+    config_refresh: Option<ConfigRefresh>,
+    spending_policies: Vec<Arc<dyn SpendingPolicy>>,
+    background_tasks: Vec<(&'static str, BackgroundTask)>,
 }
 
 pub enum DatabaseSource {
@@ -1189,6 +1821,78 @@ impl ClientBuilder {
         );
     }
 
+    /// Whether a config was already given to the builder
+    pub fn has_config(&self) -> bool {
+        self.config.is_some()
+    }
+
+    /// Whether a database was already given to the builder
+    pub fn has_database(&self) -> bool {
+        self.db.is_some()
+    }
+
+    /// Uses this secret as the root of all client secrets instead of the one
+    /// stored in the database. The secret has to be a root secret (level 0),
+    /// e.g. derived using
+    /// [`DeriveableSecretClientExt::derive_federation_secret`].
+    ///
+    /// ## Panics
+    /// If there was a root secret added previously
+    pub fn with_root_secret(&mut self, root_secret: DerivableSecret) {
+        assert_eq!(
+            root_secret.level(),
+            0,
+            "Client secret must be a root secret"
+        );
+        let was_replaced = self.root_secret.replace(root_secret).is_some();
+        assert!(
+            !was_replaced,
+            "Only one root secret can be given to the builder."
+        );
+    }
+
+    /// Uploads backups to the federation in the background according to
+    /// `schedule` once the executor is started
+    pub fn with_backup_schedule(&mut self, schedule: BackupSchedule) {
+        self.backup_schedule = Some(schedule);
+    }
+
+    /// Combines the transactions of operations submitted via
+    /// [`Client::submit_batched_transaction`] within a short window of each
+    /// other into one transaction once the executor is started, see
+    /// [`BatchingConfig`]. This includes e-cash reissuances, Lightning
+    /// payments and on-chain withdrawals. A rejection of the combined
+    /// transaction fails all operations in it.
+    pub fn with_transaction_batching(&mut self, config: BatchingConfig) {
+        self.batching = Some(config);
+    }
+
+    /// Checks the federation for config changes in the background according
+    /// to `refresh` once the executor is started, see
+    /// [`Client::refresh_config`]
+    pub fn with_config_refresh(&mut self, refresh: ConfigRefresh) {
+        self.config_refresh = Some(refresh);
+    }
+
+    /// Refuses spends not allowed by `policy`, in addition to the
+    /// [`SpendingRules`] stored in the client database and any other
+    /// registered policies
+    pub fn with_spending_policy<P: SpendingPolicy>(&mut self, policy: P) {
+        self.spending_policies.push(Arc::new(policy));
+    }
+
+    /// Runs `task` in the background once the executor is started until the
+    /// client shuts down. Used by modules to offer optional background work,
+    /// e.g. the note consolidation of the mint module.
+    pub fn with_background_task<F, Fut>(&mut self, name: &'static str, task: F)
+    where
+        F: Fn(Client) -> Fut + MaybeSend + MaybeSync + 'static,
+        Fut: Future<Output = ()> + MaybeSend + 'static,
+    {
+        self.background_tasks
+            .push((name, Arc::new(move |client| Box::pin(task(client)))));
+    }
+
     pub async fn build_restoring_from_backup<S>(
         self,
         tg: &mut TaskGroup,
@@ -1247,7 +1951,15 @@ impl ClientBuilder {
     where
         S: RootSecretStrategy,
     {
-        let config = self.config.ok_or(anyhow!("No config was provided"))?;
+        let mut config = self.config.ok_or(anyhow!("No config was provided"))?;
+        let db_source = self.db.ok_or(anyhow!("No database was provided"))?;
+        if let Some(stored_config) = load_stored_config(&db_source).await {
+            if stored_config.federation_id == config.federation_id {
+                config = stored_config;
+            } else {
+                warn!("Ignoring stored config of another federation");
+            }
+        }
         let primary_module_instance = self
             .primary_module_instance
             .ok_or(anyhow!("No primary module instance id was provided"))?;
@@ -1267,12 +1979,13 @@ impl ClientBuilder {
 
         let config = config.redecode_raw(&decoders)?;
 
-        let db = match self.db.ok_or(anyhow!("No database was provided"))? {
+        let db = match db_source {
             DatabaseSource::Fresh(db) => Database::new_from_box(db, decoders.clone()),
             DatabaseSource::Reuse(client) => {
+                // The config itself may have been refreshed in the meantime
                 assert_eq!(
-                    client.inner.config, config,
-                    "Can only reuse DB for clients started with the same config"
+                    client.inner.config.federation_id, config.federation_id,
+                    "Can only reuse DB for clients of the same federation"
                 );
                 client.inner.db.clone()
             }
@@ -1280,7 +1993,9 @@ impl ClientBuilder {
 
         let notifier = Notifier::new(db.clone());
 
-        let api = DynGlobalApi::from(WsFederationApi::from_config(&config));
+        let refreshable_api =
+            RefreshableFederationApi::new(WsFederationApi::from_config(&config).into());
+        let api = DynGlobalApi::from(refreshable_api.clone());
 
         let common_api_versions = Client::load_and_refresh_common_api_version_static(
             &config,
@@ -1290,7 +2005,10 @@ impl ClientBuilder {
         )
         .await?;
 
-        let root_secret = get_client_root_secret::<S>(&db).await;
+        let root_secret = match self.root_secret {
+            Some(root_secret) => root_secret,
+            None => get_client_root_secret::<S>(&db).await,
+        };
 
         let modules = {
             let mut modules = ClientModuleRegistry::default();
@@ -1338,17 +2056,19 @@ impl ClientBuilder {
 
             for (module_instance_id, _, module) in modules.iter_modules() {
                 executor_builder.with_module_dyn(module.context(module_instance_id));
+                executor_builder.with_retry_policy(module_instance_id, module.retry_policy());
             }
 
             executor_builder.build(db.clone(), notifier).await
         };
 
+        OperationLog::build_indexes(&db).await?;
+
         let client_inner = Arc::new(ClientInner {
             config: config.clone(),
             decoders,
             db: db.clone(),
             federation_id: config.federation_id,
-            federation_meta: config.meta,
             primary_module_instance,
             modules,
             module_gens: self.module_gens.clone(),
@@ -1357,6 +2077,14 @@ impl ClientBuilder {
             secp_ctx: Secp256k1::new(),
             root_secret,
             operation_log: OperationLog::new(db),
+            backup_schedule: self.backup_schedule,
+            batcher: self.batching.map(TransactionBatcher::new),
+            config_refresh: self.config_refresh,
+            latest_config: std::sync::RwLock::new(config),
+            refreshable_api,
+            config_changes: tokio::sync::broadcast::channel(10).0,
+            spending_policies: self.spending_policies,
+            background_tasks: self.background_tasks,
         });
 
         Ok(Client {
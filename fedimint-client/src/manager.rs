@@ -0,0 +1,296 @@
+use std::collections::BTreeMap;
+use std::marker::PhantomData;
+use std::sync::Arc;
+
+use anyhow::{anyhow, bail, ensure};
+use fedimint_core::config::{ClientConfig, FederationId};
+use fedimint_core::core::ModuleKind;
+use fedimint_core::db::prefixed::PrefixedDatabase;
+use fedimint_core::db::{Database, IDatabase};
+use fedimint_core::module::registry::ModuleDecoderRegistry;
+use fedimint_core::task::TaskGroup;
+use fedimint_core::Amount;
+use fedimint_derive_secret::DerivableSecret;
+use futures::StreamExt;
+use tokio::sync::Mutex;
+use tracing::info;
+
+use crate::db::{ChronologicalOperationLogKey, JoinedFederationKey, JoinedFederationKeyPrefix};
+use crate::module::gen::ClientModuleGenRegistry;
+use crate::oplog::{OperationLogEntry, OperationLogQuery, OperationStatus};
+use crate::secret::{DeriveableSecretClientExt, RootSecretStrategy};
+use crate::{get_client_root_secret, Client, ClientBuilder};
+
+/// Key prefix of the manager's own records in the shared database
+const MANAGER_DB_PREFIX: u8 = 0x01;
+/// Key prefix of the client databases in the shared database, followed by the
+/// federation id
+const CLIENT_DB_PREFIX: u8 = 0x02;
+
+/// Runs one [`Client`] per joined federation on top of one shared database.
+///
+/// Every client gets its own part of the database, so the clients can't
+/// interfere with each other, and its own root secret derived from the
+/// manager's root secret using
+/// [`DeriveableSecretClientExt::derive_federation_secret`]. Joined federations
+/// are remembered, so the same clients are started again when the manager is
+/// re-created from the same database.
+pub struct ClientManager<S> {
+    backend: Arc<dyn IDatabase>,
+    db: Database,
+    module_gens: ClientModuleGenRegistry,
+    primary_module_kind: ModuleKind,
+    root_secret: DerivableSecret,
+    task_group: TaskGroup,
+    clients: Mutex<BTreeMap<FederationId, ManagedClient>>,
+    _strategy: PhantomData<S>,
+}
+
+struct ManagedClient {
+    client: Client,
+    /// Runs the executor of the client, shut down when leaving the federation
+    task_group: TaskGroup,
+}
+
+impl<S> ClientManager<S>
+where
+    S: RootSecretStrategy,
+{
+    /// Opens the manager stored in `db` and starts clients for all previously
+    /// joined federations. The modules of kind `primary_module_kind` are used
+    /// as primary modules of the clients.
+    ///
+    /// The root secret of the manager is generated on first use and stored in
+    /// `db`.
+    pub async fn new(
+        db: Box<dyn IDatabase>,
+        module_gens: ClientModuleGenRegistry,
+        primary_module_kind: ModuleKind,
+        task_group: &mut TaskGroup,
+    ) -> anyhow::Result<Self> {
+        let backend: Arc<dyn IDatabase> = Arc::from(db);
+        let db = Database::new(
+            PrefixedDatabase::new(backend.clone(), vec![MANAGER_DB_PREFIX]),
+            ModuleDecoderRegistry::default(),
+        );
+        let root_secret = get_client_root_secret::<S>(&db).await;
+
+        let manager = ClientManager {
+            backend,
+            db,
+            module_gens,
+            primary_module_kind,
+            root_secret,
+            task_group: task_group.make_subgroup().await,
+            clients: Mutex::new(BTreeMap::new()),
+            _strategy: PhantomData,
+        };
+
+        let configs = manager
+            .db
+            .begin_transaction()
+            .await
+            .find_by_prefix(&JoinedFederationKeyPrefix)
+            .await
+            .map(|(_, config)| config)
+            .collect::<Vec<_>>()
+            .await;
+
+        let mut clients = manager.clients.lock().await;
+        for config in configs {
+            let federation_id = config.federation_id;
+            clients.insert(federation_id, manager.build_client(config).await?);
+        }
+        drop(clients);
+
+        Ok(manager)
+    }
+
+    /// Starts a client for the federation described by `config` and remembers
+    /// it across restarts
+    pub async fn join(&self, config: ClientConfig) -> anyhow::Result<Client> {
+        let federation_id = config.federation_id;
+        let mut clients = self.clients.lock().await;
+        ensure!(
+            !clients.contains_key(&federation_id),
+            "Federation {federation_id} was already joined"
+        );
+
+        let managed_client = self.build_client(config.clone()).await?;
+        let client = managed_client.client.clone();
+
+        let mut dbtx = self.db.begin_transaction().await;
+        dbtx.insert_entry(&JoinedFederationKey { federation_id }, &config)
+            .await;
+        dbtx.commit_tx_result().await?;
+
+        clients.insert(federation_id, managed_client);
+        info!(%federation_id, "Joined federation");
+        Ok(client)
+    }
+
+    /// Stops the client of the given federation and deletes all its data.
+    ///
+    /// Fails if the client still holds funds or has operations in progress,
+    /// e.g. incoming payments or refunds that aren't part of the balance yet,
+    /// since these would be lost. Setting `force` skips these checks and drops
+    /// everything.
+    pub async fn leave(&self, federation_id: FederationId, force: bool) -> anyhow::Result<()> {
+        let mut clients = self.clients.lock().await;
+        let Some(managed_client) = clients.get(&federation_id) else {
+            bail!("Federation {federation_id} was not joined");
+        };
+
+        if !force {
+            let client = &managed_client.client;
+            let balance = client.get_balance().await;
+            ensure!(
+                balance == Amount::ZERO,
+                "Client of federation {federation_id} still holds {balance}"
+            );
+
+            let active_operations = client.get_active_operations().await.len();
+            ensure!(
+                active_operations == 0,
+                "Federation {federation_id} has {active_operations} operations in progress"
+            );
+
+            let pending_operations = client
+                .operation_log()
+                .query_operations(&OperationLogQuery {
+                    status: Some(OperationStatus::Pending),
+                    limit: Some(1),
+                    ..Default::default()
+                })
+                .await;
+            ensure!(
+                pending_operations.is_empty(),
+                "Federation {federation_id} has pending operations"
+            );
+        }
+
+        let ManagedClient { task_group, .. } = clients
+            .remove(&federation_id)
+            .expect("Client exists, checked above");
+        task_group.shutdown_join_all(None).await?;
+
+        let mut dbtx = self.db.begin_transaction().await;
+        dbtx.remove_entry(&JoinedFederationKey { federation_id })
+            .await;
+        dbtx.commit_tx_result().await?;
+
+        let mut raw_dbtx = self.backend.begin_transaction().await;
+        raw_dbtx
+            .raw_remove_by_prefix(&client_db_prefix(&federation_id))
+            .await?;
+        raw_dbtx.commit_tx().await?;
+
+        info!(%federation_id, "Left federation");
+        Ok(())
+    }
+
+    /// Returns the client of the given federation if it was joined
+    pub async fn get(&self, federation_id: &FederationId) -> Option<Client> {
+        self.clients
+            .lock()
+            .await
+            .get(federation_id)
+            .map(|managed_client| managed_client.client.clone())
+    }
+
+    /// Returns the ids of all joined federations
+    pub async fn federations(&self) -> Vec<FederationId> {
+        self.clients.lock().await.keys().copied().collect()
+    }
+
+    /// Returns the balance of each joined federation
+    pub async fn balances(&self) -> BTreeMap<FederationId, Amount> {
+        let clients = self.clients().await;
+        let mut balances = BTreeMap::new();
+        for (federation_id, client) in clients {
+            balances.insert(federation_id, client.get_balance().await);
+        }
+        balances
+    }
+
+    /// Returns the sum of the balances of all joined federations
+    pub async fn total_balance(&self) -> Amount {
+        self.balances().await.into_values().sum()
+    }
+
+    /// Queries the operation logs of all joined federations and merges the
+    /// results, newest first. The `limit` of the query applies to the merged
+    /// result.
+    pub async fn list_operations(
+        &self,
+        query: &OperationLogQuery,
+    ) -> Vec<(
+        FederationId,
+        ChronologicalOperationLogKey,
+        OperationLogEntry,
+    )> {
+        let mut operations = vec![];
+        for (federation_id, client) in self.clients().await {
+            operations.extend(
+                client
+                    .query_operations(query)
+                    .await
+                    .into_iter()
+                    .map(|(key, entry)| (federation_id, key, entry)),
+            );
+        }
+
+        operations.sort_by_key(|(_, key, _)| std::cmp::Reverse(key.creation_time));
+        if let Some(limit) = query.limit {
+            operations.truncate(limit);
+        }
+        operations
+    }
+
+    /// Snapshot of all clients, so callers don't hold the lock while awaiting
+    /// client operations
+    async fn clients(&self) -> Vec<(FederationId, Client)> {
+        self.clients
+            .lock()
+            .await
+            .iter()
+            .map(|(federation_id, managed_client)| (*federation_id, managed_client.client.clone()))
+            .collect()
+    }
+
+    async fn build_client(&self, config: ClientConfig) -> anyhow::Result<ManagedClient> {
+        let federation_id = config.federation_id;
+        let primary_module_instance = config
+            .modules
+            .iter()
+            .find(|(_, module_config)| module_config.kind() == &self.primary_module_kind)
+            .map(|(module_instance_id, _)| *module_instance_id)
+            .ok_or_else(|| {
+                anyhow!(
+                    "Federation {federation_id} has no module of kind {}",
+                    self.primary_module_kind
+                )
+            })?;
+
+        let mut client_builder = ClientBuilder::default();
+        client_builder.with_module_gens(self.module_gens.clone());
+        client_builder.with_primary_module(primary_module_instance);
+        client_builder.with_config(config);
+        client_builder.with_database(PrefixedDatabase::new(
+            self.backend.clone(),
+            client_db_prefix(&federation_id),
+        ));
+        client_builder.with_root_secret(self.root_secret.derive_federation_secret(&federation_id));
+
+        let mut task_group = self.task_group.make_subgroup().await;
+        let client = client_builder.build::<S>(&mut task_group).await?;
+
+        Ok(ManagedClient { client, task_group })
+    }
+}
+
+fn client_db_prefix(federation_id: &FederationId) -> Vec<u8> {
+    let mut prefix = vec![CLIENT_DB_PREFIX];
+    prefix.extend_from_slice(&federation_id.0.to_bytes());
+    prefix
+}
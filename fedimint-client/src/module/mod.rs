@@ -15,7 +15,11 @@ use fedimint_core::{
     TransactionId,
 };
 
-use crate::sm::{Context, DynContext, DynState, Executor, OperationId, State};
+use crate::event::StateSummary;
+use crate::ledger::LedgerEntry;
+use crate::oplog::{OperationLog, OperationLogEntry};
+use crate::policy::SpendDestination;
+use crate::sm::{Context, DynContext, DynState, Executor, OperationId, RetryPolicy, State};
 use crate::transaction::{ClientInput, ClientOutput};
 use crate::{Client, DynGlobalClientContext};
 
@@ -68,6 +72,25 @@ pub trait ClientModule: Debug + MaybeSend + MaybeSync + 'static {
         output: &<Self::Common as ModuleCommon>::Output,
     ) -> TransactionItemAmount;
 
+    /// Returns where the funds sent by the output end up, so
+    /// [`SpendingPolicy`](crate::policy::SpendingPolicy)s can restrict
+    /// recipients. Returns `None` if the output doesn't pay a third party
+    /// covered by any policy rule.
+    fn output_destination(
+        &self,
+        _output: &<Self::Common as ModuleCommon>::Output,
+    ) -> Option<SpendDestination> {
+        None
+    }
+
+    /// Returns `true` if the output pays funds back to this client, e.g. new
+    /// e-cash notes, which
+    /// [`SpendingPolicy`](crate::policy::SpendingPolicy)s don't count as
+    /// spent
+    fn output_is_own(&self, _output: &<Self::Common as ModuleCommon>::Output) -> bool {
+        false
+    }
+
     fn supports_backup(&self) -> bool {
         false
     }
@@ -82,12 +105,17 @@ pub trait ClientModule: Debug + MaybeSend + MaybeSync + 'static {
         anyhow::bail!("Backup not supported");
     }
 
+    /// Restores the module's state from `snapshot`. Operations that are
+    /// resumed should be added to `operation_log`, so users can follow them
+    /// like ones created by this client.
+    #[allow(clippy::too_many_arguments)]
     async fn restore(
         &self,
         // _dbtx: &mut ModuleDatabaseTransaction<'_>,
         _dbtx: &mut DatabaseTransaction<'_>,
         _module_instance_id: ModuleInstanceId,
         _executor: Executor<DynGlobalClientContext>,
+        _operation_log: &OperationLog,
         _api: DynGlobalApi,
         _snapshot: Option<&[u8]>,
     ) -> anyhow::Result<()> {
@@ -183,6 +211,33 @@ pub trait ClientModule: Debug + MaybeSend + MaybeSync + 'static {
     async fn subscribe_balance_changes(&self) -> BoxStream<'static, ()> {
         unimplemented!()
     }
+
+    /// Describes an operation created by this module for
+    /// [`Client::export_ledger`], returns `None` if the operation didn't move
+    /// any funds.
+    async fn ledger_entry(
+        &self,
+        _dbtx: &mut ModuleDatabaseTransaction<'_>,
+        _operation_id: OperationId,
+        _operation: &OperationLogEntry,
+    ) -> Option<LedgerEntry> {
+        None
+    }
+
+    /// Maps a state of this module's state machines to the module-independent
+    /// summary emitted by [`Client::subscribe_events`]. By default every state
+    /// is reported as in progress, described by its [`Debug`] output and
+    /// operation id, modules should override it to report final states.
+    fn summarize_state(&self, state: &Self::States) -> StateSummary {
+        StateSummary::in_progress(format!("{state:?} (operation {})", state.operation_id()))
+    }
+
+    /// How the executor retries failing state transitions of this module,
+    /// see [`RetryPolicy`]. By default it never gives up on them, modules whose
+    /// state machines hold funds must keep it that way.
+    fn retry_policy(&self) -> RetryPolicy {
+        RetryPolicy::default()
+    }
 }
 
 /// Type-erased version of [`ClientModule`]
@@ -204,6 +259,10 @@ pub trait IClientModule: Debug {
 
     fn output_amount(&self, output: &DynOutput) -> TransactionItemAmount;
 
+    fn output_destination(&self, output: &DynOutput) -> Option<SpendDestination>;
+
+    fn output_is_own(&self, output: &DynOutput) -> bool;
+
     fn supports_backup(&self) -> bool;
 
     async fn backup(
@@ -214,12 +273,14 @@ pub trait IClientModule: Debug {
         module_instance_id: ModuleInstanceId,
     ) -> anyhow::Result<Vec<u8>>;
 
+    #[allow(clippy::too_many_arguments)]
     async fn restore(
         &self,
         // dbtx: &mut ModuleDatabaseTransaction<'_>,
         dbtx: &mut DatabaseTransaction<'_>,
         module_instance_id: ModuleInstanceId,
         executor: Executor<DynGlobalClientContext>,
+        operation_log: &OperationLog,
         api: DynGlobalApi,
         snapshot: Option<&[u8]>,
     ) -> anyhow::Result<()>;
@@ -262,6 +323,18 @@ pub trait IClientModule: Debug {
     ) -> Amount;
 
     async fn subscribe_balance_changes(&self) -> BoxStream<'static, ()>;
+
+    async fn ledger_entry(
+        &self,
+        module_instance: ModuleInstanceId,
+        dbtx: &mut DatabaseTransaction<'_>,
+        operation_id: OperationId,
+        operation: &OperationLogEntry,
+    ) -> Option<LedgerEntry>;
+
+    fn summarize_state(&self, state: &DynState<DynGlobalClientContext>) -> StateSummary;
+
+    fn retry_policy(&self) -> RetryPolicy;
 }
 
 #[apply(async_trait_maybe_send!)]
@@ -309,6 +382,26 @@ where
         )
     }
 
+    fn output_destination(&self, output: &DynOutput) -> Option<SpendDestination> {
+        <T as ClientModule>::output_destination(
+            self,
+            output
+                .as_any()
+                .downcast_ref()
+                .expect("Dispatched to correct module"),
+        )
+    }
+
+    fn output_is_own(&self, output: &DynOutput) -> bool {
+        <T as ClientModule>::output_is_own(
+            self,
+            output
+                .as_any()
+                .downcast_ref()
+                .expect("Dispatched to correct module"),
+        )
+    }
+
     fn supports_backup(&self) -> bool {
         <T as ClientModule>::supports_backup(self)
     }
@@ -329,10 +422,20 @@ where
         dbtx: &mut DatabaseTransaction<'_>,
         module_instance_id: ModuleInstanceId,
         executor: Executor<DynGlobalClientContext>,
+        operation_log: &OperationLog,
         api: DynGlobalApi,
         snapshot: Option<&[u8]>,
     ) -> anyhow::Result<()> {
-        <T as ClientModule>::restore(self, dbtx, module_instance_id, executor, api, snapshot).await
+        <T as ClientModule>::restore(
+            self,
+            dbtx,
+            module_instance_id,
+            executor,
+            operation_log,
+            api,
+            snapshot,
+        )
+        .await
     }
 
     async fn wipe(
@@ -401,6 +504,36 @@ where
     async fn subscribe_balance_changes(&self) -> BoxStream<'static, ()> {
         <T as ClientModule>::subscribe_balance_changes(self).await
     }
+
+    async fn ledger_entry(
+        &self,
+        module_instance: ModuleInstanceId,
+        dbtx: &mut DatabaseTransaction<'_>,
+        operation_id: OperationId,
+        operation: &OperationLogEntry,
+    ) -> Option<LedgerEntry> {
+        <T as ClientModule>::ledger_entry(
+            self,
+            &mut dbtx.with_module_prefix(module_instance),
+            operation_id,
+            operation,
+        )
+        .await
+    }
+
+    fn summarize_state(&self, state: &DynState<DynGlobalClientContext>) -> StateSummary {
+        <T as ClientModule>::summarize_state(
+            self,
+            state
+                .as_any()
+                .downcast_ref()
+                .expect("Dispatched to correct module"),
+        )
+    }
+
+    fn retry_policy(&self) -> RetryPolicy {
+        <T as ClientModule>::retry_policy(self)
+    }
 }
 
 dyn_newtype_define!(
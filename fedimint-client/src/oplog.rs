@@ -1,7 +1,10 @@
 use std::fmt::Debug;
 use std::future;
 use std::io::{Read, Write};
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
 
+use anyhow::bail;
 use async_stream::stream;
 use fedimint_core::db::{Database, DatabaseTransaction};
 use fedimint_core::encoding::{Decodable, DecodeError, Encodable};
@@ -9,15 +12,20 @@ use fedimint_core::module::registry::ModuleDecoderRegistry;
 use fedimint_core::task::{MaybeSend, MaybeSync};
 use fedimint_core::time::now;
 use fedimint_core::util::BoxStream;
+use fedimint_core::Amount;
 use futures::{stream, Stream, StreamExt};
 use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use tracing::{error, instrument, warn};
 
 use crate::db::{
-    ChronologicalOperationLogKey, ChronologicalOperationLogKeyPrefix, OperationLogKey,
+    ChronologicalOperationLogKey, ChronologicalOperationLogKeyPrefix, OperationLogIndexesBuiltKey,
+    OperationLogKey, OperationTypeIndexKey, OperationTypeIndexKeyPrefix, PendingOperationKey,
+    PendingOperationKeyPrefix,
 };
+use crate::sm::executor::has_active_states;
 use crate::sm::OperationId;
+use crate::DynGlobalClientContext;
 
 #[derive(Debug, Clone)]
 pub struct OperationLog {
@@ -36,6 +44,7 @@ impl OperationLog {
         operation_type: &str,
         operation_meta: impl serde::Serialize,
     ) {
+        let creation_time = now();
         dbtx.insert_new_entry(
             &OperationLogKey { operation_id },
             &OperationLogEntry {
@@ -48,12 +57,65 @@ impl OperationLog {
         .await;
         dbtx.insert_new_entry(
             &ChronologicalOperationLogKey {
-                creation_time: now(),
+                creation_time,
                 operation_id,
             },
             &(),
         )
         .await;
+        dbtx.insert_new_entry(
+            &OperationTypeIndexKey {
+                operation_type: operation_type.to_string(),
+                creation_time,
+                operation_id,
+            },
+            &(),
+        )
+        .await;
+        dbtx.insert_new_entry(&PendingOperationKey { operation_id }, &creation_time)
+            .await;
+    }
+
+    /// Builds the secondary indexes used by [`OperationLog::query_operations`]
+    /// for operations that were created before they were introduced. Only does
+    /// any work the first time it is called on a database.
+    pub async fn build_indexes(db: &Database) -> anyhow::Result<()> {
+        let mut dbtx = db.begin_transaction().await;
+        if dbtx.get_value(&OperationLogIndexesBuiltKey).await.is_some() {
+            return Ok(());
+        }
+
+        let operations = dbtx
+            .find_by_prefix(&ChronologicalOperationLogKeyPrefix)
+            .await
+            .map(|(key, _)| key)
+            .collect::<Vec<_>>()
+            .await;
+        for ChronologicalOperationLogKey {
+            creation_time,
+            operation_id,
+        } in operations
+        {
+            let entry = Self::get_operation_inner(&mut dbtx, operation_id)
+                .await
+                .expect("Inconsistent DB");
+            dbtx.insert_entry(
+                &OperationTypeIndexKey {
+                    operation_type: entry.operation_type,
+                    creation_time,
+                    operation_id,
+                },
+                &(),
+            )
+            .await;
+            if has_active_states::<DynGlobalClientContext>(&mut dbtx, operation_id).await {
+                dbtx.insert_entry(&PendingOperationKey { operation_id }, &creation_time)
+                    .await;
+            }
+        }
+
+        dbtx.insert_entry(&OperationLogIndexesBuiltKey, &()).await;
+        dbtx.commit_tx_result().await
     }
 
     /// Returns the last `limit` operations. To fetch the next page, pass the
@@ -64,30 +126,27 @@ impl OperationLog {
         start_after: Option<ChronologicalOperationLogKey>,
     ) -> Vec<(ChronologicalOperationLogKey, OperationLogEntry)> {
         let mut dbtx = self.db.begin_transaction().await;
-        let operations: Vec<ChronologicalOperationLogKey> = dbtx
-            .find_by_prefix_sorted_descending(&ChronologicalOperationLogKeyPrefix)
-            .await
-            .map(|(key, _)| key)
-            // FIXME: this is a schlemil-the-painter algorithm that will take longer the further
-            // back in history one goes. To avoid that I see two options:
-            //   1. Add a reference to the previous operation to each operation log entry,
-            //      essentially creating a linked list, which seem a little bit inelegant.
-            //   2. Add an option to prefix queries that allows to specify a start key
-            //
-            // The current implementation may also skip operations due to `SystemTime` not being
-            // guaranteed to be monotonous. The linked list approach would also fix that.
-            .skip_while(move |key| {
-                let skip = if let Some(start_after) = start_after {
-                    key.creation_time >= start_after.creation_time
-                } else {
-                    false
-                };
-
-                std::future::ready(skip)
-            })
-            .take(limit)
-            .collect::<Vec<_>>()
-            .await;
+        let operations: Vec<ChronologicalOperationLogKey> = match start_after {
+            Some(start_after) => {
+                dbtx.find_by_prefix_sorted_descending_after(
+                    &ChronologicalOperationLogKeyPrefix,
+                    &start_after,
+                )
+                .await
+                .map(|(key, _)| key)
+                .take(limit)
+                .collect::<Vec<_>>()
+                .await
+            }
+            None => {
+                dbtx.find_by_prefix_sorted_descending(&ChronologicalOperationLogKeyPrefix)
+                    .await
+                    .map(|(key, _)| key)
+                    .take(limit)
+                    .collect::<Vec<_>>()
+                    .await
+            }
+        };
 
         let mut operation_entries = Vec::with_capacity(operations.len());
 
@@ -104,17 +163,157 @@ impl OperationLog {
         operation_entries
     }
 
+    /// Returns the operations matching all filters of `query`, newest first.
+    ///
+    /// The operation type, pending status and creation time filters are
+    /// answered from secondary indexes, so only candidate entries are read.
+    /// Amount filters use [`OperationLogEntry::amount`], which only knows
+    /// metas with an `amount` field. Use [`crate::Client::query_operations`]
+    /// to let the modules determine the amount of their operations.
+    pub async fn query_operations(
+        &self,
+        query: &OperationLogQuery,
+    ) -> Vec<(ChronologicalOperationLogKey, OperationLogEntry)> {
+        let mut dbtx = self.db.begin_transaction().await;
+        let candidates = Self::query_candidates(&mut dbtx, query).await;
+
+        let mut operation_entries = vec![];
+        for candidate in candidates {
+            if query
+                .limit
+                .map_or(false, |limit| operation_entries.len() >= limit)
+            {
+                break;
+            }
+
+            let entry = Self::get_operation_inner(&mut dbtx, candidate.operation_id)
+                .await
+                .expect("Inconsistent DB");
+            let status = Self::get_operation_status_inner(&mut dbtx, candidate.operation_id).await;
+            if query.matches(&entry, status) {
+                operation_entries.push((candidate, entry));
+            }
+        }
+
+        operation_entries
+    }
+
+    /// Returns the keys of all operations inside the query's time window,
+    /// newest first, using the most selective index available
+    async fn query_candidates(
+        dbtx: &mut DatabaseTransaction<'_>,
+        query: &OperationLogQuery,
+    ) -> Vec<ChronologicalOperationLogKey> {
+        let start_time = query.start_time.unwrap_or(UNIX_EPOCH);
+        if query
+            .end_time
+            .map_or(false, |end_time| end_time <= start_time)
+        {
+            return vec![];
+        }
+
+        if query.status == Some(OperationStatus::Pending) {
+            // Only few operations are pending at any time, so sorting them in
+            // memory is cheaper than maintaining another chronological index
+            let mut pending = dbtx
+                .find_by_prefix(&PendingOperationKeyPrefix)
+                .await
+                .map(|(key, creation_time)| ChronologicalOperationLogKey {
+                    creation_time,
+                    operation_id: key.operation_id,
+                })
+                .filter(|key| future::ready(query.contains_time(key.creation_time)))
+                .collect::<Vec<_>>()
+                .await;
+            pending.sort_by(|a, b| {
+                b.creation_time
+                    .cmp(&a.creation_time)
+                    .then(b.operation_id.0.cmp(&a.operation_id.0))
+            });
+            return pending;
+        }
+
+        // Creation time is followed by the operation id in all index keys, so
+        // the lowest possible id makes the range start inclusive and its end
+        // exclusive
+        let lowest_operation_id = OperationId([0; 32]);
+        match (&query.operation_type, query.end_time) {
+            (Some(operation_type), Some(end_time)) => {
+                let index_key = |creation_time| OperationTypeIndexKey {
+                    operation_type: operation_type.clone(),
+                    creation_time,
+                    operation_id: lowest_operation_id,
+                };
+                dbtx.find_by_range_sorted_descending(index_key(start_time)..index_key(end_time))
+                    .await
+                    .map(|(key, _)| ChronologicalOperationLogKey::from(key))
+                    .collect::<Vec<_>>()
+                    .await
+            }
+            (Some(operation_type), None) => {
+                dbtx.find_by_prefix_sorted_descending(&OperationTypeIndexKeyPrefix {
+                    operation_type: operation_type.clone(),
+                })
+                .await
+                .map(|(key, _)| ChronologicalOperationLogKey::from(key))
+                .take_while(|key| future::ready(key.creation_time >= start_time))
+                .collect::<Vec<_>>()
+                .await
+            }
+            (None, Some(end_time)) => {
+                let index_key = |creation_time| ChronologicalOperationLogKey {
+                    creation_time,
+                    operation_id: lowest_operation_id,
+                };
+                dbtx.find_by_range_sorted_descending(index_key(start_time)..index_key(end_time))
+                    .await
+                    .map(|(key, _)| key)
+                    .collect::<Vec<_>>()
+                    .await
+            }
+            (None, None) => {
+                dbtx.find_by_prefix_sorted_descending(&ChronologicalOperationLogKeyPrefix)
+                    .await
+                    .map(|(key, _)| key)
+                    .take_while(|key| future::ready(key.creation_time >= start_time))
+                    .collect::<Vec<_>>()
+                    .await
+            }
+        }
+    }
+
     pub async fn get_operation(&self, operation_id: OperationId) -> Option<OperationLogEntry> {
         Self::get_operation_inner(&mut self.db.begin_transaction().await, operation_id).await
     }
 
-    async fn get_operation_inner(
+    pub(crate) async fn get_operation_inner(
         dbtx: &mut DatabaseTransaction<'_>,
         operation_id: OperationId,
     ) -> Option<OperationLogEntry> {
         dbtx.get_value(&OperationLogKey { operation_id }).await
     }
 
+    /// Returns whether the operation is still pending, see
+    /// [`OperationStatus`]
+    pub async fn get_operation_status(&self, operation_id: OperationId) -> OperationStatus {
+        Self::get_operation_status_inner(&mut self.db.begin_transaction().await, operation_id).await
+    }
+
+    async fn get_operation_status_inner(
+        dbtx: &mut DatabaseTransaction<'_>,
+        operation_id: OperationId,
+    ) -> OperationStatus {
+        if dbtx
+            .get_value(&PendingOperationKey { operation_id })
+            .await
+            .is_some()
+        {
+            OperationStatus::Pending
+        } else {
+            OperationStatus::Completed
+        }
+    }
+
     /// Sets the outcome of an operation
     #[instrument(skip(db), level = "debug")]
     pub async fn set_operation_outcome(
@@ -131,6 +330,8 @@ impl OperationLog {
         operation.outcome = Some(outcome_json);
         dbtx.insert_entry(&OperationLogKey { operation_id }, &operation)
             .await;
+        dbtx.remove_entry(&PendingOperationKey { operation_id })
+            .await;
         dbtx.commit_tx_result().await?;
 
         Ok(())
@@ -151,6 +352,97 @@ impl OperationLog {
     }
 }
 
+/// Filters for [`OperationLog::query_operations`], unset filters match every
+/// operation
+#[derive(Debug, Clone, Default)]
+pub struct OperationLogQuery {
+    /// Only return operations of this type, usually the kind of the module
+    /// that created them
+    pub operation_type: Option<String>,
+    /// Only return operations created at or after this time
+    pub start_time: Option<SystemTime>,
+    /// Only return operations created before this time
+    pub end_time: Option<SystemTime>,
+    pub status: Option<OperationStatus>,
+    /// Only return operations with an amount of at least this amount,
+    /// operations without a known amount never match
+    pub min_amount: Option<Amount>,
+    /// Only return operations with an amount of at most this amount,
+    /// operations without a known amount never match
+    pub max_amount: Option<Amount>,
+    /// Maximum number of operations to return
+    pub limit: Option<usize>,
+}
+
+impl OperationLogQuery {
+    fn contains_time(&self, creation_time: SystemTime) -> bool {
+        self.start_time
+            .map_or(true, |start_time| start_time <= creation_time)
+            && self
+                .end_time
+                .map_or(true, |end_time| creation_time < end_time)
+    }
+
+    fn matches(&self, entry: &OperationLogEntry, status: OperationStatus) -> bool {
+        if self
+            .operation_type
+            .as_ref()
+            .map_or(false, |operation_type| {
+                operation_type != &entry.operation_type
+            })
+        {
+            return false;
+        }
+
+        if self
+            .status
+            .map_or(false, |query_status| query_status != status)
+        {
+            return false;
+        }
+
+        self.contains_amount(entry.amount())
+    }
+
+    /// Returns `true` if `amount` is within the amount filters, unknown
+    /// amounts only match if there are none
+    pub(crate) fn contains_amount(&self, amount: Option<Amount>) -> bool {
+        if self.min_amount.is_none() && self.max_amount.is_none() {
+            return true;
+        }
+        amount.map_or(false, |amount| {
+            self.min_amount
+                .map_or(true, |min_amount| min_amount <= amount)
+                && self
+                    .max_amount
+                    .map_or(true, |max_amount| amount <= max_amount)
+        })
+    }
+}
+
+/// Whether an operation is still in progress. An operation is
+/// [`OperationStatus::Pending`] from its creation until the executor moved the
+/// last of its state machines to a terminal state, or until its final outcome
+/// was cached, whichever happens first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OperationStatus {
+    Pending,
+    Completed,
+}
+
+impl FromStr for OperationStatus {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pending" => Ok(OperationStatus::Pending),
+            "completed" => Ok(OperationStatus::Completed),
+            _ => bail!("Unknown operation status {s}, expected pending or completed"),
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct OperationLogEntry {
     operation_type: String,
@@ -168,6 +460,14 @@ impl OperationLogEntry {
         serde_json::from_value(self.meta.clone()).expect("JSON deserialization should not fail")
     }
 
+    /// Returns the amount of the operation if its meta is an object containing
+    /// an `amount` field. By convention this field is an [`Amount`], metas
+    /// using other units must name the field differently.
+    pub fn amount(&self) -> Option<Amount> {
+        let amount = self.meta.get("amount")?;
+        serde_json::from_value(amount.clone()).ok()
+    }
+
     /// Returns the last state update of the operation, if any was cached yet.
     /// If this hasn't been the case yet and `None` is returned subscribe to the
     /// appropriate update stream.
@@ -304,8 +604,8 @@ mod tests {
     use serde::{Deserialize, Serialize};
 
     use super::UpdateStreamOrOutcome;
-    use crate::db::ChronologicalOperationLogKey;
-    use crate::oplog::{OperationLog, OperationLogEntry};
+    use crate::db::{ChronologicalOperationLogKey, OperationLogKey};
+    use crate::oplog::{OperationLog, OperationLogEntry, OperationLogQuery, OperationStatus};
     use crate::sm::OperationId;
 
     #[test]
@@ -447,4 +747,210 @@ mod tests {
         assert_eq!(page.len(), 8);
         assert_page_entries(page, 9);
     }
+
+    #[tokio::test]
+    async fn test_pagination_same_creation_time() {
+        let db = Database::new(MemDatabase::new(), Default::default());
+        let op_log = OperationLog::new(db.clone());
+        let creation_time = fedimint_core::time::now();
+
+        let mut dbtx = db.begin_transaction().await;
+        for operation_idx in 0u8..5 {
+            let operation_id = OperationId([operation_idx; 32]);
+            dbtx.insert_new_entry(
+                &OperationLogKey { operation_id },
+                &OperationLogEntry {
+                    operation_type: "foo".to_string(),
+                    meta: serde_json::to_value(operation_idx).unwrap(),
+                    outcome: None,
+                },
+            )
+            .await;
+            dbtx.insert_new_entry(
+                &ChronologicalOperationLogKey {
+                    creation_time,
+                    operation_id,
+                },
+                &(),
+            )
+            .await;
+        }
+        dbtx.commit_tx().await;
+
+        // Entries sharing a timestamp must not be skipped between pages
+        let first_page = op_log.list_operations(2, None).await;
+        let second_page = op_log.list_operations(2, Some(first_page[1].0)).await;
+        let third_page = op_log.list_operations(2, Some(second_page[1].0)).await;
+
+        let metas = first_page
+            .into_iter()
+            .chain(second_page)
+            .chain(third_page)
+            .map(|(_, entry)| entry.meta::<u8>())
+            .collect::<Vec<_>>();
+        assert_eq!(metas, vec![4, 3, 2, 1, 0]);
+    }
+
+    #[tokio::test]
+    async fn test_query_operations() {
+        #[derive(Serialize)]
+        struct Meta {
+            amount: fedimint_core::Amount,
+        }
+
+        let db = Database::new(MemDatabase::new(), Default::default());
+        let op_log = OperationLog::new(db.clone());
+
+        let mut creation_times = vec![];
+        for operation_idx in 0u8..6 {
+            creation_times.push(fedimint_core::time::now());
+            let operation_type = if operation_idx % 2 == 0 { "mint" } else { "ln" };
+            let mut dbtx = db.begin_transaction().await;
+            op_log
+                .add_operation_log_entry(
+                    &mut dbtx,
+                    OperationId([operation_idx; 32]),
+                    operation_type,
+                    Meta {
+                        amount: fedimint_core::Amount::from_sats(operation_idx.into()),
+                    },
+                )
+                .await;
+            dbtx.commit_tx().await;
+        }
+        OperationLog::set_operation_outcome(&db, OperationId([2; 32]), &"done")
+            .await
+            .unwrap();
+        OperationLog::set_operation_outcome(&db, OperationId([3; 32]), &"done")
+            .await
+            .unwrap();
+
+        let query_ids = |query: OperationLogQuery| {
+            let op_log = op_log.clone();
+            async move {
+                op_log
+                    .query_operations(&query)
+                    .await
+                    .into_iter()
+                    .map(|(key, _)| key.operation_id.0[0])
+                    .collect::<Vec<_>>()
+            }
+        };
+
+        assert_eq!(
+            query_ids(OperationLogQuery::default()).await,
+            vec![5, 4, 3, 2, 1, 0]
+        );
+        assert_eq!(
+            query_ids(OperationLogQuery {
+                operation_type: Some("mint".to_string()),
+                ..Default::default()
+            })
+            .await,
+            vec![4, 2, 0]
+        );
+        assert_eq!(
+            query_ids(OperationLogQuery {
+                status: Some(OperationStatus::Pending),
+                ..Default::default()
+            })
+            .await,
+            vec![5, 4, 1, 0]
+        );
+        assert_eq!(
+            query_ids(OperationLogQuery {
+                operation_type: Some("ln".to_string()),
+                status: Some(OperationStatus::Completed),
+                ..Default::default()
+            })
+            .await,
+            vec![3]
+        );
+        assert_eq!(
+            query_ids(OperationLogQuery {
+                start_time: Some(creation_times[1]),
+                end_time: Some(creation_times[4]),
+                ..Default::default()
+            })
+            .await,
+            vec![3, 2, 1]
+        );
+        assert_eq!(
+            query_ids(OperationLogQuery {
+                operation_type: Some("mint".to_string()),
+                start_time: Some(creation_times[1]),
+                ..Default::default()
+            })
+            .await,
+            vec![4, 2]
+        );
+        assert_eq!(
+            query_ids(OperationLogQuery {
+                status: Some(OperationStatus::Pending),
+                end_time: Some(creation_times[2]),
+                ..Default::default()
+            })
+            .await,
+            vec![1, 0]
+        );
+        assert_eq!(
+            query_ids(OperationLogQuery {
+                min_amount: Some(fedimint_core::Amount::from_sats(2)),
+                max_amount: Some(fedimint_core::Amount::from_sats(4)),
+                limit: Some(2),
+                ..Default::default()
+            })
+            .await,
+            vec![4, 3]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_build_indexes() {
+        let db = Database::new(MemDatabase::new(), Default::default());
+        let op_log = OperationLog::new(db.clone());
+
+        // Operation created before the indexes were introduced
+        let operation_id = OperationId([0x42; 32]);
+        let mut dbtx = db.begin_transaction().await;
+        dbtx.insert_new_entry(
+            &OperationLogKey { operation_id },
+            &OperationLogEntry {
+                operation_type: "foo".to_string(),
+                meta: serde_json::to_value(()).unwrap(),
+                outcome: None,
+            },
+        )
+        .await;
+        dbtx.insert_new_entry(
+            &ChronologicalOperationLogKey {
+                creation_time: fedimint_core::time::now(),
+                operation_id,
+            },
+            &(),
+        )
+        .await;
+        dbtx.commit_tx().await;
+
+        let query = OperationLogQuery {
+            operation_type: Some("foo".to_string()),
+            ..Default::default()
+        };
+        assert!(op_log.query_operations(&query).await.is_empty());
+
+        OperationLog::build_indexes(&db).await.unwrap();
+        assert_eq!(op_log.query_operations(&query).await.len(), 1);
+
+        // Without any active state machines the operation isn't pending, even
+        // though its outcome was never cached
+        let pending_query = OperationLogQuery {
+            status: Some(OperationStatus::Pending),
+            ..query
+        };
+        assert!(op_log.query_operations(&pending_query).await.is_empty());
+        assert_eq!(
+            op_log.get_operation_status(operation_id).await,
+            OperationStatus::Completed
+        );
+    }
 }
@@ -0,0 +1,288 @@
+use std::fmt::Debug;
+use std::time::Duration;
+
+use fedimint_core::db::DatabaseTransaction;
+use fedimint_core::encoding::{Decodable, Encodable};
+use fedimint_core::task::{MaybeSend, MaybeSync};
+use fedimint_core::time::now;
+use fedimint_core::{apply, async_trait_maybe_send, Amount, TransactionId};
+use futures::StreamExt;
+use secp256k1_zkp::PublicKey;
+use thiserror::Error;
+
+use crate::db::{ChronologicalOperationLogKeyPrefix, OperationSpendKey, TransactionSpendKeyPrefix};
+
+const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Restricts how the client may spend its funds, checked by
+/// [`Client::finalize_and_submit_transaction`](crate::Client::finalize_and_submit_transaction)
+/// before a transaction is signed or submitted and by
+/// [`Client::enforce_out_of_band_spend`](crate::Client::enforce_out_of_band_spend)
+/// for funds handed out without a transaction.
+///
+/// Custom policies are registered with
+/// [`ClientBuilder::with_spending_policy`](crate::ClientBuilder::with_spending_policy),
+/// the built-in ones are configured at runtime through the [`SpendingRules`]
+/// stored in the client database. A spend only happens if all policies allow
+/// it.
+#[apply(async_trait_maybe_send!)]
+pub trait SpendingPolicy: Debug + MaybeSend + MaybeSync + 'static {
+    /// Fails if `request` must not be spent, usually with a
+    /// [`SpendingPolicyViolation`]. `dbtx` is the transaction the spend will
+    /// be recorded in, previous spends can be looked up with [`spent_within`].
+    async fn check(
+        &self,
+        dbtx: &mut DatabaseTransaction<'_>,
+        request: &SpendRequest,
+    ) -> anyhow::Result<()>;
+}
+
+/// The built-in [`SpendingPolicy`]s enforced by the client, stored in the
+/// client database, see
+/// [`Client::set_spending_rules`](crate::Client::set_spending_rules)
+#[derive(Debug, Clone, Default, PartialEq, Eq, Encodable, Decodable)]
+pub struct SpendingRules {
+    pub max_per_operation: Option<MaxPerOperation>,
+    pub rolling_limits: Vec<RollingLimit>,
+    pub lightning_payees: Option<LightningPayeeAllowList>,
+    pub withdraw_addresses: Option<WithdrawAddressAllowList>,
+}
+
+#[apply(async_trait_maybe_send!)]
+impl SpendingPolicy for SpendingRules {
+    async fn check(
+        &self,
+        dbtx: &mut DatabaseTransaction<'_>,
+        request: &SpendRequest,
+    ) -> anyhow::Result<()> {
+        if let Some(max_per_operation) = &self.max_per_operation {
+            max_per_operation.check(dbtx, request).await?;
+        }
+        for rolling_limit in &self.rolling_limits {
+            rolling_limit.check(dbtx, request).await?;
+        }
+        if let Some(lightning_payees) = &self.lightning_payees {
+            lightning_payees.check(dbtx, request).await?;
+        }
+        if let Some(withdraw_addresses) = &self.withdraw_addresses {
+            withdraw_addresses.check(dbtx, request).await?;
+        }
+        Ok(())
+    }
+}
+
+/// Maximum amount a single operation may spend, including fees
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Encodable, Decodable)]
+pub struct MaxPerOperation(pub Amount);
+
+#[apply(async_trait_maybe_send!)]
+impl SpendingPolicy for MaxPerOperation {
+    async fn check(
+        &self,
+        _dbtx: &mut DatabaseTransaction<'_>,
+        request: &SpendRequest,
+    ) -> anyhow::Result<()> {
+        if request.amount > self.0 {
+            return Err(SpendingPolicyViolation::OperationLimitExceeded {
+                amount: request.amount,
+                limit: self.0,
+            }
+            .into());
+        }
+        Ok(())
+    }
+}
+
+/// Maximum amount all operations created within the last `period` may spend
+/// together, including the new operation
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Encodable, Decodable)]
+pub struct RollingLimit {
+    pub period: Duration,
+    pub limit: Amount,
+}
+
+impl RollingLimit {
+    /// Limits the amount spent within the last 24 hours
+    pub fn daily(limit: Amount) -> Self {
+        RollingLimit { period: DAY, limit }
+    }
+
+    /// Limits the amount spent within the last 7 days
+    pub fn weekly(limit: Amount) -> Self {
+        RollingLimit {
+            period: DAY * 7,
+            limit,
+        }
+    }
+}
+
+#[apply(async_trait_maybe_send!)]
+impl SpendingPolicy for RollingLimit {
+    async fn check(
+        &self,
+        dbtx: &mut DatabaseTransaction<'_>,
+        request: &SpendRequest,
+    ) -> anyhow::Result<()> {
+        let spent = spent_within(dbtx, self.period).await;
+        if spent + request.amount > self.limit {
+            return Err(SpendingPolicyViolation::RollingLimitExceeded {
+                amount: request.amount,
+                spent,
+                period: self.period,
+                limit: self.limit,
+            }
+            .into());
+        }
+        Ok(())
+    }
+}
+
+/// Lightning payments may only be sent to these nodes. Payments to other users
+/// of the federation are settled internally and not restricted.
+#[derive(Debug, Clone, PartialEq, Eq, Encodable, Decodable)]
+pub struct LightningPayeeAllowList(pub Vec<PublicKey>);
+
+#[apply(async_trait_maybe_send!)]
+impl SpendingPolicy for LightningPayeeAllowList {
+    async fn check(
+        &self,
+        _dbtx: &mut DatabaseTransaction<'_>,
+        request: &SpendRequest,
+    ) -> anyhow::Result<()> {
+        for destination in &request.destinations {
+            if let SpendDestination::LightningPayee(payee) = destination {
+                if !self.0.contains(payee) {
+                    return Err(SpendingPolicyViolation::LightningPayeeNotAllowed(*payee).into());
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// On-chain withdrawals may only be sent to these addresses
+#[derive(Debug, Clone, PartialEq, Eq, Encodable, Decodable)]
+pub struct WithdrawAddressAllowList(pub Vec<bitcoin::Address>);
+
+#[apply(async_trait_maybe_send!)]
+impl SpendingPolicy for WithdrawAddressAllowList {
+    async fn check(
+        &self,
+        _dbtx: &mut DatabaseTransaction<'_>,
+        request: &SpendRequest,
+    ) -> anyhow::Result<()> {
+        for destination in &request.destinations {
+            if let SpendDestination::BitcoinAddress(address) = destination {
+                if !self.0.contains(address) {
+                    return Err(SpendingPolicyViolation::WithdrawAddressNotAllowed(
+                        address.clone(),
+                    )
+                    .into());
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Where the funds sent by a transaction output end up, returned by
+/// [`ClientModule::output_destination`](crate::module::ClientModule::output_destination)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SpendDestination {
+    /// A lightning payment to the given node
+    LightningPayee(PublicKey),
+    /// An on-chain withdrawal to the given address
+    BitcoinAddress(bitcoin::Address),
+}
+
+/// What a transaction is about to spend, checked against the
+/// [`SpendingPolicy`]s
+#[derive(Debug, Clone)]
+pub struct SpendRequest {
+    /// Amount leaving the client, including fees
+    pub amount: Amount,
+    pub destinations: Vec<SpendDestination>,
+}
+
+/// Reason a transaction was refused by one of the built-in
+/// [`SpendingPolicy`]s.
+///
+/// Returned as the error of
+/// [`Client::finalize_and_submit_transaction`](crate::Client::finalize_and_submit_transaction),
+/// use [`anyhow::Error::downcast_ref`] to inspect it.
+#[derive(Debug, Clone, Error, PartialEq, Eq)]
+pub enum SpendingPolicyViolation {
+    #[error("Operation would spend {amount}, the maximum per operation is {limit}")]
+    OperationLimitExceeded { amount: Amount, limit: Amount },
+    #[error(
+        "Operation would spend {amount} in addition to {spent} already spent within {period:?}, \
+         the limit is {limit}"
+    )]
+    RollingLimitExceeded {
+        amount: Amount,
+        spent: Amount,
+        period: Duration,
+        limit: Amount,
+    },
+    #[error("Lightning payee {0} is not on the allow-list")]
+    LightningPayeeNotAllowed(PublicKey),
+    #[error("Withdrawal address {0} is not on the allow-list")]
+    WithdrawAddressNotAllowed(bitcoin::Address),
+}
+
+/// Sums the amounts spent by operations created within the last `period`
+pub async fn spent_within(dbtx: &mut DatabaseTransaction<'_>, period: Duration) -> Amount {
+    let cutoff = now().checked_sub(period);
+    let operations = dbtx
+        .find_by_prefix_sorted_descending(&ChronologicalOperationLogKeyPrefix)
+        .await
+        .map(|(key, _)| key)
+        .take_while(|key| {
+            let in_period = cutoff.map_or(true, |cutoff| key.creation_time >= cutoff);
+            async move { in_period }
+        })
+        .collect::<Vec<_>>()
+        .await;
+
+    let mut spent = Amount::ZERO;
+    for operation in operations {
+        spent += dbtx
+            .get_value(&OperationSpendKey {
+                operation_id: operation.operation_id,
+            })
+            .await
+            .unwrap_or(Amount::ZERO);
+    }
+    spent
+}
+
+/// Releases the amounts spent by the rejected or expired transaction `txid`,
+/// so they don't count towards rolling limits anymore
+pub(crate) async fn release_transaction_spends(
+    dbtx: &mut DatabaseTransaction<'_>,
+    txid: TransactionId,
+) {
+    let spends = dbtx
+        .find_by_prefix(&TransactionSpendKeyPrefix { txid })
+        .await
+        .collect::<Vec<_>>()
+        .await;
+
+    for (key, amount) in spends {
+        dbtx.remove_entry(&key).await;
+
+        let spend_key = OperationSpendKey {
+            operation_id: key.operation_id,
+        };
+        let remaining = dbtx
+            .get_value(&spend_key)
+            .await
+            .unwrap_or(Amount::ZERO)
+            .saturating_sub(amount);
+        if remaining == Amount::ZERO {
+            dbtx.remove_entry(&spend_key).await;
+        } else {
+            dbtx.insert_entry(&spend_key, &remaining).await;
+        }
+    }
+}
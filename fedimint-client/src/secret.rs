@@ -1,6 +1,7 @@
 use std::fmt::Debug;
 use std::io::{Read, Write};
 
+use fedimint_core::config::FederationId;
 use fedimint_core::core::ModuleInstanceId;
 use fedimint_core::encoding::{Decodable, DecodeError, Encodable};
 use fedimint_derive_secret::{ChildId, DerivableSecret};
@@ -8,10 +9,12 @@ use rand::{CryptoRng, Rng, RngCore};
 
 const TYPE_MODULE: ChildId = ChildId(0);
 const TYPE_BACKUP: ChildId = ChildId(1);
+const TYPE_FEDERATION: ChildId = ChildId(2);
 
 pub trait DeriveableSecretClientExt {
     fn derive_module_secret(&self, module_instance_id: ModuleInstanceId) -> DerivableSecret;
     fn derive_backup_secret(&self) -> DerivableSecret;
+    fn derive_federation_secret(&self, federation_id: &FederationId) -> DerivableSecret;
 }
 
 impl DeriveableSecretClientExt for DerivableSecret {
@@ -25,6 +28,28 @@ impl DeriveableSecretClientExt for DerivableSecret {
         assert_eq!(self.level(), 0);
         self.child_key(TYPE_BACKUP)
     }
+
+    /// Derives an independent root secret for a client of the given
+    /// federation, used to run clients of many federations from one secret
+    fn derive_federation_secret(&self, federation_id: &FederationId) -> DerivableSecret {
+        const FEDIMINT_FEDERATION_NONCE: &[u8] = b"Fedimint Federation Client Salt";
+
+        assert_eq!(self.level(), 0);
+        let federation_secret = federation_id
+            .0
+            .to_bytes()
+            .chunks_exact(8)
+            .map(|chunk| ChildId(u64::from_be_bytes(chunk.try_into().expect("8 byte chunk"))))
+            .fold(self.child_key(TYPE_FEDERATION), |secret, child_id| {
+                secret.child_key(child_id)
+            });
+
+        // Client secrets have to be root secrets, so we re-root the derived one
+        DerivableSecret::new_root(
+            &federation_secret.to_random_bytes::<64>(),
+            FEDIMINT_FEDERATION_NONCE,
+        )
+    }
 }
 
 /// Trait defining a way to generate, serialize and deserialize a root secret.
@@ -1,11 +1,13 @@
+use std::any::Any;
 use std::collections::{BTreeMap, HashSet};
 use std::fmt::{Debug, Formatter};
 use std::io::{Error, Read, Write};
 use std::marker::PhantomData;
+use std::panic::AssertUnwindSafe;
 use std::sync::Arc;
 use std::time::{Duration, SystemTime};
 
-use anyhow::bail;
+use anyhow::{anyhow, bail};
 use fedimint_core::core::{IntoDynInstance, ModuleInstanceId};
 use fedimint_core::db::{AutocommitError, Database, DatabaseKeyWithNotify, DatabaseTransaction};
 use fedimint_core::encoding::{Decodable, DecodeError, Encodable};
@@ -16,11 +18,13 @@ use fedimint_core::util::BoxFuture;
 use fedimint_core::{maybe_add_send_sync, task};
 use futures::future::select_all;
 use futures::stream::StreamExt;
+use futures::FutureExt;
 use tokio::select;
-use tokio::sync::Mutex;
+use tokio::sync::{Mutex, Notify};
 use tracing::{debug, error, info, trace, warn};
 
 use super::state::StateTransitionFunction;
+use crate::db::PendingOperationKey;
 use crate::sm::notifier::Notifier;
 use crate::sm::state::{DynContext, DynState};
 use crate::sm::{ClientSMDatabaseTransaction, GlobalContext, OperationId, State, StateTransition};
@@ -41,6 +45,48 @@ enum ExecutorDbPrefixes {
     ActiveStates = 0xa1,
     /// See [`InactiveStateKey`]
     InactiveStates = 0xa2,
+    /// See [`DeadLetterStateKey`]
+    DeadLetterStates = 0xa3,
+    /// See [`FailedAttemptsKey`]
+    FailedAttempts = 0xa4,
+}
+
+/// Limits how often and how fast the executor retries a state whose trigger or
+/// transition failed, e.g. by panicking or because its DB transaction couldn't
+/// be committed.
+///
+/// Retries are delayed by an exponentially growing backoff. If `max_attempts`
+/// is set, the state is moved to the dead-letter table once that many attempts
+/// failed, see [`Executor::get_dead_letter_states`]. The default policy never
+/// gives up, since a dead-lettered state machine stops making progress and
+/// funds it holds, e.g. a pending refund, are stuck until it is requeued.
+/// Only modules whose state machines don't hold funds should set a limit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    pub max_attempts: Option<u32>,
+    /// Delay before the first retry, doubled for every further retry
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: None,
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(10 * 60),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Delay before retrying after `failed_attempts` failed attempts
+    pub fn backoff(&self, failed_attempts: u32) -> Duration {
+        let factor = 2u32.saturating_pow(failed_attempts.saturating_sub(1));
+        self.initial_backoff
+            .saturating_mul(factor)
+            .min(self.max_backoff)
+    }
 }
 
 /// Executor that drives forward state machines under its management.
@@ -60,6 +106,11 @@ struct ExecutorInner<GC> {
     context: Mutex<Option<ContextGen<GC>>>,
     module_contexts: BTreeMap<ModuleInstanceId, DynContext>,
     notifier: Notifier<GC>,
+    /// Makes the executor drop all pending transitions and re-evaluate the
+    /// active states, see [`Executor::abort_state`] and
+    /// [`Executor::retry_state`]
+    restart_transitions: Notify,
+    retry_policies: BTreeMap<ModuleInstanceId, RetryPolicy>,
 }
 
 /// Builder to which module clients can be attached and used to build an
@@ -67,6 +118,7 @@ struct ExecutorInner<GC> {
 #[derive(Debug, Default)]
 pub struct ExecutorBuilder {
     module_contexts: BTreeMap<ModuleInstanceId, DynContext>,
+    retry_policies: BTreeMap<ModuleInstanceId, RetryPolicy>,
 }
 
 impl<GC> Executor<GC>
@@ -82,6 +134,100 @@ where
         self.inner.get_active_states().await
     }
 
+    pub async fn get_inactive_states(&self) -> Vec<(DynState<GC>, InactiveState)> {
+        self.inner.get_inactive_states().await
+    }
+
+    /// Returns all states that were given up on after exceeding the
+    /// [`RetryPolicy`] of their module
+    pub async fn get_dead_letter_states(&self) -> Vec<(DynState<GC>, DeadLetterState)> {
+        self.inner
+            .db
+            .begin_transaction()
+            .await
+            .find_by_prefix(&DeadLetterStateKeyPrefix::new())
+            .await
+            .map(|(key, meta)| (key.state, meta))
+            .collect::<Vec<_>>()
+            .await
+    }
+
+    /// Stops driving the active `state` forward by moving it to the inactive
+    /// states, without running any of its transitions. The state machine it
+    /// belongs to won't make progress anymore.
+    ///
+    /// **Attention**: this is a repair tool, funds held by the aborted state
+    /// machine may be lost.
+    pub async fn abort_state(&self, state: DynState<GC>) -> anyhow::Result<()> {
+        let mut dbtx = self.inner.db.begin_transaction().await;
+        let Some(meta) = dbtx
+            .remove_entry(&ActiveStateKey::from_state(state.clone()))
+            .await
+        else {
+            bail!("State is not active");
+        };
+        dbtx.remove_entry(&FailedAttemptsKey::from_state(state.clone()))
+            .await;
+        let operation_id = state.operation_id();
+        dbtx.insert_entry(&InactiveStateKey::from_state(state), &meta.into_inactive())
+            .await;
+        complete_operation_if_inactive::<GC>(&mut dbtx, operation_id).await;
+        dbtx.commit_tx_result().await?;
+
+        self.inner.restart_transitions.notify_one();
+        Ok(())
+    }
+
+    /// Re-evaluates the triggers of the active `state` immediately, e.g. after
+    /// a trigger failed permanently because of a since resolved network issue.
+    /// Previously failed attempts are forgotten, so the state doesn't wait
+    /// for the backoff of its [`RetryPolicy`].
+    ///
+    /// Since the executor waits for all active states at once, the triggers of
+    /// all other active states are restarted as well.
+    pub async fn retry_state(&self, state: DynState<GC>) -> anyhow::Result<()> {
+        let mut dbtx = self.inner.db.begin_transaction().await;
+        if dbtx
+            .get_value(&ActiveStateKey::from_state(state.clone()))
+            .await
+            .is_none()
+        {
+            bail!("State is not active");
+        }
+        dbtx.remove_entry(&FailedAttemptsKey::from_state(state))
+            .await;
+        dbtx.commit_tx_result().await?;
+
+        self.inner.restart_transitions.notify_one();
+        Ok(())
+    }
+
+    /// Moves the dead-lettered `state` back to the active states, e.g. after
+    /// the cause of its failures was resolved. Its failed attempts are
+    /// forgotten, so its [`RetryPolicy`] applies from the start again.
+    pub async fn requeue_dead_letter(&self, state: DynState<GC>) -> anyhow::Result<()> {
+        let mut dbtx = self.inner.db.begin_transaction().await;
+        let Some(dead_letter) = dbtx
+            .remove_entry(&DeadLetterStateKey::from_state(state.clone()))
+            .await
+        else {
+            bail!("State is not dead-lettered");
+        };
+        dbtx.remove_entry(&FailedAttemptsKey::from_state(state.clone()))
+            .await;
+        dbtx.insert_entry(
+            &ActiveStateKey::from_state(state),
+            &ActiveState {
+                created_at: dead_letter.created_at,
+            },
+        )
+        .await;
+        dbtx.commit_tx_result().await?;
+
+        self.inner.restart_transitions.notify_one();
+        Ok(())
+    }
+
     /// Adds a number of state machines to the executor atomically. They will be
     /// driven to completion automatically in the background.
     ///
@@ -270,7 +416,7 @@ where
 }
 
 type TransitionForActiveState<GC> = (
-    serde_json::Value,
+    anyhow::Result<serde_json::Value>,
     DynState<GC>,
     StateTransitionFunction<DynState<GC>>,
     ActiveState,
@@ -318,7 +464,13 @@ where
                         trigger,
                         transition,
                     } = transition;
-                    (trigger.await, state, transition, meta)
+                    let outcome = AssertUnwindSafe(trigger)
+                        .catch_unwind()
+                        .await
+                        .map_err(|panic| {
+                            anyhow!("State trigger panicked: {}", panic_message(&*panic))
+                        });
+                    (outcome, state, transition, meta)
                 });
                 f
             })
@@ -327,6 +479,9 @@ where
             None
         } else {
             Some(Box::pin(async move {
+                if let Some(retry_delay) = self.retry_delay(&state).await {
+                    task::sleep(retry_delay).await;
+                }
                 let (first_completed_result, _index, _unused_transitions) =
                     select_all(transitions).await;
                 first_completed_result
@@ -383,9 +538,17 @@ where
                     debug!("New state added, re-starting state transitions");
                     return Ok(());
                 }
+                () = self.restart_transitions.notified() => {
+                    debug!("Restart requested, re-starting state transitions");
+                    return Ok(());
+                }
             };
             transitions = remaining_transitions;
             let (transition_outcome, state, transition_fn, meta) = completed_result;
+            let transition_outcome = match transition_outcome {
+                Ok(transition_outcome) => transition_outcome,
+                Err(err) => return Err(self.handle_failed_attempt(state, meta, err).await),
+            };
             debug!(
                 ?state,
                 transition_outcome = ?AbbreviateJson(&transition_outcome),
@@ -400,17 +563,38 @@ where
                         let transition_fn = transition_fn.clone();
                         let transition_outcome = transition_outcome.clone();
                         Box::pin(async move {
-                            let new_state = transition_fn(
+                            // The state may have been aborted while its trigger was pending
+                            if dbtx
+                                .get_value(&ActiveStateKey::from_state(state.clone()))
+                                .await
+                                .is_none()
+                            {
+                                return Ok(None);
+                            }
+
+                            let new_state = match AssertUnwindSafe(transition_fn(
                                 &mut ClientSMDatabaseTransaction::new(
                                     dbtx,
                                     state.module_instance_id(),
                                 ),
                                 transition_outcome,
                                 state.clone(),
-                            )
-                            .await;
+                            ))
+                            .catch_unwind()
+                            .await
+                            {
+                                Ok(new_state) => new_state,
+                                Err(panic) => {
+                                    return Err(anyhow!(
+                                        "State transition panicked: {}",
+                                        panic_message(&*panic)
+                                    ))
+                                }
+                            };
                             dbtx.remove_entry(&ActiveStateKey::from_state(state.clone()))
                                 .await;
+                            dbtx.remove_entry(&FailedAttemptsKey::from_state(state.clone()))
+                                .await;
                             dbtx.insert_entry(
                                 &InactiveStateKey::from_state(state.clone()),
                                 &meta.into_inactive(),
@@ -432,17 +616,19 @@ where
                                 let k = InactiveStateKey::from_state(new_state.clone());
                                 let v = ActiveState::new().into_inactive();
                                 dbtx.insert_entry(&k, &v).await;
-                                Ok(ActiveOrInactiveState::Inactive {
+                                complete_operation_if_inactive::<GC>(dbtx, state.operation_id())
+                                    .await;
+                                Ok(Some(ActiveOrInactiveState::Inactive {
                                     dyn_state: new_state,
-                                })
+                                }))
                             } else {
                                 let k = ActiveStateKey::from_state(new_state.clone());
                                 let v = ActiveState::new();
                                 dbtx.insert_entry(&k, &v).await;
-                                Ok(ActiveOrInactiveState::Active {
+                                Ok(Some(ActiveOrInactiveState::Active {
                                     dyn_state: new_state,
                                     active_state: v,
-                                })
+                                }))
                             }
                         })
                     },
@@ -455,9 +641,17 @@ where
                         attempts,
                     } => last_error.context(format!("Failed to commit after {attempts} attempts")),
                     AutocommitError::ClosureError { error, .. } => error,
-                })?;
+                });
+            let active_or_inactive_state = match active_or_inactive_state {
+                Ok(active_or_inactive_state) => active_or_inactive_state,
+                Err(err) => return Err(self.handle_failed_attempt(state, meta, err).await),
+            };
 
             active_state_count -= 1;
+            let Some(active_or_inactive_state) = active_or_inactive_state else {
+                debug!(?state, "State was aborted, dropping its transition");
+                continue;
+            };
             match active_or_inactive_state {
                 ActiveOrInactiveState::Active {
                     dyn_state,
@@ -469,15 +663,110 @@ where
                         active_state_count += 1;
                         transitions.push(transition);
                     }
-                    self.notifier.notify(dyn_state);
+                    self.notifier.notify_transition(state, dyn_state);
                 }
                 ActiveOrInactiveState::Inactive { dyn_state } => {
-                    self.notifier.notify(dyn_state);
+                    self.notifier.notify_transition(state, dyn_state);
                 }
             }
         }
     }
 
+    /// Time left until the next attempt of `state` may run according to its
+    /// [`RetryPolicy`], `None` if it didn't fail before
+    async fn retry_delay(&self, state: &DynState<GC>) -> Option<Duration> {
+        let retry_at = self
+            .db
+            .begin_transaction()
+            .await
+            .get_value(&FailedAttemptsKey::from_state(state.clone()))
+            .await?
+            .retry_at;
+        retry_at.duration_since(fedimint_core::time::now()).ok()
+    }
+
+    /// Records a failed attempt to transition `state` and moves it to the
+    /// dead-letter table if it exceeded its [`RetryPolicy`]. Returns `error`
+    /// for the caller to propagate.
+    async fn handle_failed_attempt(
+        &self,
+        state: DynState<GC>,
+        meta: ActiveState,
+        error: anyhow::Error,
+    ) -> anyhow::Error {
+        let policy = self
+            .retry_policies
+            .get(&state.module_instance_id())
+            .copied()
+            .unwrap_or_default();
+
+        let mut dbtx = self.db.begin_transaction().await;
+        // The state may have been aborted in the meantime
+        if dbtx
+            .get_value(&ActiveStateKey::from_state(state.clone()))
+            .await
+            .is_none()
+        {
+            return error;
+        }
+
+        let failed_attempts_key = FailedAttemptsKey::from_state(state.clone());
+        let attempts = dbtx
+            .get_value(&failed_attempts_key)
+            .await
+            .map_or(0, |failed| failed.attempts)
+            + 1;
+
+        if policy
+            .max_attempts
+            .map_or(true, |max_attempts| attempts < max_attempts)
+        {
+            dbtx.insert_entry(
+                &failed_attempts_key,
+                &FailedAttempts {
+                    attempts,
+                    retry_at: fedimint_core::time::now() + policy.backoff(attempts),
+                },
+            )
+            .await;
+            if let Err(commit_error) = dbtx.commit_tx_result().await {
+                return commit_error.context(error);
+            }
+
+            warn!(
+                ?state,
+                attempts,
+                %error,
+                "State transition failed, retrying after backoff"
+            );
+            return error;
+        }
+
+        let dead_letter = DeadLetterState {
+            created_at: meta.created_at,
+            dead_lettered_at: fedimint_core::time::now(),
+            attempts,
+            last_error: error.to_string(),
+        };
+        dbtx.remove_entry(&failed_attempts_key).await;
+        dbtx.remove_entry(&ActiveStateKey::from_state(state.clone()))
+            .await;
+        dbtx.insert_entry(&DeadLetterStateKey::from_state(state.clone()), &dead_letter)
+            .await;
+        if let Err(commit_error) = dbtx.commit_tx_result().await {
+            return commit_error.context(error);
+        }
+
+        error!(
+            ?state,
+            attempts,
+            %error,
+            "State transition failed too often, moved state to dead-letter table"
+        );
+        self.notifier.notify_dead_letter(state, dead_letter);
+        error
+    }
+
     async fn get_active_states(&self) -> Vec<(DynState<GC>, ActiveState)> {
         self.db
             .begin_transaction()
@@ -501,6 +790,43 @@ where
     }
 }
 
+/// Returns `true` if any state of the operation is active
+pub(crate) async fn has_active_states<GC: GlobalContext>(
+    dbtx: &mut DatabaseTransaction<'_>,
+    operation_id: OperationId,
+) -> bool {
+    dbtx.find_by_prefix(&ActiveOperationStateKeyPrefix::<GC> {
+        operation_id,
+        _pd: PhantomData,
+    })
+    .await
+    .next()
+    .await
+    .is_some()
+}
+
+/// Marks the operation as completed in the operation log once none of its
+/// states are active anymore
+async fn complete_operation_if_inactive<GC: GlobalContext>(
+    dbtx: &mut DatabaseTransaction<'_>,
+    operation_id: OperationId,
+) {
+    if !has_active_states::<GC>(dbtx, operation_id).await {
+        dbtx.remove_entry(&PendingOperationKey { operation_id })
+            .await;
+    }
+}
+
+fn panic_message(panic: &(dyn Any + Send)) -> String {
+    if let Some(message) = panic.downcast_ref::<&str>() {
+        (*message).to_owned()
+    } else if let Some(message) = panic.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "unknown panic payload".to_owned()
+    }
+}
+
 impl<GC: GlobalContext> Debug for ExecutorInner<GC> {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         let (active, inactive) = futures::executor::block_on(async {
@@ -527,6 +853,12 @@ impl ExecutorBuilder {
         self.with_module_dyn(context.into_dyn(instance_id));
     }
 
+    /// Sets the [`RetryPolicy`] for state machines of the supplied module,
+    /// modules without one use [`RetryPolicy::default`]
+    pub fn with_retry_policy(&mut self, instance_id: ModuleInstanceId, policy: RetryPolicy) {
+        self.retry_policies.insert(instance_id, policy);
+    }
+
     /// Allow executor being built to run state machines associated with the
     /// supplied module
     pub fn with_module_dyn(&mut self, context: DynContext) {
@@ -551,6 +883,8 @@ impl ExecutorBuilder {
             context: Mutex::new(None),
             module_contexts: self.module_contexts,
             notifier,
+            restart_transitions: Notify::new(),
+            retry_policies: self.retry_policies,
         });
 
         debug!(
@@ -830,6 +1164,154 @@ where
     type Record = InactiveStateKey<GC>;
 }
 
+/// A state that was given up on after its trigger or transition failed too
+/// often, see [`RetryPolicy`]
+#[derive(Debug, Clone)]
+pub struct DeadLetterStateKey<GC> {
+    pub operation_id: OperationId,
+    pub state: DynState<GC>,
+}
+
+impl<GC> DeadLetterStateKey<GC> {
+    pub(crate) fn from_state(state: DynState<GC>) -> DeadLetterStateKey<GC> {
+        DeadLetterStateKey {
+            operation_id: state.operation_id(),
+            state,
+        }
+    }
+}
+
+impl<GC> Encodable for DeadLetterStateKey<GC> {
+    fn consensus_encode<W: Write>(&self, writer: &mut W) -> Result<usize, Error> {
+        let mut len = 0;
+        len += self.operation_id.consensus_encode(writer)?;
+        len += self.state.consensus_encode(writer)?;
+        Ok(len)
+    }
+}
+
+impl<GC> Decodable for DeadLetterStateKey<GC>
+where
+    GC: GlobalContext,
+{
+    fn consensus_decode<R: Read>(
+        reader: &mut R,
+        modules: &ModuleDecoderRegistry,
+    ) -> Result<Self, DecodeError> {
+        let operation_id = OperationId::consensus_decode(reader, modules)?;
+        let state = DynState::consensus_decode(reader, modules)?;
+
+        Ok(DeadLetterStateKey {
+            operation_id,
+            state,
+        })
+    }
+}
+
+#[derive(Debug)]
+struct DeadLetterStateKeyPrefix<GC>(PhantomData<GC>);
+
+impl<GC> DeadLetterStateKeyPrefix<GC> {
+    pub fn new() -> Self {
+        DeadLetterStateKeyPrefix(PhantomData)
+    }
+}
+
+impl<GC> Encodable for DeadLetterStateKeyPrefix<GC> {
+    fn consensus_encode<W: Write>(&self, _writer: &mut W) -> Result<usize, Error> {
+        Ok(0)
+    }
+}
+
+#[derive(Debug, Clone, Decodable, Encodable)]
+pub struct DeadLetterState {
+    pub created_at: SystemTime,
+    pub dead_lettered_at: SystemTime,
+    /// Number of failed attempts before giving up
+    pub attempts: u32,
+    /// Error of the last failed attempt
+    pub last_error: String,
+}
+
+impl<GC> ::fedimint_core::db::DatabaseRecord for DeadLetterStateKey<GC>
+where
+    GC: GlobalContext,
+{
+    const DB_PREFIX: u8 = ExecutorDbPrefixes::DeadLetterStates as u8;
+    const NOTIFY_ON_MODIFY: bool = false;
+    type Key = Self;
+    type Value = DeadLetterState;
+}
+
+impl<GC> ::fedimint_core::db::DatabaseLookup for DeadLetterStateKeyPrefix<GC>
+where
+    GC: GlobalContext,
+{
+    type Record = DeadLetterStateKey<GC>;
+}
+
+/// Failed attempts of an active state to run one of its transitions, kept
+/// until the state makes progress so the [`RetryPolicy`] applies across
+/// restarts
+#[derive(Debug, Clone)]
+pub struct FailedAttemptsKey<GC> {
+    pub operation_id: OperationId,
+    pub state: DynState<GC>,
+}
+
+impl<GC> FailedAttemptsKey<GC> {
+    pub(crate) fn from_state(state: DynState<GC>) -> FailedAttemptsKey<GC> {
+        FailedAttemptsKey {
+            operation_id: state.operation_id(),
+            state,
+        }
+    }
+}
+
+impl<GC> Encodable for FailedAttemptsKey<GC> {
+    fn consensus_encode<W: Write>(&self, writer: &mut W) -> Result<usize, Error> {
+        let mut len = 0;
+        len += self.operation_id.consensus_encode(writer)?;
+        len += self.state.consensus_encode(writer)?;
+        Ok(len)
+    }
+}
+
+impl<GC> Decodable for FailedAttemptsKey<GC>
+where
+    GC: GlobalContext,
+{
+    fn consensus_decode<R: Read>(
+        reader: &mut R,
+        modules: &ModuleDecoderRegistry,
+    ) -> Result<Self, DecodeError> {
+        let operation_id = OperationId::consensus_decode(reader, modules)?;
+        let state = DynState::consensus_decode(reader, modules)?;
+
+        Ok(FailedAttemptsKey {
+            operation_id,
+            state,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, Decodable, Encodable)]
+pub struct FailedAttempts {
+    pub attempts: u32,
+    /// Earliest time the next attempt may run
+    pub retry_at: SystemTime,
+}
+
+impl<GC> ::fedimint_core::db::DatabaseRecord for FailedAttemptsKey<GC>
+where
+    GC: GlobalContext,
+{
+    const DB_PREFIX: u8 = ExecutorDbPrefixes::FailedAttempts as u8;
+    const NOTIFY_ON_MODIFY: bool = false;
+    type Key = Self;
+    type Value = FailedAttempts;
+}
+
 enum ActiveOrInactiveState<GC> {
     Active {
         dyn_state: DynState<GC>,
@@ -852,17 +1334,26 @@ mod tests {
     use fedimint_core::encoding::{Decodable, Encodable};
     use fedimint_core::module::registry::ModuleDecoderRegistry;
     use fedimint_core::task::{self, TaskGroup};
+    use futures::StreamExt;
     use tokio::sync::broadcast::Sender;
     use tracing::{info, trace};
 
+    use super::{ActiveState, ActiveStateKey, FailedAttempts, FailedAttemptsKey};
+    use crate::db::PendingOperationKey;
     use crate::sm::state::{Context, DynContext, DynState};
-    use crate::sm::{Executor, Notifier, OperationId, State, StateTransition};
+    use crate::sm::{Executor, Notifier, OperationId, RetryPolicy, State, StateTransition};
 
     #[derive(Debug, Clone, Eq, PartialEq, Decodable, Encodable)]
     enum MockStateMachine {
         Start,
         ReceivedNonNull(u64),
         Final,
+        /// Transition to `Final` always panics
+        Panicking,
+    }
+
+    async fn panicking_transition() -> MockStateMachine {
+        panic!("Mock transition failed")
     }
 
     impl State for MockStateMachine {
@@ -926,6 +1417,11 @@ mod tests {
                 MockStateMachine::Final => {
                     vec![]
                 }
+                MockStateMachine::Panicking => {
+                    vec![StateTransition::new(async {}, |_dbtx, (), _state| {
+                        Box::pin(panicking_transition())
+                    })]
+                }
             }
         }
 
@@ -975,6 +1471,14 @@ mod tests {
                 broadcast: broadcast.clone(),
             },
         );
+        executor_builder.with_retry_policy(
+            42,
+            RetryPolicy {
+                max_attempts: Some(2),
+                initial_backoff: Duration::from_millis(100),
+                max_backoff: Duration::from_millis(100),
+            },
+        );
         let executor = executor_builder
             .build(db.clone(), Notifier::new(db.clone()))
             .await;
@@ -991,14 +1495,24 @@ mod tests {
         const MOCK_INSTANCE_2: ModuleInstanceId = 21;
 
         let mut task_group = TaskGroup::new();
-        let (executor, sender, _db) = get_executor(&mut task_group).await;
+        let (executor, sender, db) = get_executor(&mut task_group).await;
+        let pending_operation_key = PendingOperationKey {
+            operation_id: OperationId([0u8; 32]),
+        };
+        let mut dbtx = db.begin_transaction().await;
+        dbtx.insert_entry(&pending_operation_key, &fedimint_core::time::now())
+            .await;
         executor
-            .add_state_machines(vec![DynState::from_typed(
-                MOCK_INSTANCE_1,
-                MockStateMachine::Start,
-            )])
+            .add_state_machines_dbtx(
+                &mut dbtx,
+                vec![DynState::from_typed(
+                    MOCK_INSTANCE_1,
+                    MockStateMachine::Start,
+                )],
+            )
             .await
             .unwrap();
+        dbtx.commit_tx().await;
 
         assert!(
             executor
@@ -1035,5 +1549,197 @@ mod tests {
                 .await,
             "State was written to DB and waits for broadcast"
         );
+        assert!(
+            db.begin_transaction()
+                .await
+                .get_value(&pending_operation_key)
+                .await
+                .is_none(),
+            "Operation is no longer pending once its last state is inactive"
+        );
+    }
+
+    #[tokio::test]
+    #[tracing_test::traced_test]
+    async fn test_executor_abort_state() {
+        const MOCK_INSTANCE: ModuleInstanceId = 42;
+
+        let mut task_group = TaskGroup::new();
+        let (executor, sender, _db) = get_executor(&mut task_group).await;
+        let state = DynState::from_typed(MOCK_INSTANCE, MockStateMachine::Start);
+        executor
+            .add_state_machines(vec![state.clone()])
+            .await
+            .unwrap();
+
+        task::sleep(Duration::from_secs(1)).await;
+        executor.abort_state(state.clone()).await.unwrap();
+        assert!(
+            executor.abort_state(state).await.is_err(),
+            "Only active states can be aborted"
+        );
+
+        // Fails if the aborted state's transitions were already dropped
+        let _ = sender.send(0);
+        task::sleep(Duration::from_secs(2)).await;
+
+        assert!(
+            executor
+                .contains_inactive_state(MOCK_INSTANCE, MockStateMachine::Start)
+                .await,
+            "Aborted state was moved to the inactive states"
+        );
+        assert!(
+            !executor
+                .contains_inactive_state(MOCK_INSTANCE, MockStateMachine::Final)
+                .await,
+            "Aborted state machine didn't make progress"
+        );
+    }
+
+    #[tokio::test]
+    #[tracing_test::traced_test]
+    async fn test_executor_dead_letter() {
+        const MOCK_INSTANCE: ModuleInstanceId = 42;
+
+        let mut task_group = TaskGroup::new();
+        let (executor, _sender, _db) = get_executor(&mut task_group).await;
+        let mut dead_letters = executor.notifier().subscribe_dead_letters();
+        let state = DynState::from_typed(MOCK_INSTANCE, MockStateMachine::Panicking);
+        executor
+            .add_state_machines(vec![state.clone()])
+            .await
+            .unwrap();
+
+        let (dead_state, dead_letter) = task::timeout(Duration::from_secs(10), dead_letters.next())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(dead_state, state);
+        assert_eq!(dead_letter.attempts, 2);
+        assert!(dead_letter.last_error.contains("Mock transition failed"));
+
+        assert!(
+            !executor
+                .contains_active_state(MOCK_INSTANCE, MockStateMachine::Panicking)
+                .await,
+            "Dead-lettered state isn't retried anymore"
+        );
+        assert_eq!(executor.get_dead_letter_states().await.len(), 1);
+
+        executor.requeue_dead_letter(state.clone()).await.unwrap();
+        assert!(executor.get_dead_letter_states().await.is_empty());
+        assert!(
+            executor.requeue_dead_letter(state.clone()).await.is_err(),
+            "Only dead-lettered states can be requeued"
+        );
+
+        let (requeued_state, dead_letter) =
+            task::timeout(Duration::from_secs(10), dead_letters.next())
+                .await
+                .unwrap()
+                .unwrap();
+        assert_eq!(requeued_state, state);
+        assert_eq!(
+            dead_letter.attempts, 2,
+            "Failed attempts are reset on requeue"
+        );
+    }
+
+    #[tokio::test]
+    #[tracing_test::traced_test]
+    async fn test_executor_retry_state_skips_backoff() {
+        const MOCK_INSTANCE: ModuleInstanceId = 42;
+
+        let mut task_group = TaskGroup::new();
+        let (executor, sender, db) = get_executor(&mut task_group).await;
+
+        // A state that is waiting for the backoff of a previously failed attempt
+        let state = DynState::from_typed(MOCK_INSTANCE, MockStateMachine::Start);
+        let mut dbtx = db.begin_transaction().await;
+        dbtx.insert_entry(
+            &ActiveStateKey::from_state(state.clone()),
+            &ActiveState::new(),
+        )
+        .await;
+        dbtx.insert_entry(
+            &FailedAttemptsKey::from_state(state.clone()),
+            &FailedAttempts {
+                attempts: 1,
+                retry_at: fedimint_core::time::now() + Duration::from_secs(3600),
+            },
+        )
+        .await;
+        dbtx.commit_tx().await;
+
+        task::sleep(Duration::from_secs(1)).await;
+        executor.retry_state(state.clone()).await.unwrap();
+        task::sleep(Duration::from_secs(1)).await;
+        sender.send(0).unwrap();
+        task::sleep(Duration::from_secs(2)).await;
+
+        assert!(
+            executor
+                .contains_inactive_state(MOCK_INSTANCE, MockStateMachine::Final)
+                .await,
+            "Retried state transitioned without waiting for its backoff"
+        );
+        assert!(
+            db.begin_transaction()
+                .await
+                .get_value(&FailedAttemptsKey::from_state(state))
+                .await
+                .is_none(),
+            "Failed attempts are reset on retry"
+        );
+    }
+
+    #[tokio::test]
+    #[tracing_test::traced_test]
+    async fn test_executor_failed_attempts_are_persisted() {
+        const MOCK_INSTANCE: ModuleInstanceId = 42;
+
+        let mut task_group = TaskGroup::new();
+        let (executor, _sender, db) = get_executor(&mut task_group).await;
+        let mut dead_letters = executor.notifier().subscribe_dead_letters();
+
+        // Simulate a state that already failed before the executor was restarted
+        let state = DynState::from_typed(MOCK_INSTANCE, MockStateMachine::Panicking);
+        let mut dbtx = db.begin_transaction().await;
+        dbtx.insert_entry(
+            &ActiveStateKey::from_state(state.clone()),
+            &ActiveState::new(),
+        )
+        .await;
+        dbtx.insert_entry(
+            &FailedAttemptsKey::from_state(state.clone()),
+            &FailedAttempts {
+                attempts: 1,
+                retry_at: fedimint_core::time::now() + Duration::from_secs(3),
+            },
+        )
+        .await;
+        dbtx.commit_tx().await;
+
+        assert!(
+            task::timeout(Duration::from_secs(2), dead_letters.next())
+                .await
+                .is_err(),
+            "Persisted backoff is respected"
+        );
+        let (dead_state, dead_letter) = task::timeout(Duration::from_secs(10), dead_letters.next())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(dead_state, state);
+        assert_eq!(dead_letter.attempts, 2, "Persisted attempts are counted");
+        assert!(
+            db.begin_transaction()
+                .await
+                .get_value(&FailedAttemptsKey::from_state(state))
+                .await
+                .is_none(),
+            "Failed attempts are cleaned up"
+        );
     }
 }
@@ -12,7 +12,9 @@ use std::fmt::{Debug, Display, Formatter};
 use std::str::FromStr;
 
 pub use dbtx::ClientSMDatabaseTransaction;
-pub use executor::{ActiveState, Executor, ExecutorBuilder, InactiveState};
+pub use executor::{
+    ActiveState, DeadLetterState, Executor, ExecutorBuilder, InactiveState, RetryPolicy,
+};
 use fedimint_core::encoding::{Decodable, Encodable};
 use fedimint_core::task::{MaybeSend, MaybeSync};
 pub use notifier::{ModuleNotifier, Notifier};
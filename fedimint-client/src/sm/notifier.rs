@@ -5,13 +5,15 @@ use fedimint_core::db::Database;
 use fedimint_core::util::broadcaststream::BroadcastStream;
 use fedimint_core::util::BoxStream;
 use futures::StreamExt;
-use tracing::error;
+use tracing::{error, warn};
 
 use crate::sm::executor::{
     ActiveModuleOperationStateKeyPrefix, ActiveStateKey, InactiveModuleOperationStateKeyPrefix,
     InactiveStateKey,
 };
-use crate::sm::{ActiveState, DynState, GlobalContext, InactiveState, OperationId, State};
+use crate::sm::{
+    ActiveState, DeadLetterState, DynState, GlobalContext, InactiveState, OperationId, State,
+};
 
 /// State transition notifier owned by the modularized client used to inform
 /// modules of state transitions.
@@ -23,6 +25,11 @@ use crate::sm::{ActiveState, DynState, GlobalContext, InactiveState, OperationId
 pub struct Notifier<GC> {
     /// Broadcast channel used to send state transitions to all subscribers
     broadcast: tokio::sync::broadcast::Sender<DynState<GC>>,
+    /// Broadcast channel used to send state transitions including the previous
+    /// state to subscribers of all modules
+    transitions: tokio::sync::broadcast::Sender<(DynState<GC>, DynState<GC>)>,
+    /// Broadcast channel used to send states moved to the dead-letter table
+    dead_letters: tokio::sync::broadcast::Sender<(DynState<GC>, DeadLetterState)>,
     /// Database used to load all states that happened before subscribing
     db: Database,
 }
@@ -30,8 +37,12 @@ pub struct Notifier<GC> {
 impl<GC> Notifier<GC> {
     pub fn new(db: Database) -> Self {
         let (sender, _receiver) = tokio::sync::broadcast::channel(100);
+        let (transitions, _receiver) = tokio::sync::broadcast::channel(100);
+        let (dead_letters, _receiver) = tokio::sync::broadcast::channel(100);
         Self {
             broadcast: sender,
+            transitions,
+            dead_letters,
             db,
         }
     }
@@ -41,6 +52,58 @@ impl<GC> Notifier<GC> {
         let _res = self.broadcast.send(state);
     }
 
+    /// Notify all subscribers of a state transition from `old_state` to
+    /// `new_state`
+    pub fn notify_transition(&self, old_state: DynState<GC>, new_state: DynState<GC>) {
+        self.notify(new_state.clone());
+        let _res = self.transitions.send((old_state, new_state));
+    }
+
+    /// Notify all subscribers that `state` was given up on and moved to the
+    /// dead-letter table
+    pub fn notify_dead_letter(&self, state: DynState<GC>, dead_letter: DeadLetterState) {
+        let _res = self.dead_letters.send((state, dead_letter));
+    }
+
+    /// Subscribe to states of all module instances being moved to the
+    /// dead-letter table. Only returns future events.
+    pub fn subscribe_dead_letters(&self) -> BoxStream<'static, (DynState<GC>, DeadLetterState)>
+    where
+        GC: GlobalContext,
+    {
+        Box::pin(
+            BroadcastStream::new(self.dead_letters.subscribe()).filter_map(|res| async move {
+                match res {
+                    Ok(dead_letter) => Some(dead_letter),
+                    Err(err) => {
+                        warn!(?err, "Dead-letter subscriber lagged, skipping events");
+                        None
+                    }
+                }
+            }),
+        )
+    }
+
+    /// Subscribe to the state transitions of all module instances as pairs of
+    /// old and new state. Unlike [`ModuleNotifier::subscribe`] this only
+    /// returns future transitions.
+    pub fn subscribe_transitions(&self) -> BoxStream<'static, (DynState<GC>, DynState<GC>)>
+    where
+        GC: GlobalContext,
+    {
+        Box::pin(
+            BroadcastStream::new(self.transitions.subscribe()).filter_map(|res| async move {
+                match res {
+                    Ok(transition) => Some(transition),
+                    Err(err) => {
+                        warn!(?err, "Transition subscriber lagged, skipping transitions");
+                        None
+                    }
+                }
+            }),
+        )
+    }
+
     /// Create a new notifier for a specific module instance that can only
     /// subscribe to the instance's state transitions
     pub fn module_notifier<S>(&self, module_instance: ModuleInstanceId) -> ModuleNotifier<GC, S> {
@@ -59,6 +59,7 @@ where
 pub struct TransactionBuilder {
     pub(crate) inputs: Vec<ClientInput>,
     pub(crate) outputs: Vec<ClientOutput>,
+    pub(crate) expiry: Option<u64>,
 }
 
 impl TransactionBuilder {
@@ -76,6 +77,25 @@ impl TransactionBuilder {
         self
     }
 
+    /// Makes the federation reject the transaction from consensus epoch
+    /// `epoch` on. Once that epoch is reached without the transaction being
+    /// accepted its submission is given up on and the inputs are reclaimed.
+    pub fn with_expiry(mut self, epoch: u64) -> Self {
+        self.expiry = Some(epoch);
+        self
+    }
+
+    /// Appends the inputs and outputs of `other`, keeping the earlier of both
+    /// expiries
+    pub(crate) fn extend(&mut self, other: TransactionBuilder) {
+        self.inputs.extend(other.inputs);
+        self.outputs.extend(other.outputs);
+        self.expiry = match (self.expiry, other.expiry) {
+            (Some(expiry), Some(other_expiry)) => Some(expiry.min(other_expiry)),
+            (expiry, other_expiry) => expiry.or(other_expiry),
+        };
+    }
+
     pub fn build<C, R: RngCore + CryptoRng>(
         self,
         secp_ctx: &Secp256k1<C>,
@@ -95,7 +115,7 @@ impl TransactionBuilder {
             .map(|output| (output.output, output.state_machines))
             .unzip();
 
-        let txid = Transaction::tx_hash_from_parts(&inputs, &outputs);
+        let txid = Transaction::tx_hash_from_parts(&inputs, &outputs, self.expiry);
 
         let signature = if !input_keys.is_empty() {
             let keys = input_keys.into_iter().flatten().collect::<Vec<_>>();
@@ -110,6 +130,7 @@ impl TransactionBuilder {
         let transaction = Transaction {
             inputs,
             outputs,
+            expiry: self.expiry,
             signature,
         };
 
@@ -1,5 +1,7 @@
 mod builder;
+mod preview;
 mod sm;
 
 pub use builder::*;
+pub use preview::*;
 pub use sm::*;
@@ -0,0 +1,90 @@
+use std::collections::BTreeMap;
+
+use fedimint_core::core::{DynInput, DynOutput, ModuleInstanceId};
+use fedimint_core::module::TransactionItemAmount;
+use fedimint_core::Amount;
+
+/// Breakdown of a transaction as it would be submitted, returned by
+/// [`Client::preview_transaction`](crate::Client::preview_transaction)
+#[derive(Debug, Clone)]
+pub struct TransactionPreview {
+    /// All inputs, including the ones the primary module added to fund the
+    /// transaction
+    pub inputs: Vec<InputPreview>,
+    /// All outputs, including the change output
+    pub outputs: Vec<OutputPreview>,
+    /// Index of the change output in `outputs`, if the transaction was
+    /// overfunded
+    pub change_idx: Option<usize>,
+}
+
+/// An input of a [`TransactionPreview`]
+#[derive(Debug, Clone)]
+pub struct InputPreview {
+    pub input: DynInput,
+    pub amount: TransactionItemAmount,
+    /// `true` if the primary module added the input to fund the transaction,
+    /// e.g. e-cash notes that would be spent
+    pub is_funding: bool,
+}
+
+/// An output of a [`TransactionPreview`]
+#[derive(Debug, Clone)]
+pub struct OutputPreview {
+    pub output: DynOutput,
+    pub amount: TransactionItemAmount,
+}
+
+impl TransactionPreview {
+    /// Inputs added by the primary module to fund the transaction
+    pub fn funding_inputs(&self) -> impl Iterator<Item = &InputPreview> {
+        self.inputs.iter().filter(|input| input.is_funding)
+    }
+
+    /// Amount returned to the primary module as change
+    pub fn change(&self) -> Amount {
+        self.change_idx
+            .map(|idx| self.outputs[idx].amount.amount)
+            .unwrap_or(Amount::ZERO)
+    }
+
+    /// Fees charged by each module instance for processing its inputs and
+    /// outputs
+    pub fn fees_by_module(&self) -> BTreeMap<ModuleInstanceId, Amount> {
+        let mut fees = BTreeMap::new();
+        let items = self
+            .inputs
+            .iter()
+            .map(|input| (input.input.module_instance_id(), input.amount.fee))
+            .chain(
+                self.outputs
+                    .iter()
+                    .map(|output| (output.output.module_instance_id(), output.amount.fee)),
+            );
+        for (module_instance_id, fee) in items {
+            *fees.entry(module_instance_id).or_insert(Amount::ZERO) += fee;
+        }
+        fees
+    }
+
+    /// Sum of all fees charged by the federation
+    pub fn total_fee(&self) -> Amount {
+        self.fees_by_module().into_values().sum()
+    }
+
+    /// Sum of all inputs
+    pub fn total_input(&self) -> Amount {
+        self.inputs.iter().map(|input| input.amount.amount).sum()
+    }
+
+    /// Sum of all outputs, including change
+    pub fn total_output(&self) -> Amount {
+        self.outputs.iter().map(|output| output.amount.amount).sum()
+    }
+
+    /// Sum of all outputs except change plus all fees, i.e. what the
+    /// transaction costs
+    pub fn total_cost(&self) -> Amount {
+        self.total_output() - self.change() + self.total_fee()
+    }
+}
@@ -13,6 +13,8 @@ use serde::{Deserialize, Serialize};
 use thiserror::Error;
 use tracing::warn;
 
+use crate::event::StateSummary;
+use crate::policy::release_transaction_spends;
 use crate::sm::{Context, DynContext, OperationId, OperationState, State, StateTransition};
 use crate::{DynGlobalClientContext, DynState};
 
@@ -28,6 +30,10 @@ const RESUBMISSION_INTERVAL: Duration = Duration::from_secs(5);
 /// Every how many seconds the transaction status is checked
 const FETCH_INTERVAL: Duration = Duration::from_secs(1);
 
+/// Every how many seconds the epoch count is checked for transactions with an
+/// expiry
+const EXPIRY_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
 #[derive(Debug, Clone)]
 pub struct TxSubmissionContext;
 
@@ -53,6 +59,7 @@ impl IntoDynInstance for TxSubmissionContext {
 ///     Created -- await consensus --> Rejected
 ///     Created -- Periodically submit --> Created
 ///     Created -- Error on submit --> Rejected
+///     Created -- expiry epoch reached --> Expired
 /// ```
 #[derive(Debug, Clone, Eq, PartialEq, Decodable, Encodable)]
 pub enum TxSubmissionStates {
@@ -82,6 +89,11 @@ pub enum TxSubmissionStates {
         txid: TransactionId,
         error: TxSubmissionError,
     },
+    /// The federation reached the transaction's expiry epoch without accepting
+    /// it, so it can't be accepted anymore and its inputs can be reclaimed
+    ///
+    /// **This state is final**
+    Expired { txid: TransactionId, expiry: u64 },
 }
 
 #[derive(Debug, Error, Clone, Eq, PartialEq, Serialize, Deserialize, Decodable, Encodable)]
@@ -90,6 +102,24 @@ pub enum TxSubmissionError {
     SubmitRejected(String),
     #[error("Tx rejected by consensus: {0}")]
     ConsensusRejected(String),
+    #[error("Tx expired at epoch {0}")]
+    Expired(u64),
+}
+
+impl TxSubmissionStates {
+    /// Summarizes the state for [`crate::Client::subscribe_events`]
+    pub fn summary(&self) -> StateSummary {
+        match self {
+            TxSubmissionStates::Created { .. } => {
+                StateSummary::in_progress("Waiting for the transaction to be accepted")
+            }
+            TxSubmissionStates::Accepted { .. } => StateSummary::Succeeded,
+            TxSubmissionStates::Rejected { error, .. } => StateSummary::failed(error),
+            TxSubmissionStates::Expired { expiry, .. } => {
+                StateSummary::failed(TxSubmissionError::Expired(*expiry))
+            }
+        }
+    }
 }
 
 impl State for TxSubmissionStates {
@@ -108,14 +138,14 @@ impl State for TxSubmissionStates {
                 next_submission,
             } => {
                 let txid = *txid;
-                vec![
+                let mut transitions = vec![
                     StateTransition::new(
                         trigger_created_submit(
                             tx.clone(),
                             *next_submission,
                             global_context.clone(),
                         ),
-                        |_dbtx, res, state| {
+                        |dbtx, res, state| {
                             Box::pin(async move {
                                 let TxSubmissionStates::Created {
                                     txid,
@@ -131,29 +161,49 @@ impl State for TxSubmissionStates {
                                         tx,
                                         next_submission: next_submission + RESUBMISSION_INTERVAL,
                                     },
-                                    Err(error) => TxSubmissionStates::Rejected {
-                                        txid,
-                                        error: TxSubmissionError::SubmitRejected(error),
-                                    },
+                                    Err(error) => {
+                                        release_transaction_spends(dbtx.global_tx(), txid).await;
+                                        TxSubmissionStates::Rejected {
+                                            txid,
+                                            error: TxSubmissionError::SubmitRejected(error),
+                                        }
+                                    }
                                 }
                             })
                         },
                     ),
                     StateTransition::new(
                         trigger_created_accepted(tx.tx_hash(), global_context.clone()),
-                        move |_dbtx, res, _state| {
+                        move |dbtx, res, _state| {
                             Box::pin(async move {
                                 match res {
                                     Ok(_epoch) => TxSubmissionStates::Accepted { txid },
-                                    Err(error) => TxSubmissionStates::Rejected {
-                                        txid,
-                                        error: TxSubmissionError::ConsensusRejected(error),
-                                    },
+                                    Err(error) => {
+                                        release_transaction_spends(dbtx.global_tx(), txid).await;
+                                        TxSubmissionStates::Rejected {
+                                            txid,
+                                            error: TxSubmissionError::ConsensusRejected(error),
+                                        }
+                                    }
                                 }
                             })
                         },
                     ),
-                ]
+                ];
+
+                if let Some(expiry) = tx.expiry {
+                    transitions.push(StateTransition::new(
+                        trigger_created_expired(txid, expiry, global_context.clone()),
+                        move |dbtx, (), _state| {
+                            Box::pin(async move {
+                                release_transaction_spends(dbtx.global_tx(), txid).await;
+                                TxSubmissionStates::Expired { txid, expiry }
+                            })
+                        },
+                    ));
+                }
+
+                transitions
             }
             TxSubmissionStates::Accepted { .. } => {
                 vec![]
@@ -161,6 +211,9 @@ impl State for TxSubmissionStates {
             TxSubmissionStates::Rejected { .. } => {
                 vec![]
             }
+            TxSubmissionStates::Expired { .. } => {
+                vec![]
+            }
         }
     }
 
@@ -189,6 +242,13 @@ async fn trigger_created_submit(
     )
     .await;
 
+    // Submitting would only fail, leave it to the expiry trigger
+    if let Some(expiry) = tx.expiry {
+        if is_epoch_reached(expiry, &context).await {
+            std::future::pending::<()>().await;
+        }
+    }
+
     context
         .api()
         .submit_transaction(tx)
@@ -216,6 +276,39 @@ async fn trigger_created_accepted(
     }
 }
 
+async fn trigger_created_expired(
+    txid: TransactionId,
+    expiry: u64,
+    context: DynGlobalClientContext,
+) {
+    loop {
+        // The transaction may still have been accepted in the epoch before
+        // expiry, in which case the accepted trigger takes care of it
+        if is_epoch_reached(expiry, &context).await {
+            match context.api().fetch_tx_outcome(&txid).await {
+                Ok(None) => return,
+                Ok(Some(_)) => std::future::pending::<()>().await,
+                Err(error) => {
+                    warn!(target: LOG_TARGET, ?error, "Failed to fetch transaction outcome");
+                }
+            }
+        }
+        task::sleep(EXPIRY_CHECK_INTERVAL).await;
+    }
+}
+
+/// Returns `true` if the federation is past `epoch`, i.e. no transaction can be
+/// included in it anymore
+async fn is_epoch_reached(epoch: u64, context: &DynGlobalClientContext) -> bool {
+    match context.api().fetch_epoch_count().await {
+        Ok(epoch_count) => epoch_count >= epoch,
+        Err(error) => {
+            warn!(target: LOG_TARGET, ?error, "Failed to fetch epoch count");
+            false
+        }
+    }
+}
+
 pub fn tx_submission_sm_decoder() -> Decoder {
     let mut decoder_builder = Decoder::builder();
     decoder_builder.with_decodable_type::<OperationState<TxSubmissionStates>>();
@@ -242,16 +335,17 @@ mod tests {
     use fedimint_core::task::{sleep, TaskGroup};
     use fedimint_core::transaction::SerdeTransaction;
     use fedimint_core::util::BoxStream;
-    use fedimint_core::{maybe_add_send_sync, OutPoint, PeerId, TransactionId};
+    use fedimint_core::{maybe_add_send_sync, Amount, OutPoint, PeerId, TransactionId};
     use rand::thread_rng;
     use serde_json::Value;
     use tokio::sync::Mutex;
     use tokio::time::timeout;
 
+    use crate::db::{OperationSpendKey, TransactionSpendKey};
     use crate::sm::{ClientSMDatabaseTransaction, Executor, Notifier, OperationId, OperationState};
     use crate::transaction::{
-        tx_submission_sm_decoder, TransactionBuilder, TxSubmissionContext, TxSubmissionStates,
-        TRANSACTION_SUBMISSION_MODULE_INSTANCE,
+        tx_submission_sm_decoder, TransactionBuilder, TxSubmissionContext, TxSubmissionError,
+        TxSubmissionStates, TRANSACTION_SUBMISSION_MODULE_INSTANCE,
     };
     use crate::{
         DynGlobalClientContext, IGlobalClientContext, IState, InstancelessDynClientInput,
@@ -262,6 +356,7 @@ mod tests {
     struct FakeApiClient {
         txns: Arc<Mutex<Vec<TransactionId>>>,
         fake_peers: BTreeSet<PeerId>,
+        epoch_count: u64,
     }
 
     impl Default for FakeApiClient {
@@ -269,6 +364,7 @@ mod tests {
             FakeApiClient {
                 txns: Arc::new(Mutex::new(vec![])),
                 fake_peers: vec![PeerId::from(0)].into_iter().collect(),
+                epoch_count: 0,
             }
         }
     }
@@ -324,6 +420,20 @@ mod tests {
                     };
                     Ok(serde_json::to_value(outcome).unwrap())
                 }
+                "fetch_transaction" => {
+                    let api_req: ApiRequestErased =
+                        serde_json::from_value(params[0].clone()).unwrap();
+                    let txid: TransactionId = serde_json::from_value(api_req.params).unwrap();
+
+                    let outcome = self.txns.lock().await.contains(&txid).then(|| {
+                        fedimint_core::outcome::TransactionStatus::Accepted {
+                            epoch: 0,
+                            outputs: vec![],
+                        }
+                    });
+                    Ok(serde_json::to_value(outcome).unwrap())
+                }
+                "fetch_epoch_count" => Ok(serde_json::to_value(self.epoch_count).unwrap()),
                 _ => unimplemented!(),
             }
         }
@@ -413,6 +523,14 @@ mod tests {
             unimplemented!()
         }
 
+        async fn enforce_out_of_band_spend(
+            &self,
+            _dbtx: &mut ClientSMDatabaseTransaction<'_, '_>,
+            _amount: Amount,
+        ) -> anyhow::Result<()> {
+            unimplemented!()
+        }
+
         async fn transaction_update_stream(
             &self,
             operation_id: OperationId,
@@ -427,9 +545,10 @@ mod tests {
         }
     }
 
-    #[tokio::test]
-    #[tracing_test::traced_test]
-    async fn test_submission() {
+    async fn setup(
+        fake_api: FakeApiClient,
+        tg: &mut TaskGroup,
+    ) -> (Database, Arc<FakeGlobalContext>, DynGlobalClientContext) {
         let db = Database::new(
             MemDatabase::new(),
             ModuleDecoderRegistry::new([(
@@ -439,15 +558,12 @@ mod tests {
             )]),
         );
 
-        let mut tg = TaskGroup::new();
-
         let mut executor_builder = Executor::<DynGlobalClientContext>::builder();
         executor_builder.with_module(TRANSACTION_SUBMISSION_MODULE_INSTANCE, TxSubmissionContext);
         let executor = executor_builder
             .build(db.clone(), Notifier::new(db.clone()))
             .await;
 
-        let fake_api = FakeApiClient::default();
         let context = Arc::new(FakeGlobalContext {
             api: fake_api.clone(),
             dyn_api: DynGlobalApi::from(fake_api),
@@ -456,9 +572,18 @@ mod tests {
         let dyn_context = DynGlobalClientContext::from(context.clone());
         let dyn_context_gen_clone = dyn_context.clone();
         executor
-            .start_executor(&mut tg, Arc::new(move |_, _| dyn_context_gen_clone.clone()))
+            .start_executor(tg, Arc::new(move |_, _| dyn_context_gen_clone.clone()))
             .await;
 
+        (db, context, dyn_context)
+    }
+
+    #[tokio::test]
+    #[tracing_test::traced_test]
+    async fn test_submission() {
+        let mut tg = TaskGroup::new();
+        let (db, context, dyn_context) = setup(FakeApiClient::default(), &mut tg).await;
+
         let operation_id = OperationId([0x42; 32]);
 
         let tx_builder = TransactionBuilder::new();
@@ -486,4 +611,58 @@ mod tests {
             "Transaction wasn't submitted as expected"
         );
     }
+
+    #[tokio::test]
+    #[tracing_test::traced_test]
+    async fn test_expiry() {
+        let mut tg = TaskGroup::new();
+        let fake_api = FakeApiClient {
+            epoch_count: 10,
+            ..Default::default()
+        };
+        let (db, context, dyn_context) = setup(fake_api, &mut tg).await;
+
+        let operation_id = OperationId([0x42; 32]);
+
+        let tx_builder = TransactionBuilder::new().with_expiry(5);
+
+        let mut dbtx = db.begin_transaction().await;
+        let mut client_tx = ClientSMDatabaseTransaction::new(&mut dbtx, 0);
+        let txid = context
+            .finalize_and_submit_transaction(&mut client_tx, operation_id, tx_builder)
+            .await
+            .unwrap();
+        dbtx.insert_entry(&OperationSpendKey { operation_id }, &Amount::from_sats(1))
+            .await;
+        dbtx.insert_entry(
+            &TransactionSpendKey { txid, operation_id },
+            &Amount::from_sats(1),
+        )
+        .await;
+        dbtx.commit_tx().await;
+
+        let result = timeout(Duration::from_secs(10), async move {
+            dyn_context.await_tx_accepted(operation_id, txid).await
+        })
+        .await
+        .unwrap();
+
+        assert_eq!(result, Err(TxSubmissionError::Expired(5)));
+        assert!(
+            context.api.txns.lock().await.is_empty(),
+            "Expired transaction was submitted"
+        );
+
+        // The expired transaction doesn't count towards spending limits anymore
+        let mut dbtx = db.begin_transaction().await;
+        assert_eq!(
+            dbtx.get_value(&OperationSpendKey { operation_id }).await,
+            None
+        );
+        assert_eq!(
+            dbtx.get_value(&TransactionSpendKey { txid, operation_id })
+                .await,
+            None
+        );
+    }
 }
@@ -0,0 +1,591 @@
+use std::fmt::Debug;
+use std::ops::Range;
+
+use anyhow::{bail, Context, Result};
+use bitcoin_hashes::{sha256, Hash, HashEngine, Hmac, HmacEngine};
+use fedimint_aead::{decrypt, decrypt_with_aad, encrypt, encrypt_with_aad, LessSafeKey};
+use futures::{stream, StreamExt};
+use macro_rules_attribute::apply;
+
+use super::{IDatabase, ISingleUseDatabaseTransaction, PrefixStream};
+use crate::async_trait_maybe_send;
+use crate::encoding::{Decodable, Encodable};
+use crate::module::registry::ModuleDecoderRegistry;
+
+/// Raw key under which the salt and key check value are stored unencrypted
+const METADATA_KEY: &[u8] = &[0x00];
+
+/// Prefix under which all encrypted entries are stored, keeps them from
+/// colliding with [`METADATA_KEY`]
+const ENCRYPTED_DATA_PREFIX: u8 = 0x01;
+
+/// Known plaintext that is encrypted with the value key to detect a wrong
+/// passphrase when opening the database
+const KEY_CHECK_PLAINTEXT: &[u8] = b"fedimint-encrypted-database";
+
+#[derive(Debug, Encodable, Decodable)]
+struct EncryptionMetadata {
+    salt: String,
+    key_check: Vec<u8>,
+}
+
+/// Keys derived from the passphrase, one for authenticated encryption of
+/// values and one for the deterministic encryption of database keys.
+struct EncryptionKeys {
+    salt: String,
+    value_key: LessSafeKey,
+    key_prf_key: [u8; 32],
+}
+
+impl Debug for EncryptionKeys {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("EncryptionKeys")
+            .field("salt", &self.salt)
+            .finish_non_exhaustive()
+    }
+}
+
+impl EncryptionKeys {
+    fn derive(passphrase: &str, salt: String) -> Result<EncryptionKeys> {
+        let master_key = fedimint_aead::get_encryption_key_bytes(passphrase, &salt)?;
+        let value_key = fedimint_aead::encryption_key_from_bytes(&Self::sub_key(
+            &master_key,
+            b"value-encryption",
+        ))?;
+        let key_prf_key = Self::sub_key(&master_key, b"key-encryption");
+
+        Ok(EncryptionKeys {
+            salt,
+            value_key,
+            key_prf_key,
+        })
+    }
+
+    fn sub_key(master_key: &[u8], tag: &[u8]) -> [u8; 32] {
+        let mut engine = HmacEngine::<sha256::Hash>::new(master_key);
+        engine.input(tag);
+        Hmac::from_engine(engine).into_inner()
+    }
+
+    fn metadata(&self) -> Result<EncryptionMetadata> {
+        Ok(EncryptionMetadata {
+            salt: self.salt.clone(),
+            key_check: encrypt(KEY_CHECK_PLAINTEXT.to_vec(), &self.value_key)?,
+        })
+    }
+
+    fn verify(&self, metadata: &EncryptionMetadata) -> Result<()> {
+        let mut key_check = metadata.key_check.clone();
+        match decrypt(&mut key_check, &self.value_key) {
+            Ok(plaintext) if plaintext == KEY_CHECK_PLAINTEXT => Ok(()),
+            _ => bail!("Wrong passphrase for encrypted database"),
+        }
+    }
+
+    /// Encrypts a database key byte by byte, mapping every byte through a
+    /// keyed pseudorandom permutation of all byte values that is seeded by the
+    /// plaintext bytes preceding it. This is deterministic and
+    /// prefix-preserving, so the encryption of a key prefix is a prefix of the
+    /// encryption of every key starting with it, which keeps prefix scans
+    /// working.
+    ///
+    /// In exchange it leaks the length of keys, which keys are equal and how
+    /// long the common prefix of any two keys is. Bytes following the common
+    /// prefix are mapped through the same permutation, so it also leaks that
+    /// they differ, but nothing about how they differ.
+    fn encrypt_key(&self, key: &[u8]) -> Vec<u8> {
+        let mut engine = HmacEngine::<sha256::Hash>::new(&self.key_prf_key);
+        let mut encrypted_key = Vec::with_capacity(key.len() + 1);
+        encrypted_key.push(ENCRYPTED_DATA_PREFIX);
+        for byte in key {
+            encrypted_key.push(Self::byte_permutation(&engine)[usize::from(*byte)]);
+            engine.input(&[*byte]);
+        }
+        encrypted_key
+    }
+
+    fn decrypt_key(&self, encrypted_key: &[u8]) -> Result<Vec<u8>> {
+        let Some((&ENCRYPTED_DATA_PREFIX, encrypted_key)) = encrypted_key.split_first() else {
+            bail!("Encrypted database key has an invalid prefix");
+        };
+
+        let mut engine = HmacEngine::<sha256::Hash>::new(&self.key_prf_key);
+        let mut key = Vec::with_capacity(encrypted_key.len());
+        for encrypted_byte in encrypted_key {
+            let byte = Self::byte_permutation(&engine)
+                .iter()
+                .position(|permuted| permuted == encrypted_byte)
+                .expect("Permutation contains all byte values") as u8;
+            key.push(byte);
+            engine.input(&[byte]);
+        }
+        Ok(key)
+    }
+
+    /// Derives a permutation of all byte values from the key PRF state
+    /// `prefix_engine`, which has already absorbed the preceding key bytes,
+    /// using a Fisher-Yates shuffle driven by a keystream hashed from the
+    /// secret PRF output
+    fn byte_permutation(prefix_engine: &HmacEngine<sha256::Hash>) -> [u8; 256] {
+        let seed = Hmac::from_engine(prefix_engine.clone()).into_inner();
+        let mut keystream = (0u64..).flat_map(|block| {
+            let mut engine = sha256::Hash::engine();
+            engine.input(&seed);
+            engine.input(&block.to_be_bytes());
+            sha256::Hash::from_engine(engine).into_inner()
+        });
+
+        let mut permutation = [0u8; 256];
+        for (idx, value) in permutation.iter_mut().enumerate() {
+            *value = idx as u8;
+        }
+        for idx in (1..permutation.len()).rev() {
+            // Rejection sampling keeps the choice of the swap index unbiased
+            let bound = idx + 1;
+            let limit = 256 - 256 % bound;
+            let swap_idx = loop {
+                let random = usize::from(keystream.next().expect("Keystream is infinite"));
+                if random < limit {
+                    break random % bound;
+                }
+            };
+            permutation.swap(idx, swap_idx);
+        }
+        permutation
+    }
+
+    /// Encrypts the value stored under `encrypted_key`, which is authenticated
+    /// along with it so values can't be moved to other keys unnoticed
+    fn encrypt_value(&self, encrypted_key: &[u8], value: &[u8]) -> Result<Vec<u8>> {
+        encrypt_with_aad(value.to_vec(), &self.value_key, encrypted_key)
+    }
+
+    fn decrypt_value(&self, encrypted_key: &[u8], mut encrypted_value: Vec<u8>) -> Result<Vec<u8>> {
+        Ok(
+            decrypt_with_aad(&mut encrypted_value, &self.value_key, encrypted_key)
+                .context("Could not decrypt database value, it may have been tampered with")?
+                .to_vec(),
+        )
+    }
+
+    fn decrypt_entry(&self, (key, value): (Vec<u8>, Vec<u8>)) -> Result<(Vec<u8>, Vec<u8>)> {
+        let decrypted_value = self.decrypt_value(&key, value)?;
+        Ok((self.decrypt_key(&key)?, decrypted_value))
+    }
+
+    /// Decrypts all `entries`, failing if any of them can't be decrypted
+    async fn decrypt_entries(&self, entries: PrefixStream<'_>) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        entries
+            .map(|entry| self.decrypt_entry(entry))
+            .collect::<Vec<_>>()
+            .await
+            .into_iter()
+            .collect()
+    }
+}
+
+/// [`IDatabase`] wrapper that transparently encrypts all data written to the
+/// underlying database with keys derived from a passphrase.
+///
+/// Values are encrypted with ChaCha20-Poly1305 using a random nonce and
+/// authenticated together with their encrypted key, so they can't be moved
+/// between keys without failing to decrypt. Keys are encrypted
+/// deterministically so that point lookups and prefix scans map directly onto
+/// the underlying database. Since that encryption does not preserve the
+/// ordering of keys, sorted and range queries have to decrypt and sort all
+/// entries under the longest common prefix of their bounds in memory.
+#[derive(Debug)]
+pub struct EncryptedDatabase<D: IDatabase> {
+    inner: D,
+    keys: EncryptionKeys,
+}
+
+impl<D: IDatabase> EncryptedDatabase<D> {
+    /// Opens an encrypted database on top of `inner`. If `inner` was not
+    /// encrypted before a new random salt is generated and stored in it,
+    /// otherwise `passphrase` is checked against the stored key check value.
+    pub async fn new(inner: D, passphrase: &str) -> Result<EncryptedDatabase<D>> {
+        let mut dbtx = inner.begin_transaction().await;
+        let keys = match dbtx.raw_get_bytes(METADATA_KEY).await? {
+            Some(metadata_bytes) => {
+                let metadata = EncryptionMetadata::consensus_decode(
+                    &mut std::io::Cursor::new(metadata_bytes),
+                    &ModuleDecoderRegistry::default(),
+                )
+                .context("Could not decode encrypted database metadata")?;
+                let keys = EncryptionKeys::derive(passphrase, metadata.salt.clone())?;
+                keys.verify(&metadata)?;
+                keys
+            }
+            None => {
+                let keys = EncryptionKeys::derive(passphrase, fedimint_aead::random_salt())?;
+                dbtx.raw_insert_bytes(METADATA_KEY, &keys.metadata()?.consensus_encode_to_vec()?)
+                    .await?;
+                dbtx.commit_tx().await?;
+                keys
+            }
+        };
+        drop(dbtx);
+
+        Ok(EncryptedDatabase { inner, keys })
+    }
+
+    pub fn inner(&self) -> &D {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> D {
+        self.inner
+    }
+
+    /// Re-encrypts all entries under a new passphrase and a fresh salt in a
+    /// single transaction of the underlying database. All entries are held in
+    /// memory while doing so.
+    pub async fn rekey(&mut self, new_passphrase: &str) -> Result<()> {
+        let new_keys = EncryptionKeys::derive(new_passphrase, fedimint_aead::random_salt())?;
+
+        let mut dbtx = self.inner.begin_transaction().await;
+        let entries = dbtx
+            .raw_find_by_prefix(&[ENCRYPTED_DATA_PREFIX])
+            .await?
+            .collect::<Vec<_>>()
+            .await;
+        dbtx.raw_remove_by_prefix(&[ENCRYPTED_DATA_PREFIX]).await?;
+
+        for entry in entries {
+            let (key, value) = self.keys.decrypt_entry(entry)?;
+            let new_encrypted_key = new_keys.encrypt_key(&key);
+            dbtx.raw_insert_bytes(
+                &new_encrypted_key,
+                &new_keys.encrypt_value(&new_encrypted_key, &value)?,
+            )
+            .await?;
+        }
+
+        dbtx.raw_remove_entry(METADATA_KEY).await?;
+        dbtx.raw_insert_bytes(
+            METADATA_KEY,
+            &new_keys.metadata()?.consensus_encode_to_vec()?,
+        )
+        .await?;
+        dbtx.commit_tx().await?;
+        drop(dbtx);
+
+        self.keys = new_keys;
+        Ok(())
+    }
+}
+
+#[apply(async_trait_maybe_send!)]
+impl<D: IDatabase> IDatabase for EncryptedDatabase<D> {
+    async fn begin_transaction<'a>(&'a self) -> Box<dyn ISingleUseDatabaseTransaction<'a>> {
+        Box::new(EncryptedTransaction {
+            dbtx: self.inner.begin_transaction().await,
+            keys: &self.keys,
+        })
+    }
+}
+
+/// Transaction of an [`EncryptedDatabase`], encrypts keys and values before
+/// passing them to the transaction of the underlying database
+struct EncryptedTransaction<'a> {
+    dbtx: Box<dyn ISingleUseDatabaseTransaction<'a>>,
+    keys: &'a EncryptionKeys,
+}
+
+impl<'a> EncryptedTransaction<'a> {
+    /// Decrypts all entries with keys starting with the longest common prefix
+    /// of the range bounds and returns the ones inside the range, sorted
+    /// ascending by key.
+    async fn find_by_range_sorted(
+        &mut self,
+        key_range: Range<&[u8]>,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let common_prefix_len = key_range
+            .start
+            .iter()
+            .zip(key_range.end)
+            .take_while(|(start, end)| start == end)
+            .count();
+        let mut entries = self
+            .find_by_prefix_sorted(&key_range.start[..common_prefix_len])
+            .await?;
+        entries
+            .retain(|(key, _)| key_range.start <= key.as_slice() && key.as_slice() < key_range.end);
+        Ok(entries)
+    }
+
+    async fn find_by_prefix_sorted(
+        &mut self,
+        key_prefix: &[u8],
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let keys = self.keys;
+        let encrypted_entries = self
+            .dbtx
+            .raw_find_by_prefix(&keys.encrypt_key(key_prefix))
+            .await?;
+        let mut entries = keys.decrypt_entries(encrypted_entries).await?;
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+        Ok(entries)
+    }
+}
+
+#[apply(async_trait_maybe_send!)]
+impl<'a> ISingleUseDatabaseTransaction<'a> for EncryptedTransaction<'a> {
+    async fn raw_insert_bytes(&mut self, key: &[u8], value: &[u8]) -> Result<Option<Vec<u8>>> {
+        let encrypted_key = self.keys.encrypt_key(key);
+        let encrypted_value = self.keys.encrypt_value(&encrypted_key, value)?;
+        self.dbtx
+            .raw_insert_bytes(&encrypted_key, &encrypted_value)
+            .await?
+            .map(|old_value| self.keys.decrypt_value(&encrypted_key, old_value))
+            .transpose()
+    }
+
+    async fn raw_get_bytes(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        let encrypted_key = self.keys.encrypt_key(key);
+        self.dbtx
+            .raw_get_bytes(&encrypted_key)
+            .await?
+            .map(|value| self.keys.decrypt_value(&encrypted_key, value))
+            .transpose()
+    }
+
+    async fn raw_remove_entry(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        let encrypted_key = self.keys.encrypt_key(key);
+        self.dbtx
+            .raw_remove_entry(&encrypted_key)
+            .await?
+            .map(|value| self.keys.decrypt_value(&encrypted_key, value))
+            .transpose()
+    }
+
+    /// Entries are decrypted before the stream is returned, so tampered
+    /// entries fail the whole query instead of ending the stream early
+    async fn raw_find_by_prefix(&mut self, key_prefix: &[u8]) -> Result<PrefixStream<'_>> {
+        let keys = self.keys;
+        let encrypted_entries = self
+            .dbtx
+            .raw_find_by_prefix(&keys.encrypt_key(key_prefix))
+            .await?;
+        let entries = keys.decrypt_entries(encrypted_entries).await?;
+        Ok(Box::pin(stream::iter(entries)))
+    }
+
+    async fn raw_find_by_prefix_sorted_descending(
+        &mut self,
+        key_prefix: &[u8],
+    ) -> Result<PrefixStream<'_>> {
+        let mut entries = self.find_by_prefix_sorted(key_prefix).await?;
+        entries.reverse();
+        Ok(Box::pin(stream::iter(entries)))
+    }
+
+    async fn raw_find_by_range(&mut self, key_range: Range<&[u8]>) -> Result<PrefixStream<'_>> {
+        let entries = self.find_by_range_sorted(key_range).await?;
+        Ok(Box::pin(stream::iter(entries)))
+    }
+
+    async fn raw_find_by_range_sorted_descending(
+        &mut self,
+        key_range: Range<&[u8]>,
+    ) -> Result<PrefixStream<'_>> {
+        let mut entries = self.find_by_range_sorted(key_range).await?;
+        entries.reverse();
+        Ok(Box::pin(stream::iter(entries)))
+    }
+
+    async fn raw_remove_by_prefix(&mut self, key_prefix: &[u8]) -> Result<()> {
+        self.dbtx
+            .raw_remove_by_prefix(&self.keys.encrypt_key(key_prefix))
+            .await
+    }
+
+    async fn commit_tx(&mut self) -> Result<()> {
+        self.dbtx.commit_tx().await
+    }
+
+    async fn rollback_tx_to_savepoint(&mut self) -> Result<()> {
+        self.dbtx.rollback_tx_to_savepoint().await
+    }
+
+    async fn set_tx_savepoint(&mut self) -> Result<()> {
+        self.dbtx.set_tx_savepoint().await
+    }
+
+    fn add_notification_key(&mut self, key: &[u8]) -> Result<()> {
+        self.dbtx.add_notification_key(key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::StreamExt;
+
+    use super::{EncryptedDatabase, EncryptionKeys, ENCRYPTED_DATA_PREFIX};
+    use crate::db::mem_impl::MemDatabase;
+    use crate::db::{Database, IDatabase};
+    use crate::module::registry::ModuleDecoderRegistry;
+
+    async fn database() -> Database {
+        Database::new(
+            EncryptedDatabase::new(MemDatabase::new(), "passphrase")
+                .await
+                .unwrap(),
+            ModuleDecoderRegistry::default(),
+        )
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_dbtx_insert_elements() {
+        fedimint_core::db::verify_insert_elements(database().await).await;
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_dbtx_remove_existing() {
+        fedimint_core::db::verify_remove_existing(database().await).await;
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_dbtx_read_own_writes() {
+        fedimint_core::db::verify_read_own_writes(database().await).await;
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_dbtx_find_by_prefix() {
+        fedimint_core::db::verify_find_by_prefix(database().await).await;
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_dbtx_find_by_range() {
+        fedimint_core::db::verify_find_by_range(database().await).await;
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_dbtx_rollback_to_savepoint() {
+        fedimint_core::db::verify_rollback_to_savepoint(database().await).await;
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_dbtx_remove_by_prefix() {
+        fedimint_core::db::verify_remove_by_prefix(database().await).await;
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_module_dbtx() {
+        fedimint_core::db::verify_module_prefix(database().await).await;
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_wrong_passphrase() {
+        let encrypted = EncryptedDatabase::new(MemDatabase::new(), "right")
+            .await
+            .unwrap();
+
+        assert!(EncryptedDatabase::new(encrypted.into_inner(), "wrong")
+            .await
+            .is_err());
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_rekey() {
+        let mut encrypted = EncryptedDatabase::new(MemDatabase::new(), "old")
+            .await
+            .unwrap();
+
+        let mut dbtx = encrypted.begin_transaction().await;
+        dbtx.raw_insert_bytes(b"secret-key", b"secret-value")
+            .await
+            .unwrap();
+        dbtx.commit_tx().await.unwrap();
+        drop(dbtx);
+
+        // Neither keys nor values are stored in plaintext
+        let mut inner_dbtx = encrypted.inner().begin_transaction().await;
+        let raw_entries = inner_dbtx
+            .raw_find_by_prefix(&[])
+            .await
+            .unwrap()
+            .collect::<Vec<_>>()
+            .await;
+        assert_eq!(raw_entries.len(), 2);
+        for (key, value) in raw_entries {
+            assert_ne!(key, b"secret-key".to_vec());
+            assert!(!value.windows(12).any(|window| window == b"secret-value"));
+        }
+        drop(inner_dbtx);
+
+        encrypted.rekey("new").await.unwrap();
+
+        let reopened = EncryptedDatabase::new(encrypted.into_inner(), "new")
+            .await
+            .unwrap();
+        let mut dbtx = reopened.begin_transaction().await;
+        assert_eq!(
+            dbtx.raw_get_bytes(b"secret-key").await.unwrap(),
+            Some(b"secret-value".to_vec())
+        );
+        drop(dbtx);
+
+        assert!(EncryptedDatabase::new(reopened.into_inner(), "old")
+            .await
+            .is_err());
+    }
+
+    #[test]
+    fn test_key_encryption_hides_byte_differences() {
+        let keys = EncryptionKeys::derive("passphrase", fedimint_aead::random_salt()).unwrap();
+
+        // Sequential counters only differ in their last byte, a XOR pad would
+        // reveal it as the XOR of the ciphertexts
+        let encrypted_first = keys.encrypt_key(&0u64.to_be_bytes());
+        let leaking_pairs = (1u64..256)
+            .filter(|counter| {
+                let encrypted = keys.encrypt_key(&counter.to_be_bytes());
+                assert_eq!(encrypted[..8], encrypted_first[..8]);
+                u64::from(encrypted[8] ^ encrypted_first[8]) == *counter
+            })
+            .count();
+        // A random permutation matches the plaintext XOR about once by chance
+        assert!(leaking_pairs < 16);
+
+        for counter in 0u64..256 {
+            let key = counter.to_be_bytes();
+            assert_eq!(keys.decrypt_key(&keys.encrypt_key(&key)).unwrap(), key);
+        }
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_swapped_values_are_rejected() {
+        let encrypted = EncryptedDatabase::new(MemDatabase::new(), "passphrase")
+            .await
+            .unwrap();
+
+        let mut dbtx = encrypted.begin_transaction().await;
+        dbtx.raw_insert_bytes(b"balance-a", b"1").await.unwrap();
+        dbtx.raw_insert_bytes(b"balance-b", b"1000").await.unwrap();
+        dbtx.commit_tx().await.unwrap();
+        drop(dbtx);
+
+        // Swap the ciphertexts of both entries in the underlying database
+        let mut inner_dbtx = encrypted.inner().begin_transaction().await;
+        let mut raw_entries = inner_dbtx
+            .raw_find_by_prefix(&[ENCRYPTED_DATA_PREFIX])
+            .await
+            .unwrap()
+            .collect::<Vec<_>>()
+            .await;
+        assert_eq!(raw_entries.len(), 2);
+        let (key_b, value_b) = raw_entries.pop().unwrap();
+        let (key_a, value_a) = raw_entries.pop().unwrap();
+        inner_dbtx.raw_insert_bytes(&key_a, &value_b).await.unwrap();
+        inner_dbtx.raw_insert_bytes(&key_b, &value_a).await.unwrap();
+        inner_dbtx.commit_tx().await.unwrap();
+        drop(inner_dbtx);
+
+        let mut dbtx = encrypted.begin_transaction().await;
+        assert!(dbtx.raw_get_bytes(b"balance-a").await.is_err());
+        assert!(dbtx.raw_get_bytes(b"balance-b").await.is_err());
+        assert!(dbtx.raw_find_by_prefix(b"balance").await.is_err());
+    }
+}
@@ -1,5 +1,6 @@
 use std::collections::BTreeMap;
 use std::fmt::Debug;
+use std::ops::{Bound, Range};
 use std::sync::Mutex;
 
 use anyhow::Result;
@@ -138,6 +139,42 @@ impl<'a> IDatabaseTransaction<'a> for MemTransaction<'a> {
         Ok(Box::pin(stream::iter(data)))
     }
 
+    async fn raw_find_by_range(&mut self, key_range: Range<&[u8]>) -> Result<PrefixStream<'_>> {
+        if key_range.start > key_range.end {
+            return Ok(Box::pin(stream::empty()));
+        }
+
+        let data = self
+            .tx_data
+            .range::<[u8], _>((
+                Bound::Included(key_range.start),
+                Bound::Excluded(key_range.end),
+            ))
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect::<Vec<_>>();
+        Ok(Box::pin(stream::iter(data)))
+    }
+
+    async fn raw_find_by_range_sorted_descending(
+        &mut self,
+        key_range: Range<&[u8]>,
+    ) -> Result<PrefixStream<'_>> {
+        if key_range.start > key_range.end {
+            return Ok(Box::pin(stream::empty()));
+        }
+
+        let data = self
+            .tx_data
+            .range::<[u8], _>((
+                Bound::Included(key_range.start),
+                Bound::Excluded(key_range.end),
+            ))
+            .rev()
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect::<Vec<_>>();
+        Ok(Box::pin(stream::iter(data)))
+    }
+
     async fn commit_tx(self) -> Result<()> {
         for op in self.operations {
             match op {
@@ -219,6 +256,16 @@ mod tests {
         fedimint_core::db::verify_find_by_prefix(database()).await;
     }
 
+    #[test_log::test(tokio::test)]
+    async fn test_dbtx_find_by_range() {
+        fedimint_core::db::verify_find_by_range(database()).await;
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_dbtx_snapshot_roundtrip() {
+        fedimint_core::db::verify_snapshot_roundtrip(database()).await;
+    }
+
     #[test_log::test(tokio::test)]
     async fn test_dbtx_commit() {
         fedimint_core::db::verify_commit(database()).await;
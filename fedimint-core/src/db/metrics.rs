@@ -0,0 +1,282 @@
+//! [`IDatabase`] wrapper that exports Prometheus metrics about database
+//! accesses, grouped by key prefix byte and module instance.
+//!
+//! All metrics are registered in the default registry, so they are served by
+//! any endpoint that gathers it (e.g. `fedimint-metrics`).
+
+use std::io::Cursor;
+use std::ops::Range;
+
+use fedimint_core::{apply, async_trait_maybe_send};
+use futures::StreamExt;
+use lazy_static::lazy_static;
+use prometheus::{
+    exponential_buckets, histogram_opts, opts, register_histogram, register_histogram_vec,
+    register_int_counter, register_int_counter_vec, Histogram, HistogramVec, IntCounter,
+    IntCounterVec,
+};
+
+use super::{IDatabase, ISingleUseDatabaseTransaction, PrefixStream, Result, MODULE_GLOBAL_PREFIX};
+use crate::core::ModuleInstanceId;
+use crate::encoding::Decodable;
+use crate::module::registry::ModuleDecoderRegistry;
+
+lazy_static! {
+    static ref DB_LATENCY_BUCKETS: Vec<f64> =
+        exponential_buckets(0.000_01, 4.0, 10).expect("valid buckets");
+    static ref DB_OPERATIONS: IntCounterVec = register_int_counter_vec!(
+        opts!(
+            "db_operations_total",
+            "Number of database operations by operation, key prefix and module"
+        ),
+        &["operation", "prefix", "module"]
+    )
+    .unwrap();
+    static ref DB_OPERATION_DURATION: HistogramVec = register_histogram_vec!(
+        histogram_opts!(
+            "db_operation_duration_seconds",
+            "Duration of database operations by operation, key prefix and module, scans are \
+             measured until the result stream is dropped",
+            DB_LATENCY_BUCKETS.clone()
+        ),
+        &["operation", "prefix", "module"]
+    )
+    .unwrap();
+    static ref DB_COMMIT_DURATION: Histogram = register_histogram!(histogram_opts!(
+        "db_commit_duration_seconds",
+        "Duration of database transaction commits",
+        DB_LATENCY_BUCKETS.clone()
+    ))
+    .unwrap();
+    static ref DB_COMMIT_FAILURES: IntCounter = register_int_counter!(opts!(
+        "db_commit_failures_total",
+        "Number of failed database transaction commits, usually caused by write conflicts"
+    ))
+    .unwrap();
+    pub(super) static ref DB_AUTOCOMMIT_CONFLICTS: IntCounter = register_int_counter!(opts!(
+        "db_autocommit_conflicts_total",
+        "Number of failed commits in autocommit blocks"
+    ))
+    .unwrap();
+    pub(super) static ref DB_AUTOCOMMIT_RETRIES: IntCounter = register_int_counter!(opts!(
+        "db_autocommit_retries_total",
+        "Number of times an autocommit block was retried after a failed commit"
+    ))
+    .unwrap();
+}
+
+/// Returns the `prefix` and `module` labels for a raw key (prefix)
+fn key_labels(key: &[u8]) -> (String, String) {
+    let (module, key) = match key.split_first() {
+        Some((&MODULE_GLOBAL_PREFIX, rest)) => {
+            let mut cursor = Cursor::new(rest);
+            match ModuleInstanceId::consensus_decode(&mut cursor, &ModuleDecoderRegistry::default())
+            {
+                Ok(module_instance_id) => (
+                    module_instance_id.to_string(),
+                    &rest[cursor.position() as usize..],
+                ),
+                Err(_) => ("invalid".to_string(), &[][..]),
+            }
+        }
+        _ => ("global".to_string(), key),
+    };
+
+    let prefix = key
+        .first()
+        .map(|prefix| format!("0x{prefix:02x}"))
+        .unwrap_or_else(|| "all".to_string());
+
+    (prefix, module)
+}
+
+fn record_operation(operation: &str, key: &[u8]) -> prometheus::HistogramTimer {
+    let (prefix, module) = key_labels(key);
+    let labels = [operation, prefix.as_str(), module.as_str()];
+    DB_OPERATIONS.with_label_values(&labels).inc();
+    DB_OPERATION_DURATION
+        .with_label_values(&labels)
+        .start_timer()
+}
+
+/// Records the duration of a scan until the returned stream is dropped
+fn timed_stream<'a>(
+    stream: PrefixStream<'a>,
+    timer: prometheus::HistogramTimer,
+) -> PrefixStream<'a> {
+    Box::pin(stream.inspect(move |_| {
+        let _timer = &timer;
+    }))
+}
+
+/// Wraps any [`IDatabase`] and records metrics for every operation, see the
+/// [module documentation](self)
+#[derive(Debug)]
+pub struct MetricsDatabase<D> {
+    inner: D,
+}
+
+impl<D: IDatabase> MetricsDatabase<D> {
+    pub fn new(inner: D) -> Self {
+        MetricsDatabase { inner }
+    }
+
+    pub fn into_inner(self) -> D {
+        self.inner
+    }
+}
+
+#[apply(async_trait_maybe_send!)]
+impl<D: IDatabase> IDatabase for MetricsDatabase<D> {
+    async fn begin_transaction<'a>(&'a self) -> Box<dyn ISingleUseDatabaseTransaction<'a>> {
+        Box::new(MetricsTransaction {
+            inner: self.inner.begin_transaction().await,
+        })
+    }
+}
+
+struct MetricsTransaction<'a> {
+    inner: Box<dyn ISingleUseDatabaseTransaction<'a>>,
+}
+
+#[apply(async_trait_maybe_send!)]
+impl<'a> ISingleUseDatabaseTransaction<'a> for MetricsTransaction<'a> {
+    async fn raw_insert_bytes(&mut self, key: &[u8], value: &[u8]) -> Result<Option<Vec<u8>>> {
+        let _timer = record_operation("insert", key);
+        self.inner.raw_insert_bytes(key, value).await
+    }
+
+    async fn raw_get_bytes(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        let _timer = record_operation("get", key);
+        self.inner.raw_get_bytes(key).await
+    }
+
+    async fn raw_remove_entry(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        let _timer = record_operation("remove", key);
+        self.inner.raw_remove_entry(key).await
+    }
+
+    async fn raw_find_by_prefix(&mut self, key_prefix: &[u8]) -> Result<PrefixStream<'_>> {
+        let timer = record_operation("find_by_prefix", key_prefix);
+        let stream = self.inner.raw_find_by_prefix(key_prefix).await?;
+        Ok(timed_stream(stream, timer))
+    }
+
+    async fn raw_find_by_prefix_sorted_descending(
+        &mut self,
+        key_prefix: &[u8],
+    ) -> Result<PrefixStream<'_>> {
+        let timer = record_operation("find_by_prefix", key_prefix);
+        let stream = self
+            .inner
+            .raw_find_by_prefix_sorted_descending(key_prefix)
+            .await?;
+        Ok(timed_stream(stream, timer))
+    }
+
+    async fn raw_find_by_range(&mut self, key_range: Range<&[u8]>) -> Result<PrefixStream<'_>> {
+        let timer = record_operation("find_by_range", key_range.start);
+        let stream = self.inner.raw_find_by_range(key_range).await?;
+        Ok(timed_stream(stream, timer))
+    }
+
+    async fn raw_find_by_range_sorted_descending(
+        &mut self,
+        key_range: Range<&[u8]>,
+    ) -> Result<PrefixStream<'_>> {
+        let timer = record_operation("find_by_range", key_range.start);
+        let stream = self
+            .inner
+            .raw_find_by_range_sorted_descending(key_range)
+            .await?;
+        Ok(timed_stream(stream, timer))
+    }
+
+    async fn raw_remove_by_prefix(&mut self, key_prefix: &[u8]) -> Result<()> {
+        let _timer = record_operation("remove_by_prefix", key_prefix);
+        self.inner.raw_remove_by_prefix(key_prefix).await
+    }
+
+    async fn commit_tx(&mut self) -> Result<()> {
+        let timer = DB_COMMIT_DURATION.start_timer();
+        let result = self.inner.commit_tx().await;
+        timer.observe_duration();
+        if result.is_err() {
+            DB_COMMIT_FAILURES.inc();
+        }
+        result
+    }
+
+    async fn rollback_tx_to_savepoint(&mut self) -> Result<()> {
+        self.inner.rollback_tx_to_savepoint().await
+    }
+
+    async fn set_tx_savepoint(&mut self) -> Result<()> {
+        self.inner.set_tx_savepoint().await
+    }
+
+    fn add_notification_key(&mut self, key: &[u8]) -> Result<()> {
+        self.inner.add_notification_key(key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{key_labels, MetricsDatabase, DB_OPERATIONS};
+    use crate::db::mem_impl::MemDatabase;
+    use crate::db::Database;
+    use crate::module::registry::ModuleDecoderRegistry;
+
+    fn database() -> Database {
+        Database::new(
+            MetricsDatabase::new(MemDatabase::new()),
+            ModuleDecoderRegistry::default(),
+        )
+    }
+
+    #[test]
+    fn test_key_labels() {
+        assert_eq!(
+            key_labels(&[0x42, 0x01]),
+            ("0x42".to_string(), "global".to_string())
+        );
+        assert_eq!(key_labels(&[]), ("all".to_string(), "global".to_string()));
+        assert_eq!(
+            key_labels(&[0xff, 0x03, 0x42, 0x01]),
+            ("0x42".to_string(), "3".to_string())
+        );
+        assert_eq!(
+            key_labels(&[0xff, 0x03]),
+            ("all".to_string(), "3".to_string())
+        );
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_dbtx_insert_elements() {
+        crate::db::verify_insert_elements(database()).await;
+        assert!(
+            DB_OPERATIONS
+                .with_label_values(&["insert", "0x42", "global"])
+                .get()
+                > 0
+        );
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_dbtx_find_by_prefix() {
+        crate::db::verify_find_by_prefix(database()).await;
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_dbtx_commit() {
+        crate::db::verify_commit(database()).await;
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_module_db() {
+        let db = database();
+        let module_db = db.new_isolated(3);
+        crate::db::verify_module_db(db, module_db).await;
+        assert!(DB_OPERATIONS.with_label_values(&["get", "0x42", "3"]).get() > 0);
+    }
+}
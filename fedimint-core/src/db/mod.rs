@@ -2,6 +2,7 @@ use std::collections::BTreeMap;
 use std::error::Error;
 use std::fmt::Debug;
 use std::marker::PhantomData;
+use std::ops::Range;
 use std::pin::Pin;
 use std::sync::Arc;
 
@@ -21,8 +22,13 @@ use crate::fmt_utils::AbbreviateHexBytes;
 use crate::task::{MaybeSend, MaybeSync};
 use crate::{async_trait_maybe_send, maybe_add_send, timing};
 
+pub mod encrypted;
 pub mod mem_impl;
+#[cfg(feature = "metrics")]
+pub mod metrics;
 pub mod notifications;
+pub mod prefixed;
+pub mod snapshot;
 
 pub use test_utils::*;
 
@@ -247,6 +253,8 @@ impl Database {
                                 target: LOG_DB,
                                 curr_attempts, "Database commit failed in an autocommit block"
                             );
+                            #[cfg(feature = "metrics")]
+                            metrics::DB_AUTOCOMMIT_CONFLICTS.inc();
                             if max_attempts
                                 .map(|max_att| max_att <= curr_attempts)
                                 .unwrap_or(false)
@@ -256,6 +264,8 @@ impl Database {
                                     last_error: err,
                                 });
                             }
+                            #[cfg(feature = "metrics")]
+                            metrics::DB_AUTOCOMMIT_RETRIES.inc();
                         }
                     }
                 }
@@ -281,16 +291,8 @@ impl Database {
     where
         K: DatabaseKey + DatabaseRecord + DatabaseKeyWithNotify,
     {
-        let key_bytes = if let Some(module_id) = self.module_instance_id {
-            let mut prefix_bytes = vec![MODULE_GLOBAL_PREFIX];
-            module_id
-                .consensus_encode(&mut prefix_bytes)
-                .expect("Error encoding module instance id as prefix");
-            prefix_bytes.extend(key.to_bytes());
-            prefix_bytes
-        } else {
-            key.to_bytes()
-        };
+        let mut key_bytes = self.module_prefix_bytes();
+        key_bytes.extend(key.to_bytes());
         loop {
             // register for notification
             let notify = self.inner_db.notifications.register(&key_bytes);
@@ -337,6 +339,71 @@ impl Database {
     {
         self.wait_key_check(key, std::convert::identity).await.0
     }
+
+    /// Subscribes to changes of all keys starting with `key_prefix`. After
+    /// every commit the stream yields the new value of each inserted key and
+    /// `None` for each removed key, in the order they were written. Changes
+    /// committed before subscribing are not included, use
+    /// [`DatabaseTransaction::find_by_prefix`] to read the current state.
+    ///
+    /// Changes are buffered for slow consumers only up to a limit. If the
+    /// consumer falls further behind, changes are lost and the stream ends,
+    /// so the caller has to subscribe again and re-read the current state.
+    pub fn subscribe_prefix<KP>(
+        &self,
+        key_prefix: &KP,
+    ) -> impl Stream<
+        Item = (
+            KP::Record,
+            Option<<<KP as DatabaseLookup>::Record as DatabaseRecord>::Value>,
+        ),
+    >
+    where
+        KP: DatabaseLookup,
+        KP::Record: DatabaseKey,
+    {
+        let module_prefix_len = self.module_prefix_bytes().len();
+        let mut prefix_bytes = self.module_prefix_bytes();
+        prefix_bytes.extend(key_prefix.to_bytes());
+
+        let receiver = self.inner_db.notifications.subscribe_prefix(prefix_bytes);
+        let decoders = self.inner_db.module_decoders.clone();
+        futures::stream::unfold(receiver, |mut receiver| async move {
+            match receiver.recv().await {
+                Ok(change) => Some((change, receiver)),
+                Err(tokio::sync::broadcast::error::RecvError::Lagged(missed)) => {
+                    warn!(
+                        target: LOG_DB,
+                        missed, "Prefix subscriber fell behind, ending subscription"
+                    );
+                    None
+                }
+                Err(tokio::sync::broadcast::error::RecvError::Closed) => None,
+            }
+        })
+        .map(move |(key_bytes, value_bytes)| {
+            let key = KP::Record::from_bytes(&key_bytes[module_prefix_len..], &decoders)
+                .expect("Unrecoverable error reading the DatabaseKey");
+            let value = value_bytes.map(|value_bytes| {
+                decode_value(&value_bytes, &decoders)
+                    .expect("Unrecoverable error decoding the DatabaseValue")
+            });
+            (key, value)
+        })
+    }
+
+    /// Key prefix of the module this database is isolated to, empty if it
+    /// isn't isolated
+    fn module_prefix_bytes(&self) -> Vec<u8> {
+        let mut prefix_bytes = vec![];
+        if let Some(module_id) = self.module_instance_id {
+            prefix_bytes.push(MODULE_GLOBAL_PREFIX);
+            module_id
+                .consensus_encode(&mut prefix_bytes)
+                .expect("Error encoding module instance id as prefix");
+        }
+        prefix_bytes
+    }
 }
 
 /// Fedimint requires that the database implementation implement Snapshot
@@ -388,6 +455,16 @@ pub trait IDatabaseTransaction<'a>: 'a + MaybeSend {
         key_prefix: &[u8],
     ) -> Result<PrefixStream<'_>>;
 
+    /// Returns a stream of key-value pairs with keys in `key_range` (start
+    /// inclusive, end exclusive), sorted ascending by key.
+    async fn raw_find_by_range(&mut self, key_range: Range<&[u8]>) -> Result<PrefixStream<'_>>;
+
+    /// Same as [`Self::raw_find_by_range`] but the order is descending by key.
+    async fn raw_find_by_range_sorted_descending(
+        &mut self,
+        key_range: Range<&[u8]>,
+    ) -> Result<PrefixStream<'_>>;
+
     /// Default implementation is a combination of [`Self::raw_find_by_prefix`]
     /// + loop over [`Self::raw_remove_entry`]
     async fn raw_remove_by_prefix(&mut self, key_prefix: &[u8]) -> Result<()> {
@@ -442,6 +519,13 @@ pub trait ISingleUseDatabaseTransaction<'a>: 'a + MaybeSend {
         key_prefix: &[u8],
     ) -> Result<PrefixStream<'_>>;
 
+    async fn raw_find_by_range(&mut self, key_range: Range<&[u8]>) -> Result<PrefixStream<'_>>;
+
+    async fn raw_find_by_range_sorted_descending(
+        &mut self,
+        key_range: Range<&[u8]>,
+    ) -> Result<PrefixStream<'_>>;
+
     async fn raw_remove_by_prefix(&mut self, key_prefix: &[u8]) -> Result<()>;
 
     async fn commit_tx(&mut self) -> Result<()>;
@@ -513,6 +597,25 @@ impl<'a, Tx: IDatabaseTransaction<'a> + MaybeSend> ISingleUseDatabaseTransaction
             .await
     }
 
+    async fn raw_find_by_range(&mut self, key_range: Range<&[u8]>) -> Result<PrefixStream<'_>> {
+        self.0
+            .as_mut()
+            .context("Cannot retrieve from already consumed transaction")?
+            .raw_find_by_range(key_range)
+            .await
+    }
+
+    async fn raw_find_by_range_sorted_descending(
+        &mut self,
+        key_range: Range<&[u8]>,
+    ) -> Result<PrefixStream<'_>> {
+        self.0
+            .as_mut()
+            .context("Cannot retrieve from already consumed transaction")?
+            .raw_find_by_range_sorted_descending(key_range)
+            .await
+    }
+
     async fn raw_remove_by_prefix(&mut self, key_prefix: &[u8]) -> Result<()> {
         self.0
             .as_mut()
@@ -561,11 +664,12 @@ impl<'a, Tx: IDatabaseTransaction<'a> + MaybeSend> ISingleUseDatabaseTransaction
 pub struct CommitTracker {
     is_committed: bool,
     has_writes: bool,
+    ignore_uncommitted: bool,
 }
 
 impl Drop for CommitTracker {
     fn drop(&mut self) {
-        if self.has_writes && !self.is_committed {
+        if self.has_writes && !self.is_committed && !self.ignore_uncommitted {
             warn!(
                 target: LOG_DB,
                 "DatabaseTransaction has writes and has not called commit."
@@ -641,6 +745,29 @@ impl<'a> ISingleUseDatabaseTransaction<'a> for CommittableIsolatedDatabaseTransa
         .await
     }
 
+    async fn raw_find_by_range(&mut self, key_range: Range<&[u8]>) -> Result<PrefixStream<'_>> {
+        let prefix_with_module = IsolatedDatabaseTransaction::prefix_with_module(&self.prefix);
+        IsolatedDatabaseTransaction::<u16>::raw_find_by_range(
+            prefix_with_module,
+            self.dbtx.as_mut(),
+            key_range,
+        )
+        .await
+    }
+
+    async fn raw_find_by_range_sorted_descending(
+        &mut self,
+        key_range: Range<&[u8]>,
+    ) -> Result<PrefixStream<'_>> {
+        let prefix_with_module = IsolatedDatabaseTransaction::prefix_with_module(&self.prefix);
+        IsolatedDatabaseTransaction::<u16>::raw_find_by_range_sorted_descending(
+            prefix_with_module,
+            self.dbtx.as_mut(),
+            key_range,
+        )
+        .await
+    }
+
     async fn raw_remove_by_prefix(&mut self, key_prefix: &[u8]) -> Result<()> {
         let mut isolated = IsolatedDatabaseTransaction::new(self.dbtx.as_mut(), Some(&self.prefix));
         isolated.raw_remove_by_prefix(key_prefix).await
@@ -771,6 +898,53 @@ impl<'isolated, T: MaybeSend + Encodable> ModuleDatabaseTransaction<'isolated, T
         .await
     }
 
+    #[instrument(level = "debug", skip_all, fields(?key_range))]
+    pub async fn find_by_range<K>(
+        &mut self,
+        key_range: Range<K>,
+    ) -> impl Stream<Item = (K, K::Value)> + '_
+    where
+        K: DatabaseKey + DatabaseRecord,
+    {
+        find_by_range(self.isolated_tx.as_mut(), self.decoders.clone(), key_range).await
+    }
+
+    #[instrument(level = "debug", skip_all, fields(?key_range))]
+    pub async fn find_by_range_sorted_descending<K>(
+        &mut self,
+        key_range: Range<K>,
+    ) -> impl Stream<Item = (K, K::Value)> + '_
+    where
+        K: DatabaseKey + DatabaseRecord,
+    {
+        find_by_range_sorted_descending(self.isolated_tx.as_mut(), self.decoders.clone(), key_range)
+            .await
+    }
+
+    #[instrument(level = "debug", skip_all, fields(key = ?key_prefix, ?start_after))]
+    pub async fn find_by_prefix_sorted_descending_after<KP>(
+        &mut self,
+        key_prefix: &KP,
+        start_after: &KP::Record,
+    ) -> impl Stream<
+        Item = (
+            KP::Record,
+            <<KP as DatabaseLookup>::Record as DatabaseRecord>::Value,
+        ),
+    > + '_
+    where
+        KP: DatabaseLookup,
+        KP::Record: DatabaseKey,
+    {
+        find_by_prefix_sorted_descending_after(
+            self.isolated_tx.as_mut(),
+            self.decoders.clone(),
+            key_prefix,
+            start_after,
+        )
+        .await
+    }
+
     #[instrument(level = "debug", skip_all, fields(?key))]
     fn add_notification_key<K>(&mut self, key: &K)
     where
@@ -938,6 +1112,42 @@ impl<'isolated, 'parent: 'isolated, T: MaybeSend + Encodable>
             (stripped_key.to_vec(), value)
         })))
     }
+
+    async fn raw_find_by_range(
+        prefix_with_module: Vec<u8>,
+        dbtx: &'isolated mut dyn ISingleUseDatabaseTransaction<'parent>,
+        key_range: Range<&[u8]>,
+    ) -> Result<PrefixStream<'isolated>> {
+        let original_prefix_len = prefix_with_module.len();
+        let start = [prefix_with_module.as_slice(), key_range.start].concat();
+        let end = [prefix_with_module.as_slice(), key_range.end].concat();
+        let raw_range = dbtx
+            .raw_find_by_range(start.as_slice()..end.as_slice())
+            .await?;
+
+        Ok(Box::pin(raw_range.map(move |(key, value)| {
+            let stripped_key = &key[original_prefix_len..];
+            (stripped_key.to_vec(), value)
+        })))
+    }
+
+    async fn raw_find_by_range_sorted_descending(
+        prefix_with_module: Vec<u8>,
+        dbtx: &'isolated mut dyn ISingleUseDatabaseTransaction<'parent>,
+        key_range: Range<&[u8]>,
+    ) -> Result<PrefixStream<'isolated>> {
+        let original_prefix_len = prefix_with_module.len();
+        let start = [prefix_with_module.as_slice(), key_range.start].concat();
+        let end = [prefix_with_module.as_slice(), key_range.end].concat();
+        let raw_range = dbtx
+            .raw_find_by_range_sorted_descending(start.as_slice()..end.as_slice())
+            .await?;
+
+        Ok(Box::pin(raw_range.map(move |(key, value)| {
+            let stripped_key = &key[original_prefix_len..];
+            (stripped_key.to_vec(), value)
+        })))
+    }
 }
 
 #[apply(async_trait_maybe_send!)]
@@ -990,6 +1200,27 @@ impl<'isolated, 'parent, T: MaybeSend + Encodable + 'isolated>
         .await
     }
 
+    async fn raw_find_by_range(&mut self, key_range: Range<&[u8]>) -> Result<PrefixStream<'_>> {
+        IsolatedDatabaseTransaction::<T>::raw_find_by_range(
+            self.prefix.clone(),
+            self.inner_tx,
+            key_range,
+        )
+        .await
+    }
+
+    async fn raw_find_by_range_sorted_descending(
+        &mut self,
+        key_range: Range<&[u8]>,
+    ) -> Result<PrefixStream<'_>> {
+        IsolatedDatabaseTransaction::<T>::raw_find_by_range_sorted_descending(
+            self.prefix.clone(),
+            self.inner_tx,
+            key_range,
+        )
+        .await
+    }
+
     async fn raw_remove_by_prefix(&mut self, key: &[u8]) -> Result<()> {
         let mut key_with_prefix = self.prefix.clone();
         key_with_prefix.extend_from_slice(key);
@@ -1056,6 +1287,7 @@ impl<'parent> DatabaseTransaction<'parent> {
             commit_tracker: CommitTracker {
                 is_committed: false,
                 has_writes: false,
+                ignore_uncommitted: false,
             },
         }
     }
@@ -1095,6 +1327,13 @@ impl<'parent> DatabaseTransaction<'parent> {
         }
     }
 
+    /// Suppresses the warning about uncommitted writes when the transaction is
+    /// dropped, for transactions that are deliberately never committed
+    pub fn ignore_uncommitted(&mut self) -> &mut Self {
+        self.commit_tracker.ignore_uncommitted = true;
+        self
+    }
+
     pub async fn commit_tx_result(mut self) -> Result<()> {
         self.commit_tracker.is_committed = true;
         return self.tx.commit_tx().await;
@@ -1177,6 +1416,58 @@ impl<'parent> DatabaseTransaction<'parent> {
         find_by_prefix_sorted_descending(self.tx.as_mut(), self.decoders.clone(), key_prefix).await
     }
 
+    /// Returns all entries with keys in `key_range` (start inclusive, end
+    /// exclusive), sorted ascending by key.
+    #[instrument(level = "debug", skip_all, fields(?key_range))]
+    pub async fn find_by_range<K>(
+        &mut self,
+        key_range: Range<K>,
+    ) -> impl Stream<Item = (K, K::Value)> + '_
+    where
+        K: DatabaseKey + DatabaseRecord,
+    {
+        find_by_range(self.tx.as_mut(), self.decoders.clone(), key_range).await
+    }
+
+    /// Same as [`Self::find_by_range`] but sorted descending by key.
+    #[instrument(level = "debug", skip_all, fields(?key_range))]
+    pub async fn find_by_range_sorted_descending<K>(
+        &mut self,
+        key_range: Range<K>,
+    ) -> impl Stream<Item = (K, K::Value)> + '_
+    where
+        K: DatabaseKey + DatabaseRecord,
+    {
+        find_by_range_sorted_descending(self.tx.as_mut(), self.decoders.clone(), key_range).await
+    }
+
+    /// Cursor variant of [`Self::find_by_prefix_sorted_descending`] that seeks
+    /// directly to the first entry after `start_after` instead of iterating
+    /// over all newer entries first. `start_after` itself is not returned.
+    #[instrument(level = "debug", skip_all, fields(key = ?key_prefix, ?start_after))]
+    pub async fn find_by_prefix_sorted_descending_after<KP>(
+        &mut self,
+        key_prefix: &KP,
+        start_after: &KP::Record,
+    ) -> impl Stream<
+        Item = (
+            KP::Record,
+            <<KP as DatabaseLookup>::Record as DatabaseRecord>::Value,
+        ),
+    > + '_
+    where
+        KP: DatabaseLookup,
+        KP::Record: DatabaseKey,
+    {
+        find_by_prefix_sorted_descending_after(
+            self.tx.as_mut(),
+            self.decoders.clone(),
+            key_prefix,
+            start_after,
+        )
+        .await
+    }
+
     #[instrument(level = "debug", skip_all, fields(?key, ?value), ret)]
     pub async fn insert_entry<K>(&mut self, key: &K, value: &K::Value) -> Option<K::Value>
     where
@@ -1615,7 +1906,7 @@ mod test_utils {
     pub(super) struct TestKey(pub u64);
 
     #[derive(Debug, Encodable, Decodable)]
-    struct DbPrefixTestPrefix;
+    pub(super) struct DbPrefixTestPrefix;
 
     impl_db_record!(
         key = TestKey,
@@ -1639,7 +1930,7 @@ mod test_utils {
     impl_db_lookup!(key = TestKeyV0, query_prefix = DbPrefixTestPrefixV0);
 
     #[derive(Debug, Eq, PartialEq, PartialOrd, Ord, Encodable, Decodable)]
-    struct AltTestKey(u64);
+    pub(super) struct AltTestKey(pub u64);
 
     #[derive(Debug, Encodable, Decodable)]
     struct AltDbPrefixTestPrefix;
@@ -1801,6 +2092,63 @@ mod test_utils {
         assert_eq!(reversed, reversed_expected);
     }
 
+    pub async fn verify_find_by_range(db: Database) {
+        let mut dbtx = db.begin_transaction().await;
+        dbtx.insert_entry(&TestKey(54), &TestVal(8888)).await;
+        dbtx.insert_entry(&TestKey(55), &TestVal(9999)).await;
+        dbtx.insert_entry(&TestKey(56), &TestVal(1111)).await;
+        dbtx.insert_entry(&TestKey(57), &TestVal(2222)).await;
+
+        dbtx.insert_entry(&AltTestKey(55), &TestVal(7777)).await;
+        dbtx.commit_tx().await;
+
+        let mut dbtx = db.begin_transaction().await;
+
+        let returned_keys = dbtx
+            .find_by_range(TestKey(55)..TestKey(57))
+            .await
+            .collect::<Vec<_>>()
+            .await;
+        let expected = vec![(TestKey(55), TestVal(9999)), (TestKey(56), TestVal(1111))];
+        assert_eq!(returned_keys, expected);
+
+        let reversed = dbtx
+            .find_by_range_sorted_descending(TestKey(55)..TestKey(57))
+            .await
+            .collect::<Vec<_>>()
+            .await;
+        let mut reversed_expected = expected;
+        reversed_expected.reverse();
+        assert_eq!(reversed, reversed_expected);
+
+        let empty = dbtx
+            .find_by_range(TestKey(57)..TestKey(55))
+            .await
+            .collect::<Vec<_>>()
+            .await;
+        assert!(empty.is_empty());
+
+        // Verify the cursor neither returns the start key nor leaves the prefix
+        let after = dbtx
+            .find_by_prefix_sorted_descending_after(&DbPrefixTestPrefix, &TestKey(56))
+            .await
+            .collect::<Vec<_>>()
+            .await;
+        assert_eq!(
+            after,
+            vec![(TestKey(55), TestVal(9999)), (TestKey(54), TestVal(8888))]
+        );
+
+        let mut module_dbtx = dbtx.with_module_prefix(TEST_MODULE_PREFIX);
+        module_dbtx.insert_entry(&TestKey(55), &TestVal(3333)).await;
+        let module_keys = module_dbtx
+            .find_by_range(TestKey(0)..TestKey(100))
+            .await
+            .collect::<Vec<_>>()
+            .await;
+        assert_eq!(module_keys, vec![(TestKey(55), TestVal(3333))]);
+    }
+
     pub async fn verify_commit(db: Database) {
         let mut dbtx = db.begin_transaction().await;
 
@@ -2028,6 +2376,35 @@ mod test_utils {
         assert_eq!(returned_keys, expected_keys);
     }
 
+    pub async fn verify_snapshot_roundtrip(db: Database) {
+        let source = Database::new(MemDatabase::new(), ModuleDecoderRegistry::default());
+        let mut dbtx = source.begin_transaction().await;
+        for i in 0..100 {
+            dbtx.insert_entry(&TestKey(i), &TestVal(i * 2)).await;
+        }
+        dbtx.insert_entry(&AltTestKey(55), &TestVal(7777)).await;
+        dbtx.commit_tx().await;
+
+        let mut snapshot = vec![];
+        assert_eq!(source.export_snapshot(&mut snapshot).await.unwrap(), 101);
+        assert_eq!(db.import_snapshot(snapshot.as_slice()).await.unwrap(), 101);
+
+        let mut dbtx = db.begin_transaction().await;
+        for i in 0..100 {
+            assert_eq!(dbtx.get_value(&TestKey(i)).await, Some(TestVal(i * 2)));
+        }
+        assert_eq!(dbtx.get_value(&AltTestKey(55)).await, Some(TestVal(7777)));
+
+        // Exporting from the restored database yields the identical snapshot
+        let mut exported = vec![];
+        assert_eq!(dbtx.export_snapshot(&mut exported).await.unwrap(), 101);
+        assert_eq!(exported, snapshot);
+
+        // Importing into a non-empty database is refused
+        drop(dbtx);
+        assert!(db.import_snapshot(snapshot.as_slice()).await.is_err());
+    }
+
     pub async fn verify_module_db(db: Database, module_db: Database) {
         let mut dbtx = db.begin_transaction().await;
 
@@ -2289,6 +2666,20 @@ mod test_utils {
                 unimplemented!()
             }
 
+            async fn raw_find_by_range(
+                &mut self,
+                _key_range: std::ops::Range<&[u8]>,
+            ) -> anyhow::Result<crate::db::PrefixStream<'_>> {
+                unimplemented!()
+            }
+
+            async fn raw_find_by_range_sorted_descending(
+                &mut self,
+                _key_range: std::ops::Range<&[u8]>,
+            ) -> anyhow::Result<crate::db::PrefixStream<'_>> {
+                unimplemented!()
+            }
+
             async fn commit_tx(self) -> anyhow::Result<()> {
                 Err(anyhow!("Can't commit!"))
             }
@@ -2351,6 +2742,93 @@ where
         })
 }
 
+pub async fn find_by_prefix_sorted_descending_after<'r, 'inner, KP>(
+    tx: &'r mut dyn ISingleUseDatabaseTransaction<'inner>,
+    decoders: ModuleDecoderRegistry,
+    key_prefix: &KP,
+    start_after: &KP::Record,
+) -> impl Stream<
+    Item = (
+        KP::Record,
+        <<KP as DatabaseLookup>::Record as DatabaseRecord>::Value,
+    ),
+> + 'r
+where
+    'inner: 'r,
+    KP: DatabaseLookup,
+    KP::Record: DatabaseKey,
+{
+    debug!("find by prefix sorted descending after key");
+    // Every key that is lexicographically between the prefix and a key starting
+    // with that prefix also starts with the prefix, so a range query is enough
+    let prefix_bytes = key_prefix.to_bytes();
+    let start_after_bytes = start_after.to_bytes();
+    tx.raw_find_by_range_sorted_descending(prefix_bytes.as_slice()..start_after_bytes.as_slice())
+        .await
+        .expect("Error doing range search in database")
+        .map(move |(key_bytes, value_bytes)| {
+            let key = KP::Record::from_bytes(&key_bytes, &decoders)
+                .with_context(|| anyhow::anyhow!("key: {}", AbbreviateHexBytes(&key_bytes)))
+                .expect("Unrecoverable error reading DatabaseKey");
+            let value = decode_value(&value_bytes, &decoders)
+                .with_context(|| anyhow::anyhow!("key: {}", AbbreviateHexBytes(&key_bytes)))
+                .expect("Unrecoverable decoding DatabaseValue");
+            (key, value)
+        })
+}
+
+pub async fn find_by_range<'r, 'inner, K>(
+    tx: &'r mut dyn ISingleUseDatabaseTransaction<'inner>,
+    decoders: ModuleDecoderRegistry,
+    key_range: Range<K>,
+) -> impl Stream<Item = (K, K::Value)> + 'r
+where
+    'inner: 'r,
+    K: DatabaseKey + DatabaseRecord,
+{
+    debug!("find by range");
+    let start_bytes = key_range.start.to_bytes();
+    let end_bytes = key_range.end.to_bytes();
+    tx.raw_find_by_range(start_bytes.as_slice()..end_bytes.as_slice())
+        .await
+        .expect("Error doing range search in database")
+        .map(move |(key_bytes, value_bytes)| {
+            let key = K::from_bytes(&key_bytes, &decoders)
+                .with_context(|| anyhow::anyhow!("key: {}", AbbreviateHexBytes(&key_bytes)))
+                .expect("Unrecoverable error reading DatabaseKey");
+            let value = decode_value(&value_bytes, &decoders)
+                .with_context(|| anyhow::anyhow!("key: {}", AbbreviateHexBytes(&key_bytes)))
+                .expect("Unrecoverable decoding DatabaseValue");
+            (key, value)
+        })
+}
+
+pub async fn find_by_range_sorted_descending<'r, 'inner, K>(
+    tx: &'r mut dyn ISingleUseDatabaseTransaction<'inner>,
+    decoders: ModuleDecoderRegistry,
+    key_range: Range<K>,
+) -> impl Stream<Item = (K, K::Value)> + 'r
+where
+    'inner: 'r,
+    K: DatabaseKey + DatabaseRecord,
+{
+    debug!("find by range sorted descending");
+    let start_bytes = key_range.start.to_bytes();
+    let end_bytes = key_range.end.to_bytes();
+    tx.raw_find_by_range_sorted_descending(start_bytes.as_slice()..end_bytes.as_slice())
+        .await
+        .expect("Error doing range search in database")
+        .map(move |(key_bytes, value_bytes)| {
+            let key = K::from_bytes(&key_bytes, &decoders)
+                .with_context(|| anyhow::anyhow!("key: {}", AbbreviateHexBytes(&key_bytes)))
+                .expect("Unrecoverable error reading DatabaseKey");
+            let value = decode_value(&value_bytes, &decoders)
+                .with_context(|| anyhow::anyhow!("key: {}", AbbreviateHexBytes(&key_bytes)))
+                .expect("Unrecoverable decoding DatabaseValue");
+            (key, value)
+        })
+}
+
 #[cfg(test)]
 mod tests {
     use tokio::sync::oneshot;
@@ -2488,6 +2966,75 @@ mod tests {
         );
     }
 
+    #[tokio::test]
+    async fn test_subscribe_prefix() {
+        let db = Database::new(MemDatabase::new(), ModuleDecoderRegistry::default());
+        let mut changes = Box::pin(db.subscribe_prefix(&DbPrefixTestPrefix));
+
+        let mut tx = db.begin_transaction().await;
+        tx.insert_new_entry(&TestKey(1), &TestVal(2)).await;
+        tx.insert_new_entry(&AltTestKey(1), &TestVal(3)).await;
+        assert_eq!(
+            future_returns_shortly(changes.next()).await,
+            None,
+            "should not notify before commit"
+        );
+        tx.commit_tx().await;
+
+        assert_eq!(
+            future_returns_shortly(changes.next()).await,
+            Some(Some((TestKey(1), Some(TestVal(2))))),
+            "should notify insert"
+        );
+
+        let mut tx = db.begin_transaction().await;
+        tx.remove_entry(&TestKey(1)).await;
+        tx.commit_tx().await;
+
+        assert_eq!(
+            future_returns_shortly(changes.next()).await,
+            Some(Some((TestKey(1), None))),
+            "should notify removal"
+        );
+        assert_eq!(
+            future_returns_shortly(changes.next()).await,
+            None,
+            "should not notify other prefixes"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_subscribe_prefix_isolated_db() {
+        let module_instance_id = 10;
+        let db = Database::new(MemDatabase::new(), ModuleDecoderRegistry::default());
+        let isolated_db = db.new_isolated(module_instance_id);
+        let mut changes = Box::pin(isolated_db.subscribe_prefix(&DbPrefixTestPrefix));
+
+        let mut tx = db.begin_transaction().await;
+        tx.insert_new_entry(&TestKey(1), &TestVal(2)).await;
+        let mut tx_mod = tx.with_module_prefix(module_instance_id);
+        tx_mod.insert_new_entry(&TestKey(3), &TestVal(4)).await;
+        tx_mod.remove_by_prefix(&DbPrefixTestPrefix).await;
+        drop(tx_mod);
+        tx.commit_tx().await;
+
+        assert_eq!(
+            future_returns_shortly(changes.next()).await,
+            Some(Some((TestKey(3), Some(TestVal(4))))),
+            "should notify insert"
+        );
+        assert_eq!(
+            future_returns_shortly(changes.next()).await,
+            Some(Some((TestKey(3), None))),
+            "should notify removal by prefix"
+        );
+        assert_eq!(
+            future_returns_shortly(changes.next()).await,
+            None,
+            "should not notify keys outside of the module"
+        );
+    }
+
     #[tokio::test]
     async fn test_wait_key_no_transaction() {
         let db = Database::new(MemDatabase::new(), ModuleDecoderRegistry::default());
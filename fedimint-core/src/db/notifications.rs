@@ -1,31 +1,53 @@
 use std::collections::hash_map::DefaultHasher;
+use std::collections::BTreeMap;
 use std::hash::{Hash, Hasher};
+use std::ops::{Bound, Range};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::RwLock;
 
 use anyhow::Context;
 use bitvec::vec::BitVec;
 use fedimint_core::{apply, async_trait_maybe_send};
+use futures::StreamExt;
 use tokio::sync::futures::Notified;
-use tokio::sync::Notify;
+use tokio::sync::{broadcast, Notify};
 
 use super::{ISingleUseDatabaseTransaction, PrefixStream, Result};
 
 /// Number of buckets used for `Notifications`.
 const NOTIFY_BUCKETS: usize = 32;
 
+/// Number of changes a prefix subscriber can fall behind before it lags and
+/// misses changes
+const PREFIX_SUBSCRIBER_CAPACITY: usize = 1024;
+
+/// A committed change of a raw key, `None` if the key was removed
+pub type PrefixChange = (Vec<u8>, Option<Vec<u8>>);
+
 /// The state of Notification.
 ///
 /// This stores `NOTIFY_BUCKETS` number of `Notifies`.
 /// Each key is assigned a bucket based on its hash value.
 /// This will cause some false positives.
+///
+/// Additionally it keeps track of prefix subscribers, which receive every
+/// committed change of a key under their prefix. As long as nobody
+/// subscribed, tracking them costs a single atomic load per write.
 #[derive(Debug)]
 pub struct Notifications {
     buckets: Vec<Notify>,
+    /// Senders of all prefix subscribers, indexed by their prefix
+    prefix_subscribers: RwLock<BTreeMap<Vec<u8>, Vec<broadcast::Sender<PrefixChange>>>>,
+    /// Number of senders in `prefix_subscribers`
+    prefix_subscriber_count: AtomicUsize,
 }
 
 impl Default for Notifications {
     fn default() -> Self {
         Self {
             buckets: (0..NOTIFY_BUCKETS).map(|_| Notify::new()).collect(),
+            prefix_subscribers: RwLock::new(BTreeMap::new()),
+            prefix_subscriber_count: AtomicUsize::new(0),
         }
     }
 }
@@ -74,6 +96,73 @@ impl Notifications {
             self.buckets[bucket].notify_waiters();
         }
     }
+
+    /// Subscribes to all changes of keys starting with `prefix` that are
+    /// committed from now on. The subscription ends when the receiver is
+    /// dropped. A receiver that falls more than `PREFIX_SUBSCRIBER_CAPACITY`
+    /// changes behind gets [`broadcast::error::RecvError::Lagged`] and misses
+    /// the oldest changes.
+    pub fn subscribe_prefix(&self, prefix: Vec<u8>) -> broadcast::Receiver<PrefixChange> {
+        let (sender, receiver) = broadcast::channel(PREFIX_SUBSCRIBER_CAPACITY);
+        let mut subscribers = self.prefix_subscribers.write().expect("lock poisoned");
+        subscribers.entry(prefix).or_default().push(sender);
+        self.prefix_subscriber_count.fetch_add(1, Ordering::SeqCst);
+        receiver
+    }
+
+    fn has_prefix_subscribers(&self) -> bool {
+        self.prefix_subscriber_count.load(Ordering::SeqCst) != 0
+    }
+
+    /// Returns `true` if a subscriber is interested in changes of `key`
+    pub fn is_key_subscribed(&self, key: &[u8]) -> bool {
+        if !self.has_prefix_subscribers() {
+            return false;
+        }
+
+        let subscribers = self.prefix_subscribers.read().expect("lock poisoned");
+        (0..=key.len()).any(|len| subscribers.contains_key(&key[..len]))
+    }
+
+    /// Returns `true` if a subscriber is interested in changes of any key
+    /// starting with `prefix`
+    pub fn is_prefix_subscribed(&self, prefix: &[u8]) -> bool {
+        if !self.has_prefix_subscribers() {
+            return false;
+        }
+
+        let subscribers = self.prefix_subscribers.read().expect("lock poisoned");
+        (0..=prefix.len()).any(|len| subscribers.contains_key(&prefix[..len]))
+            || subscribers
+                .range::<[u8], _>((Bound::Included(prefix), Bound::Unbounded))
+                .next()
+                .map_or(false, |(subscriber_prefix, _)| {
+                    subscriber_prefix.starts_with(prefix)
+                })
+    }
+
+    /// Sends committed changes to all subscribers of matching prefixes,
+    /// subscribers whose receiver was dropped are removed.
+    pub fn submit_prefix_changes(&self, changes: Vec<PrefixChange>) {
+        if changes.is_empty() || !self.has_prefix_subscribers() {
+            return;
+        }
+
+        let mut subscribers = self.prefix_subscribers.write().expect("lock poisoned");
+        for change in changes {
+            for len in 0..=change.0.len() {
+                let Some(senders) = subscribers.get_mut(&change.0[..len]) else {
+                    continue;
+                };
+                // Sending only fails if the receiver was dropped
+                senders.retain(|sender| sender.send(change.clone()).is_ok());
+            }
+        }
+
+        subscribers.retain(|_, senders| !senders.is_empty());
+        let count = subscribers.values().map(Vec::len).sum();
+        self.prefix_subscriber_count.store(count, Ordering::SeqCst);
+    }
 }
 
 /// Save notifications to be sent after transaction is complete.
@@ -109,6 +198,10 @@ pub struct NotifyingTransaction<'a> {
     dbtx: Box<dyn ISingleUseDatabaseTransaction<'a>>,
     // notifications to be submitted after commit
     notify_queue: Option<NotifyQueue>,
+    // changes of keys with prefix subscribers to be submitted after commit
+    prefix_changes: Vec<PrefixChange>,
+    // number of recorded prefix changes when the savepoint was set
+    savepoint_prefix_changes: usize,
     notifications: &'a Notifications,
 }
 
@@ -120,6 +213,8 @@ impl<'a> NotifyingTransaction<'a> {
         Self {
             dbtx,
             notify_queue: Some(NotifyQueue::new()),
+            prefix_changes: vec![],
+            savepoint_prefix_changes: 0,
             notifications,
         }
     }
@@ -128,7 +223,12 @@ impl<'a> NotifyingTransaction<'a> {
 #[apply(async_trait_maybe_send!)]
 impl<'a> ISingleUseDatabaseTransaction<'a> for NotifyingTransaction<'a> {
     async fn raw_insert_bytes(&mut self, key: &[u8], value: &[u8]) -> Result<Option<Vec<u8>>> {
-        self.dbtx.raw_insert_bytes(key, value).await
+        let previous = self.dbtx.raw_insert_bytes(key, value).await?;
+        if self.notifications.is_key_subscribed(key) {
+            self.prefix_changes
+                .push((key.to_vec(), Some(value.to_vec())));
+        }
+        Ok(previous)
     }
 
     async fn raw_get_bytes(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>> {
@@ -136,7 +236,11 @@ impl<'a> ISingleUseDatabaseTransaction<'a> for NotifyingTransaction<'a> {
     }
 
     async fn raw_remove_entry(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>> {
-        self.dbtx.raw_remove_entry(key).await
+        let previous = self.dbtx.raw_remove_entry(key).await?;
+        if previous.is_some() && self.notifications.is_key_subscribed(key) {
+            self.prefix_changes.push((key.to_vec(), None));
+        }
+        Ok(previous)
     }
 
     async fn raw_find_by_prefix(&mut self, key_prefix: &[u8]) -> Result<PrefixStream<'_>> {
@@ -152,7 +256,37 @@ impl<'a> ISingleUseDatabaseTransaction<'a> for NotifyingTransaction<'a> {
             .await
     }
 
+    async fn raw_find_by_range(&mut self, key_range: Range<&[u8]>) -> Result<PrefixStream<'_>> {
+        self.dbtx.raw_find_by_range(key_range).await
+    }
+
+    async fn raw_find_by_range_sorted_descending(
+        &mut self,
+        key_range: Range<&[u8]>,
+    ) -> Result<PrefixStream<'_>> {
+        self.dbtx
+            .raw_find_by_range_sorted_descending(key_range)
+            .await
+    }
+
     async fn raw_remove_by_prefix(&mut self, key_prefix: &[u8]) -> Result<()> {
+        if self.notifications.is_prefix_subscribed(key_prefix) {
+            // We need to know the removed keys to notify subscribers
+            let removed_keys = self
+                .dbtx
+                .raw_find_by_prefix(key_prefix)
+                .await?
+                .map(|(key, _)| key)
+                .collect::<Vec<_>>()
+                .await;
+            let notifications = self.notifications;
+            self.prefix_changes.extend(
+                removed_keys
+                    .into_iter()
+                    .filter(|key| notifications.is_key_subscribed(key))
+                    .map(|key| (key, None)),
+            );
+        }
         self.dbtx.raw_remove_by_prefix(key_prefix).await
     }
 
@@ -163,15 +297,21 @@ impl<'a> ISingleUseDatabaseTransaction<'a> for NotifyingTransaction<'a> {
                 .take()
                 .expect("commit must be called only once"),
         );
+        self.notifications
+            .submit_prefix_changes(std::mem::take(&mut self.prefix_changes));
         Ok(())
     }
 
     async fn rollback_tx_to_savepoint(&mut self) -> Result<()> {
-        self.dbtx.rollback_tx_to_savepoint().await
+        self.dbtx.rollback_tx_to_savepoint().await?;
+        self.prefix_changes.truncate(self.savepoint_prefix_changes);
+        Ok(())
     }
 
     async fn set_tx_savepoint(&mut self) -> Result<()> {
-        self.dbtx.set_tx_savepoint().await
+        self.dbtx.set_tx_savepoint().await?;
+        self.savepoint_prefix_changes = self.prefix_changes.len();
+        Ok(())
     }
 
     fn add_notification_key(&mut self, key: &[u8]) -> Result<()> {
@@ -228,6 +368,51 @@ mod tests {
         );
     }
 
+    #[tokio::test]
+    async fn test_prefix_subscription() {
+        let notifs = Notifications::new();
+        let mut sub = notifs.subscribe_prefix(vec![0x42]);
+        assert!(notifs.is_key_subscribed(&[0x42, 0x01]));
+        assert!(!notifs.is_key_subscribed(&[0x43, 0x01]));
+        assert!(notifs.is_prefix_subscribed(&[]));
+        assert!(notifs.is_prefix_subscribed(&[0x42, 0x01]));
+        assert!(!notifs.is_prefix_subscribed(&[0x43]));
+
+        notifs.submit_prefix_changes(vec![
+            (vec![0x42, 0x01], Some(vec![0x01])),
+            (vec![0x43, 0x01], Some(vec![0x02])),
+            (vec![0x42, 0x02], None),
+        ]);
+        assert_eq!(sub.recv().await, Ok((vec![0x42, 0x01], Some(vec![0x01]))));
+        assert_eq!(sub.recv().await, Ok((vec![0x42, 0x02], None)));
+        assert!(sub.try_recv().is_err());
+
+        drop(sub);
+        notifs.submit_prefix_changes(vec![(vec![0x42, 0x03], None)]);
+        assert!(!notifs.is_key_subscribed(&[0x42, 0x01]));
+        assert!(!notifs.is_prefix_subscribed(&[]));
+    }
+
+    #[tokio::test]
+    async fn test_prefix_subscription_lag() {
+        let notifs = Notifications::new();
+        let mut sub = notifs.subscribe_prefix(vec![0x42]);
+
+        notifs.submit_prefix_changes(
+            (0..=PREFIX_SUBSCRIBER_CAPACITY)
+                .map(|idx| (vec![0x42], Some(idx.to_be_bytes().to_vec())))
+                .collect(),
+        );
+        assert_eq!(
+            sub.recv().await,
+            Err(broadcast::error::RecvError::Lagged(1))
+        );
+        assert_eq!(
+            sub.recv().await,
+            Ok((vec![0x42], Some(1usize.to_be_bytes().to_vec())))
+        );
+    }
+
     #[tokio::test]
     async fn test_notify_queue() {
         let notifs = Notifications::new();
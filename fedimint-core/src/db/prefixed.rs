@@ -0,0 +1,213 @@
+//! [`IDatabase`] wrapper that stores all entries under a fixed key prefix, so
+//! that multiple independent [`Database`](super::Database)s can share one
+//! backend.
+//!
+//! Unlike [`Database::new_isolated`](super::Database::new_isolated) the
+//! resulting database can itself be isolated again, which makes it possible to
+//! e.g. run multiple clients with their own module databases on top of one
+//! backend.
+
+use std::ops::Range;
+use std::sync::Arc;
+
+use fedimint_core::{apply, async_trait_maybe_send};
+use futures::StreamExt;
+
+use super::{IDatabase, ISingleUseDatabaseTransaction, PrefixStream, Result};
+
+/// Wraps a shared [`IDatabase`] and transparently prepends `prefix` to every
+/// key, see the [module documentation](self)
+#[derive(Debug, Clone)]
+pub struct PrefixedDatabase {
+    inner: Arc<dyn IDatabase>,
+    prefix: Vec<u8>,
+}
+
+impl PrefixedDatabase {
+    /// Creates a view of `inner` that only contains keys starting with
+    /// `prefix`. Prefixes of databases sharing the same backend must not be
+    /// prefixes of each other, otherwise their entries overlap.
+    pub fn new(inner: Arc<dyn IDatabase>, prefix: Vec<u8>) -> Self {
+        PrefixedDatabase { inner, prefix }
+    }
+}
+
+#[apply(async_trait_maybe_send!)]
+impl IDatabase for PrefixedDatabase {
+    async fn begin_transaction<'a>(&'a self) -> Box<dyn ISingleUseDatabaseTransaction<'a>> {
+        Box::new(PrefixedTransaction {
+            inner: self.inner.begin_transaction().await,
+            prefix: &self.prefix,
+        })
+    }
+}
+
+struct PrefixedTransaction<'a> {
+    inner: Box<dyn ISingleUseDatabaseTransaction<'a>>,
+    prefix: &'a [u8],
+}
+
+impl<'a> PrefixedTransaction<'a> {
+    fn prefixed(&self, key: &[u8]) -> Vec<u8> {
+        let mut prefixed = Vec::with_capacity(self.prefix.len() + key.len());
+        prefixed.extend_from_slice(self.prefix);
+        prefixed.extend_from_slice(key);
+        prefixed
+    }
+
+    /// Removes the prefix from all keys returned by the inner transaction
+    fn strip_prefix(prefix_len: usize, stream: PrefixStream<'_>) -> PrefixStream<'_> {
+        Box::pin(stream.map(move |(key, value)| (key[prefix_len..].to_vec(), value)))
+    }
+}
+
+#[apply(async_trait_maybe_send!)]
+impl<'a> ISingleUseDatabaseTransaction<'a> for PrefixedTransaction<'a> {
+    async fn raw_insert_bytes(&mut self, key: &[u8], value: &[u8]) -> Result<Option<Vec<u8>>> {
+        let key = self.prefixed(key);
+        self.inner.raw_insert_bytes(&key, value).await
+    }
+
+    async fn raw_get_bytes(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        let key = self.prefixed(key);
+        self.inner.raw_get_bytes(&key).await
+    }
+
+    async fn raw_remove_entry(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        let key = self.prefixed(key);
+        self.inner.raw_remove_entry(&key).await
+    }
+
+    async fn raw_find_by_prefix(&mut self, key_prefix: &[u8]) -> Result<PrefixStream<'_>> {
+        let key_prefix = self.prefixed(key_prefix);
+        let stream = self.inner.raw_find_by_prefix(&key_prefix).await?;
+        Ok(Self::strip_prefix(self.prefix.len(), stream))
+    }
+
+    async fn raw_find_by_prefix_sorted_descending(
+        &mut self,
+        key_prefix: &[u8],
+    ) -> Result<PrefixStream<'_>> {
+        let key_prefix = self.prefixed(key_prefix);
+        let stream = self
+            .inner
+            .raw_find_by_prefix_sorted_descending(&key_prefix)
+            .await?;
+        Ok(Self::strip_prefix(self.prefix.len(), stream))
+    }
+
+    async fn raw_find_by_range(&mut self, key_range: Range<&[u8]>) -> Result<PrefixStream<'_>> {
+        let start = self.prefixed(key_range.start);
+        let end = self.prefixed(key_range.end);
+        let stream = self.inner.raw_find_by_range(&start[..]..&end[..]).await?;
+        Ok(Self::strip_prefix(self.prefix.len(), stream))
+    }
+
+    async fn raw_find_by_range_sorted_descending(
+        &mut self,
+        key_range: Range<&[u8]>,
+    ) -> Result<PrefixStream<'_>> {
+        let start = self.prefixed(key_range.start);
+        let end = self.prefixed(key_range.end);
+        let stream = self
+            .inner
+            .raw_find_by_range_sorted_descending(&start[..]..&end[..])
+            .await?;
+        Ok(Self::strip_prefix(self.prefix.len(), stream))
+    }
+
+    async fn raw_remove_by_prefix(&mut self, key_prefix: &[u8]) -> Result<()> {
+        let key_prefix = self.prefixed(key_prefix);
+        self.inner.raw_remove_by_prefix(&key_prefix).await
+    }
+
+    async fn commit_tx(&mut self) -> Result<()> {
+        self.inner.commit_tx().await
+    }
+
+    async fn rollback_tx_to_savepoint(&mut self) -> Result<()> {
+        self.inner.rollback_tx_to_savepoint().await
+    }
+
+    async fn set_tx_savepoint(&mut self) -> Result<()> {
+        self.inner.set_tx_savepoint().await
+    }
+
+    fn add_notification_key(&mut self, key: &[u8]) -> Result<()> {
+        let key = self.prefixed(key);
+        self.inner.add_notification_key(&key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::PrefixedDatabase;
+    use crate::db::mem_impl::MemDatabase;
+    use crate::db::test_utils::{TestKey, TestVal};
+    use crate::db::{Database, IDatabase};
+    use crate::module::registry::ModuleDecoderRegistry;
+
+    fn database(backend: &Arc<dyn IDatabase>, prefix: u8) -> Database {
+        Database::new(
+            PrefixedDatabase::new(backend.clone(), vec![0xfe, prefix]),
+            ModuleDecoderRegistry::default(),
+        )
+    }
+
+    fn fresh_database() -> Database {
+        database(&(Arc::new(MemDatabase::new()) as Arc<dyn IDatabase>), 0)
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_dbtx_insert_elements() {
+        crate::db::verify_insert_elements(fresh_database()).await;
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_dbtx_find_by_prefix() {
+        crate::db::verify_find_by_prefix(fresh_database()).await;
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_dbtx_find_by_range() {
+        crate::db::verify_find_by_range(fresh_database()).await;
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_dbtx_remove_by_prefix() {
+        crate::db::verify_remove_by_prefix(fresh_database()).await;
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_module_db() {
+        let db = fresh_database();
+        let module_db = db.new_isolated(3);
+        crate::db::verify_module_db(db, module_db).await;
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_prefixed_dbs_are_disjoint() {
+        let backend: Arc<dyn IDatabase> = Arc::new(MemDatabase::new());
+        let first = database(&backend, 1);
+        let second = database(&backend, 2);
+
+        let mut dbtx = first.begin_transaction().await;
+        dbtx.insert_entry(&TestKey(1), &TestVal(1)).await;
+        dbtx.commit_tx().await;
+
+        let mut dbtx = second.begin_transaction().await;
+        assert_eq!(dbtx.get_value(&TestKey(1)).await, None);
+        dbtx.insert_entry(&TestKey(1), &TestVal(2)).await;
+        dbtx.commit_tx().await;
+
+        let mut dbtx = first.begin_transaction().await;
+        assert_eq!(dbtx.get_value(&TestKey(1)).await, Some(TestVal(1)));
+        assert_eq!(dbtx.remove_entry(&TestKey(1)).await, Some(TestVal(1)));
+        dbtx.commit_tx().await;
+
+        let mut dbtx = second.begin_transaction().await;
+        assert_eq!(dbtx.get_value(&TestKey(1)).await, Some(TestVal(2)));
+    }
+}
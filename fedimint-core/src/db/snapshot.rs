@@ -0,0 +1,225 @@
+//! Export and import of the complete contents of a database as a single,
+//! versioned and checksummed snapshot file.
+//!
+//! The format is:
+//!
+//! ```text
+//! magic (8 bytes) | version (u8) | entry* | END_MARKER | entry count (u64) | sha256 (32 bytes)
+//! entry = ENTRY_MARKER | key (Vec<u8>) | value (Vec<u8>)
+//! ```
+//!
+//! All fields use the consensus encoding, the checksum covers every byte
+//! preceding it.
+
+use std::io::{Read, Write};
+
+use anyhow::{bail, ensure, Result};
+use bitcoin_hashes::{sha256, Hash, HashEngine};
+use futures::StreamExt;
+
+use super::{Database, DatabaseTransaction};
+use crate::encoding::{Decodable, Encodable};
+use crate::module::registry::ModuleDecoderRegistry;
+
+const SNAPSHOT_MAGIC: [u8; 8] = *b"FMDBSNAP";
+const SNAPSHOT_VERSION: u8 = 1;
+const ENTRY_MARKER: u8 = 0x01;
+const END_MARKER: u8 = 0x00;
+
+impl Database {
+    /// Writes a snapshot of all entries to `writer`. Since everything is read
+    /// in a single transaction the snapshot is consistent even if the
+    /// database is in use concurrently. Returns the number of entries written.
+    pub async fn export_snapshot<W: Write>(&self, writer: W) -> Result<u64> {
+        self.begin_transaction().await.export_snapshot(writer).await
+    }
+
+    /// Restores a snapshot written by [`Database::export_snapshot`]. The
+    /// database has to be empty. All entries are written in a single
+    /// transaction that is only committed after the checksum was verified.
+    /// Returns the number of entries restored.
+    pub async fn import_snapshot<R: Read>(&self, reader: R) -> Result<u64> {
+        let mut dbtx = self.begin_transaction().await;
+        if dbtx
+            .tx
+            .raw_find_by_prefix(&[])
+            .await?
+            .next()
+            .await
+            .is_some()
+        {
+            bail!("Snapshots can only be imported into an empty database");
+        }
+
+        let decoders = ModuleDecoderRegistry::default();
+        let mut reader = HashingReader::new(reader);
+
+        let mut magic = [0u8; 8];
+        reader.read_exact(&mut magic)?;
+        ensure!(magic == SNAPSHOT_MAGIC, "Not a database snapshot");
+        let version = u8::consensus_decode(&mut reader, &decoders)?;
+        ensure!(
+            version == SNAPSHOT_VERSION,
+            "Unsupported snapshot version {version}"
+        );
+
+        let mut entry_count = 0u64;
+        loop {
+            match u8::consensus_decode(&mut reader, &decoders)? {
+                ENTRY_MARKER => {
+                    let key = Vec::<u8>::consensus_decode(&mut reader, &decoders)?;
+                    let value = Vec::<u8>::consensus_decode(&mut reader, &decoders)?;
+                    dbtx.tx.raw_insert_bytes(&key, &value).await?;
+                    entry_count += 1;
+                }
+                END_MARKER => break,
+                marker => bail!("Invalid snapshot entry marker {marker}"),
+            }
+        }
+
+        let expected_entry_count = u64::consensus_decode(&mut reader, &decoders)?;
+        ensure!(
+            entry_count == expected_entry_count,
+            "Snapshot contains {entry_count} entries, expected {expected_entry_count}"
+        );
+
+        let (mut reader, checksum) = reader.finalize();
+        let mut expected_checksum = [0u8; 32];
+        reader.read_exact(&mut expected_checksum)?;
+        ensure!(
+            checksum.into_inner() == expected_checksum,
+            "Snapshot checksum mismatch"
+        );
+
+        dbtx.commit_tx_result().await?;
+        Ok(entry_count)
+    }
+}
+
+impl<'parent> DatabaseTransaction<'parent> {
+    /// Writes a snapshot of all entries visible to this transaction to
+    /// `writer`, see [`Database::export_snapshot`]. Useful for exporting from
+    /// read-only database handles.
+    pub async fn export_snapshot<W: Write>(&mut self, writer: W) -> Result<u64> {
+        let mut writer = HashingWriter::new(writer);
+        writer.write_all(&SNAPSHOT_MAGIC)?;
+        SNAPSHOT_VERSION.consensus_encode(&mut writer)?;
+
+        let mut entry_count = 0u64;
+        let mut entries = self.tx.raw_find_by_prefix(&[]).await?;
+        while let Some((key, value)) = entries.next().await {
+            ENTRY_MARKER.consensus_encode(&mut writer)?;
+            key.consensus_encode(&mut writer)?;
+            value.consensus_encode(&mut writer)?;
+            entry_count += 1;
+        }
+
+        END_MARKER.consensus_encode(&mut writer)?;
+        entry_count.consensus_encode(&mut writer)?;
+
+        let (mut writer, checksum) = writer.finalize();
+        writer.write_all(&checksum.into_inner())?;
+        writer.flush()?;
+
+        Ok(entry_count)
+    }
+}
+
+/// Writer that hashes everything written through it
+struct HashingWriter<W> {
+    inner: W,
+    engine: sha256::HashEngine,
+}
+
+impl<W: Write> HashingWriter<W> {
+    fn new(inner: W) -> Self {
+        HashingWriter {
+            inner,
+            engine: sha256::Hash::engine(),
+        }
+    }
+
+    fn finalize(self) -> (W, sha256::Hash) {
+        (self.inner, sha256::Hash::from_engine(self.engine))
+    }
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.engine.input(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Reader that hashes everything read through it
+struct HashingReader<R> {
+    inner: R,
+    engine: sha256::HashEngine,
+}
+
+impl<R: Read> HashingReader<R> {
+    fn new(inner: R) -> Self {
+        HashingReader {
+            inner,
+            engine: sha256::Hash::engine(),
+        }
+    }
+
+    fn finalize(self) -> (R, sha256::Hash) {
+        (self.inner, sha256::Hash::from_engine(self.engine))
+    }
+}
+
+impl<R: Read> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.engine.input(&buf[..read]);
+        Ok(read)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::mem_impl::MemDatabase;
+    use crate::db::{Database, TestKey, TestVal};
+    use crate::module::registry::ModuleDecoderRegistry;
+
+    fn database() -> Database {
+        Database::new(MemDatabase::new(), ModuleDecoderRegistry::default())
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_snapshot_corrupted() {
+        let db = database();
+        let mut dbtx = db.begin_transaction().await;
+        for i in 0..100 {
+            dbtx.insert_entry(&TestKey(i), &TestVal(i * 2)).await;
+        }
+        dbtx.commit_tx().await;
+
+        let mut snapshot = vec![];
+        db.export_snapshot(&mut snapshot).await.unwrap();
+
+        let restored = database();
+
+        let mut corrupted = snapshot.clone();
+        let last_value_byte = corrupted.len() - 35;
+        corrupted[last_value_byte] ^= 0x01;
+        assert!(restored
+            .import_snapshot(corrupted.as_slice())
+            .await
+            .is_err());
+
+        let truncated = &snapshot[..snapshot.len() - 1];
+        assert!(restored.import_snapshot(truncated).await.is_err());
+
+        // Nothing was committed by the failed imports
+        let mut dbtx = restored.begin_transaction().await;
+        assert!(dbtx.get_value(&TestKey(0)).await.is_none());
+    }
+}
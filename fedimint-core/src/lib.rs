@@ -118,6 +118,14 @@ impl Amount {
             msats: self.msats.saturating_sub(other.msats),
         }
     }
+
+    /// Subtracts `other`, returns `None` instead of panicking if it's larger
+    /// than `self`
+    pub fn checked_sub(self, other: Amount) -> Option<Self> {
+        Some(Amount {
+            msats: self.msats.checked_sub(other.msats)?,
+        })
+    }
 }
 
 /// Shorthand for [`Amount::from_msats`]
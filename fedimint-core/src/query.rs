@@ -434,6 +434,9 @@ fn discover_common_core_api_version_sanity() {
     );
 }
 
+/// Unlike the core API version, the discovered module API version carries the
+/// highest minor version all compatible peers support, so modules can tell
+/// which additions to their API they may use.
 fn discover_common_module_api_version(
     client_versions: &SupportedModuleApiVersions,
     peer_versions: BTreeMap<PeerId, SupportedModuleApiVersions>,
@@ -442,9 +445,9 @@ fn discover_common_module_api_version(
     let mut best_major_peer_num = 0;
 
     for client_api_version in &client_versions.api {
-        let peers_compatible_num = peer_versions
+        let peers_compatible = peer_versions
             .values()
-            .filter(|supported_versions| {
+            .filter_map(|supported_versions| {
                 (supported_versions.core_consensus == client_versions.core_consensus
                     && supported_versions.module_consensus == client_versions.module_consensus)
                     .then(|| {
@@ -453,20 +456,67 @@ fn discover_common_module_api_version(
                             .get_by_major(client_api_version.major)
                     })
                     .flatten()
-                    .map(|peer_version| client_api_version.minor <= peer_version.minor)
-                    .unwrap_or(false)
+                    .filter(|peer_version| client_api_version.minor <= peer_version.minor)
             })
-            .count();
+            .collect::<Vec<_>>();
 
-        if best_major_peer_num < peers_compatible_num {
-            best_major = Some(client_api_version);
-            best_major_peer_num = peers_compatible_num;
+        if best_major_peer_num < peers_compatible.len() {
+            best_major = peers_compatible
+                .iter()
+                .map(|peer_version| peer_version.minor)
+                .min()
+                .map(|minor| ApiVersion {
+                    major: client_api_version.major,
+                    minor,
+                });
+            best_major_peer_num = peers_compatible.len();
         }
     }
 
     best_major
 }
 
+#[test]
+fn discover_common_module_api_version_sanity() {
+    use fedimint_core::module::MultiApiVersion;
+
+    let module_versions = |module_consensus: u32, api: ApiVersion| SupportedModuleApiVersions {
+        core_consensus: 0.into(),
+        module_consensus: module_consensus.into(),
+        api: MultiApiVersion::try_from_iter([api]).unwrap(),
+    };
+    let client_versions = module_versions(1, ApiVersion { major: 0, minor: 0 });
+
+    assert!(discover_common_module_api_version(&client_versions, BTreeMap::from([])).is_none());
+    assert_eq!(
+        discover_common_module_api_version(
+            &client_versions,
+            BTreeMap::from([
+                (
+                    PeerId(0),
+                    module_versions(1, ApiVersion { major: 0, minor: 2 })
+                ),
+                (
+                    PeerId(1),
+                    module_versions(1, ApiVersion { major: 0, minor: 1 })
+                ),
+            ])
+        ),
+        Some(ApiVersion { major: 0, minor: 1 })
+    );
+    assert_eq!(
+        discover_common_module_api_version(
+            &client_versions,
+            BTreeMap::from([(
+                PeerId(0),
+                // wrong consensus version
+                module_versions(0, ApiVersion { major: 0, minor: 1 })
+            )])
+        ),
+        None
+    );
+}
+
 fn discover_common_api_versions_set(
     client_versions: &SupportedApiVersionsSummary,
     peer_versions: BTreeMap<PeerId, SupportedApiVersionsSummary>,
@@ -2,7 +2,8 @@ use bitcoin::hashes::Hash as BitcoinHash;
 use bitcoin::XOnlyPublicKey;
 use bitcoin_hashes::hex::ToHex;
 use fedimint_core::core::{DynInput, DynOutput};
-use fedimint_core::encoding::{Decodable, Encodable};
+use fedimint_core::encoding::{Decodable, DecodeError, Encodable};
+use fedimint_core::module::registry::ModuleDecoderRegistry;
 use fedimint_core::module::SerdeModuleEncoding;
 use fedimint_core::{Amount, TransactionId};
 use rand::Rng;
@@ -15,18 +16,86 @@ use thiserror::Error;
 /// of the inputs, to prevent creating funds out of thin air. In some cases, the
 /// value of the inputs and outputs can both be 0 e.g. when creating an offer to
 /// a Lightning Gateway.
-#[derive(Debug, Clone, Eq, PartialEq, Hash, Encodable, Decodable)]
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
 pub struct Transaction {
     /// [`DynInput`]s consumed by the transaction
     pub inputs: Vec<DynInput>,
     /// [`DynOutput`]s created as a result of the transaction
     pub outputs: Vec<DynOutput>,
+    /// Epoch from which on the transaction is rejected, `None` if it never
+    /// expires
+    pub expiry: Option<u64>,
     /// Aggregated MuSig2 signature over all the public keys of the inputs
     pub signature: Option<schnorr::Signature>,
 }
 
 pub type SerdeTransaction = SerdeModuleEncoding<Transaction>;
 
+/// Flag set in the byte preceding the signature if the transaction has one
+const TX_FLAG_SIGNATURE: u8 = 0x01;
+/// Flag set in the byte preceding the signature if an expiry follows it
+const TX_FLAG_EXPIRY: u8 = 0x02;
+
+/// Transactions were encoded as `(inputs, outputs, signature)` before expiries
+/// were introduced. To keep all previously encoded transactions (e.g. in the
+/// epoch history) valid, the `Option` flag of the signature is extended to a
+/// bit field announcing an expiry after the signature. Transactions without
+/// expiry are encoded exactly as before.
+impl Encodable for Transaction {
+    fn consensus_encode<W: std::io::Write>(&self, writer: &mut W) -> Result<usize, std::io::Error> {
+        let mut flags = 0;
+        if self.signature.is_some() {
+            flags |= TX_FLAG_SIGNATURE;
+        }
+        if self.expiry.is_some() {
+            flags |= TX_FLAG_EXPIRY;
+        }
+
+        let mut len = 0;
+        len += self.inputs.consensus_encode(writer)?;
+        len += self.outputs.consensus_encode(writer)?;
+        len += flags.consensus_encode(writer)?;
+        if let Some(signature) = &self.signature {
+            len += signature.consensus_encode(writer)?;
+        }
+        if let Some(expiry) = self.expiry {
+            len += expiry.consensus_encode(writer)?;
+        }
+        Ok(len)
+    }
+}
+
+impl Decodable for Transaction {
+    fn consensus_decode<R: std::io::Read>(
+        reader: &mut R,
+        modules: &ModuleDecoderRegistry,
+    ) -> Result<Self, DecodeError> {
+        let inputs = Vec::<DynInput>::consensus_decode(reader, modules)?;
+        let outputs = Vec::<DynOutput>::consensus_decode(reader, modules)?;
+        let flags = u8::consensus_decode(reader, modules)?;
+        if flags & !(TX_FLAG_SIGNATURE | TX_FLAG_EXPIRY) != 0 {
+            return Err(DecodeError::from_str("Unknown transaction flags"));
+        }
+        let signature = if flags & TX_FLAG_SIGNATURE != 0 {
+            Some(schnorr::Signature::consensus_decode(reader, modules)?)
+        } else {
+            None
+        };
+        let expiry = if flags & TX_FLAG_EXPIRY != 0 {
+            Some(u64::consensus_decode(reader, modules)?)
+        } else {
+            None
+        };
+
+        Ok(Transaction {
+            inputs,
+            outputs,
+            expiry,
+            signature,
+        })
+    }
+}
+
 impl Transaction {
     /// Hash of the transaction (excluding the signature).
     ///
@@ -34,11 +103,18 @@ impl Transaction {
     /// To generate it without already having a signature use
     /// [`Self::tx_hash_from_parts`].
     pub fn tx_hash(&self) -> TransactionId {
-        Self::tx_hash_from_parts(&self.inputs, &self.outputs)
+        Self::tx_hash_from_parts(&self.inputs, &self.outputs, self.expiry)
     }
 
     /// Generate the transaction hash.
-    pub fn tx_hash_from_parts(inputs: &[DynInput], outputs: &[DynOutput]) -> TransactionId {
+    ///
+    /// The expiry is only committed to if there is one, so transactions without
+    /// expiry keep the same id as before expiries were introduced.
+    pub fn tx_hash_from_parts(
+        inputs: &[DynInput],
+        outputs: &[DynOutput],
+        expiry: Option<u64>,
+    ) -> TransactionId {
         let mut engine = TransactionId::engine();
         inputs
             .consensus_encode(&mut engine)
@@ -46,9 +122,22 @@ impl Transaction {
         outputs
             .consensus_encode(&mut engine)
             .expect("write to hash engine can't fail");
+        if let Some(expiry) = expiry {
+            expiry
+                .consensus_encode(&mut engine)
+                .expect("write to hash engine can't fail");
+        }
         TransactionId::from_engine(engine)
     }
 
+    /// Checks that the transaction may still be included in `epoch`
+    pub fn validate_expiry(&self, epoch: u64) -> Result<(), TransactionError> {
+        match self.expiry {
+            Some(expiry) if epoch >= expiry => Err(TransactionError::Expired { expiry, epoch }),
+            _ => Ok(()),
+        }
+    }
+
     /// Validate the aggregated Schnorr Signature signed over the `tx_hash`
     pub fn validate_signature(
         &self,
@@ -178,4 +267,80 @@ pub enum TransactionError {
     },
     #[error("The transaction did not have a signature although there were inputs to be signed")]
     MissingSignature,
+    #[error("The transaction expired at epoch {expiry}, the current epoch is {epoch}")]
+    Expired { expiry: u64, epoch: u64 },
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use fedimint_core::encoding::{Decodable, Encodable};
+    use fedimint_core::module::registry::ModuleDecoderRegistry;
+    use secp256k1_zkp::schnorr;
+
+    use super::Transaction;
+
+    /// Encoding of transactions before expiries were introduced
+    #[derive(Encodable)]
+    struct TransactionV0 {
+        inputs: Vec<super::DynInput>,
+        outputs: Vec<super::DynOutput>,
+        signature: Option<schnorr::Signature>,
+    }
+
+    fn signature() -> schnorr::Signature {
+        schnorr::Signature::from_slice(&[42; 64]).expect("valid length")
+    }
+
+    #[test]
+    fn transaction_encoding_is_backwards_compatible() {
+        for signature in [None, Some(signature())] {
+            let old_encoding = TransactionV0 {
+                inputs: vec![],
+                outputs: vec![],
+                signature,
+            }
+            .consensus_encode_to_vec()
+            .unwrap();
+            let tx = Transaction {
+                inputs: vec![],
+                outputs: vec![],
+                expiry: None,
+                signature,
+            };
+
+            assert_eq!(tx.consensus_encode_to_vec().unwrap(), old_encoding);
+            assert_eq!(
+                Transaction::consensus_decode(
+                    &mut Cursor::new(old_encoding),
+                    &ModuleDecoderRegistry::default()
+                )
+                .unwrap(),
+                tx
+            );
+        }
+    }
+
+    #[test]
+    fn transaction_with_expiry_roundtrips() {
+        for signature in [None, Some(signature())] {
+            let tx = Transaction {
+                inputs: vec![],
+                outputs: vec![],
+                expiry: Some(1234),
+                signature,
+            };
+            let encoded = tx.consensus_encode_to_vec().unwrap();
+
+            assert_eq!(
+                Transaction::consensus_decode(
+                    &mut Cursor::new(encoded),
+                    &ModuleDecoderRegistry::default()
+                )
+                .unwrap(),
+                tx
+            );
+        }
+    }
 }
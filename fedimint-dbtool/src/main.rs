@@ -1,17 +1,23 @@
 #![allow(where_clauses_object_safety)] // https://github.com/dtolnay/async-trait/issues/228
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
 use std::path::PathBuf;
 
 use anyhow::Result;
 use bitcoin_hashes::hex::ToHex;
 use bytes::Bytes;
 use clap::{Parser, Subcommand};
-use fedimint_core::db::IDatabase;
+use fedimint_core::db::notifications::Notifications;
+use fedimint_core::db::{Database, DatabaseTransaction, IDatabase, SingleUseDatabaseTransaction};
+use fedimint_core::module::registry::ModuleDecoderRegistry;
 use fedimint_logging::TracingSetup;
 use futures::StreamExt;
 
 use crate::dump::DatabaseDump;
+use crate::migrate::{migrate, DatabaseLocation};
 
 mod dump;
+mod migrate;
 
 #[derive(Debug, Clone, Parser)]
 struct Options {
@@ -58,6 +64,28 @@ enum DbCommand {
         #[arg(long, required = false)]
         prefixes: Option<String>,
     },
+    /// Write a consistent snapshot of the complete database to `output`. The
+    /// database is opened read-only, so this can be used on the database of a
+    /// running guardian.
+    ExportSnapshot {
+        #[arg(long)]
+        output: PathBuf,
+    },
+    /// Restore a snapshot created by `export-snapshot` into the database,
+    /// which has to be empty
+    ImportSnapshot {
+        #[arg(long)]
+        input: PathBuf,
+    },
+    /// Copy all entries into another, empty database and verify the copy
+    /// afterwards. Databases are specified as `rocksdb:<path>` or
+    /// `sqlite:<path>`, `--database` is ignored.
+    Migrate {
+        #[arg(long)]
+        from: DatabaseLocation,
+        #[arg(long)]
+        to: DatabaseLocation,
+    },
 }
 
 fn hex_parser(hex: &str) -> Result<Bytes> {
@@ -133,6 +161,31 @@ async fn main() -> Result<()> {
                 DatabaseDump::new(cfg_dir, options.database, password, modules, prefix_names);
             dbdump.dump_database().await;
         }
+        DbCommand::ExportSnapshot { output } => {
+            let read_only = fedimint_rocksdb::RocksDbReadOnly::open_read_only(&options.database)?;
+            // leak here is OK, it only happens once.
+            let notifications = Box::leak(Box::new(Notifications::new()));
+            let mut dbtx = DatabaseTransaction::new(
+                Box::new(SingleUseDatabaseTransaction::new(read_only)),
+                ModuleDecoderRegistry::default(),
+                notifications,
+            );
+            let file = BufWriter::new(File::create(&output)?);
+            let entries = dbtx.export_snapshot(file).await?;
+            println!("Exported {entries} entries to {}", output.display());
+        }
+        DbCommand::ImportSnapshot { input } => {
+            let db = Database::new(
+                fedimint_rocksdb::RocksDb::open(&options.database)?,
+                ModuleDecoderRegistry::default(),
+            );
+            let file = BufReader::new(File::open(&input)?);
+            let entries = db.import_snapshot(file).await?;
+            println!("Imported {entries} entries from {}", input.display());
+        }
+        DbCommand::Migrate { from, to } => {
+            migrate(&from, &to).await?;
+        }
     }
 
     Ok(())
@@ -0,0 +1,270 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use anyhow::{bail, ensure, format_err, Result};
+use bitcoin_hashes::hex::ToHex;
+use bitcoin_hashes::{sha256, Hash, HashEngine};
+use fedimint_core::db::{DatabaseKeyPrefix, DatabaseVersionKey, IDatabase};
+use futures::StreamExt;
+
+/// A database of one of the supported backends, specified as
+/// `<backend>:<path>`, e.g. `rocksdb:/var/lib/fedimint/database`
+#[derive(Debug, Clone)]
+pub enum DatabaseLocation {
+    RocksDb(PathBuf),
+    Sqlite(PathBuf),
+}
+
+impl FromStr for DatabaseLocation {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (backend, path) = s
+            .split_once(':')
+            .ok_or_else(|| format_err!("Expected <backend>:<path>, got {s}"))?;
+        ensure!(!path.is_empty(), "Database path must not be empty");
+        match backend {
+            "rocksdb" => Ok(DatabaseLocation::RocksDb(path.into())),
+            "sqlite" => Ok(DatabaseLocation::Sqlite(path.into())),
+            _ => bail!("Unknown database backend {backend}, expected rocksdb or sqlite"),
+        }
+    }
+}
+
+impl fmt::Display for DatabaseLocation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DatabaseLocation::RocksDb(path) => write!(f, "rocksdb:{}", path.display()),
+            DatabaseLocation::Sqlite(path) => write!(f, "sqlite:{}", path.display()),
+        }
+    }
+}
+
+impl DatabaseLocation {
+    async fn open(&self) -> Result<Box<dyn IDatabase>> {
+        Ok(match self {
+            DatabaseLocation::RocksDb(path) => Box::new(fedimint_rocksdb::RocksDb::open(path)?),
+            DatabaseLocation::Sqlite(path) => {
+                let connection_string = format!("sqlite://{}", path.display());
+                Box::new(fedimint_sqlite::SqliteDb::open(&connection_string).await?)
+            }
+        })
+    }
+}
+
+/// Number of entries and hash over all keys and values sharing the same first
+/// key byte, entries with an empty key are summarized under `None`
+#[derive(Debug, PartialEq, Eq)]
+struct PrefixSummary {
+    entries: u64,
+    hash: sha256::Hash,
+}
+
+/// Copies every entry from `from` into the empty database `to` and verifies
+/// that both databases contain the same entries afterwards. Since the
+/// `DatabaseVersionKey`s are copied verbatim the migrated database continues
+/// to work with `apply_migrations`.
+pub async fn migrate(from: &DatabaseLocation, to: &DatabaseLocation) -> Result<()> {
+    let source = from.open().await?;
+    let destination = to.open().await?;
+
+    ensure!(
+        read_all(destination.as_ref()).await?.is_empty(),
+        "Destination database {to} is not empty"
+    );
+
+    let entries = read_all(source.as_ref()).await?;
+    let mut dbtx = destination.begin_transaction().await;
+    for (key, value) in &entries {
+        dbtx.raw_insert_bytes(key, value).await?;
+    }
+    dbtx.commit_tx().await?;
+    println!("Copied {} entries from {from} to {to}", entries.len());
+
+    let version_key = DatabaseVersionKey.to_bytes();
+    let source_summary = summarize(&entries);
+    let destination_entries = read_all(destination.as_ref()).await?;
+    let destination_summary = summarize(&destination_entries);
+
+    for (prefix, summary) in &source_summary {
+        let prefix_name = match prefix {
+            Some(prefix) => format!("{prefix:02x}"),
+            None => "<empty>".to_string(),
+        };
+        println!(
+            "Prefix {prefix_name}: {} entries, hash {}",
+            summary.entries,
+            summary.hash.to_hex()
+        );
+        if destination_summary.get(prefix) != Some(summary) {
+            bail!("Verification failed, entries with prefix {prefix_name} differ");
+        }
+    }
+    ensure!(
+        source_summary.len() == destination_summary.len(),
+        "Verification failed, destination contains unexpected prefixes"
+    );
+    ensure!(
+        entries.get(&version_key) == destination_entries.get(&version_key),
+        "Verification failed, database version differs"
+    );
+
+    println!("Verified {} prefixes", source_summary.len());
+    Ok(())
+}
+
+/// Reads all entries of `db` sorted by key
+async fn read_all(db: &dyn IDatabase) -> Result<BTreeMap<Vec<u8>, Vec<u8>>> {
+    let mut dbtx = db.begin_transaction().await;
+    let mut entries = BTreeMap::new();
+    let mut stream = dbtx.raw_find_by_prefix(&[]).await?;
+    while let Some((key, value)) = stream.next().await {
+        // SQLite can contain stale duplicates of a key, the first one returned
+        // is the value `raw_get_bytes` would return
+        entries.entry(key).or_insert(value);
+    }
+    drop(stream);
+    dbtx.commit_tx().await?;
+    Ok(entries)
+}
+
+fn summarize(entries: &BTreeMap<Vec<u8>, Vec<u8>>) -> BTreeMap<Option<u8>, PrefixSummary> {
+    let mut engines = BTreeMap::<Option<u8>, (u64, sha256::HashEngine)>::new();
+    for (key, value) in entries {
+        let (count, engine) = engines
+            .entry(key.first().copied())
+            .or_insert_with(|| (0, sha256::Hash::engine()));
+        *count += 1;
+        for bytes in [key, value] {
+            engine.input(&(bytes.len() as u64).to_be_bytes());
+            engine.input(bytes);
+        }
+    }
+
+    engines
+        .into_iter()
+        .map(|(prefix, (entries, engine))| {
+            (
+                prefix,
+                PrefixSummary {
+                    entries,
+                    hash: sha256::Hash::from_engine(engine),
+                },
+            )
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+
+    async fn insert_entries(location: &DatabaseLocation, entries: &BTreeMap<Vec<u8>, Vec<u8>>) {
+        let db = location.open().await.unwrap();
+        let mut dbtx = db.begin_transaction().await;
+        for (key, value) in entries {
+            dbtx.raw_insert_bytes(key, value).await.unwrap();
+        }
+        dbtx.commit_tx().await.unwrap();
+    }
+
+    async fn read_location(location: &DatabaseLocation) -> BTreeMap<Vec<u8>, Vec<u8>> {
+        let db = location.open().await.unwrap();
+        read_all(db.as_ref()).await.unwrap()
+    }
+
+    fn test_entries() -> BTreeMap<Vec<u8>, Vec<u8>> {
+        let mut entries = BTreeMap::new();
+        entries.insert(DatabaseVersionKey.to_bytes(), vec![0, 2]);
+        entries.insert(vec![0x01, 0xaa], vec![]);
+        entries.insert(vec![0x01, 0xbb, 0xcc], vec![1, 2, 3]);
+        entries.insert(vec![0x2c, 0x00], vec![0xff; 64]);
+        entries
+    }
+
+    fn location(dir: &Path, name: &str) -> (DatabaseLocation, DatabaseLocation) {
+        (
+            DatabaseLocation::RocksDb(dir.join(format!("{name}-rocksdb"))),
+            DatabaseLocation::Sqlite(dir.join(format!("{name}.db"))),
+        )
+    }
+
+    #[test]
+    fn parses_database_location() {
+        assert!(matches!(
+            "rocksdb:/var/lib/fedimint".parse(),
+            Ok(DatabaseLocation::RocksDb(path)) if path == Path::new("/var/lib/fedimint")
+        ));
+        assert!(matches!(
+            "sqlite:relative/db.sqlite".parse(),
+            Ok(DatabaseLocation::Sqlite(path)) if path == Path::new("relative/db.sqlite")
+        ));
+        // Only the first colon separates the backend from the path
+        assert!(matches!(
+            "sqlite:C:/fedimint.db".parse(),
+            Ok(DatabaseLocation::Sqlite(path)) if path == Path::new("C:/fedimint.db")
+        ));
+        assert_eq!(
+            "rocksdb:/tmp/db"
+                .parse::<DatabaseLocation>()
+                .unwrap()
+                .to_string(),
+            "rocksdb:/tmp/db"
+        );
+
+        assert!("/var/lib/fedimint".parse::<DatabaseLocation>().is_err());
+        assert!("rocksdb:".parse::<DatabaseLocation>().is_err());
+        assert!("postgres:/tmp/db".parse::<DatabaseLocation>().is_err());
+    }
+
+    #[test]
+    fn summarizes_empty_key() {
+        let mut entries = test_entries();
+        entries.insert(vec![], vec![42]);
+
+        let summary = summarize(&entries);
+        assert_eq!(summary.get(&None).map(|summary| summary.entries), Some(1));
+        assert_eq!(
+            summary.get(&Some(0x01)).map(|summary| summary.entries),
+            Some(2)
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn migrates_rocksdb_to_sqlite_and_back() {
+        let dir = tempfile::Builder::new()
+            .prefix("fedimint-dbtool-migrate")
+            .tempdir()
+            .unwrap();
+        let (source, sqlite) = location(dir.path(), "source");
+        let (destination, _) = location(dir.path(), "destination");
+        let entries = test_entries();
+
+        insert_entries(&source, &entries).await;
+        migrate(&source, &sqlite).await.unwrap();
+        migrate(&sqlite, &destination).await.unwrap();
+
+        assert_eq!(read_location(&sqlite).await, entries);
+        assert_eq!(read_location(&destination).await, entries);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn refuses_non_empty_destination() {
+        let dir = tempfile::Builder::new()
+            .prefix("fedimint-dbtool-migrate-non-empty")
+            .tempdir()
+            .unwrap();
+        let (source, destination) = location(dir.path(), "db");
+        let existing = BTreeMap::from([(vec![0x01], vec![0x02])]);
+
+        insert_entries(&source, &test_entries()).await;
+        insert_entries(&destination, &existing).await;
+
+        assert!(migrate(&source, &destination).await.is_err());
+        assert_eq!(read_location(&destination).await, existing);
+    }
+}
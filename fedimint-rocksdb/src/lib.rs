@@ -1,4 +1,5 @@
 #![allow(where_clauses_object_safety)] // https://github.com/dtolnay/async-trait/issues/228
+use std::ops::Range;
 use std::path::Path;
 
 use anyhow::Result;
@@ -160,6 +161,51 @@ impl<'a> IDatabaseTransaction<'a> for RocksDbTransaction<'a> {
         }))
     }
 
+    async fn raw_find_by_range(&mut self, key_range: Range<&[u8]>) -> Result<PrefixStream<'_>> {
+        Ok(fedimint_core::task::block_in_place(|| {
+            let start = key_range.start.to_vec();
+            let end = key_range.end.to_vec();
+            let mut options = rocksdb::ReadOptions::default();
+            options.set_iterate_range(start.clone()..end.clone());
+            let iter = self.0.snapshot().iterator_opt(
+                rocksdb::IteratorMode::From(&start, rocksdb::Direction::Forward),
+                options,
+            );
+            let rocksdb_iter = iter
+                .map_while(move |res| {
+                    let (key_bytes, value_bytes) = res.expect("Error reading from RocksDb");
+                    (key_bytes.as_ref() < end.as_slice()).then_some((key_bytes, value_bytes))
+                })
+                .map(|(key_bytes, value_bytes)| (key_bytes.to_vec(), value_bytes.to_vec()));
+            Box::pin(stream::iter(rocksdb_iter))
+        }))
+    }
+
+    async fn raw_find_by_range_sorted_descending(
+        &mut self,
+        key_range: Range<&[u8]>,
+    ) -> Result<PrefixStream<'_>> {
+        Ok(fedimint_core::task::block_in_place(|| {
+            let start = key_range.start.to_vec();
+            let end = key_range.end.to_vec();
+            let mut options = rocksdb::ReadOptions::default();
+            options.set_iterate_range(start.clone()..end);
+            // The upper bound is exclusive, so iterating from the end of the
+            // bounded range starts at the last key before `end`
+            let iter = self
+                .0
+                .snapshot()
+                .iterator_opt(rocksdb::IteratorMode::End, options);
+            let rocksdb_iter = iter
+                .map_while(move |res| {
+                    let (key_bytes, value_bytes) = res.expect("Error reading from RocksDb");
+                    (key_bytes.as_ref() >= start.as_slice()).then_some((key_bytes, value_bytes))
+                })
+                .map(|(key_bytes, value_bytes)| (key_bytes.to_vec(), value_bytes.to_vec()));
+            Box::pin(stream::iter(rocksdb_iter))
+        }))
+    }
+
     async fn commit_tx(self) -> Result<()> {
         fedimint_core::task::block_in_place(|| {
             self.0.commit()?;
@@ -241,6 +287,51 @@ impl IDatabaseTransaction<'_> for RocksDbReadOnly {
         }))
     }
 
+    async fn raw_find_by_range(&mut self, key_range: Range<&[u8]>) -> Result<PrefixStream<'_>> {
+        Ok(fedimint_core::task::block_in_place(|| {
+            let start = key_range.start.to_vec();
+            let end = key_range.end.to_vec();
+            let mut options = rocksdb::ReadOptions::default();
+            options.set_iterate_range(start.clone()..end.clone());
+            let iter = self.0.snapshot().iterator_opt(
+                rocksdb::IteratorMode::From(&start, rocksdb::Direction::Forward),
+                options,
+            );
+            let rocksdb_iter = iter
+                .map_while(move |res| {
+                    let (key_bytes, value_bytes) = res.expect("Error reading from RocksDb");
+                    (key_bytes.as_ref() < end.as_slice()).then_some((key_bytes, value_bytes))
+                })
+                .map(|(key_bytes, value_bytes)| (key_bytes.to_vec(), value_bytes.to_vec()));
+            Box::pin(stream::iter(rocksdb_iter))
+        }))
+    }
+
+    async fn raw_find_by_range_sorted_descending(
+        &mut self,
+        key_range: Range<&[u8]>,
+    ) -> Result<PrefixStream<'_>> {
+        Ok(fedimint_core::task::block_in_place(|| {
+            let start = key_range.start.to_vec();
+            let end = key_range.end.to_vec();
+            let mut options = rocksdb::ReadOptions::default();
+            options.set_iterate_range(start.clone()..end);
+            // The upper bound is exclusive, so iterating from the end of the
+            // bounded range starts at the last key before `end`
+            let iter = self
+                .0
+                .snapshot()
+                .iterator_opt(rocksdb::IteratorMode::End, options);
+            let rocksdb_iter = iter
+                .map_while(move |res| {
+                    let (key_bytes, value_bytes) = res.expect("Error reading from RocksDb");
+                    (key_bytes.as_ref() >= start.as_slice()).then_some((key_bytes, value_bytes))
+                })
+                .map(|(key_bytes, value_bytes)| (key_bytes.to_vec(), value_bytes.to_vec()));
+            Box::pin(stream::iter(rocksdb_iter))
+        }))
+    }
+
     async fn commit_tx(self) -> Result<()> {
         panic!("Cannot commit a read only transaction");
     }
@@ -316,6 +407,20 @@ mod fedimint_rocksdb_tests {
             .await;
     }
 
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_dbtx_find_by_range() {
+        fedimint_core::db::verify_find_by_range(open_temp_db("fcb-rocksdb-test-find-by-range"))
+            .await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_dbtx_snapshot_roundtrip() {
+        fedimint_core::db::verify_snapshot_roundtrip(open_temp_db(
+            "fcb-rocksdb-test-snapshot-roundtrip",
+        ))
+        .await;
+    }
+
     #[tokio::test(flavor = "multi_thread")]
     async fn test_dbtx_commit() {
         fedimint_core::db::verify_commit(open_temp_db("fcb-rocksdb-test-commit")).await;
@@ -148,7 +148,7 @@ impl FedimintConsensus {
                                 .await
                                 .expect("Setting transaction savepoint failed");
 
-                            let decision = match self.process_consensus_item(dbtx, consensus_item.clone(), peer_id).await {
+                            let decision = match self.process_consensus_item(dbtx, consensus_item.clone(), peer_id, consensus_outcome.epoch).await {
                                 Ok(decision) => decision,
                                 Err(error) => {
                                     warn!(target: "consensus", "Invalid consensus item from {peer_id}: {error}");
@@ -204,6 +204,7 @@ impl FedimintConsensus {
         dbtx: &mut DatabaseTransaction<'_>,
         consensus_item: ConsensusItem,
         peer_id: PeerId,
+        epoch: u64,
     ) -> anyhow::Result<ConsensusDecision> {
         match consensus_item {
             ConsensusItem::Module(module_item) => {
@@ -223,6 +224,8 @@ impl FedimintConsensus {
                     bail!("The transaction is already accepted");
                 }
 
+                transaction.validate_expiry(epoch)?;
+
                 let txid = transaction.tx_hash();
                 let caches = self.build_verification_caches(transaction.clone());
 
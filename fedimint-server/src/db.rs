@@ -193,6 +193,7 @@ mod fedimint_migration_tests {
                     account: key_pair.x_only_public_key().0,
                 },
             )],
+            expiry: None,
             signature: Some(schnorr),
         };
 
@@ -103,6 +103,9 @@ impl ConsensusApi {
         let tx_hash = transaction.tx_hash();
         debug!(%tx_hash, "Received mint transaction");
 
+        // The next epoch is the earliest one the transaction can be included in
+        transaction.validate_expiry(self.get_epoch_count().await)?;
+
         let mut funding_verifier = FundingVerifier::default();
 
         let mut pub_keys = Vec::new();
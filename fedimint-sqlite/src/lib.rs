@@ -1,4 +1,5 @@
 #![allow(where_clauses_object_safety)] // https://github.com/dtolnay/async-trait/issues/228
+use std::ops::Range;
 use std::str::FromStr;
 
 use anyhow::{anyhow, Result};
@@ -152,6 +153,43 @@ impl<'a> IDatabaseTransaction<'a> for SqliteDbTransaction<'a> {
         Ok(Box::pin(stream::iter(rows)))
     }
 
+    async fn raw_find_by_range(&mut self, key_range: Range<&[u8]>) -> Result<PrefixStream<'_>> {
+        let query =
+            "SELECT key, value FROM kv WHERE key >= ? AND key < ? ORDER BY key ASC, value DESC";
+        let query_prepared = sqlx::query(query).bind(key_range.start).bind(key_range.end);
+        // FIXME: this should be a stream
+        let results = self.tx.fetch_all(query_prepared).await?;
+
+        let rows = results.into_iter().map(|row| {
+            (
+                row.get::<Vec<u8>, &str>("key"),
+                row.get::<Vec<u8>, &str>("value"),
+            )
+        });
+
+        Ok(Box::pin(stream::iter(rows)))
+    }
+
+    async fn raw_find_by_range_sorted_descending(
+        &mut self,
+        key_range: Range<&[u8]>,
+    ) -> Result<PrefixStream<'_>> {
+        let query =
+            "SELECT key, value FROM kv WHERE key >= ? AND key < ? ORDER BY key DESC, value DESC";
+        let query_prepared = sqlx::query(query).bind(key_range.start).bind(key_range.end);
+        // FIXME: this should be a stream
+        let results = self.tx.fetch_all(query_prepared).await?;
+
+        let rows = results.into_iter().map(|row| {
+            (
+                row.get::<Vec<u8>, &str>("key"),
+                row.get::<Vec<u8>, &str>("value"),
+            )
+        });
+
+        Ok(Box::pin(stream::iter(rows)))
+    }
+
     async fn raw_remove_by_prefix(&mut self, key_prefix: &[u8]) -> Result<()> {
         let str_prefix = get_key_prefix_search_hex(key_prefix);
         let query = "DELETE FROM kv WHERE hex(key) LIKE ?";
@@ -243,6 +281,17 @@ mod fedimint_sqlite_tests {
         fedimint_core::db::verify_find_by_prefix(open_temp_db("find_by_prefix").await).await;
     }
 
+    #[test_log::test(tokio::test)]
+    async fn test_dbtx_find_by_range() {
+        fedimint_core::db::verify_find_by_range(open_temp_db("find_by_range").await).await;
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_dbtx_snapshot_roundtrip() {
+        fedimint_core::db::verify_snapshot_roundtrip(open_temp_db("snapshot_roundtrip").await)
+            .await;
+    }
+
     #[test_log::test(tokio::test)]
     async fn test_dbtx_commit() {
         fedimint_core::db::verify_commit(open_temp_db("commit").await).await;
@@ -0,0 +1,411 @@
+//! Database wrapper that injects faults into an otherwise working database, to
+//! test how components behave when storage misbehaves.
+
+use std::ops::Range;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::{bail, Result};
+use async_trait::async_trait;
+use fedimint_core::db::{IDatabase, ISingleUseDatabaseTransaction, PrefixStream};
+use fedimint_core::task::sleep;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+/// Wraps any [`IDatabase`] and injects the faults configured through its
+/// [`FaultInjector`].
+///
+/// Faults are only injected into commits of transactions that wrote to the
+/// database, read-only transactions always succeed. A failed commit discards
+/// all writes of the transaction, just like a failed commit of a real backend.
+#[derive(Debug)]
+pub struct FaultyDatabase<D> {
+    inner: D,
+    faults: FaultInjector,
+}
+
+impl<D: IDatabase> FaultyDatabase<D> {
+    /// Wraps `inner`, initially without injecting any faults
+    pub fn new(inner: D) -> Self {
+        FaultyDatabase {
+            inner,
+            faults: FaultInjector::default(),
+        }
+    }
+
+    /// Returns a handle to configure the injected faults, it stays valid after
+    /// the database was moved into e.g. a `Database` or client
+    pub fn faults(&self) -> FaultInjector {
+        self.faults.clone()
+    }
+}
+
+#[async_trait]
+impl<D: IDatabase> IDatabase for FaultyDatabase<D> {
+    async fn begin_transaction<'a>(&'a self) -> Box<dyn ISingleUseDatabaseTransaction<'a>> {
+        Box::new(FaultyTransaction {
+            inner: self.inner.begin_transaction().await,
+            faults: self.faults.clone(),
+            written_keys: vec![],
+        })
+    }
+}
+
+/// Shared handle to configure the faults injected by a [`FaultyDatabase`]
+#[derive(Debug, Clone, Default)]
+pub struct FaultInjector(Arc<Mutex<FaultState>>);
+
+#[derive(Debug, Default)]
+struct FaultState {
+    failing_commits: usize,
+    random_failures: Option<RandomFailures>,
+    conflicting_prefix: Option<(Vec<u8>, usize)>,
+    operation_latency: Duration,
+    commit_latency: Duration,
+    writes_until_crash: Option<usize>,
+    crashed: bool,
+    injected_failures: usize,
+}
+
+#[derive(Debug)]
+struct RandomFailures {
+    probability: f64,
+    rng: StdRng,
+}
+
+impl FaultInjector {
+    /// Lets the next `commits` commits fail
+    pub fn fail_next_commits(&self, commits: usize) {
+        self.state().failing_commits = commits;
+    }
+
+    /// Lets every commit fail with the given probability. The failures are
+    /// drawn from an RNG seeded with `seed`, so the same commits fail on every
+    /// run as long as they happen in the same order.
+    pub fn set_commit_failure_probability(&self, probability: f64, seed: u64) {
+        assert!((0.0..=1.0).contains(&probability));
+        self.state().random_failures = Some(RandomFailures {
+            probability,
+            rng: StdRng::seed_from_u64(seed),
+        });
+    }
+
+    /// Lets the next `commits` commits writing to keys starting with `prefix`
+    /// fail with a write conflict, as if a concurrent transaction modified
+    /// the same keys
+    pub fn conflict_on_prefix(&self, prefix: Vec<u8>, commits: usize) {
+        self.state().conflicting_prefix = Some((prefix, commits));
+    }
+
+    /// Delays every read and write by `latency`
+    pub fn set_operation_latency(&self, latency: Duration) {
+        self.state().operation_latency = latency;
+    }
+
+    /// Delays every commit by `latency`
+    pub fn set_commit_latency(&self, latency: Duration) {
+        self.state().commit_latency = latency;
+    }
+
+    /// Crashes the database once more than `writes` further writes are
+    /// committed. The commit exceeding the limit and all following commits
+    /// fail until [`FaultInjector::recover`] is called, which corresponds to
+    /// restarting the process.
+    pub fn crash_after_writes(&self, writes: usize) {
+        self.state().writes_until_crash = Some(writes);
+    }
+
+    /// Returns `true` if the database crashed, see
+    /// [`FaultInjector::crash_after_writes`]
+    pub fn is_crashed(&self) -> bool {
+        self.state().crashed
+    }
+
+    /// Recovers the database from a crash
+    pub fn recover(&self) {
+        let mut state = self.state();
+        state.crashed = false;
+        state.writes_until_crash = None;
+    }
+
+    /// Stops injecting any faults
+    pub fn reset(&self) {
+        let injected_failures = self.injected_failures();
+        *self.state() = FaultState {
+            injected_failures,
+            ..FaultState::default()
+        };
+    }
+
+    /// Number of commits that failed due to injected faults so far
+    pub fn injected_failures(&self) -> usize {
+        self.state().injected_failures
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, FaultState> {
+        self.0.lock().expect("lock poisoned")
+    }
+
+    fn operation_latency(&self) -> Duration {
+        self.state().operation_latency
+    }
+
+    /// Decides if the commit of a transaction that wrote `written_keys` fails
+    fn check_commit(&self, written_keys: &[Vec<u8>]) -> Result<()> {
+        let mut state = self.state();
+        let result = state.inject_commit_fault(written_keys);
+        if result.is_err() {
+            state.injected_failures += 1;
+        }
+        result
+    }
+}
+
+impl FaultState {
+    fn inject_commit_fault(&mut self, written_keys: &[Vec<u8>]) -> Result<()> {
+        if self.crashed {
+            bail!("Injected fault: database crashed");
+        }
+
+        if let Some(writes_until_crash) = self.writes_until_crash.as_mut() {
+            if written_keys.len() > *writes_until_crash {
+                self.crashed = true;
+                bail!("Injected fault: database crashed");
+            }
+            *writes_until_crash -= written_keys.len();
+        }
+
+        if self.failing_commits > 0 {
+            self.failing_commits -= 1;
+            bail!("Injected fault: commit failed");
+        }
+
+        if let Some((prefix, conflicts)) = self.conflicting_prefix.as_mut() {
+            if *conflicts > 0 && written_keys.iter().any(|key| key.starts_with(prefix)) {
+                *conflicts -= 1;
+                bail!("Injected fault: write conflict");
+            }
+        }
+
+        if let Some(random_failures) = self.random_failures.as_mut() {
+            if random_failures.rng.gen_bool(random_failures.probability) {
+                bail!("Injected fault: commit failed randomly");
+            }
+        }
+
+        Ok(())
+    }
+}
+
+struct FaultyTransaction<'a> {
+    inner: Box<dyn ISingleUseDatabaseTransaction<'a>>,
+    faults: FaultInjector,
+    written_keys: Vec<Vec<u8>>,
+}
+
+impl<'a> FaultyTransaction<'a> {
+    async fn delay(&self) {
+        let latency = self.faults.operation_latency();
+        if !latency.is_zero() {
+            sleep(latency).await;
+        }
+    }
+}
+
+#[async_trait]
+impl<'a> ISingleUseDatabaseTransaction<'a> for FaultyTransaction<'a> {
+    async fn raw_insert_bytes(&mut self, key: &[u8], value: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.delay().await;
+        self.written_keys.push(key.to_vec());
+        self.inner.raw_insert_bytes(key, value).await
+    }
+
+    async fn raw_get_bytes(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.delay().await;
+        self.inner.raw_get_bytes(key).await
+    }
+
+    async fn raw_remove_entry(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.delay().await;
+        self.written_keys.push(key.to_vec());
+        self.inner.raw_remove_entry(key).await
+    }
+
+    async fn raw_find_by_prefix(&mut self, key_prefix: &[u8]) -> Result<PrefixStream<'_>> {
+        self.delay().await;
+        self.inner.raw_find_by_prefix(key_prefix).await
+    }
+
+    async fn raw_find_by_prefix_sorted_descending(
+        &mut self,
+        key_prefix: &[u8],
+    ) -> Result<PrefixStream<'_>> {
+        self.delay().await;
+        self.inner
+            .raw_find_by_prefix_sorted_descending(key_prefix)
+            .await
+    }
+
+    async fn raw_find_by_range(&mut self, key_range: Range<&[u8]>) -> Result<PrefixStream<'_>> {
+        self.delay().await;
+        self.inner.raw_find_by_range(key_range).await
+    }
+
+    async fn raw_find_by_range_sorted_descending(
+        &mut self,
+        key_range: Range<&[u8]>,
+    ) -> Result<PrefixStream<'_>> {
+        self.delay().await;
+        self.inner
+            .raw_find_by_range_sorted_descending(key_range)
+            .await
+    }
+
+    async fn raw_remove_by_prefix(&mut self, key_prefix: &[u8]) -> Result<()> {
+        self.delay().await;
+        self.written_keys.push(key_prefix.to_vec());
+        self.inner.raw_remove_by_prefix(key_prefix).await
+    }
+
+    async fn commit_tx(&mut self) -> Result<()> {
+        if !self.written_keys.is_empty() {
+            let latency = self.faults.state().commit_latency;
+            if !latency.is_zero() {
+                sleep(latency).await;
+            }
+            self.faults.check_commit(&self.written_keys)?;
+        }
+        self.inner.commit_tx().await
+    }
+
+    async fn rollback_tx_to_savepoint(&mut self) -> Result<()> {
+        self.inner.rollback_tx_to_savepoint().await
+    }
+
+    async fn set_tx_savepoint(&mut self) -> Result<()> {
+        self.inner.set_tx_savepoint().await
+    }
+
+    fn add_notification_key(&mut self, key: &[u8]) -> Result<()> {
+        self.inner.add_notification_key(key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fedimint_core::db::mem_impl::MemDatabase;
+    use fedimint_core::db::{AutocommitError, Database};
+    use fedimint_core::encoding::{Decodable, Encodable};
+    use fedimint_core::impl_db_record;
+    use fedimint_core::module::registry::ModuleDecoderRegistry;
+
+    use super::{FaultInjector, FaultyDatabase};
+
+    #[derive(Debug, Encodable, Decodable)]
+    struct TestKey(u64);
+
+    impl_db_record!(key = TestKey, value = u64, db_prefix = 0x42);
+
+    fn database() -> (Database, FaultInjector) {
+        let db = FaultyDatabase::new(MemDatabase::new());
+        let faults = db.faults();
+        (Database::new(db, ModuleDecoderRegistry::default()), faults)
+    }
+
+    async fn insert(db: &Database, keys: &[u64], max_attempts: usize) -> Result<(), String> {
+        db.autocommit(
+            |dbtx| {
+                Box::pin(async move {
+                    for key in keys {
+                        dbtx.insert_entry(&TestKey(*key), key).await;
+                    }
+                    Ok::<_, ()>(())
+                })
+            },
+            Some(max_attempts),
+        )
+        .await
+        .map_err(|e| match e {
+            AutocommitError::CommitFailed { last_error, .. } => last_error.to_string(),
+            AutocommitError::ClosureError { .. } => unreachable!(),
+        })
+    }
+
+    async fn get(db: &Database, key: u64) -> Option<u64> {
+        db.begin_transaction().await.get_value(&TestKey(key)).await
+    }
+
+    #[tokio::test]
+    async fn autocommit_retries_failed_commits() {
+        let (db, faults) = database();
+
+        faults.fail_next_commits(3);
+        insert(&db, &[1], 4).await.unwrap();
+        assert_eq!(faults.injected_failures(), 3);
+        assert_eq!(get(&db, 1).await, Some(1));
+
+        faults.fail_next_commits(4);
+        assert!(insert(&db, &[2], 4).await.is_err());
+        assert_eq!(get(&db, 2).await, None);
+    }
+
+    #[tokio::test]
+    async fn autocommit_retries_conflicts() {
+        let (db, faults) = database();
+
+        faults.conflict_on_prefix(vec![0x42], 2);
+        insert(&db, &[1], 3).await.unwrap();
+        assert_eq!(faults.injected_failures(), 2);
+        assert_eq!(get(&db, 1).await, Some(1));
+    }
+
+    #[tokio::test]
+    async fn crash_discards_uncommitted_writes() {
+        let (db, faults) = database();
+
+        faults.crash_after_writes(3);
+        insert(&db, &[1, 2], 1).await.unwrap();
+        assert!(!faults.is_crashed());
+
+        let error = insert(&db, &[3, 4], 10).await.unwrap_err();
+        assert!(error.contains("crashed"));
+        assert!(faults.is_crashed());
+        assert_eq!(get(&db, 2).await, Some(2));
+        assert_eq!(get(&db, 3).await, None);
+
+        faults.recover();
+        insert(&db, &[3, 4], 1).await.unwrap();
+        assert_eq!(get(&db, 4).await, Some(4));
+    }
+
+    #[tokio::test]
+    async fn autocommit_survives_random_failures() {
+        let (db, faults) = database();
+
+        faults.set_commit_failure_probability(0.5, 0);
+        faults.set_commit_latency(std::time::Duration::from_millis(1));
+        for key in 0..10 {
+            insert(&db, &[key], 100).await.unwrap();
+        }
+
+        faults.reset();
+        for key in 0..10 {
+            assert_eq!(get(&db, key).await, Some(key));
+        }
+    }
+
+    #[tokio::test]
+    async fn random_failures_are_reproducible() {
+        async fn failed_inserts(seed: u64) -> Vec<bool> {
+            let (db, faults) = database();
+            faults.set_commit_failure_probability(0.5, seed);
+            let mut failed = vec![];
+            for key in 0..20 {
+                failed.push(insert(&db, &[key], 1).await.is_err());
+            }
+            failed
+        }
+
+        assert_eq!(failed_inserts(1).await, failed_inserts(1).await);
+    }
+}
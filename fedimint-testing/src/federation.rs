@@ -11,7 +11,7 @@ use fedimint_core::config::{
 };
 use fedimint_core::core::ModuleInstanceId;
 use fedimint_core::db::mem_impl::MemDatabase;
-use fedimint_core::db::Database;
+use fedimint_core::db::{Database, IDatabase};
 use fedimint_core::module::ApiAuth;
 use fedimint_core::task::TaskGroup;
 use fedimint_core::PeerId;
@@ -41,26 +41,55 @@ impl FederationTest {
 
     /// Create a client connected to this fed
     pub async fn new_client(&self) -> Client {
-        let client_config = self.configs[&PeerId::from(0)]
-            .consensus
-            .to_client_config(&self.server_gen)
-            .unwrap();
-
-        self.new_client_with_config(client_config).await
+        self.new_client_with_config(self.client_config()).await
     }
 
     pub async fn new_client_with_config(&self, client_config: ClientConfig) -> Client {
+        self.new_client_with(|builder| builder.with_config(client_config))
+            .await
+    }
+
+    /// Create a client connected to this fed that stores its state in `db`,
+    /// e.g. a [`crate::faulty_db::FaultyDatabase`]
+    pub async fn new_client_with_database(&self, db: impl IDatabase + 'static) -> Client {
+        self.new_client_with(|builder| builder.with_database(db))
+            .await
+    }
+
+    /// Create a client connected to this fed after letting `configure` set up
+    /// its [`ClientBuilder`], e.g. to enable optional features. Unless
+    /// `configure` provides them the fed's config and a fresh [`MemDatabase`]
+    /// are used.
+    pub async fn new_client_with(&self, configure: impl FnOnce(&mut ClientBuilder)) -> Client {
         let mut client_builder = ClientBuilder::default();
         client_builder.with_module_gens(self.client_gen.clone());
         client_builder.with_primary_module(self.primary_client);
-        client_builder.with_config(client_config);
-        client_builder.with_database(MemDatabase::new());
+        configure(&mut client_builder);
+        if !client_builder.has_config() {
+            client_builder.with_config(self.client_config());
+        }
+        if !client_builder.has_database() {
+            client_builder.with_database(MemDatabase::new());
+        }
         client_builder
             .build::<PlainRootSecretStrategy>(&mut self.task.make_subgroup().await)
             .await
             .expect("Failed to build client")
     }
 
+    /// Config clients use to connect to this fed
+    pub fn client_config(&self) -> ClientConfig {
+        self.configs[&PeerId::from(0)]
+            .consensus
+            .to_client_config(&self.server_gen)
+            .unwrap()
+    }
+
+    /// Client module gens of all modules of this fed
+    pub fn client_module_gens(&self) -> ClientModuleGenRegistry {
+        self.client_gen.clone()
+    }
+
     /// Return first connection code for gateways
     pub fn connection_code(&self) -> WsClientConnectInfo {
         self.configs[&PeerId::from(0)].get_connect_info()
@@ -68,11 +97,7 @@ impl FederationTest {
 
     ///  Return first id for gateways
     pub fn id(&self) -> FederationId {
-        self.configs[&PeerId::from(0)]
-            .consensus
-            .to_client_config(&self.server_gen)
-            .unwrap()
-            .federation_id
+        self.client_config().federation_id
     }
 
     pub(crate) async fn new(
@@ -1,5 +1,6 @@
 pub mod btc;
 pub mod db;
+pub mod faulty_db;
 pub mod federation;
 pub mod fixtures;
 pub mod gateway;
@@ -1,12 +1,15 @@
 use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::BufWriter;
 use std::net::SocketAddr;
-use std::path::PathBuf;
-use std::time::Duration;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
 
 use clap::Parser;
 use fedimint_core::admin_client::ConfigGenParamsRequest;
 use fedimint_core::bitcoinrpc::BitcoinRpcConfig;
 use fedimint_core::config::{ServerModuleGenParamsRegistry, ServerModuleGenRegistry};
+use fedimint_core::db::metrics::MetricsDatabase;
 use fedimint_core::db::Database;
 use fedimint_core::module::ServerModuleGen;
 use fedimint_core::task::{sleep, TaskGroup};
@@ -20,12 +23,17 @@ use fedimint_server::config::io::{CODE_VERSION, DB_FILE, PLAINTEXT_PASSWORD};
 use fedimint_server::FedimintServer;
 use fedimint_wallet_server::WalletGen;
 use futures::FutureExt;
+use tokio::runtime::Handle;
 use tokio::select;
+use tokio::task::spawn_blocking;
 use tracing::{debug, error, info, warn};
 use url::Url;
 
 use crate::attach_default_module_gen_params;
 
+const BACKUP_FILE_PREFIX: &str = "database-";
+const BACKUP_FILE_SUFFIX: &str = ".snapshot";
+
 /// Time we will wait before forcefully shutting down tasks
 const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
 
@@ -71,6 +79,17 @@ pub struct ServerOpts {
 
     #[arg(long, env = "FM_BIND_METRICS_API")]
     bind_metrics_api: Option<SocketAddr>,
+
+    /// Directory to periodically write database snapshots to, no snapshots
+    /// are taken if unset
+    #[arg(long, env = "FM_BACKUP_DIR")]
+    backup_dir: Option<PathBuf>,
+    /// Interval between database snapshots in seconds
+    #[arg(long, env = "FM_BACKUP_INTERVAL_SECS", default_value = "3600")]
+    backup_interval_secs: u64,
+    /// Number of most recent database snapshots to keep
+    #[arg(long, env = "FM_BACKUP_KEEP", default_value = "24")]
+    backup_keep: usize,
 }
 
 /// `fedimintd` builder
@@ -231,10 +250,23 @@ async fn run(
         .iter_modules()
         .map(|(id, kind, _)| (id, kind));
     let decoders = module_gens.decoders(module_kinds.into_iter())?;
-    let db = Database::new(
-        fedimint_rocksdb::RocksDb::open(opts.data_dir.join(DB_FILE))?,
-        decoders.clone(),
-    );
+    let rocksdb = fedimint_rocksdb::RocksDb::open(opts.data_dir.join(DB_FILE))?;
+    let db = if opts.bind_metrics_api.is_some() {
+        Database::new(MetricsDatabase::new(rocksdb), decoders.clone())
+    } else {
+        Database::new(rocksdb, decoders.clone())
+    };
+
+    if let Some(backup_dir) = opts.backup_dir {
+        spawn_backup_task(
+            db.clone(),
+            backup_dir,
+            Duration::from_secs(opts.backup_interval_secs),
+            opts.backup_keep,
+            task_group.clone(),
+        )
+        .await?;
+    }
 
     // TODO: Fedimintd should use the config gen API
     // on each run we want to pass the currently passed passsword, so we need to
@@ -273,6 +305,91 @@ async fn run(
     Ok(())
 }
 
+/// Periodically writes a snapshot of the database to `backup_dir`, deleting
+/// all but the `keep` most recent ones
+async fn spawn_backup_task(
+    db: Database,
+    backup_dir: PathBuf,
+    interval: Duration,
+    keep: usize,
+    mut task_group: TaskGroup,
+) -> anyhow::Result<()> {
+    std::fs::create_dir_all(&backup_dir)?;
+    task_group
+        .spawn("database backup", move |handle| async move {
+            let mut shutdown_rx = handle.make_shutdown_rx().await;
+            loop {
+                select! {
+                    _ = &mut shutdown_rx => break,
+                    _ = sleep(interval) => {}
+                }
+
+                match write_backup(db.clone(), backup_dir.clone()).await {
+                    Ok(path) => info!("Wrote database snapshot to {}", path.display()),
+                    Err(error) => error!(?error, "Failed to write database snapshot"),
+                }
+                let prune_dir = backup_dir.clone();
+                if let Err(error) = run_blocking(move || prune_backups(&prune_dir, keep)).await {
+                    warn!(?error, "Failed to prune old database snapshots");
+                }
+            }
+        })
+        .await;
+    Ok(())
+}
+
+async fn write_backup(db: Database, backup_dir: PathBuf) -> anyhow::Result<PathBuf> {
+    let timestamp = fedimint_core::time::now()
+        .duration_since(UNIX_EPOCH)?
+        .as_secs();
+    let path = backup_dir.join(format!(
+        "{BACKUP_FILE_PREFIX}{timestamp}{BACKUP_FILE_SUFFIX}"
+    ));
+    // The snapshot is written to the file as the database is read, so the
+    // blocking thread drives reading the database too
+    let runtime = Handle::current();
+    run_blocking(move || {
+        // Write to a temporary file first so an interrupted backup is never
+        // mistaken for a complete one
+        let tmp_path = path.with_extension("tmp");
+        let mut file = BufWriter::new(File::create(&tmp_path)?);
+        runtime.block_on(db.export_snapshot(&mut file))?;
+        file.into_inner()?.sync_all()?;
+        std::fs::rename(&tmp_path, &path)?;
+        Ok(path)
+    })
+    .await
+}
+
+/// Runs blocking file IO on a thread reserved for it, so it doesn't stall the
+/// tasks of the runtime
+async fn run_blocking<T: Send + 'static>(
+    f: impl FnOnce() -> anyhow::Result<T> + Send + 'static,
+) -> anyhow::Result<T> {
+    spawn_blocking(f).await?
+}
+
+fn prune_backups(backup_dir: &Path, keep: usize) -> anyhow::Result<()> {
+    let mut backups = std::fs::read_dir(backup_dir)?
+        .filter_map(|entry| {
+            let name = entry.ok()?.file_name().into_string().ok()?;
+            let timestamp = name
+                .strip_prefix(BACKUP_FILE_PREFIX)?
+                .strip_suffix(BACKUP_FILE_SUFFIX)?
+                .parse::<u64>()
+                .ok()?;
+            Some((timestamp, name))
+        })
+        .collect::<Vec<_>>();
+    backups.sort();
+
+    let excess = backups.len().saturating_sub(keep);
+    for (_, name) in backups.into_iter().take(excess) {
+        std::fs::remove_file(backup_dir.join(name))?;
+    }
+    Ok(())
+}
+
 async fn spawn_metrics_server(
     bind_address: &SocketAddr,
     mut task_group: TaskGroup,
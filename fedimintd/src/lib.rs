@@ -55,6 +55,7 @@ pub fn attach_default_module_gen_params(
                         .tiers()
                         .cloned()
                         .collect(),
+                    fee_consensus: Default::default(),
                 },
             },
         )
@@ -1,3 +1,4 @@
+use fedimint_client::event::StateSummary;
 use fedimint_client::sm::{OperationId, State, StateTransition};
 use fedimint_client::DynGlobalClientContext;
 use fedimint_core::encoding::{Decodable, Encodable};
@@ -79,6 +80,21 @@ impl State for GatewayCompleteStateMachine {
     }
 }
 
+impl GatewayCompleteStateMachine {
+    pub(crate) fn summary(&self) -> StateSummary {
+        match &self.state {
+            GatewayCompleteStates::WaitForPreimage(_) => {
+                StateSummary::in_progress("Waiting for the preimage of the incoming contract")
+            }
+            GatewayCompleteStates::CompleteHtlc(_) => {
+                StateSummary::in_progress("Settling or canceling the intercepted HTLC")
+            }
+            GatewayCompleteStates::HtlcFinished => StateSummary::Succeeded,
+            GatewayCompleteStates::Failure => StateSummary::failed("Completing the HTLC failed"),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Eq, PartialEq, Decodable, Encodable)]
 pub struct WaitForPreimageState;
 
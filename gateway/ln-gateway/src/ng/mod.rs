@@ -7,6 +7,7 @@ use std::time::Duration;
 use async_stream::stream;
 use bitcoin_hashes::{sha256, Hash};
 use fedimint_client::derivable_secret::{ChildId, DerivableSecret};
+use fedimint_client::event::StateSummary;
 use fedimint_client::module::gen::ClientModuleGen;
 use fedimint_client::module::{ClientModule, IClientModule};
 use fedimint_client::oplog::UpdateStreamOrOutcome;
@@ -426,6 +427,14 @@ impl ClientModule for GatewayClientModule {
             }
         }
     }
+
+    fn summarize_state(&self, state: &Self::States) -> StateSummary {
+        match state {
+            GatewayClientStateMachines::Pay(state) => state.summary(),
+            GatewayClientStateMachines::Receive(state) => state.summary(),
+            GatewayClientStateMachines::Complete(state) => state.summary(),
+        }
+    }
 }
 
 impl GatewayClientModule {
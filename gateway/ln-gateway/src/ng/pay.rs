@@ -1,5 +1,6 @@
 use std::sync::Arc;
 
+use fedimint_client::event::StateSummary;
 use fedimint_client::sm::{ClientSMDatabaseTransaction, OperationId, State, StateTransition};
 use fedimint_client::transaction::{ClientInput, ClientOutput};
 use fedimint_client::DynGlobalClientContext;
@@ -94,6 +95,32 @@ impl State for GatewayPayStateMachine {
     }
 }
 
+impl GatewayPayStateMachine {
+    pub(crate) fn summary(&self) -> StateSummary {
+        match &self.state {
+            GatewayPayStates::PayInvoice(_) => {
+                StateSummary::in_progress("Paying the invoice over lightning")
+            }
+            GatewayPayStates::ClaimOutgoingContract(_) => {
+                StateSummary::in_progress("Waiting for the claim transaction to be accepted")
+            }
+            GatewayPayStates::CancelContract(_) => {
+                StateSummary::in_progress("Canceling the outgoing contract")
+            }
+            GatewayPayStates::Preimage(_, _) => StateSummary::Succeeded,
+            GatewayPayStates::OfferDoesNotExist(contract_id) => {
+                StateSummary::failed(format!("No offer exists for contract {contract_id}"))
+            }
+            GatewayPayStates::Canceled(_, _) => {
+                StateSummary::failed("Payment failed, the outgoing contract was canceled")
+            }
+            GatewayPayStates::Failed => {
+                StateSummary::failed("Canceling the outgoing contract failed")
+            }
+        }
+    }
+}
+
 #[derive(Error, Debug, Serialize, Deserialize, Encodable, Decodable, Clone, Eq, PartialEq)]
 pub enum OutgoingContractError {
     #[error("Invalid OutgoingContract {contract_id}")]
@@ -4,10 +4,14 @@ use std::time::Duration;
 
 use assert_matches::assert_matches;
 use bitcoin_hashes::{sha256, Hash};
+use fedimint_client::backup::Metadata;
+use fedimint_client::secret::PlainRootSecretStrategy;
 use fedimint_client::sm::OperationId;
 use fedimint_client::transaction::{ClientInput, ClientOutput, TransactionBuilder};
-use fedimint_client::Client;
+use fedimint_client::{Client, ClientBuilder, ClientSecret};
 use fedimint_core::core::IntoDynInstance;
+use fedimint_core::db::mem_impl::MemDatabase;
+use fedimint_core::task::TaskGroup;
 use fedimint_core::util::NextOrPending;
 use fedimint_core::{sats, Amount, OutPoint, TransactionId};
 use fedimint_dummy_client::{DummyClientExt, DummyClientGen};
@@ -231,6 +235,70 @@ async fn test_gateway_client_pay_invalid_invoice() -> anyhow::Result<()> {
     .await
 }
 
+fn client_builder(fed: &FederationTest) -> ClientBuilder {
+    let mut client_builder = ClientBuilder::default();
+    client_builder.with_module_gens(fed.client_module_gens());
+    client_builder.with_primary_module(0);
+    client_builder.with_config(fed.client_config());
+    client_builder.with_database(MemDatabase::new());
+    client_builder
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_gateway_client_refunds_restored_payment() -> anyhow::Result<()> {
+    gateway_test(|gateway, other_lightning_client, fed, _| async move {
+        let gateway = gateway.remove_client(&fed).await;
+        let mut user_tasks = TaskGroup::new();
+        let user_client = client_builder(&fed)
+            .build::<PlainRootSecretStrategy>(&mut user_tasks)
+            .await?;
+        let (_, outpoint) = user_client.print_money(sats(1000)).await?;
+        user_client.receive_money(outpoint).await?;
+
+        let invoice = other_lightning_client
+            .invalid_invoice(sats(250), None)
+            .unwrap();
+        let (pay_type, contract_id) = user_client.pay_bolt11_invoice(invoice).await?;
+        let PayType::Lightning(pay_op) = pay_type else {
+            panic!("Expected Lightning payment!")
+        };
+        let mut pay_sub = user_client.subscribe_ln_pay(pay_op).await?.into_stream();
+        assert_eq!(pay_sub.ok().await?, LnPayState::Created);
+        assert_matches!(pay_sub.ok().await?, LnPayState::Funded);
+        drop(pay_sub);
+        user_client.backup_to_federation(Metadata::empty()).await?;
+
+        // Lose the paying client while the contract is pending and restore it
+        let secret = user_client
+            .root_secret_encoding::<PlainRootSecretStrategy>()
+            .await;
+        user_tasks.shutdown_join_all(None).await?;
+        let (restored, _) = client_builder(&fed)
+            .build_restoring_from_backup(
+                &mut TaskGroup::new(),
+                ClientSecret::<PlainRootSecretStrategy>::new(secret),
+            )
+            .await?;
+
+        let gw_pay_op = gateway.gateway_pay_bolt11_invoice(contract_id).await?;
+        let mut gw_pay_sub = gateway
+            .gateway_subscribe_ln_pay(gw_pay_op)
+            .await?
+            .into_stream();
+        assert_eq!(gw_pay_sub.ok().await?, GatewayExtPayStates::Created);
+        assert_eq!(gw_pay_sub.ok().await?, GatewayExtPayStates::Canceled);
+
+        // The restored client can follow the payment and gets refunded
+        let mut pay_sub = restored.subscribe_ln_pay(pay_op).await?.into_stream();
+        assert_eq!(pay_sub.ok().await?, LnPayState::Created);
+        assert_matches!(pay_sub.ok().await?, LnPayState::Funded);
+        assert_matches!(pay_sub.ok().await?, LnPayState::WaitingForRefund { .. });
+        assert_matches!(pay_sub.ok().await?, LnPayState::Refunded { .. });
+        Ok(())
+    })
+    .await
+}
+
 #[tokio::test(flavor = "multi_thread")]
 async fn test_gateway_client_intercept_valid_htlc() -> anyhow::Result<()> {
     gateway_test(|gateway, _, fed, user_client| async move {
@@ -382,7 +450,7 @@ async fn test_gateway_client_intercept_htlc_invalid_offer() -> anyhow::Result<()
             };
             let tx = TransactionBuilder::new().with_output(client_output.into_dyn(instance.id));
             let operation_meta_gen = |txid, _| LightningMeta::Receive {
-                out_point: OutPoint { txid, out_idx: 0 },
+                out_point: Some(OutPoint { txid, out_idx: 0 }),
                 invoice: invoice.clone(),
             };
             let operation_id = OperationId(invoice.payment_hash().into_inner());
@@ -689,6 +689,7 @@ impl FederationTest {
                     self.mint_id,
                     MintOutput(notes.clone()),
                 )],
+                expiry: None,
                 signature: None,
             };
 
@@ -3,6 +3,7 @@ use std::time::Duration;
 
 use anyhow::{format_err, Context as _};
 use fedimint_client::derivable_secret::DerivableSecret;
+use fedimint_client::event::StateSummary;
 use fedimint_client::module::gen::ClientModuleGen;
 use fedimint_client::module::{ClientModule, IClientModule};
 use fedimint_client::sm::{Context, ModuleNotifier, OperationId};
@@ -213,6 +214,10 @@ impl ClientModule for DummyClientModule {
         }
     }
 
+    fn output_is_own(&self, output: &<Self::Common as ModuleCommon>::Output) -> bool {
+        output.account == self.key.x_only_public_key().0
+    }
+
     fn supports_being_primary(&self) -> bool {
         true
     }
@@ -305,6 +310,21 @@ impl ClientModule for DummyClientModule {
                 }),
         )
     }
+
+    fn summarize_state(&self, state: &Self::States) -> StateSummary {
+        match state {
+            DummyStateMachine::Input(..) => {
+                StateSummary::in_progress("Waiting for the transaction to be accepted")
+            }
+            DummyStateMachine::Output(..) => {
+                StateSummary::in_progress("Waiting for the output outcome")
+            }
+            DummyStateMachine::InputDone(_) | DummyStateMachine::OutputDone(..) => {
+                StateSummary::Succeeded
+            }
+            DummyStateMachine::Refund(_) => StateSummary::failed("Transaction was rejected"),
+        }
+    }
 }
 
 async fn get_funds(dbtx: &mut ModuleDatabaseTransaction<'_>) -> Amount {
@@ -1,11 +1,33 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::bail;
+use fedimint_client::backup::BackupSchedule;
+use fedimint_client::batch::BatchingConfig;
+use fedimint_client::event::{ClientEvent, StateSummary};
+use fedimint_client::manager::ClientManager;
+use fedimint_client::policy::{
+    MaxPerOperation, RollingLimit, SpendRequest, SpendingPolicy, SpendingPolicyViolation,
+    SpendingRules,
+};
+use fedimint_client::secret::PlainRootSecretStrategy;
+use fedimint_client::sm::OperationId;
+use fedimint_client::transaction::{ClientOutput, TransactionBuilder};
 use fedimint_core::config::ClientModuleConfig;
-use fedimint_core::core::ModuleKind;
+use fedimint_core::core::{IntoDynInstance, ModuleKind};
+use fedimint_core::db::mem_impl::MemDatabase;
+use fedimint_core::db::DatabaseTransaction;
 use fedimint_core::module::ModuleConsensusVersion;
-use fedimint_core::{sats, Amount};
+use fedimint_core::task::TaskGroup;
+use fedimint_core::{apply, async_trait_maybe_send, sats, Amount};
 use fedimint_dummy_client::{DummyClientExt, DummyClientGen};
 use fedimint_dummy_common::config::{DummyClientConfig, DummyGenParams};
+use fedimint_dummy_common::DummyOutput;
 use fedimint_dummy_server::DummyGen;
-use fedimint_testing::fixtures::Fixtures;
+use fedimint_rocksdb::RocksDb;
+use fedimint_testing::faulty_db::FaultyDatabase;
+use fedimint_testing::fixtures::{test_dir, Fixtures};
+use futures::StreamExt;
 
 fn fixtures() -> Fixtures {
     Fixtures::new_primary(DummyClientGen, DummyGen, DummyGenParams::default())
@@ -27,6 +49,352 @@ async fn can_print_and_send_money() -> anyhow::Result<()> {
     Ok(())
 }
 
+#[tokio::test(flavor = "multi_thread")]
+async fn preview_transaction_does_not_spend_funds() -> anyhow::Result<()> {
+    let fed = fixtures().new_fed().await;
+    let (client1, client2) = fed.two_clients().await;
+
+    let (_, outpoint) = client1.print_money(sats(1000)).await?;
+    client1.receive_money(outpoint).await?;
+
+    let instance = client1
+        .get_first_instance(&fedimint_dummy_common::KIND)
+        .unwrap();
+    let output = ClientOutput {
+        output: DummyOutput {
+            amount: sats(250),
+            account: client2.account(),
+        }
+        .into_dyn(instance),
+        state_machines: Arc::new(|_, _| vec![]),
+    };
+    let preview = client1
+        .preview_transaction(TransactionBuilder::new().with_output(output))
+        .await?;
+
+    let funding_inputs = preview.funding_inputs().collect::<Vec<_>>();
+    assert_eq!(funding_inputs.len(), 1);
+    assert_eq!(funding_inputs[0].amount.amount, preview.total_cost());
+    assert_eq!(preview.total_cost(), sats(250) + preview.total_fee());
+    assert_eq!(preview.change(), Amount::ZERO);
+
+    // Nothing was spent and the funds can still be sent for real
+    assert_eq!(client1.get_balance().await, sats(1000));
+    let outpoint = client1.send_money(client2.account(), sats(250)).await?;
+    client2.receive_money(outpoint).await?;
+    assert_eq!(client2.get_balance().await, sats(250));
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn client_emits_typed_events() -> anyhow::Result<()> {
+    let fed = fixtures().new_fed().await;
+    let client = fed.new_client().await;
+    let mut events = client.subscribe_events().await;
+
+    let (op_id, _) = client.print_money(sats(1000)).await?;
+
+    let mut tx_accepted = false;
+    let mut output_done = false;
+    let mut new_balance = None;
+    while !(tx_accepted && output_done && new_balance == Some(sats(1000))) {
+        let event = fedimint_core::task::timeout(Duration::from_secs(10), events.next())
+            .await?
+            .expect("Event stream doesn't end");
+        match event {
+            ClientEvent::StateTransition(transition) => {
+                assert_eq!(transition.operation_id, op_id);
+                assert_eq!(transition.operation_type.as_deref(), Some("dummy"));
+                match transition.module_kind {
+                    None => tx_accepted |= transition.new_state == StateSummary::Succeeded,
+                    Some(kind) => {
+                        assert_eq!(kind, fedimint_dummy_common::KIND);
+                        output_done |= transition.new_state == StateSummary::Succeeded;
+                    }
+                }
+            }
+            ClientEvent::BalanceChanged(balance) => new_balance = Some(balance),
+            ClientEvent::DeadLetter(dead_letter) => {
+                panic!("Unexpected dead letter {dead_letter:?}")
+            }
+            ClientEvent::ConfigChanged(config_changed) => {
+                panic!("Unexpected config change {config_changed:?}")
+            }
+        }
+    }
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn spending_policy_limits_spends() -> anyhow::Result<()> {
+    let fed = fixtures().new_fed().await;
+    let (client1, client2) = fed.two_clients().await;
+
+    let (_, outpoint) = client1.print_money(sats(2000)).await?;
+    client1.receive_money(outpoint).await?;
+
+    client1
+        .set_spending_rules(SpendingRules {
+            max_per_operation: Some(MaxPerOperation(sats(300))),
+            rolling_limits: vec![RollingLimit::daily(sats(500))],
+            ..Default::default()
+        })
+        .await?;
+
+    let error = client1
+        .send_money(client2.account(), sats(400))
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        error.downcast_ref::<SpendingPolicyViolation>(),
+        Some(SpendingPolicyViolation::OperationLimitExceeded { .. })
+    ));
+
+    let outpoint = client1.send_money(client2.account(), sats(250)).await?;
+    client2.receive_money(outpoint).await?;
+    assert_eq!(
+        client1.spent_within(Duration::from_secs(60)).await,
+        sats(250)
+    );
+
+    let error = client1
+        .send_money(client2.account(), sats(260))
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        error.downcast_ref::<SpendingPolicyViolation>(),
+        Some(SpendingPolicyViolation::RollingLimitExceeded { .. })
+    ));
+
+    // Refused operations don't count towards the limits
+    assert_eq!(
+        client1.spent_within(Duration::from_secs(60)).await,
+        sats(250)
+    );
+
+    client1.set_spending_rules(SpendingRules::default()).await?;
+    let outpoint = client1.send_money(client2.account(), sats(260)).await?;
+    client2.receive_money(outpoint).await?;
+    assert_eq!(client2.get_balance().await, sats(510));
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn spending_policy_serializes_concurrent_spends() -> anyhow::Result<()> {
+    let fed = fixtures().new_fed().await;
+    // Unlike `MemDatabase`, RocksDB detects conflicting writes of concurrent
+    // database transactions like a client would in production
+    let (path, _dir_guard) = test_dir("spending_policy_serializes_concurrent_spends");
+    let client = fed.new_client_with_database(RocksDb::open(path)?).await;
+    client
+        .set_spending_rules(SpendingRules {
+            rolling_limits: vec![RollingLimit::daily(sats(500))],
+            ..Default::default()
+        })
+        .await?;
+
+    // Both spends are checked before either is committed, so only one may commit
+    let mut dbtx1 = client.db().begin_transaction().await;
+    let mut dbtx2 = client.db().begin_transaction().await;
+    for dbtx in [&mut dbtx1, &mut dbtx2] {
+        let operation_id = OperationId::new_random();
+        client
+            .operation_log()
+            .add_operation_log_entry(dbtx, operation_id, "test", ())
+            .await;
+        client
+            .enforce_out_of_band_spend(dbtx, operation_id, sats(400))
+            .await?;
+    }
+    dbtx1.commit_tx_result().await?;
+    assert!(dbtx2.commit_tx_result().await.is_err());
+
+    // Retrying the second spend takes the first one into account
+    let mut dbtx = client.db().begin_transaction().await;
+    let error = client
+        .enforce_out_of_band_spend(&mut dbtx, OperationId::new_random(), sats(400))
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        error.downcast_ref::<SpendingPolicyViolation>(),
+        Some(SpendingPolicyViolation::RollingLimitExceeded { .. })
+    ));
+    Ok(())
+}
+
+/// Custom policy refusing all spends
+#[derive(Debug)]
+struct FrozenFunds;
+
+#[apply(async_trait_maybe_send!)]
+impl SpendingPolicy for FrozenFunds {
+    async fn check(
+        &self,
+        _dbtx: &mut DatabaseTransaction<'_>,
+        _request: &SpendRequest,
+    ) -> anyhow::Result<()> {
+        bail!("Funds are frozen")
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn client_enforces_custom_spending_policy() -> anyhow::Result<()> {
+    let fed = fixtures().new_fed().await;
+    let client2 = fed.new_client().await;
+
+    let client1 = fed
+        .new_client_with(|builder| builder.with_spending_policy(FrozenFunds))
+        .await;
+
+    // Receiving funds doesn't spend anything
+    let (_, outpoint) = client1.print_money(sats(1000)).await?;
+    client1.receive_money(outpoint).await?;
+
+    let error = client1
+        .send_money(client2.account(), sats(100))
+        .await
+        .unwrap_err();
+    assert_eq!(error.to_string(), "Funds are frozen");
+    assert_eq!(
+        client1.spent_within(Duration::from_secs(60)).await,
+        Amount::ZERO
+    );
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn client_batches_operations_into_one_transaction() -> anyhow::Result<()> {
+    let fed = fixtures().new_fed().await;
+    let client2 = fed.new_client().await;
+
+    let client1 = fed
+        .new_client_with(|builder| {
+            builder.with_transaction_batching(BatchingConfig {
+                window: Duration::from_secs(1),
+                ..Default::default()
+            })
+        })
+        .await;
+
+    let (_, outpoint) = client1.print_money(sats(1000)).await?;
+    client1.receive_money(outpoint).await?;
+
+    let instance = client1
+        .get_first_instance(&fedimint_dummy_common::KIND)
+        .unwrap();
+    let send = |amount| {
+        let output = ClientOutput {
+            output: DummyOutput {
+                amount,
+                account: client2.account(),
+            }
+            .into_dyn(instance),
+            state_machines: Arc::new(|_, _| vec![]),
+        };
+        client1.submit_batched_transaction(
+            OperationId::new_random(),
+            "dummy",
+            |batched_tx| batched_tx.out_point(0),
+            TransactionBuilder::new().with_output(output),
+        )
+    };
+    let (first, second, unfunded) =
+        futures::join!(send(sats(100)), send(sats(200)), send(sats(5000)));
+    let (first, second) = (first?, second?);
+
+    // Both operations ended up in the same transaction, the unfunded one failed on
+    // its own
+    assert_eq!(first.txid, second.txid);
+    assert_ne!(first.first_output_idx, second.first_output_idx);
+    assert!(unfunded.is_err());
+
+    client2.receive_money(first.out_point(0)).await?;
+    client2.receive_money(second.out_point(0)).await?;
+    assert_eq!(client1.get_balance().await, sats(700));
+    assert_eq!(client2.get_balance().await, sats(300));
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn client_refreshes_changed_config() -> anyhow::Result<()> {
+    let fed = fixtures().new_fed().await;
+    let mut stale_config = fed.client_config();
+    stale_config
+        .meta
+        .insert("stale_key".to_owned(), "stale_value".to_owned());
+    let client = fed.new_client_with_config(stale_config.clone()).await;
+    let mut events = client.subscribe_events().await;
+    assert_eq!(client.get_meta("stale_key"), Some("stale_value".to_owned()));
+
+    assert!(client.refresh_config(&fed.connection_code()).await?);
+    assert_eq!(client.get_meta("stale_key"), None);
+    assert_eq!(client.get_config(), fed.client_config());
+    let config_changed = fedimint_core::task::timeout(Duration::from_secs(10), async {
+        loop {
+            if let Some(ClientEvent::ConfigChanged(config_changed)) = events.next().await {
+                return config_changed;
+            }
+        }
+    })
+    .await?;
+    assert!(config_changed.meta_changed);
+    assert!(!config_changed.api_endpoints_changed);
+    assert!(config_changed.modules_changed.is_empty());
+
+    // The config hash didn't change since the last refresh
+    assert!(!client.refresh_config(&fed.connection_code()).await?);
+
+    // The refreshed config is used instead of the stale one after a restart
+    let client = fed
+        .new_client_with(|builder| {
+            builder.with_config(stale_config);
+            builder.with_old_client_database(client);
+        })
+        .await;
+    assert_eq!(client.get_meta("stale_key"), None);
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn client_uploads_backups_automatically() -> anyhow::Result<()> {
+    let fed = fixtures().new_fed().await;
+
+    let client = fed
+        .new_client_with(|builder| {
+            builder.with_backup_schedule(BackupSchedule {
+                debounce: Duration::from_millis(100),
+                ..Default::default()
+            })
+        })
+        .await;
+
+    // Without any previous backup one is uploaded right away
+    let first_backup = fedimint_core::task::timeout(Duration::from_secs(10), async {
+        loop {
+            if let Some(last_backup) = client.last_backup().await {
+                return last_backup;
+            }
+            fedimint_core::task::sleep(Duration::from_millis(100)).await;
+        }
+    })
+    .await?;
+
+    // Balance changes trigger another backup
+    client.print_money(sats(1000)).await?;
+    fedimint_core::task::timeout(Duration::from_secs(10), async {
+        while client
+            .last_backup()
+            .await
+            .map(|last_backup| last_backup.time)
+            == Some(first_backup.time)
+        {
+            fedimint_core::task::sleep(Duration::from_millis(100)).await;
+        }
+    })
+    .await?;
+    Ok(())
+}
+
 #[tokio::test(flavor = "multi_thread")]
 async fn can_threshold_sign_message() {
     let fed = fixtures().new_fed().await;
@@ -37,12 +405,65 @@ async fn can_threshold_sign_message() {
     assert!(client.fed_public_key().verify(&sig, message));
 }
 
+#[tokio::test(flavor = "multi_thread")]
+async fn client_recovers_from_commit_failures() -> anyhow::Result<()> {
+    let fed = fixtures().new_fed().await;
+    let db = FaultyDatabase::new(MemDatabase::new());
+    let faults = db.faults();
+    let client = fed.new_client_with_database(db).await;
+
+    // Transaction submission and the state machines retry failed commits
+    faults.fail_next_commits(10);
+    let (_, outpoint) = client.print_money(sats(1000)).await?;
+    assert_eq!(faults.injected_failures(), 10);
+
+    client.receive_money(outpoint).await?;
+    assert_eq!(client.get_balance().await, sats(1000));
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn client_recovers_from_random_commit_failures() -> anyhow::Result<()> {
+    let fed = fixtures().new_fed().await;
+    let db = FaultyDatabase::new(MemDatabase::new());
+    let faults = db.faults();
+    let client = fed.new_client_with_database(db).await;
+
+    faults.set_commit_failure_probability(0.3, 7);
+    faults.set_commit_latency(Duration::from_millis(5));
+    let (_, outpoint) = client.print_money(sats(1000)).await?;
+
+    faults.reset();
+    client.receive_money(outpoint).await?;
+    assert_eq!(client.get_balance().await, sats(1000));
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn transaction_submission_fails_after_too_many_commit_failures() -> anyhow::Result<()> {
+    let fed = fixtures().new_fed().await;
+    let db = FaultyDatabase::new(MemDatabase::new());
+    let faults = db.faults();
+    let client = fed.new_client_with_database(db).await;
+
+    faults.fail_next_commits(100);
+    let error = client.print_money(sats(1000)).await.unwrap_err();
+    assert!(error.to_string().contains("after 100 attempts"));
+    assert_eq!(client.get_balance().await, sats(0));
+
+    // The client keeps working once the database recovers
+    let (_, outpoint) = client.print_money(sats(1000)).await?;
+    client.receive_money(outpoint).await?;
+    assert_eq!(client.get_balance().await, sats(1000));
+    Ok(())
+}
+
 #[tokio::test(flavor = "multi_thread")]
 async fn client_ignores_unknown_module() {
     let fed = fixtures().new_fed().await;
     let client = fed.new_client().await;
 
-    let mut cfg = client.get_config().clone();
+    let mut cfg = client.get_config();
     let module_id = 2142;
     let extra_mod = ClientModuleConfig::from_typed(
         module_id,
@@ -59,3 +480,47 @@ async fn client_ignores_unknown_module() {
     // Test that building the client worked
     let _client = fed.new_client_with_config(cfg).await;
 }
+
+#[tokio::test(flavor = "multi_thread")]
+async fn client_manager_runs_clients_of_multiple_federations() -> anyhow::Result<()> {
+    let fixtures = fixtures();
+    let fed1 = fixtures.new_fed().await;
+    let fed2 = fixtures.new_fed().await;
+
+    let manager = ClientManager::<PlainRootSecretStrategy>::new(
+        Box::new(MemDatabase::new()),
+        fed1.client_module_gens(),
+        fedimint_dummy_common::KIND,
+        &mut TaskGroup::new(),
+    )
+    .await?;
+    let client1 = manager.join(fed1.client_config()).await?;
+    let client2 = manager.join(fed2.client_config()).await?;
+    assert!(manager.join(fed1.client_config()).await.is_err());
+
+    // Clients of different federations derive different secrets
+    assert_ne!(client1.account(), client2.account());
+
+    let (_, outpoint) = client1.print_money(sats(1000)).await?;
+    client1.receive_money(outpoint).await?;
+    let (_, outpoint) = client2.print_money(sats(500)).await?;
+    client2.receive_money(outpoint).await?;
+    assert_eq!(client1.get_balance().await, sats(1000));
+    assert_eq!(client2.get_balance().await, sats(500));
+    assert_eq!(manager.total_balance().await, sats(1500));
+
+    // Leaving is only possible once all funds were spent
+    assert!(manager.leave(fed2.id(), false).await.is_err());
+    client2
+        .send_money(fed2.new_client().await.account(), sats(500))
+        .await?;
+    while !client2.get_active_operations().await.is_empty() {
+        fedimint_core::task::sleep(Duration::from_millis(100)).await;
+    }
+    manager.leave(fed2.id(), false).await?;
+
+    assert!(manager.get(&fed2.id()).await.is_none());
+    assert_eq!(manager.federations().await, vec![fed1.id()]);
+    assert_eq!(manager.total_balance().await, sats(1000));
+    Ok(())
+}
@@ -0,0 +1,284 @@
+use std::time::Duration;
+
+use bitcoin_hashes::Hash;
+use fedimint_client::backup::restore_operations;
+use fedimint_client::oplog::OperationLog;
+use fedimint_client::sm::{Executor, OperationId};
+use fedimint_client::DynGlobalClientContext;
+use fedimint_core::api::{DynGlobalApi, GlobalFederationApi};
+use fedimint_core::config::FederationId;
+use fedimint_core::core::ModuleInstanceId;
+use fedimint_core::db::{DatabaseTransaction, ModuleDatabaseTransaction};
+use fedimint_core::encoding::{Decodable, Encodable};
+use fedimint_core::module::CommonModuleGen;
+use fedimint_core::outcome::TransactionStatus;
+use fedimint_core::task::timeout;
+use fedimint_core::{Amount, TransactionId};
+use fedimint_ln_common::contracts::outgoing::{OutgoingContractAccount, OutgoingContractData};
+use fedimint_ln_common::contracts::IdentifiableContract;
+use fedimint_ln_common::LightningCommonGen;
+use lightning_invoice::Invoice;
+use tracing::warn;
+
+use crate::db::{NextKeyIndexKey, ReceiveKeyIndexKey, RecoveryKeyIndexKey};
+use crate::pay::{
+    GatewayPayError, LightningPayCommon, LightningPayRefundable, LightningPayStateMachine,
+    LightningPayStates,
+};
+use crate::receive::{
+    LightningReceiveConfirmedInvoice, LightningReceiveStateMachine, LightningReceiveStates,
+};
+use crate::{LightningClientModule, LightningClientStateMachines, LightningMeta};
+
+/// How long restoring waits for the federation to decide on an offer that
+/// wasn't accepted yet when the backup was made
+const RESTORED_OFFER_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Snapshot of the lightning payments that still hold funds
+///
+/// Only derivation indices are stored instead of keys, the keys are derived
+/// from the module secret again on restore.
+#[derive(Debug, Clone, PartialEq, Eq, Encodable, Decodable)]
+pub struct LightningBackup {
+    next_key_idx: u64,
+    pending_receives: Vec<PendingReceive>,
+    pending_payments: Vec<PendingPayment>,
+}
+
+/// Incoming payment that wasn't claimed yet
+#[derive(Debug, Clone, PartialEq, Eq, Encodable, Decodable)]
+struct PendingReceive {
+    key_idx: u64,
+    invoice: Invoice,
+    /// Transaction submitting the offer, if it wasn't accepted yet
+    offer_txid: Option<TransactionId>,
+}
+
+/// Outgoing contract that may have to be refunded
+#[derive(Debug, Clone, PartialEq, Eq, Encodable, Decodable)]
+struct PendingPayment {
+    key_idx: u64,
+    operation_id: OperationId,
+    federation_id: FederationId,
+    contract_account: OutgoingContractAccount,
+}
+
+impl LightningBackup {
+    /// An empty backup, like one created by a newly created client
+    pub fn new_empty() -> Self {
+        Self {
+            next_key_idx: 0,
+            pending_receives: vec![],
+            pending_payments: vec![],
+        }
+    }
+}
+
+impl LightningClientModule {
+    pub async fn prepare_lightning_backup(
+        &self,
+        dbtx: &mut ModuleDatabaseTransaction<'_>,
+        executor: Executor<DynGlobalClientContext>,
+        module_instance_id: ModuleInstanceId,
+    ) -> LightningBackup {
+        let mut backup = LightningBackup {
+            next_key_idx: dbtx.get_value(&NextKeyIndexKey).await.unwrap_or(0),
+            pending_receives: vec![],
+            pending_payments: vec![],
+        };
+
+        for (dyn_state, _active_state) in executor.get_active_states().await {
+            if dyn_state.module_instance_id() != module_instance_id {
+                continue;
+            }
+
+            let state: LightningClientStateMachines = dyn_state
+                .as_any()
+                .downcast_ref()
+                .cloned()
+                .expect("Can't downcast lightning client state machine state");
+
+            match state {
+                LightningClientStateMachines::Receive(LightningReceiveStateMachine {
+                    operation_id,
+                    state,
+                }) => {
+                    let (invoice, offer_txid) = match state {
+                        LightningReceiveStates::SubmittedOffer(offer) => {
+                            (offer.invoice, Some(offer.offer_txid))
+                        }
+                        LightningReceiveStates::ConfirmedInvoice(confirmed) => {
+                            (confirmed.invoice, None)
+                        }
+                        // Claimed funds are backed up by the primary module
+                        _ => continue,
+                    };
+                    match dbtx.get_value(&ReceiveKeyIndexKey(operation_id)).await {
+                        Some(key_idx) => backup.pending_receives.push(PendingReceive {
+                            key_idx,
+                            invoice,
+                            offer_txid,
+                        }),
+                        None => warn!(
+                            %operation_id,
+                            "Incoming payment doesn't use a derived key, can't back it up"
+                        ),
+                    }
+                }
+                LightningClientStateMachines::LightningPay(LightningPayStateMachine {
+                    common,
+                    state:
+                        LightningPayStates::CreatedOutgoingLnContract(_)
+                        | LightningPayStates::Funded(_)
+                        | LightningPayStates::Refundable(_),
+                }) => {
+                    match dbtx
+                        .get_value(&RecoveryKeyIndexKey(common.operation_id))
+                        .await
+                    {
+                        Some(key_idx) => backup.pending_payments.push(PendingPayment {
+                            key_idx,
+                            operation_id: common.operation_id,
+                            federation_id: common.federation_id,
+                            contract_account: common.contract.contract_account,
+                        }),
+                        None => warn!(
+                            operation_id = %common.operation_id,
+                            "Outgoing contract doesn't use a derived key, can't back it up"
+                        ),
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        backup
+    }
+
+    /// Re-creates the state machines of all pending payments in `backup` and
+    /// adds their operations to `operation_log`.
+    ///
+    /// Incoming payments resume waiting for the invoice to be paid, unless the
+    /// federation didn't accept their offer. Outgoing contracts are refunded
+    /// once the gateway cancels them or they time out, if the gateway
+    /// completed the payment in the meantime the refund fails.
+    pub async fn restore_lightning_backup(
+        &self,
+        dbtx: &mut DatabaseTransaction<'_>,
+        module_instance_id: ModuleInstanceId,
+        executor: Executor<DynGlobalClientContext>,
+        operation_log: &OperationLog,
+        api: DynGlobalApi,
+        backup: LightningBackup,
+    ) -> anyhow::Result<()> {
+        let mut operations = vec![];
+        let mut module_dbtx = dbtx.with_module_prefix(module_instance_id);
+
+        // Never go back to an index that could already have been used
+        let next_key_idx = module_dbtx
+            .get_value(&NextKeyIndexKey)
+            .await
+            .unwrap_or(0)
+            .max(backup.next_key_idx);
+        module_dbtx
+            .insert_entry(&NextKeyIndexKey, &next_key_idx)
+            .await;
+
+        for PendingReceive {
+            key_idx,
+            invoice,
+            offer_txid,
+        } in backup.pending_receives
+        {
+            let operation_id = OperationId(invoice.payment_hash().into_inner());
+            // The restored client can't follow the submission of the offer, so the
+            // federation is asked whether it was accepted instead
+            if let Some(offer_txid) = offer_txid {
+                let outcome =
+                    timeout(RESTORED_OFFER_TIMEOUT, api.await_tx_outcome(&offer_txid)).await;
+                if !matches!(outcome, Ok(Ok(TransactionStatus::Accepted { .. }))) {
+                    warn!(
+                        %operation_id,
+                        "Offer of incoming payment wasn't accepted, not restoring it"
+                    );
+                    continue;
+                }
+            }
+
+            module_dbtx
+                .insert_entry(&ReceiveKeyIndexKey(operation_id), &key_idx)
+                .await;
+            operations.push((
+                operation_id,
+                LightningMeta::Receive {
+                    out_point: None,
+                    invoice: invoice.clone(),
+                },
+                LightningClientStateMachines::Receive(LightningReceiveStateMachine {
+                    operation_id,
+                    state: LightningReceiveStates::ConfirmedInvoice(
+                        LightningReceiveConfirmedInvoice {
+                            invoice,
+                            keypair: self.receive_keypair(key_idx),
+                        },
+                    ),
+                }),
+            ));
+        }
+
+        for PendingPayment {
+            key_idx,
+            operation_id,
+            federation_id,
+            contract_account,
+        } in backup.pending_payments
+        {
+            module_dbtx
+                .insert_entry(&RecoveryKeyIndexKey(operation_id), &key_idx)
+                .await;
+            let contract_id = contract_account.contract.contract_id();
+            let block_timelock = contract_account.contract.timelock;
+            let invoice = contract_account.contract.invoice.clone();
+            let gateway_fee = invoice.amount_milli_satoshis().and_then(|invoice_amount| {
+                contract_account
+                    .amount
+                    .checked_sub(Amount::from_msats(invoice_amount))
+            });
+            operations.push((
+                operation_id,
+                LightningMeta::Pay {
+                    out_point: None,
+                    invoice,
+                    change_outpoint: None,
+                    gateway_fee,
+                },
+                LightningClientStateMachines::LightningPay(LightningPayStateMachine {
+                    common: LightningPayCommon {
+                        operation_id,
+                        federation_id,
+                        contract: OutgoingContractData {
+                            recovery_key: self.recovery_keypair(key_idx),
+                            contract_account,
+                        },
+                    },
+                    state: LightningPayStates::Refundable(LightningPayRefundable {
+                        contract_id,
+                        block_timelock,
+                        error: GatewayPayError::RestoredFromBackup,
+                    }),
+                }),
+            ));
+        }
+        drop(module_dbtx);
+
+        restore_operations(
+            dbtx,
+            module_instance_id,
+            LightningCommonGen::KIND.as_str(),
+            &executor,
+            operation_log,
+            operations,
+        )
+        .await
+    }
+}
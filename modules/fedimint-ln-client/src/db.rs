@@ -1,3 +1,4 @@
+use fedimint_client::sm::OperationId;
 use fedimint_core::encoding::{Decodable, Encodable};
 use fedimint_core::{impl_db_lookup, impl_db_record};
 use fedimint_ln_common::LightningGateway;
@@ -8,6 +9,9 @@ use strum_macros::EnumIter;
 #[derive(Clone, EnumIter, Debug)]
 pub enum DbKeyPrefix {
     LightningGateway = 0x28,
+    NextKeyIndex = 0x29,
+    ReceiveKeyIndex = 0x2a,
+    RecoveryKeyIndex = 0x2b,
 }
 
 #[derive(Debug, Encodable, Decodable, Serialize)]
@@ -25,3 +29,48 @@ impl_db_lookup!(
     key = LightningGatewayKey,
     query_prefix = LightningGatewayKeyPrefix
 );
+
+/// Index of the next key derived for a payment, shared by receive and
+/// recovery keys
+#[derive(Debug, Encodable, Decodable, Serialize)]
+pub struct NextKeyIndexKey;
+
+impl_db_record!(
+    key = NextKeyIndexKey,
+    value = u64,
+    db_prefix = DbKeyPrefix::NextKeyIndex,
+);
+
+/// Index of the key the preimage of an incoming payment was derived from
+#[derive(Debug, Encodable, Decodable, Serialize)]
+pub struct ReceiveKeyIndexKey(pub OperationId);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct ReceiveKeyIndexKeyPrefix;
+
+impl_db_record!(
+    key = ReceiveKeyIndexKey,
+    value = u64,
+    db_prefix = DbKeyPrefix::ReceiveKeyIndex,
+);
+impl_db_lookup!(
+    key = ReceiveKeyIndexKey,
+    query_prefix = ReceiveKeyIndexKeyPrefix
+);
+
+/// Index of the recovery key of an outgoing contract
+#[derive(Debug, Encodable, Decodable, Serialize)]
+pub struct RecoveryKeyIndexKey(pub OperationId);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct RecoveryKeyIndexKeyPrefix;
+
+impl_db_record!(
+    key = RecoveryKeyIndexKey,
+    value = u64,
+    db_prefix = DbKeyPrefix::RecoveryKeyIndex,
+);
+impl_db_lookup!(
+    key = RecoveryKeyIndexKey,
+    query_prefix = RecoveryKeyIndexKeyPrefix
+);
@@ -1,3 +1,4 @@
+pub mod backup;
 mod db;
 pub mod pay;
 pub mod receive;
@@ -10,19 +11,28 @@ use anyhow::{bail, ensure, format_err};
 use async_stream::stream;
 use bitcoin::{KeyPair, Network};
 use bitcoin_hashes::Hash;
-use db::LightningGatewayKey;
+use db::{
+    LightningGatewayKey, NextKeyIndexKey, ReceiveKeyIndexKey, ReceiveKeyIndexKeyPrefix,
+    RecoveryKeyIndexKey, RecoveryKeyIndexKeyPrefix,
+};
+use fedimint_client::batch::BatchedTransaction;
 use fedimint_client::derivable_secret::{ChildId, DerivableSecret};
+use fedimint_client::event::StateSummary;
+use fedimint_client::ledger::{LedgerDirection, LedgerEntry, LedgerStatus};
 use fedimint_client::module::gen::ClientModuleGen;
 use fedimint_client::module::{ClientModule, IClientModule};
-use fedimint_client::oplog::UpdateStreamOrOutcome;
+use fedimint_client::oplog::{OperationLog, OperationLogEntry, UpdateStreamOrOutcome};
+use fedimint_client::policy::SpendDestination;
 use fedimint_client::sm::util::MapStateTransitions;
-use fedimint_client::sm::{DynState, ModuleNotifier, OperationId, State, StateTransition};
-use fedimint_client::transaction::{ClientOutput, TransactionBuilder};
+use fedimint_client::sm::{
+    DynState, Executor, ModuleNotifier, OperationId, State, StateTransition,
+};
+use fedimint_client::transaction::{ClientOutput, TransactionBuilder, TransactionPreview};
 use fedimint_client::{sm_enum_variant_translation, Client, DynGlobalClientContext};
 use fedimint_core::api::{DynGlobalApi, DynModuleApi};
 use fedimint_core::config::FederationId;
 use fedimint_core::core::{IntoDynInstance, ModuleInstanceId};
-use fedimint_core::db::Database;
+use fedimint_core::db::{Database, DatabaseTransaction, ModuleDatabaseTransaction};
 use fedimint_core::encoding::{Decodable, Encodable};
 use fedimint_core::module::{
     ApiVersion, CommonModuleGen, ExtendsCommonModuleGen, ModuleCommon, MultiApiVersion,
@@ -54,6 +64,7 @@ use serde::{Deserialize, Serialize};
 use thiserror::Error;
 use tracing::{debug, error};
 
+use crate::backup::LightningBackup;
 use crate::pay::{
     GatewayPayError, LightningPayCommon, LightningPayCreatedOutgoingLnContract,
     LightningPayStateMachine, LightningPayStates,
@@ -67,6 +78,13 @@ use crate::receive::{
 /// client can get refund
 const OUTGOING_LN_CONTRACT_TIMELOCK: u64 = 500;
 
+/// Child of the module secret the keys of incoming payments are derived from,
+/// their public keys are used as preimages
+const RECEIVE_KEY_CHILD_ID: ChildId = ChildId(1);
+/// Child of the module secret the recovery keys of outgoing contracts are
+/// derived from
+const RECOVERY_KEY_CHILD_ID: ChildId = ChildId(2);
+
 #[apply(async_trait_maybe_send!)]
 pub trait LightningClientExt {
     /// The set active gateway, or a random one if none has been set
@@ -81,6 +99,11 @@ pub trait LightningClientExt {
     /// Pays a LN invoice with our available funds
     async fn pay_bolt11_invoice(&self, invoice: Invoice) -> anyhow::Result<(PayType, ContractId)>;
 
+    /// Calculates what paying a LN invoice using
+    /// [`LightningClientExt::pay_bolt11_invoice`] would cost *right now*
+    /// without paying it
+    async fn quote_bolt11_invoice(&self, invoice: Invoice) -> anyhow::Result<LnPayQuote>;
+
     async fn subscribe_internal_pay(
         &self,
         operation_id: OperationId,
@@ -105,6 +128,29 @@ pub trait LightningClientExt {
     ) -> anyhow::Result<UpdateStreamOrOutcome<'_, LnReceiveState>>;
 }
 
+/// The costs of paying an invoice, see
+/// [`LightningClientExt::quote_bolt11_invoice`]
+#[derive(Debug, Clone)]
+pub struct LnPayQuote {
+    /// Whether the payment would be internal to the federation or go through
+    /// a gateway
+    pub pay_type: PayType,
+    /// Amount requested by the invoice
+    pub invoice_amount: Amount,
+    /// Fee charged by the gateway, zero for internal payments
+    pub gateway_fee: Amount,
+    /// The transaction that would fund the payment, including the
+    /// federation's fees
+    pub transaction: TransactionPreview,
+}
+
+impl LnPayQuote {
+    /// Total amount the payment would cost including all fees
+    pub fn total_cost(&self) -> Amount {
+        self.transaction.total_cost()
+    }
+}
+
 #[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
 pub enum PayType {
     // Payment from this client to another user within the federation
@@ -158,6 +204,55 @@ pub enum LnReceiveState {
     Claimed,
 }
 
+/// Creates the transaction funding the contract that pays `invoice`, either
+/// directly if the payee is a user of this federation or via the active
+/// gateway otherwise. Also returns the amount the contract is funded with.
+async fn create_pay_transaction(
+    client: &Client,
+    dbtx: &mut ModuleDatabaseTransaction<'_>,
+    invoice: &Invoice,
+) -> anyhow::Result<(PayType, TransactionBuilder, ContractId, Amount)> {
+    let (lightning, instance) = client.get_first_module::<LightningClientModule>(&KIND);
+    let operation_id = OperationId(invoice.payment_hash().into_inner());
+
+    let is_internal_payment =
+        invoice_has_internal_payment_markers(invoice, client.get_internal_payment_markers()?).await
+            || invoice_routes_back_to_federation(
+                invoice,
+                client.fetch_registered_gateways().await?,
+            )
+            .await;
+
+    let (pay_type, output, contract_id) = if is_internal_payment {
+        let (output, contract_id) = lightning
+            .create_incoming_output(operation_id, invoice.clone())
+            .await?;
+        (PayType::Internal(operation_id), output, contract_id)
+    } else {
+        let active_gateway = client.select_active_gateway().await?;
+        let (output, contract_id) = lightning
+            .create_outgoing_output(
+                dbtx,
+                operation_id,
+                instance.api,
+                invoice.clone(),
+                active_gateway,
+                client.get_config().federation_id,
+            )
+            .await?;
+        (PayType::Lightning(operation_id), output, contract_id)
+    };
+
+    let contract_amount = match &output.output {
+        LightningOutput::Contract(contract_output) => contract_output.amount,
+        LightningOutput::Offer(_) | LightningOutput::CancelOutgoing { .. } => {
+            bail!("Payment output doesn't fund a contract")
+        }
+    };
+    let tx = TransactionBuilder::new().with_output(output.into_dyn(instance.id));
+    Ok((pay_type, tx, contract_id, contract_amount))
+}
+
 async fn invoice_has_internal_payment_markers(
     invoice: &Invoice,
     markers: (secp256k1::PublicKey, u64),
@@ -231,47 +326,25 @@ impl LightningClientExt for Client {
     }
 
     async fn pay_bolt11_invoice(&self, invoice: Invoice) -> anyhow::Result<(PayType, ContractId)> {
-        let (lightning, instance) = self.get_first_module::<LightningClientModule>(&KIND);
-        let payment_hash = invoice.payment_hash();
-        let operation_id = OperationId(payment_hash.into_inner());
-
-        let is_internal_payment =
-            invoice_has_internal_payment_markers(&invoice, self.get_internal_payment_markers()?)
-                .await
-                || invoice_routes_back_to_federation(
-                    &invoice,
-                    self.fetch_registered_gateways().await?,
-                )
-                .await;
-
-        let (pay_type, output, contract_id) = if is_internal_payment {
-            let (output, contract_id) = lightning
-                .create_incoming_output(operation_id, invoice.clone())
-                .await?;
-            (PayType::Internal(operation_id), output, contract_id)
-        } else {
-            let active_gateway = self.select_active_gateway().await?;
-            let (output, contract_id) = lightning
-                .create_outgoing_output(
-                    operation_id,
-                    instance.api,
-                    invoice.clone(),
-                    active_gateway,
-                    self.get_config().federation_id,
-                    rand::rngs::OsRng,
-                )
-                .await?;
-            (PayType::Lightning(operation_id), output, contract_id)
-        };
+        let (_lightning, instance) = self.get_first_module::<LightningClientModule>(&KIND);
+        let mut dbtx = instance.db.begin_transaction().await;
+        let (pay_type, tx, contract_id, contract_amount) =
+            create_pay_transaction(self, &mut dbtx.get_isolated(), &invoice).await?;
+        // Reserve the key index of the recovery key before funding the contract
+        dbtx.commit_tx_result().await?;
+        let operation_id = OperationId(invoice.payment_hash().into_inner());
+        let gateway_fee = invoice.amount_milli_satoshis().and_then(|invoice_amount| {
+            contract_amount.checked_sub(Amount::from_msats(invoice_amount))
+        });
 
-        let tx = TransactionBuilder::new().with_output(output.into_dyn(instance.id));
-        let operation_meta_gen = |txid, change_outpoint| LightningMeta::Pay {
-            out_point: OutPoint { txid, out_idx: 0 },
+        let operation_meta_gen = move |batched_tx: BatchedTransaction| LightningMeta::Pay {
+            out_point: Some(batched_tx.out_point(0)),
             invoice: invoice.clone(),
-            change_outpoint,
+            change_outpoint: batched_tx.change_outpoint,
+            gateway_fee,
         };
 
-        self.finalize_and_submit_transaction(
+        self.submit_batched_transaction(
             operation_id,
             LightningCommonGen::KIND.as_str(),
             operation_meta_gen,
@@ -282,6 +355,34 @@ impl LightningClientExt for Client {
         Ok((pay_type, contract_id))
     }
 
+    async fn quote_bolt11_invoice(&self, invoice: Invoice) -> anyhow::Result<LnPayQuote> {
+        let invoice_amount = Amount::from_msats(
+            invoice
+                .amount_milli_satoshis()
+                .ok_or(anyhow::anyhow!("MissingInvoiceAmount"))?,
+        );
+        let (_lightning, instance) = self.get_first_module::<LightningClientModule>(&KIND);
+        let mut dbtx = instance.db.begin_transaction().await;
+        let (pay_type, tx, _, contract_amount) =
+            create_pay_transaction(self, &mut dbtx.get_isolated(), &invoice).await?;
+        // Quoting must not reserve a key index
+        dbtx.ignore_uncommitted();
+        let transaction = self.preview_transaction(tx).await?;
+        // The contract is funded with the invoice amount plus the gateway's fee
+        let gateway_fee = contract_amount
+            .checked_sub(invoice_amount)
+            .ok_or(anyhow::anyhow!(
+                "Contract is funded with less than the invoice amount"
+            ))?;
+
+        Ok(LnPayQuote {
+            pay_type,
+            invoice_amount,
+            gateway_fee,
+            transaction,
+        })
+    }
+
     async fn create_bolt11_invoice(
         &self,
         amount: Amount,
@@ -302,8 +403,10 @@ impl LightningClientExt for Client {
             }
         };
 
+        let mut dbtx = instance.db.begin_transaction().await;
         let (operation_id, invoice, output) = lightning
             .create_lightning_receive_output(
+                &mut dbtx.get_isolated(),
                 amount,
                 description,
                 rand::rngs::OsRng,
@@ -314,9 +417,11 @@ impl LightningClientExt for Client {
                 lightning.cfg.network,
             )
             .await?;
+        // Reserve the key index of the payment key before submitting the offer
+        dbtx.commit_tx_result().await?;
         let tx = TransactionBuilder::new().with_output(output.into_dyn(instance.id));
         let operation_meta_gen = |txid, _| LightningMeta::Receive {
-            out_point: OutPoint { txid, out_idx: 0 },
+            out_point: Some(OutPoint { txid, out_idx: 0 }),
             invoice: invoice.clone(),
         };
         let txid = self
@@ -351,10 +456,14 @@ impl LightningClientExt for Client {
             _ => bail!("Operation is not a lightning payment"),
         };
 
-        let tx_accepted_future = self
-            .transaction_updates(operation_id)
-            .await
-            .await_tx_accepted(out_point.txid);
+        let tx_updates = self.transaction_updates(operation_id).await;
+        // The offer of a payment restored from a backup was accepted before
+        let tx_accepted_future = async move {
+            match out_point {
+                Some(out_point) => tx_updates.await_tx_accepted(out_point.txid).await,
+                None => Ok(()),
+            }
+        };
 
         let receive_success = lightning.await_receive_success(operation_id);
         let claim_acceptance = lightning.await_claim_acceptance(operation_id);
@@ -404,14 +513,19 @@ impl LightningClientExt for Client {
                 out_point,
                 invoice,
                 change_outpoint,
+                ..
             } => (out_point, invoice, change_outpoint),
             _ => bail!("Operation is not a lightning payment"),
         };
 
-        let tx_accepted_future = self
-            .transaction_updates(operation_id)
-            .await
-            .await_tx_accepted(out_point.txid);
+        let tx_updates = self.transaction_updates(operation_id).await;
+        // The contract of a payment restored from a backup was funded before
+        let tx_accepted_future = async move {
+            match out_point {
+                Some(out_point) => tx_updates.await_tx_accepted(out_point.txid).await,
+                None => Ok(()),
+            }
+        };
         let payment_success = lightning.await_lightning_payment_success(operation_id);
 
         let refund_success = lightning.await_refund(operation_id);
@@ -500,12 +614,20 @@ impl LightningClientExt for Client {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum LightningMeta {
     Pay {
-        out_point: OutPoint,
+        /// `None` for payments restored from a backup, whose funding
+        /// transaction isn't known
+        out_point: Option<OutPoint>,
         invoice: Invoice,
         change_outpoint: Option<OutPoint>,
+        /// Fee charged by the gateway on top of the invoice amount, zero for
+        /// internal payments. Missing for payments of older clients.
+        #[serde(default)]
+        gateway_fee: Option<Amount>,
     },
     Receive {
-        out_point: OutPoint,
+        /// `None` for payments restored from a backup, whose offer
+        /// transaction isn't known
+        out_point: Option<OutPoint>,
         invoice: Invoice,
     },
 }
@@ -541,6 +663,7 @@ impl ClientModuleGen for LightningClientGen {
             cfg,
             notifier,
             redeem_key: module_root_secret.child_key(ChildId(0)).to_secp_key(&secp),
+            secret: module_root_secret,
             secp,
             module_api,
         })
@@ -552,10 +675,12 @@ pub struct LightningClientModule {
     pub cfg: LightningClientConfig,
     notifier: ModuleNotifier<DynGlobalClientContext, LightningClientStateMachines>,
     redeem_key: KeyPair,
+    secret: DerivableSecret,
     secp: Secp256k1<All>,
     module_api: DynModuleApi,
 }
 
+#[apply(async_trait_maybe_send!)]
 impl ClientModule for LightningClientModule {
     type Common = LightningModuleTypes;
     type ModuleStateMachineContext = LightningClientContext;
@@ -592,6 +717,159 @@ impl ClientModule for LightningClientModule {
             }
         }
     }
+
+    fn output_destination(
+        &self,
+        output: &<Self::Common as ModuleCommon>::Output,
+    ) -> Option<SpendDestination> {
+        // Internal payments fund incoming contracts, they never leave the federation
+        // and are not restricted
+        match output {
+            LightningOutput::Contract(ContractOutput {
+                contract: Contract::Outgoing(contract),
+                ..
+            }) => {
+                let payee = contract
+                    .invoice
+                    .payee_pub_key()
+                    .copied()
+                    .unwrap_or_else(|| contract.invoice.recover_payee_pub_key());
+                Some(SpendDestination::LightningPayee(payee))
+            }
+            _ => None,
+        }
+    }
+
+    async fn ledger_entry(
+        &self,
+        _dbtx: &mut ModuleDatabaseTransaction<'_>,
+        _operation_id: OperationId,
+        operation: &OperationLogEntry,
+    ) -> Option<LedgerEntry> {
+        let outcome = operation.outcome::<serde_json::Value>();
+        let (direction, invoice, fee, status) = match operation.meta::<LightningMeta>() {
+            LightningMeta::Pay {
+                invoice,
+                gateway_fee,
+                ..
+            } => {
+                // Internal payments share the operation type and meta with payments over
+                // lightning but have their own outcome type
+                let ln_outcome = outcome
+                    .clone()
+                    .and_then(|outcome| serde_json::from_value::<LnPayState>(outcome).ok());
+                let internal_outcome = outcome
+                    .and_then(|outcome| serde_json::from_value::<InternalPayState>(outcome).ok());
+                let status = match (ln_outcome, internal_outcome) {
+                    (Some(LnPayState::Success { .. }), _)
+                    | (_, Some(InternalPayState::Preimage(_))) => LedgerStatus::Succeeded,
+                    (Some(LnPayState::Canceled | LnPayState::Refunded { .. }), _)
+                    | (
+                        _,
+                        Some(
+                            InternalPayState::RefundSuccess(_)
+                            | InternalPayState::RefundError(_)
+                            | InternalPayState::FundingFailed(_)
+                            | InternalPayState::Error(_),
+                        ),
+                    ) => LedgerStatus::Failed,
+                    _ => LedgerStatus::Pending,
+                };
+                (LedgerDirection::Outgoing, invoice, gateway_fee, status)
+            }
+            LightningMeta::Receive { invoice, .. } => {
+                let status = match outcome
+                    .and_then(|outcome| serde_json::from_value::<LnReceiveState>(outcome).ok())
+                {
+                    Some(LnReceiveState::Claimed) => LedgerStatus::Succeeded,
+                    Some(LnReceiveState::Canceled { .. }) => LedgerStatus::Failed,
+                    _ => LedgerStatus::Pending,
+                };
+                (LedgerDirection::Incoming, invoice, None, status)
+            }
+        };
+
+        Some(LedgerEntry {
+            direction,
+            amount: invoice.amount_milli_satoshis().map(Amount::from_msats),
+            fee,
+            counterparty: Some(invoice.to_string()),
+            status,
+        })
+    }
+
+    fn summarize_state(&self, state: &Self::States) -> StateSummary {
+        match state {
+            LightningClientStateMachines::InternalPay(state) => state.summary(),
+            LightningClientStateMachines::LightningPay(state) => state.summary(),
+            LightningClientStateMachines::Receive(state) => state.summary(),
+        }
+    }
+
+    fn supports_backup(&self) -> bool {
+        true
+    }
+
+    async fn backup(
+        &self,
+        dbtx: &mut ModuleDatabaseTransaction<'_>,
+        executor: Executor<DynGlobalClientContext>,
+        _api: DynGlobalApi,
+        module_instance_id: ModuleInstanceId,
+    ) -> anyhow::Result<Vec<u8>> {
+        let backup = self
+            .prepare_lightning_backup(dbtx, executor, module_instance_id)
+            .await;
+
+        Ok(backup.consensus_encode_to_vec()?)
+    }
+
+    async fn restore(
+        &self,
+        dbtx: &mut DatabaseTransaction<'_>,
+        module_instance_id: ModuleInstanceId,
+        executor: Executor<DynGlobalClientContext>,
+        operation_log: &OperationLog,
+        api: DynGlobalApi,
+        snapshot: Option<&[u8]>,
+    ) -> anyhow::Result<()> {
+        if executor
+            .get_active_states()
+            .await
+            .into_iter()
+            .any(|s| s.0.module_instance_id() == module_instance_id)
+        {
+            bail!("Found existing active state machines. Lightning module recovery must be started on an empty state.")
+        }
+
+        let backup = snapshot
+            .map(|mut s| LightningBackup::consensus_decode(&mut s, &Default::default()))
+            .transpose()?
+            .unwrap_or(LightningBackup::new_empty());
+
+        self.restore_lightning_backup(
+            dbtx,
+            module_instance_id,
+            executor,
+            operation_log,
+            api,
+            backup,
+        )
+        .await
+    }
+
+    async fn wipe(
+        &self,
+        dbtx: &mut ModuleDatabaseTransaction<'_>,
+        _module_instance_id: ModuleInstanceId,
+        _executor: Executor<DynGlobalClientContext>,
+    ) -> anyhow::Result<()> {
+        debug!("Wiping lightning module state");
+        // The next key index is kept so no key is used twice
+        dbtx.remove_by_prefix(&ReceiveKeyIndexKeyPrefix).await;
+        dbtx.remove_by_prefix(&RecoveryKeyIndexKeyPrefix).await;
+        Ok(())
+    }
 }
 
 #[derive(Error, Debug, Serialize, Deserialize)]
@@ -607,17 +885,44 @@ pub enum PayError {
 }
 
 impl LightningClientModule {
+    /// Reserves the index of the next derived payment key
+    async fn next_key_index(dbtx: &mut ModuleDatabaseTransaction<'_>) -> u64 {
+        let key_idx = dbtx.get_value(&NextKeyIndexKey).await.unwrap_or(0);
+        dbtx.insert_entry(&NextKeyIndexKey, &(key_idx + 1)).await;
+        key_idx
+    }
+
+    /// Derives the key pair of an incoming payment, the x-only public key is
+    /// the payment's preimage
+    pub(crate) fn receive_keypair(&self, key_idx: u64) -> KeyPair {
+        self.secret
+            .child_key(RECEIVE_KEY_CHILD_ID)
+            .child_key(ChildId(key_idx))
+            .to_secp_key(&self.secp)
+    }
+
+    /// Derives the key pair used to refund an outgoing contract
+    pub(crate) fn recovery_keypair(&self, key_idx: u64) -> KeyPair {
+        self.secret
+            .child_key(RECOVERY_KEY_CHILD_ID)
+            .child_key(ChildId(key_idx))
+            .to_secp_key(&self.secp)
+    }
+
     /// Create an output that incentivizes a Lightning gateway to pay an invoice
     /// for us. It has time till the block height defined by `timelock`,
     /// after that we can claim our money back.
-    pub async fn create_outgoing_output<'a, 'b>(
-        &'a self,
+    ///
+    /// The recovery key of the contract is derived from the module secret, its
+    /// index is reserved in `dbtx`.
+    pub async fn create_outgoing_output(
+        &self,
+        dbtx: &mut ModuleDatabaseTransaction<'_>,
         operation_id: OperationId,
         api: DynModuleApi,
         invoice: Invoice,
         gateway: LightningGateway,
         fed_id: FederationId,
-        mut rng: impl RngCore + CryptoRng + 'a,
     ) -> anyhow::Result<(
         ClientOutput<LightningOutput, LightningClientStateMachines>,
         ContractId,
@@ -654,7 +959,10 @@ impl LightningClientModule {
         let contract_amount_msat = invoice_amount_msat + base_fee + margin_fee;
         let contract_amount = Amount::from_msats(contract_amount_msat);
 
-        let user_sk = bitcoin::KeyPair::new(&self.secp, &mut rng);
+        let key_idx = Self::next_key_index(dbtx).await;
+        dbtx.insert_entry(&RecoveryKeyIndexKey(operation_id), &key_idx)
+            .await;
+        let user_sk = self.recovery_keypair(key_idx);
 
         let contract = OutgoingContract {
             hash: *invoice.payment_hash(),
@@ -834,9 +1142,15 @@ impl LightningClientModule {
         }
     }
 
+    /// Creates an offer to receive `amount` over lightning and the invoice
+    /// the gateway can pay it with.
+    ///
+    /// The preimage is derived from the module secret, its key index is
+    /// reserved in `dbtx`.
     #[allow(clippy::too_many_arguments)]
     pub async fn create_lightning_receive_output<'a>(
         &'a self,
+        dbtx: &mut ModuleDatabaseTransaction<'_>,
         amount: Amount,
         description: String,
         mut rng: impl RngCore + CryptoRng + 'a,
@@ -850,7 +1164,8 @@ impl LightningClientModule {
         Invoice,
         ClientOutput<LightningOutput, LightningClientStateMachines>,
     )> {
-        let payment_keypair = KeyPair::new(&self.secp, &mut rng);
+        let key_idx = Self::next_key_index(dbtx).await;
+        let payment_keypair = self.receive_keypair(key_idx);
         let preimage: [u8; 32] = payment_keypair.x_only_public_key().0.serialize();
         let payment_hash = bitcoin::secp256k1::hashes::sha256::Hash::hash(&preimage);
 
@@ -911,6 +1226,8 @@ impl LightningClientModule {
             .build_signed(|hash| self.secp.sign_ecdsa_recoverable(hash, &node_secret_key))?;
 
         let operation_id = OperationId(invoice.payment_hash().into_inner());
+        dbtx.insert_entry(&ReceiveKeyIndexKey(operation_id), &key_idx)
+            .await;
 
         let sm_invoice = invoice.clone();
         let sm_gen = Arc::new(move |txid: TransactionId, _input_idx: u64| {
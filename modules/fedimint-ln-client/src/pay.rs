@@ -1,18 +1,17 @@
 use std::sync::Arc;
 use std::time::Duration;
 
+use fedimint_client::event::StateSummary;
 use fedimint_client::sm::{ClientSMDatabaseTransaction, OperationId, State, StateTransition};
 use fedimint_client::transaction::{ClientInput, TxSubmissionError};
 use fedimint_client::DynGlobalClientContext;
-use fedimint_core::api::GlobalFederationApi;
 use fedimint_core::config::FederationId;
-use fedimint_core::core::Decoder;
 use fedimint_core::encoding::{Decodable, Encodable};
 use fedimint_core::task::sleep;
-use fedimint_core::{OutPoint, TransactionId};
+use fedimint_core::TransactionId;
 use fedimint_ln_common::contracts::outgoing::OutgoingContractData;
 use fedimint_ln_common::contracts::ContractId;
-use fedimint_ln_common::{LightningGateway, LightningInput, LightningOutputOutcome};
+use fedimint_ln_common::{LightningGateway, LightningInput};
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 use tracing::error;
@@ -62,6 +61,33 @@ pub struct LightningPayStateMachine {
     pub state: LightningPayStates,
 }
 
+impl LightningPayStateMachine {
+    pub(crate) fn summary(&self) -> StateSummary {
+        match &self.state {
+            LightningPayStates::CreatedOutgoingLnContract(_) => {
+                StateSummary::in_progress("Waiting for the outgoing contract to be funded")
+            }
+            LightningPayStates::Canceled => {
+                StateSummary::failed("Funding transaction was rejected")
+            }
+            LightningPayStates::Funded(_) => {
+                StateSummary::in_progress("Waiting for the gateway to pay the invoice")
+            }
+            LightningPayStates::Success(_) => StateSummary::Succeeded,
+            LightningPayStates::Refundable(_) => {
+                StateSummary::in_progress("Payment failed, waiting to refund the contract")
+            }
+            LightningPayStates::Refund(_) => {
+                StateSummary::in_progress("Waiting for the refund transaction to be accepted")
+            }
+            LightningPayStates::Refunded(_) => {
+                StateSummary::failed("Payment failed, funds were refunded")
+            }
+            LightningPayStates::Failure(reason) => StateSummary::failed(reason),
+        }
+    }
+}
+
 impl State for LightningPayStateMachine {
     type ModuleContext = LightningClientContext;
     type GlobalContext = DynGlobalClientContext;
@@ -121,8 +147,8 @@ impl LightningPayCreatedOutgoingLnContract {
         let gateway = self.gateway.clone();
         vec![StateTransition::new(
             Self::await_outgoing_contract_funded(
-                context.ln_decoder.clone(),
                 success_context,
+                common.operation_id,
                 txid,
                 contract_id,
             ),
@@ -139,19 +165,15 @@ impl LightningPayCreatedOutgoingLnContract {
     }
 
     async fn await_outgoing_contract_funded(
-        module_decoder: Decoder,
         global_context: DynGlobalClientContext,
+        operation_id: OperationId,
         txid: TransactionId,
         contract_id: ContractId,
     ) -> Result<u32, GatewayPayError> {
-        let out_point = OutPoint { txid, out_idx: 0 };
+        // The contract isn't necessarily the first output if the funding transaction
+        // was batched with other operations
         global_context
-            .api()
-            .await_output_outcome::<LightningOutputOutcome>(
-                out_point,
-                Duration::from_millis(i32::MAX as u64),
-                &module_decoder,
-            )
+            .await_tx_accepted(operation_id, txid)
             .await
             .map_err(|_| GatewayPayError::OutgoingContractError)?;
 
@@ -215,6 +237,8 @@ pub enum GatewayPayError {
     },
     #[error("OutgoingContract was not created in the federation")]
     OutgoingContractError,
+    #[error("Payment was restored from a backup before its outcome was known")]
+    RestoredFromBackup,
 }
 
 impl LightningPayFunded {
@@ -300,7 +324,7 @@ impl LightningPayFunded {
 
 #[derive(Debug, Clone, Eq, PartialEq, Decodable, Encodable)]
 pub struct LightningPayRefundable {
-    contract_id: ContractId,
+    pub(crate) contract_id: ContractId,
     pub block_timelock: u32,
     pub error: GatewayPayError,
 }
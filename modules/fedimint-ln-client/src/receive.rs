@@ -3,6 +3,7 @@ use std::sync::Arc;
 use std::time::Duration;
 
 use bitcoin::util::key::KeyPair;
+use fedimint_client::event::StateSummary;
 use fedimint_client::sm::{ClientSMDatabaseTransaction, OperationId, State, StateTransition};
 use fedimint_client::transaction::{ClientInput, TxSubmissionError};
 use fedimint_client::DynGlobalClientContext;
@@ -48,6 +49,24 @@ pub struct LightningReceiveStateMachine {
     pub state: LightningReceiveStates,
 }
 
+impl LightningReceiveStateMachine {
+    pub(crate) fn summary(&self) -> StateSummary {
+        match &self.state {
+            LightningReceiveStates::SubmittedOffer(_) => {
+                StateSummary::in_progress("Waiting for the offer to be accepted")
+            }
+            LightningReceiveStates::Canceled(error) => StateSummary::failed(error),
+            LightningReceiveStates::ConfirmedInvoice(_) => {
+                StateSummary::in_progress("Waiting for the invoice to be paid")
+            }
+            LightningReceiveStates::Funded(_) => {
+                StateSummary::in_progress("Claiming the received funds")
+            }
+            LightningReceiveStates::Success(_) => StateSummary::Succeeded,
+        }
+    }
+}
+
 impl State for LightningReceiveStateMachine {
     type ModuleContext = LightningClientContext;
     type GlobalContext = DynGlobalClientContext;
@@ -155,8 +174,8 @@ impl LightningReceiveSubmittedOffer {
 
 #[derive(Debug, Clone, Eq, PartialEq, Decodable, Encodable)]
 pub struct LightningReceiveConfirmedInvoice {
-    invoice: Invoice,
-    keypair: KeyPair,
+    pub(crate) invoice: Invoice,
+    pub(crate) keypair: KeyPair,
 }
 
 impl LightningReceiveConfirmedInvoice {
@@ -10,6 +10,7 @@
 use std::sync::Arc;
 use std::time::Duration;
 
+use fedimint_client::event::StateSummary;
 use fedimint_client::sm::{ClientSMDatabaseTransaction, OperationId, State, StateTransition};
 use fedimint_client::transaction::ClientInput;
 use fedimint_client::DynGlobalClientContext;
@@ -63,6 +64,27 @@ pub struct IncomingStateMachine {
     pub state: IncomingSmStates,
 }
 
+impl IncomingStateMachine {
+    /// Summarizes the state for [`fedimint_client::Client::subscribe_events`]
+    pub fn summary(&self) -> StateSummary {
+        match &self.state {
+            IncomingSmStates::FundingOffer(_) => {
+                StateSummary::in_progress("Waiting for the incoming contract to be funded")
+            }
+            IncomingSmStates::DecryptingPreimage(_) => {
+                StateSummary::in_progress("Waiting for the preimage to be decrypted")
+            }
+            IncomingSmStates::Preimage(_) => StateSummary::Succeeded,
+            IncomingSmStates::RefundSubmitted(_) => {
+                StateSummary::failed("Preimage was invalid, funds are being refunded")
+            }
+            IncomingSmStates::FundingFailed(reason) | IncomingSmStates::Failure(reason) => {
+                StateSummary::failed(reason)
+            }
+        }
+    }
+}
+
 impl State for IncomingStateMachine {
     type ModuleContext = LightningClientContext;
     type GlobalContext = DynGlobalClientContext;
@@ -1,7 +1,13 @@
 use std::str::FromStr;
+use std::time::Duration;
 
 use assert_matches::assert_matches;
+use fedimint_client::backup::Metadata;
+use fedimint_client::secret::PlainRootSecretStrategy;
+use fedimint_client::{ClientBuilder, ClientSecret};
+use fedimint_core::db::mem_impl::MemDatabase;
 use fedimint_core::sats;
+use fedimint_core::task::TaskGroup;
 use fedimint_core::util::NextOrPending;
 use fedimint_dummy_client::{DummyClientExt, DummyClientGen};
 use fedimint_dummy_common::config::DummyGenParams;
@@ -106,6 +112,56 @@ async fn makes_internal_payments_within_federation() -> anyhow::Result<()> {
     Ok(())
 }
 
+fn client_builder(fed: &FederationTest) -> ClientBuilder {
+    let mut client_builder = ClientBuilder::default();
+    client_builder.with_module_gens(fed.client_module_gens());
+    client_builder.with_primary_module(0);
+    client_builder.with_config(fed.client_config());
+    client_builder.with_database(MemDatabase::new());
+    client_builder
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn restores_pending_receive_from_backup() -> anyhow::Result<()> {
+    let fixtures = fixtures();
+    let fed = fixtures.new_fed().await;
+    let payer = fed.new_client().await;
+    let mut receiver_tasks = TaskGroup::new();
+    let receiver = client_builder(&fed)
+        .build::<PlainRootSecretStrategy>(&mut receiver_tasks)
+        .await?;
+
+    let (op, outpoint) = payer.print_money(sats(1000)).await?;
+    payer.await_primary_module_output(op, outpoint).await?;
+
+    let (_, invoice) = receiver
+        .create_bolt11_invoice(sats(250), "restored".to_string(), None)
+        .await?;
+    receiver.backup_to_federation(Metadata::empty()).await?;
+
+    // Lose the receiving client and restore it from its secret
+    let secret = receiver
+        .root_secret_encoding::<PlainRootSecretStrategy>()
+        .await;
+    receiver_tasks.shutdown_join_all(None).await?;
+    let (restored, _) = client_builder(&fed)
+        .build_restoring_from_backup(
+            &mut TaskGroup::new(),
+            ClientSecret::<PlainRootSecretStrategy>::new(secret),
+        )
+        .await?;
+
+    // The restored client still claims the payment
+    payer.pay_bolt11_invoice(invoice).await?;
+    fedimint_core::task::timeout(Duration::from_secs(30), async {
+        while restored.get_balance().await != sats(250) {
+            fedimint_core::task::sleep(Duration::from_millis(100)).await;
+        }
+    })
+    .await?;
+    Ok(())
+}
+
 #[tokio::test(flavor = "multi_thread")]
 async fn rejects_wrong_network_invoice() -> anyhow::Result<()> {
     let fixtures = fixtures();
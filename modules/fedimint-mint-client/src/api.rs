@@ -0,0 +1,27 @@
+use fedimint_core::api::{FederationApiExt, FederationResult, IModuleFederationApi};
+use fedimint_core::module::ApiRequestErased;
+use fedimint_core::query::EventuallyConsistent;
+use fedimint_core::task::{MaybeSend, MaybeSync};
+use fedimint_core::{apply, async_trait_maybe_send, NumPeers};
+use fedimint_mint_common::Nonce;
+
+#[apply(async_trait_maybe_send!)]
+pub trait MintFederationApi {
+    /// Returns the nonces of `nonces` that were already spent
+    async fn fetch_spent_nonces(&self, nonces: Vec<Nonce>) -> FederationResult<Vec<Nonce>>;
+}
+
+#[apply(async_trait_maybe_send!)]
+impl<T: ?Sized> MintFederationApi for T
+where
+    T: IModuleFederationApi + MaybeSend + MaybeSync + 'static,
+{
+    async fn fetch_spent_nonces(&self, nonces: Vec<Nonce>) -> FederationResult<Vec<Nonce>> {
+        self.request_with_strategy(
+            EventuallyConsistent::new(self.all_members().threshold()),
+            "spent_nonces".to_string(),
+            ApiRequestErased::new(nonces),
+        )
+        .await
+    }
+}
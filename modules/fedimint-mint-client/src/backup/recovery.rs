@@ -3,6 +3,7 @@ use std::collections::{BTreeMap, BTreeSet, HashSet};
 use std::fmt;
 use std::ops::Range;
 
+use fedimint_client::event::StateSummary;
 use fedimint_client::sm::{OperationId, State, StateTransition};
 use fedimint_client::DynGlobalClientContext;
 use fedimint_core::core::LEGACY_HARDCODED_INSTANCE_ID_MINT;
@@ -807,6 +808,18 @@ pub struct MintRestoreStateMachine {
     pub(crate) state: MintRestoreStates,
 }
 
+impl MintRestoreStateMachine {
+    pub(crate) fn summary(&self) -> StateSummary {
+        match &self.state {
+            MintRestoreStates::InProgress(_) => {
+                StateSummary::in_progress("Restoring e-cash notes from the federation history")
+            }
+            MintRestoreStates::Success => StateSummary::Succeeded,
+            MintRestoreStates::Failed(failed) => StateSummary::failed(&failed.reason),
+        }
+    }
+}
+
 impl State for MintRestoreStateMachine {
     type ModuleContext = MintClientContext;
     type GlobalContext = DynGlobalClientContext;
@@ -286,6 +286,7 @@ fn sanity_check_recovery_fresh_backup() {
             LEGACY_HARDCODED_INSTANCE_ID_MINT,
             output_c1_a.clone(),
         )],
+        expiry: None,
         signature: None,
     };
 
@@ -394,6 +395,7 @@ fn sanity_check_recovery_fresh_backup() {
             LEGACY_HARDCODED_INSTANCE_ID_MINT,
             output_c1_a,
         )],
+        expiry: None,
         signature: None,
     };
 
@@ -433,6 +435,7 @@ fn sanity_check_recovery_non_empty_backup() {
             core::DynOutput::from_typed(LEGACY_HARDCODED_INSTANCE_ID_MINT, output_c1_a0.clone()),
             core::DynOutput::from_typed(LEGACY_HARDCODED_INSTANCE_ID_MINT, output_c1_a1.clone()),
         ],
+        expiry: None,
         signature: None,
     };
 
@@ -477,6 +480,7 @@ fn sanity_check_recovery_non_empty_backup() {
             c1.generate_input(notes_c1_a0),
         )],
         outputs: vec![],
+        expiry: None,
         signature: None,
     };
     let confirmations_c1_a1 = fed.confirm_mint_output(output_c1_a1_out_point, &output_c1_a1);
@@ -555,6 +559,7 @@ fn sanity_check_recovery_bn_reuse_with_invalid_amount() {
             LEGACY_HARDCODED_INSTANCE_ID_MINT,
             output_c2_a.clone(),
         )],
+        expiry: None,
         signature: None,
     };
     let output_c1_a_out_point = OutPoint {
@@ -568,6 +573,7 @@ fn sanity_check_recovery_bn_reuse_with_invalid_amount() {
             LEGACY_HARDCODED_INSTANCE_ID_MINT,
             output_c1_b,
         )],
+        expiry: None,
         signature: None,
     };
     let output_c1_b_out_point = OutPoint {
@@ -643,6 +649,7 @@ fn sanity_check_recovery_bn_reuse_with_valid_amount() {
             LEGACY_HARDCODED_INSTANCE_ID_MINT,
             output_c2_a.clone(),
         )],
+        expiry: None,
         signature: None,
     };
     let output_c2_a_out_point = OutPoint {
@@ -656,6 +663,7 @@ fn sanity_check_recovery_bn_reuse_with_valid_amount() {
             LEGACY_HARDCODED_INSTANCE_ID_MINT,
             output_c1_b.clone(),
         )],
+        expiry: None,
         signature: None,
     };
     let output_c1_b_out_point = OutPoint {
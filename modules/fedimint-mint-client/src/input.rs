@@ -1,5 +1,6 @@
 use std::sync::Arc;
 
+use fedimint_client::event::StateSummary;
 use fedimint_client::sm::{ClientSMDatabaseTransaction, OperationId, State, StateTransition};
 use fedimint_client::transaction::{ClientInput, TxSubmissionError};
 use fedimint_client::DynGlobalClientContext;
@@ -18,7 +19,7 @@ use crate::{MintClientContext, MintClientStateMachines, SpendableNote};
 ///     classDef virtual fill:#fff,stroke-dasharray: 5 5
 ///
 ///     Created -- containing tx accepted --> Success
-///     Created -- containing tx rejected --> Refund
+///     Created -- containing tx rejected or expired --> Refund
 ///     Refund -- refund tx rejected --> Error
 ///     Refund -- refund tx accepted --> RS[Refund Success]
 /// ```
@@ -44,6 +45,24 @@ pub struct MintInputStateMachine {
     pub(crate) state: MintInputStates,
 }
 
+impl MintInputStateMachine {
+    pub(crate) fn summary(&self) -> StateSummary {
+        match &self.state {
+            MintInputStates::Created(_) => {
+                StateSummary::in_progress("Waiting for the transaction to be accepted")
+            }
+            MintInputStates::Refund(_) => {
+                StateSummary::in_progress("Transaction was rejected, refunding e-cash notes")
+            }
+            MintInputStates::Success(_) => StateSummary::Succeeded,
+            MintInputStates::Error(error) => StateSummary::failed(&error.error),
+            MintInputStates::RefundSuccess(_) => {
+                StateSummary::failed("Transaction was rejected, e-cash notes were refunded")
+            }
+        }
+    }
+}
+
 impl State for MintInputStateMachine {
     type ModuleContext = MintClientContext;
     type GlobalContext = DynGlobalClientContext;
@@ -124,7 +143,7 @@ impl MintInputStateCreated {
                 }
             }
             Err(_) => {
-                // Transaction rejected: attempting to refund
+                // Transaction rejected or expired: reissuing the notes to reclaim them
                 Self::refund(dbtx, old_state, global_context).await
             }
         }
@@ -1,3 +1,5 @@
+/// Federation API endpoints of the mint module
+pub mod api;
 // Backup and restore logic
 pub(crate) mod backup;
 /// Database keys used throughout the mint client module
@@ -19,15 +21,18 @@ use anyhow::{anyhow, bail};
 use async_stream::stream;
 use backup::recovery::{MintRestoreStateMachine, MintRestoreStates};
 use bitcoin_hashes::{sha256, sha256t, Hash, HashEngine as BitcoinHashEngine};
+use fedimint_client::batch::BatchedTransaction;
+use fedimint_client::event::StateSummary;
+use fedimint_client::ledger::{LedgerDirection, LedgerEntry, LedgerStatus};
 use fedimint_client::module::gen::ClientModuleGen;
 use fedimint_client::module::{ClientModule, IClientModule};
-use fedimint_client::oplog::{OperationLogEntry, UpdateStreamOrOutcome};
+use fedimint_client::oplog::{OperationLog, OperationLogEntry, UpdateStreamOrOutcome};
 use fedimint_client::sm::util::MapStateTransitions;
 use fedimint_client::sm::{
     Context, DynState, Executor, ModuleNotifier, OperationId, State, StateTransition,
 };
 use fedimint_client::transaction::{ClientInput, ClientOutput, TransactionBuilder};
-use fedimint_client::{sm_enum_variant_translation, Client, DynGlobalClientContext};
+use fedimint_client::{sm_enum_variant_translation, Client, ClientBuilder, DynGlobalClientContext};
 use fedimint_core::api::{DynGlobalApi, DynModuleApi, GlobalFederationApi};
 use fedimint_core::core::{Decoder, IntoDynInstance, ModuleInstanceId};
 use fedimint_core::db::{
@@ -55,13 +60,16 @@ use tbs::AggregatePublicKey;
 use thiserror::Error;
 use tracing::{debug, info, warn};
 
+use crate::api::MintFederationApi;
 use crate::backup::recovery::MintRestoreInProgressState;
 use crate::backup::EcashBackup;
 use crate::db::{NextECashNoteIndexKey, NoteKey, NoteKeyPrefix};
 use crate::input::{
     MintInputCommon, MintInputStateCreated, MintInputStateMachine, MintInputStates,
 };
-use crate::oob::{MintOOBStateMachine, MintOOBStates, MintOOBStatesCreated};
+use crate::oob::{
+    MintOOBStateMachine, MintOOBStates, MintOOBStatesCreated, MintOOBStatesSplitting,
+};
 use crate::output::{
     MintOutputCommon, MintOutputStateMachine, MintOutputStates, MintOutputStatesCreated,
     MultiNoteIssuanceRequest, NoteIssuanceRequest,
@@ -71,6 +79,13 @@ const MINT_E_CASH_TYPE_CHILD_ID: ChildId = ChildId(0);
 
 const MINT_BACKUP_RESTORE_OPERATION_ID: OperationId = OperationId([0x01; 32]);
 
+/// First API version serving [`MintFederationApi::fetch_spent_nonces`]
+const SPENT_NONCES_API_VERSION: ApiVersion = ApiVersion { major: 0, minor: 1 };
+
+/// Charged for notes whose fee overflows, the federation rejects transactions
+/// with such notes anyway
+const UNPAYABLE_FEE: Amount = Amount::from_msats(u64::MAX);
+
 pub const LOG_TARGET: &str = "client::module::mint";
 
 #[apply(async_trait_maybe_send!)]
@@ -78,6 +93,9 @@ pub trait MintClientExt {
     /// Try to reissue e-cash notes received from a third party to receive them
     /// in our wallet. The progress and outcome can be observed using
     /// [`MintClientExt::subscribe_reissue_external_notes`].
+    ///
+    /// Notes that aren't validly signed by the federation are rejected right
+    /// away, see [`MintClientExt::validate_notes`].
     async fn reissue_external_notes<M: Serialize + Send>(
         &self,
         notes: TieredMulti<SpendableNote>,
@@ -91,6 +109,17 @@ pub trait MintClientExt {
         operation_id: OperationId,
     ) -> anyhow::Result<UpdateStreamOrOutcome<'_, ReissueExternalNotesState>>;
 
+    /// Checks offline that the e-cash `notes` were signed by the federation,
+    /// rejecting notes of denominations it doesn't issue. If `check_spent` is
+    /// set the federation is also asked whether any of the notes were spent
+    /// already, which fails for federations not supporting it yet. Returns the
+    /// total amount of the notes.
+    async fn validate_notes(
+        &self,
+        notes: &TieredMulti<SpendableNote>,
+        check_spent: bool,
+    ) -> anyhow::Result<Amount>;
+
     /// Fetches and removes notes of *at least* amount `min_amount` from the
     /// wallet to be sent to the recipient out of band. These spends can be
     /// canceled by calling [`MintClientExt::try_cancel_spend_notes`] as long as
@@ -109,14 +138,40 @@ pub trait MintClientExt {
         extra_meta: M,
     ) -> anyhow::Result<(OperationId, TieredMulti<SpendableNote>)>;
 
+    /// Like [`MintClientExt::spend_notes`], but takes notes of *exactly*
+    /// `amount` out of the wallet. If the notes held can't make exact change a
+    /// reissuance splitting larger notes is submitted first and the e-cash is
+    /// only taken out of the wallet once it's finished, which is reported as
+    /// [`SpendOOBState::Splitting`] by [`MintClientExt::subscribe_spend_notes`].
+    ///
+    /// The notes can be retrieved using
+    /// [`MintClientExt::await_spend_notes_exact`]. Canceling the spend while
+    /// the notes are being split keeps all e-cash in the wallet, and the
+    /// `try_cancel_after` timeout only starts once the e-cash was taken out.
+    async fn spend_notes_exact<M: Serialize + Send>(
+        &self,
+        amount: Amount,
+        try_cancel_after: Duration,
+        extra_meta: M,
+    ) -> anyhow::Result<OperationId>;
+
+    /// Waits for the notes of a spend operation started with
+    /// [`MintClientExt::spend_notes_exact`] to be taken out of the wallet and
+    /// returns them.
+    async fn await_spend_notes_exact(
+        &self,
+        operation_id: OperationId,
+    ) -> anyhow::Result<TieredMulti<SpendableNote>>;
+
     /// Try to cancel a spend operation started with
-    /// [`MintClientExt::spend_notes`]. If the e-cash notes have already been
-    /// spent this operation will fail which can be observed using
-    /// [`MintClientExt::subscribe_spend_notes`].
+    /// [`MintClientExt::spend_notes`] or [`MintClientExt::spend_notes_exact`].
+    /// If the e-cash notes have already been spent this operation will fail
+    /// which can be observed using [`MintClientExt::subscribe_spend_notes`].
     async fn try_cancel_spend_notes(&self, operation_id: OperationId);
 
     /// Subscribe to updates on the progress of a raw e-cash spend operation
-    /// started with [`MintClientExt::spend_notes`].
+    /// started with [`MintClientExt::spend_notes`] or
+    /// [`MintClientExt::spend_notes_exact`].
     async fn subscribe_spend_notes(
         &self,
         operation_id: OperationId,
@@ -124,6 +179,67 @@ pub trait MintClientExt {
 
     /// Awaits the backup restoration to complete
     async fn await_restore_finished(&self) -> anyhow::Result<()>;
+
+    /// Returns the number of e-cash notes held per denomination
+    async fn get_note_summary(&self) -> TieredSummary;
+
+    /// Reissues the notes of all denominations (except the largest one) the
+    /// wallet holds more than `target_per_tier` notes of, so the wallet ends up
+    /// with fewer, larger notes. Every transaction spends at most
+    /// [`MAX_CONSOLIDATION_INPUT_NOTES`] notes, one operation is created per
+    /// transaction and their ids are returned. Notes that are worth less than
+    /// the fee for spending them are left alone, as are the notes of
+    /// consolidations that wouldn't reduce the number of notes held.
+    ///
+    /// The progress of each operation can be observed using
+    /// [`MintClientExt::subscribe_consolidate_notes`].
+    async fn consolidate_notes(&self, target_per_tier: u16) -> anyhow::Result<Vec<OperationId>>;
+
+    /// Subscribe to updates on the progress of a consolidation operation
+    /// started with [`MintClientExt::consolidate_notes`].
+    async fn subscribe_consolidate_notes(
+        &self,
+        operation_id: OperationId,
+    ) -> anyhow::Result<UpdateStreamOrOutcome<'_, ReissueExternalNotesState>>;
+}
+
+/// Enables optional features of the mint module when building a [`Client`]
+pub trait MintClientBuilderExt {
+    /// Watches the number of notes held once the executor is started and calls
+    /// [`MintClientExt::consolidate_notes`] in the background whenever it
+    /// exceeds the threshold given by `consolidation`
+    fn with_note_consolidation(&mut self, consolidation: NoteConsolidation);
+}
+
+impl MintClientBuilderExt for ClientBuilder {
+    fn with_note_consolidation(&mut self, consolidation: NoteConsolidation) {
+        self.with_background_task("mint_note_consolidation", move |client| {
+            run_note_consolidation(client, consolidation.clone())
+        });
+    }
+}
+
+/// Maximum number of notes spent by a single transaction of
+/// [`MintClientExt::consolidate_notes`]
+pub const MAX_CONSOLIDATION_INPUT_NOTES: usize = 500;
+
+/// Settings for consolidating notes in the background, see
+/// [`MintClientBuilderExt::with_note_consolidation`]
+#[derive(Debug, Clone)]
+pub struct NoteConsolidation {
+    /// Consolidate once the wallet holds more than this many notes
+    pub max_notes: usize,
+    /// Number of notes to keep of every denomination when consolidating
+    pub target_per_tier: u16,
+}
+
+impl Default for NoteConsolidation {
+    fn default() -> Self {
+        NoteConsolidation {
+            max_notes: 200,
+            target_per_tier: 2,
+        }
+    }
 }
 
 /// The high-level state of a reissue operation started with
@@ -143,9 +259,15 @@ pub enum ReissueExternalNotesState {
 }
 
 /// The high-level state of a raw e-cash spend operation started with
-/// [`MintClientExt::spend_notes`].
+/// [`MintClientExt::spend_notes`] or [`MintClientExt::spend_notes_exact`].
 #[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
 pub enum SpendOOBState {
+    /// Exact change couldn't be made from the notes held, we are waiting for a
+    /// reissuance splitting larger notes before the e-cash is selected.
+    Splitting,
+    /// Splitting the notes failed, no e-cash was given to the caller and all
+    /// our money stays in the wallet.
+    SplitFailed(String),
     /// The e-cash has been selected and given to the caller
     Created,
     /// The user requested a cancellation of the operation, we are waiting for
@@ -175,6 +297,9 @@ impl MintClientExt for Client {
         extra_meta: M,
     ) -> anyhow::Result<OperationId> {
         let (mint, instance) = self.get_first_module::<MintClientModule>(&KIND);
+        // Already spent notes don't need to be checked for up front, the federation
+        // rejects the reissuance transaction spending them and the operation fails
+        mint.validate_notes(&notes, false).await?;
 
         let operation_id = OperationId(
             notes
@@ -197,22 +322,24 @@ impl MintClientExt for Client {
 
         let extra_meta = serde_json::to_value(extra_meta)
             .expect("MintClientExt::reissue_external_notes extra_meta is serializable");
-        let operation_meta_gen = move |txid, _| MintMeta {
+        // The reissued notes are issued as change of the transaction
+        let operation_meta_gen = move |batched_tx: BatchedTransaction| MintMeta {
             variant: MintMetaVariants::Reissuance {
-                out_point: OutPoint { txid, out_idx: 0 },
+                out_point: batched_tx
+                    .change_outpoint
+                    .unwrap_or_else(|| batched_tx.out_point(0)),
             },
             amount,
             extra_meta: extra_meta.clone(),
         };
 
-        self.finalize_and_submit_transaction(
+        self.submit_batched_transaction(
             operation_id,
             MintCommonGen::KIND.as_str(),
             operation_meta_gen,
             tx,
         )
-        .await
-        .expect("Transactions can only fail if the operation already exists, which we checked previously");
+        .await?;
 
         Ok(operation_id)
     }
@@ -229,36 +356,16 @@ impl MintClientExt for Client {
             _ => bail!("Operation is not a reissuance"),
         };
 
-        // TODO: move into closure
-        let tx_accepted_future = self
-            .transaction_updates(operation_id)
-            .await
-            .await_tx_accepted(out_point.txid);
-        let output_finalized_future = mint.await_output_finalized(operation_id, out_point);
-
-        Ok(operation.outcome_or_updates(self.db(), operation_id, || {
-            stream! {
-                yield ReissueExternalNotesState::Created;
-
-                match tx_accepted_future.await {
-                    Ok(()) => {
-                        yield ReissueExternalNotesState::Issuing;
-                    },
-                    Err(e) => {
-                        yield ReissueExternalNotesState::Failed(format!("Transaction not accepted {e:?}"));
-                    }
-                }
+        Ok(reissuance_updates(self, mint, operation, operation_id, out_point).await)
+    }
 
-                match output_finalized_future.await {
-                    Ok(_) => {
-                        yield ReissueExternalNotesState::Done;
-                    },
-                    Err(e) => {
-                        yield ReissueExternalNotesState::Failed(e.to_string());
-                    },
-                }
-            }}
-        ))
+    async fn validate_notes(
+        &self,
+        notes: &TieredMulti<SpendableNote>,
+        check_spent: bool,
+    ) -> anyhow::Result<Amount> {
+        let (mint, _instance) = self.get_first_module::<MintClientModule>(&KIND);
+        mint.validate_notes(notes, check_spent).await
     }
 
     async fn spend_notes<M: Serialize + Send>(
@@ -283,6 +390,8 @@ impl MintClientExt for Client {
                                 try_cancel_after,
                             )
                             .await?;
+                        self.enforce_out_of_band_spend(dbtx, operation_id, notes.total_amount())
+                            .await?;
 
                         let dyn_states = states
                             .into_iter()
@@ -298,6 +407,7 @@ impl MintClientExt for Client {
                                 MintMeta {
                                     variant: MintMetaVariants::SpendOOB {
                                         requested_amount: min_amount,
+                                        split_out_point: None,
                                     },
                                     amount: notes.total_amount(),
                                     extra_meta,
@@ -319,6 +429,133 @@ impl MintClientExt for Client {
             })
     }
 
+    async fn spend_notes_exact<M: Serialize + Send>(
+        &self,
+        amount: Amount,
+        try_cancel_after: Duration,
+        extra_meta: M,
+    ) -> anyhow::Result<OperationId> {
+        let (mint, instance) = self.get_first_module::<MintClientModule>(&KIND);
+        let extra_meta = serde_json::to_value(extra_meta)
+            .expect("MintClientExt::spend_notes_exact extra_meta is serializable");
+        let operation_id = OperationId::new_random();
+
+        self.db()
+            .autocommit(
+                move |dbtx| {
+                    let extra_meta = extra_meta.clone();
+                    Box::pin(async move {
+                        let notes = MintClientModule::take_exact_notes(
+                            &mut dbtx.with_module_prefix(instance.id),
+                            amount,
+                        )
+                        .await?;
+
+                        let state = match notes {
+                            Some(notes) => {
+                                self.enforce_out_of_band_spend(dbtx, operation_id, amount)
+                                    .await?;
+                                self.operation_log()
+                                    .add_operation_log_entry(
+                                        dbtx,
+                                        operation_id,
+                                        MintCommonGen::KIND.as_str(),
+                                        MintMeta {
+                                            variant: MintMetaVariants::SpendOOB {
+                                                requested_amount: amount,
+                                                split_out_point: None,
+                                            },
+                                            amount,
+                                            extra_meta,
+                                        },
+                                    )
+                                    .await;
+
+                                MintOOBStates::Created(MintOOBStatesCreated {
+                                    notes,
+                                    timeout: fedimint_core::time::now() + try_cancel_after,
+                                })
+                            }
+                            None => {
+                                // Reissue larger notes into notes of exactly `amount`, the change
+                                // goes back into the wallet. The reissuance only pays ourselves,
+                                // the spend is recorded once the split notes are handed out.
+                                let output = mint
+                                    .create_output(
+                                        &mut dbtx.with_module_prefix(instance.id),
+                                        operation_id,
+                                        0,
+                                        amount,
+                                    )
+                                    .await;
+                                let tx = TransactionBuilder::new()
+                                    .with_output(output.into_dyn(instance.id));
+                                let txid = self
+                                    .finalize_and_submit_transaction_dbtx(
+                                        dbtx,
+                                        operation_id,
+                                        MintCommonGen::KIND.as_str(),
+                                        |txid, _| MintMeta {
+                                            variant: MintMetaVariants::SpendOOB {
+                                                requested_amount: amount,
+                                                split_out_point: Some(OutPoint {
+                                                    txid,
+                                                    out_idx: 0,
+                                                }),
+                                            },
+                                            amount,
+                                            extra_meta,
+                                        },
+                                        tx,
+                                    )
+                                    .await?;
+
+                                MintOOBStates::Splitting(MintOOBStatesSplitting {
+                                    amount,
+                                    out_point: OutPoint { txid, out_idx: 0 },
+                                    try_cancel_after,
+                                })
+                            }
+                        };
+
+                        let state = MintClientStateMachines::OOB(MintOOBStateMachine {
+                            operation_id,
+                            state,
+                        });
+                        self.add_state_machines(dbtx, vec![state.into_dyn(instance.id)])
+                            .await?;
+
+                        Ok(operation_id)
+                    })
+                },
+                Some(100),
+            )
+            .await
+            .map_err(|e| match e {
+                AutocommitError::ClosureError { error, .. } => error,
+                AutocommitError::CommitFailed { last_error, .. } => {
+                    anyhow!("Commit to DB failed: {last_error}")
+                }
+            })
+    }
+
+    async fn await_spend_notes_exact(
+        &self,
+        operation_id: OperationId,
+    ) -> anyhow::Result<TieredMulti<SpendableNote>> {
+        let (mint, _instance) = self.get_first_module::<MintClientModule>(&KIND);
+
+        let operation = mint_operation(self, operation_id).await?;
+        if !matches!(
+            operation.meta::<MintMeta>().variant,
+            MintMetaVariants::SpendOOB { .. }
+        ) {
+            bail!("Operation is not a out-of-band spend");
+        };
+
+        mint.await_spend_oob_notes(operation_id).await
+    }
+
     async fn try_cancel_spend_notes(&self, operation_id: OperationId) {
         let (mint, _instance) = self.get_first_module::<MintClientModule>(&KIND);
 
@@ -333,18 +570,36 @@ impl MintClientExt for Client {
         let (mint, _instance) = self.get_first_module::<MintClientModule>(&KIND);
 
         let operation = mint_operation(self, operation_id).await?;
-        if !matches!(
-            operation.meta::<MintMeta>().variant,
-            MintMetaVariants::SpendOOB { .. }
-        ) {
-            bail!("Operation is not a out-of-band spend");
+        let split_out_point = match operation.meta::<MintMeta>().variant {
+            MintMetaVariants::SpendOOB {
+                split_out_point, ..
+            } => split_out_point,
+            _ => bail!("Operation is not a out-of-band spend"),
         };
 
         let tx_subscription = self.transaction_updates(operation_id).await;
+        let split_future = mint.await_spend_oob_split(operation_id);
         let refund_future = mint.await_spend_oob_refund(operation_id);
 
         Ok(operation.outcome_or_updates(self.db(), operation_id, || {
             stream! {
+                if split_out_point.is_some() {
+                    yield SpendOOBState::Splitting;
+                    match split_future.await {
+                        SpendOOBSplit::Done => {},
+                        // No e-cash was handed out yet, so canceling can't fail
+                        SpendOOBSplit::Canceled => {
+                            yield SpendOOBState::UserCanceledProcessing;
+                            yield SpendOOBState::UserCanceledSuccess;
+                            return;
+                        },
+                        SpendOOBSplit::Failed(e) => {
+                            yield SpendOOBState::SplitFailed(e);
+                            return;
+                        },
+                    }
+                }
+
                 yield SpendOOBState::Created;
 
                 let refund = refund_future.await;
@@ -377,6 +632,168 @@ impl MintClientExt for Client {
         let (mint, _instance) = self.get_first_module::<MintClientModule>(&KIND);
         mint.await_restore_finished().await
     }
+
+    async fn get_note_summary(&self) -> TieredSummary {
+        let (mint, instance) = self.get_first_module::<MintClientModule>(&KIND);
+        mint.get_wallet_summary(
+            &mut self
+                .db()
+                .begin_transaction()
+                .await
+                .with_module_prefix(instance.id),
+        )
+        .await
+    }
+
+    async fn consolidate_notes(&self, target_per_tier: u16) -> anyhow::Result<Vec<OperationId>> {
+        let (mint, instance) = self.get_first_module::<MintClientModule>(&KIND);
+
+        let mut operation_ids = vec![];
+        loop {
+            let operation_id = OperationId::new_random();
+            let submitted = self
+                .db()
+                .autocommit(
+                    |dbtx| {
+                        Box::pin(async move {
+                            let consolidation = mint
+                                .create_consolidation(
+                                    &mut dbtx.with_module_prefix(instance.id),
+                                    operation_id,
+                                    target_per_tier,
+                                )
+                                .await?;
+                            let Some((input, output)) = consolidation else {
+                                return Ok(false);
+                            };
+
+                            let input_amount = ClientModule::input_amount(mint, &input.input);
+                            let output_amount = ClientModule::output_amount(mint, &output.output);
+                            let fee = input_amount.fee + output_amount.fee;
+                            let tx = TransactionBuilder::new()
+                                .with_input(input.into_dyn(instance.id))
+                                .with_output(output.into_dyn(instance.id));
+
+                            self.finalize_and_submit_transaction_dbtx(
+                                dbtx,
+                                operation_id,
+                                MintCommonGen::KIND.as_str(),
+                                |txid, _| MintMeta {
+                                    variant: MintMetaVariants::Consolidation {
+                                        out_point: OutPoint { txid, out_idx: 0 },
+                                        fee,
+                                    },
+                                    amount: input_amount.amount,
+                                    extra_meta: serde_json::Value::Null,
+                                },
+                                tx,
+                            )
+                            .await?;
+
+                            Ok(true)
+                        })
+                    },
+                    Some(100),
+                )
+                .await
+                .map_err(|e| match e {
+                    AutocommitError::ClosureError { error, .. } => error,
+                    AutocommitError::CommitFailed { last_error, .. } => {
+                        anyhow!("Commit to DB failed: {last_error}")
+                    }
+                })?;
+
+            if !submitted {
+                return Ok(operation_ids);
+            }
+            operation_ids.push(operation_id);
+        }
+    }
+
+    async fn subscribe_consolidate_notes(
+        &self,
+        operation_id: OperationId,
+    ) -> anyhow::Result<UpdateStreamOrOutcome<'_, ReissueExternalNotesState>> {
+        let (mint, _instance) = self.get_first_module::<MintClientModule>(&KIND);
+
+        let operation = mint_operation(self, operation_id).await?;
+        let out_point = match operation.meta::<MintMeta>().variant {
+            MintMetaVariants::Consolidation { out_point, .. } => out_point,
+            _ => bail!("Operation is not a consolidation"),
+        };
+
+        Ok(reissuance_updates(self, mint, operation, operation_id, out_point).await)
+    }
+}
+
+/// Consolidates notes whenever the wallet holds more than
+/// `consolidation.max_notes` notes, never returns
+async fn run_note_consolidation(client: Client, consolidation: NoteConsolidation) {
+    let (mint, _instance) = client.get_first_module::<MintClientModule>(&KIND);
+    let mut balance_changes = ClientModule::subscribe_balance_changes(mint).await;
+
+    loop {
+        let num_notes = client.get_note_summary().await.count_items();
+        if num_notes > consolidation.max_notes {
+            match client
+                .consolidate_notes(consolidation.target_per_tier)
+                .await
+            {
+                Ok(operation_ids) => info!(
+                    target: LOG_TARGET,
+                    num_notes,
+                    transactions = operation_ids.len(),
+                    "Consolidated notes"
+                ),
+                Err(e) => warn!(target: LOG_TARGET, "Failed to consolidate notes: {e:?}"),
+            }
+        }
+
+        // Balance changes are the only way for the number of notes to change
+        if balance_changes.next().await.is_none() {
+            futures::future::pending::<()>().await;
+        }
+    }
+}
+
+/// Updates of a reissuance whose issued notes are the output at `out_point`
+async fn reissuance_updates<'a>(
+    client: &'a Client,
+    mint: &MintClientModule,
+    operation: OperationLogEntry,
+    operation_id: OperationId,
+    out_point: OutPoint,
+) -> UpdateStreamOrOutcome<'a, ReissueExternalNotesState> {
+    // TODO: move into closure
+    let tx_accepted_future = client
+        .transaction_updates(operation_id)
+        .await
+        .await_tx_accepted(out_point.txid);
+    let output_finalized_future = mint.await_output_finalized(operation_id, out_point);
+
+    operation.outcome_or_updates(client.db(), operation_id, || {
+        stream! {
+            yield ReissueExternalNotesState::Created;
+
+            match tx_accepted_future.await {
+                Ok(()) => {
+                    yield ReissueExternalNotesState::Issuing;
+                },
+                Err(e) => {
+                    yield ReissueExternalNotesState::Failed(format!("Transaction not accepted {e:?}"));
+                }
+            }
+
+            match output_finalized_future.await {
+                Ok(_) => {
+                    yield ReissueExternalNotesState::Done;
+                },
+                Err(e) => {
+                    yield ReissueExternalNotesState::Failed(e.to_string());
+                },
+            }
+        }}
+    )
 }
 
 async fn mint_operation(
@@ -405,8 +822,20 @@ pub struct MintMeta {
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 enum MintMetaVariants {
-    Reissuance { out_point: OutPoint },
-    SpendOOB { requested_amount: Amount },
+    Reissuance {
+        out_point: OutPoint,
+    },
+    SpendOOB {
+        requested_amount: Amount,
+        /// Output of the reissuance splitting larger notes, if exact change
+        /// couldn't be made from the notes held
+        #[serde(default)]
+        split_out_point: Option<OutPoint>,
+    },
+    Consolidation {
+        out_point: OutPoint,
+        fee: Amount,
+    },
 }
 
 #[derive(Debug, Clone)]
@@ -429,11 +858,11 @@ impl ClientModuleGen for MintClientGen {
         &self,
         cfg: MintClientConfig,
         _db: Database,
-        _api_version: ApiVersion,
+        api_version: ApiVersion,
         module_root_secret: DerivableSecret,
         notifier: ModuleNotifier<DynGlobalClientContext, <Self::Module as ClientModule>::States>,
         _api: DynGlobalApi,
-        _module_api: DynModuleApi,
+        module_api: DynModuleApi,
     ) -> anyhow::Result<Self::Module> {
         let (cancel_oob_payment_bc, _) = tokio::sync::broadcast::channel(16);
         Ok(MintClientModule {
@@ -441,6 +870,8 @@ impl ClientModuleGen for MintClientGen {
             secret: module_root_secret,
             secp: Secp256k1::new(),
             notifier,
+            module_api,
+            api_version,
             cancel_oob_payment_bc,
         })
     }
@@ -452,6 +883,8 @@ pub struct MintClientModule {
     secret: DerivableSecret,
     secp: Secp256k1<All>,
     notifier: ModuleNotifier<DynGlobalClientContext, MintClientStateMachines>,
+    module_api: DynModuleApi,
+    api_version: ApiVersion,
     cancel_oob_payment_bc: tokio::sync::broadcast::Sender<OperationId>,
 }
 
@@ -462,6 +895,7 @@ pub struct MintClientContext {
     pub mint_keys: Tiered<AggregatePublicKey>,
     pub secret: DerivableSecret,
     pub cancel_oob_payment_bc: tokio::sync::broadcast::Sender<OperationId>,
+    pub notifier: ModuleNotifier<DynGlobalClientContext, MintClientStateMachines>,
 }
 
 impl MintClientContext {
@@ -484,6 +918,7 @@ impl ClientModule for MintClientModule {
             mint_keys: self.cfg.tbs_pks.clone(),
             secret: self.secret.clone(),
             cancel_oob_payment_bc: self.cancel_oob_payment_bc.clone(),
+            notifier: self.notifier.clone(),
         }
     }
 
@@ -491,7 +926,11 @@ impl ClientModule for MintClientModule {
         TransactionItemAmount {
             amount: input.0.total_amount(),
             // FIXME: prevent overflows
-            fee: self.cfg.fee_consensus.note_spend_abs * (input.0.count_items() as u64),
+            fee: self
+                .cfg
+                .fee_consensus
+                .spend_fee(&input.0.summary())
+                .unwrap_or(UNPAYABLE_FEE),
         }
     }
 
@@ -501,10 +940,19 @@ impl ClientModule for MintClientModule {
     ) -> TransactionItemAmount {
         TransactionItemAmount {
             amount: output.0.total_amount(),
-            fee: self.cfg.fee_consensus.note_issuance_abs * (output.0.count_items() as u64),
+            fee: self
+                .cfg
+                .fee_consensus
+                .issuance_fee(&output.0.summary())
+                .unwrap_or(UNPAYABLE_FEE),
         }
     }
 
+    fn output_is_own(&self, _output: &<Self::Common as ModuleCommon>::Output) -> bool {
+        // Notes are always issued to ourselves, e-cash only leaves the wallet out of band
+        true
+    }
+
     async fn handle_cli_command(
         &self,
         client: &Client,
@@ -579,6 +1027,7 @@ impl ClientModule for MintClientModule {
         dbtx: &mut DatabaseTransaction<'_>,
         module_instance_id: ModuleInstanceId,
         executor: Executor<DynGlobalClientContext>,
+        _operation_log: &OperationLog,
         api: DynGlobalApi,
         snapshot: Option<&[u8]>,
     ) -> anyhow::Result<()> {
@@ -671,7 +1120,9 @@ impl ClientModule for MintClientModule {
         amount: Amount,
     ) -> ClientOutput<MintOutput, MintClientStateMachines> {
         // FIXME: don't hardcode notes per denomination
-        self.create_output(dbtx, operation_id, 2, amount).await
+        let denominations = self.represent_amount_after_fees(dbtx, 2, amount).await;
+        self.create_output_from_denominations(dbtx, operation_id, denominations)
+            .await
     }
 
     async fn await_primary_module_output(
@@ -719,6 +1170,62 @@ impl ClientModule for MintClientModule {
                 }),
         )
     }
+
+    async fn ledger_entry(
+        &self,
+        _dbtx: &mut ModuleDatabaseTransaction<'_>,
+        _operation_id: OperationId,
+        operation: &OperationLogEntry,
+    ) -> Option<LedgerEntry> {
+        let meta = operation.meta::<MintMeta>();
+        let reissuance_status = || match operation.outcome::<ReissueExternalNotesState>() {
+            Some(ReissueExternalNotesState::Done) => LedgerStatus::Succeeded,
+            Some(ReissueExternalNotesState::Failed(_)) => LedgerStatus::Failed,
+            _ => LedgerStatus::Pending,
+        };
+        // Mint operations only pay federation fees, which the client adds itself
+        let (direction, amount, status) = match meta.variant {
+            MintMetaVariants::Reissuance { .. } => {
+                (LedgerDirection::Incoming, meta.amount, reissuance_status())
+            }
+            MintMetaVariants::SpendOOB { .. } => {
+                let status = match operation.outcome::<SpendOOBState>() {
+                    // If canceling failed the recipient already reissued the notes
+                    Some(SpendOOBState::Success | SpendOOBState::UserCanceledFailure) => {
+                        LedgerStatus::Succeeded
+                    }
+                    Some(
+                        SpendOOBState::Refunded
+                        | SpendOOBState::UserCanceledSuccess
+                        | SpendOOBState::SplitFailed(_),
+                    ) => LedgerStatus::Failed,
+                    _ => LedgerStatus::Pending,
+                };
+                (LedgerDirection::Outgoing, meta.amount, status)
+            }
+            // Consolidations only move funds within the wallet, except for the fee
+            MintMetaVariants::Consolidation { .. } => {
+                (LedgerDirection::Outgoing, Amount::ZERO, reissuance_status())
+            }
+        };
+
+        Some(LedgerEntry {
+            direction,
+            amount: Some(amount),
+            fee: None,
+            counterparty: None,
+            status,
+        })
+    }
+
+    fn summarize_state(&self, state: &Self::States) -> StateSummary {
+        match state {
+            MintClientStateMachines::Output(state) => state.summary(),
+            MintClientStateMachines::Input(state) => state.summary(),
+            MintClientStateMachines::OOB(state) => state.summary(),
+            MintClientStateMachines::Restore(state) => state.summary(),
+        }
+    }
 }
 
 impl MintClientModule {
@@ -750,14 +1257,82 @@ impl MintClientModule {
         notes_per_denomination: u16,
         amount: Amount,
     ) -> ClientOutput<MintOutput, MintClientStateMachines> {
-        let mut amount_requests: Vec<((Amount, NoteIssuanceRequest), (Amount, BlindNonce))> =
-            Vec::new();
         let denominations = TieredSummary::represent_amount(
             amount,
             &self.get_wallet_summary(dbtx).await,
             &self.cfg.tbs_pks,
             notes_per_denomination,
         );
+        self.create_output_from_denominations(dbtx, operation_id, denominations)
+            .await
+    }
+
+    /// Determines the notes to issue such that their amount plus the issuance
+    /// fee charged by the federation is exactly `amount`.
+    ///
+    /// Since proportional fees are rounded down per note, the amount that
+    /// can't be covered by fee-charged notes is filled up with notes too small
+    /// to be charged any fee. Absolute fees can make this impossible, in which
+    /// case the notes fall short of `amount`.
+    async fn represent_amount_after_fees(
+        &self,
+        dbtx: &mut ModuleDatabaseTransaction<'_>,
+        notes_per_denomination: u16,
+        amount: Amount,
+    ) -> TieredSummary {
+        let fee_consensus = &self.cfg.fee_consensus;
+        let wallet_summary = self.get_wallet_summary(dbtx).await;
+
+        // Find the largest notes amount whose fee still fits into `amount`, the
+        // fee of a smaller amount may be higher if it needs more notes
+        let mut notes_amount = amount;
+        let (mut denominations, fee) = loop {
+            let denominations = TieredSummary::represent_amount(
+                notes_amount,
+                &wallet_summary,
+                &self.cfg.tbs_pks,
+                notes_per_denomination,
+            );
+            // A fee too large to be represented doesn't fit into `amount` either
+            let fee = fee_consensus.issuance_fee(&denominations).unwrap_or(amount);
+            if notes_amount + fee <= amount {
+                break (denominations, fee);
+            }
+            notes_amount = amount
+                .saturating_sub(fee)
+                .min(notes_amount - Amount::from_msats(1));
+        };
+
+        let mut remaining_amount = amount - notes_amount - fee;
+        for tier in self.cfg.tbs_pks.tiers().rev() {
+            if fee_consensus.note_issuance_fee(*tier) != Ok(Amount::ZERO) {
+                continue;
+            }
+            let notes = remaining_amount / *tier;
+            remaining_amount %= *tier;
+            denominations.inc(*tier, notes as usize);
+        }
+
+        if remaining_amount != Amount::ZERO {
+            warn!(
+                %amount,
+                %remaining_amount,
+                "Can't issue notes for the exact amount after fees"
+            );
+        }
+
+        denominations
+    }
+
+    /// Creates a mint output issuing e-cash notes of the given `denominations`
+    async fn create_output_from_denominations(
+        &self,
+        dbtx: &mut ModuleDatabaseTransaction<'_>,
+        operation_id: OperationId,
+        denominations: TieredSummary,
+    ) -> ClientOutput<MintOutput, MintClientStateMachines> {
+        let mut amount_requests: Vec<((Amount, NoteIssuanceRequest), (Amount, BlindNonce))> =
+            Vec::new();
         for (amt, num) in denominations.iter() {
             for _ in 0..num {
                 let (request, blind_nonce) = self.new_ecash_note(amt, dbtx).await;
@@ -780,7 +1355,7 @@ impl MintClientModule {
         });
 
         debug!(
-            %amount,
+            amount = %sig_req.0.total_amount(),
             notes = %sig_req.0.count_items(),
             tiers = ?sig_req.0.iter_tiers().collect::<Vec<_>>(),
             "Generated issuance request"
@@ -800,41 +1375,30 @@ impl MintClientModule {
         operation_id: OperationId,
         out_point: OutPoint,
     ) -> anyhow::Result<Amount> {
-        let stream = self
-            .notifier
-            .subscribe(operation_id)
-            .await
-            .filter_map(|state| async move {
-                let MintClientStateMachines::Output(state) = state else { return None };
-
-                if state.common.out_point != out_point {
-                    return None;
-                }
-
-                match state.state {
-                    MintOutputStates::Succeeded(succeeded) => Some(Ok(succeeded.amount)),
-                    MintOutputStates::Aborted(_) => Some(Err(anyhow!("Transaction was rejected"))),
-                    MintOutputStates::Failed(failed) => Some(Err(anyhow!(
-                        "Failed to finalize transaction: {}",
-                        failed.error
-                    ))),
-                    _ => None,
-                }
-            });
-        pin_mut!(stream);
-
-        stream.next_or_pending().await
+        await_output_finalized(self.notifier.clone(), operation_id, out_point).await
     }
 
     // FIXME: use lazy e-cash note loading implemented in #2183
-    /// Creates a mint input of at least `min_amount`.
+    /// Creates a mint input worth at least `min_amount` after subtracting the
+    /// fee for spending its notes.
     pub async fn create_input(
         &self,
         dbtx: &mut ModuleDatabaseTransaction<'_>,
         operation_id: OperationId,
         min_amount: Amount,
     ) -> anyhow::Result<ClientInput<MintInput, MintClientStateMachines>> {
-        let spendable_selected_notes = Self::select_notes(dbtx, min_amount).await?;
+        // Selecting more notes to cover the fee may raise the fee again, but the
+        // requested amount grows with every round, so we either find enough notes
+        // or run out of them
+        let mut requested_amount = min_amount;
+        let spendable_selected_notes = loop {
+            let notes = Self::select_notes(dbtx, requested_amount).await?;
+            let fee = self.cfg.fee_consensus.spend_fee(&notes.summary())?;
+            if notes.total_amount() >= min_amount + fee {
+                break notes;
+            }
+            requested_amount = min_amount + fee;
+        };
 
         for (amount, note) in spendable_selected_notes.iter_items() {
             dbtx.remove_entry(&NoteKey {
@@ -848,6 +1412,78 @@ impl MintClientModule {
             .await
     }
 
+    /// Selects up to [`MAX_CONSOLIDATION_INPUT_NOTES`] notes of the tiers
+    /// holding more than `target_per_tier` notes, starting with the smallest
+    /// denomination, and creates an input spending them along with an output
+    /// reissuing their value after fees. Returns `None` if there are no such
+    /// notes or reissuing them wouldn't reduce the number of notes held.
+    async fn create_consolidation(
+        &self,
+        dbtx: &mut ModuleDatabaseTransaction<'_>,
+        operation_id: OperationId,
+        target_per_tier: u16,
+    ) -> anyhow::Result<
+        Option<(
+            ClientInput<MintInput, MintClientStateMachines>,
+            ClientOutput<MintOutput, MintClientStateMachines>,
+        )>,
+    > {
+        let fee_consensus = &self.cfg.fee_consensus;
+        let max_tier = *self.cfg.tbs_pks.max_tier();
+
+        // Notes of the largest denomination can't be consolidated any further
+        let surplus_notes = Self::get_all_spendable_notes(dbtx)
+            .await
+            .iter()
+            .filter(|(amount, _)| {
+                **amount != max_tier
+                    && fee_consensus
+                        .note_spend_fee(**amount)
+                        .map_or(false, |fee| fee < **amount)
+            })
+            .flat_map(|(amount, notes)| {
+                notes
+                    .iter()
+                    .skip(target_per_tier.into())
+                    .map(move |note| (*amount, *note))
+            })
+            .take(MAX_CONSOLIDATION_INPUT_NOTES)
+            .collect::<TieredMulti<SpendableNote>>();
+        let surplus_summary = surplus_notes.summary();
+        let reissue_amount = surplus_notes
+            .total_amount()
+            .saturating_sub(fee_consensus.spend_fee(&surplus_summary)?);
+
+        // The surplus notes don't change how many notes we lack of each tier, so
+        // they don't need to be removed from the wallet before this
+        let denominations = self
+            .represent_amount_after_fees(dbtx, target_per_tier, reissue_amount)
+            .await;
+        if surplus_notes.is_empty()
+            || reissue_amount == Amount::ZERO
+            || denominations.count_items() >= surplus_summary.count_items()
+        {
+            return Ok(None);
+        }
+
+        for (amount, note) in surplus_notes.iter_items() {
+            dbtx.remove_entry(&NoteKey {
+                amount,
+                nonce: note.note.0,
+            })
+            .await;
+        }
+
+        let input = self
+            .create_input_from_notes(operation_id, surplus_notes)
+            .await?;
+        let output = self
+            .create_output_from_denominations(dbtx, operation_id, denominations)
+            .await;
+
+        Ok(Some((input, output)))
+    }
+
     /// Create a mint input from external, potentially untrusted notes
     pub async fn create_input_from_notes(
         &self,
@@ -927,6 +1563,136 @@ impl MintClientModule {
         Ok((operation_id, state_machines, spendable_selected_notes))
     }
 
+    /// See [`MintClientExt::validate_notes`]
+    pub async fn validate_notes(
+        &self,
+        notes: &TieredMulti<SpendableNote>,
+        check_spent: bool,
+    ) -> anyhow::Result<Amount> {
+        let mut notes_with_keys = vec![];
+        for (amount, note) in notes.iter_items() {
+            let Some(pk) = self.cfg.tbs_pks.get(amount) else {
+                bail!("Note has an unknown denomination of {amount}");
+            };
+            notes_with_keys.push((amount, note, *pk));
+        }
+
+        // Verifying all notes at once is much faster, but a failed batch doesn't tell
+        // which note is invalid, so only then they are checked one by one
+        let signed_messages = notes_with_keys
+            .iter()
+            .map(|(_, note, pk)| (note.note.0.to_message(), note.note.1, *pk));
+        if !tbs::verify_batch(signed_messages) {
+            for (amount, note, pk) in &notes_with_keys {
+                if !note.note.verify(*pk) {
+                    bail!("Note of {amount} has an invalid signature");
+                }
+            }
+        }
+
+        if check_spent {
+            if self.api_version.major != SPENT_NONCES_API_VERSION.major
+                || self.api_version.minor < SPENT_NONCES_API_VERSION.minor
+            {
+                bail!("The federation doesn't support checking whether notes were spent");
+            }
+
+            let nonces = notes
+                .iter_items()
+                .map(|(_, note)| note.note.0)
+                .collect::<Vec<_>>();
+            let mut num_spent = 0;
+            for nonces in nonces.chunks(MAX_SPENT_NONCES_PER_REQUEST) {
+                num_spent += self
+                    .module_api
+                    .fetch_spent_nonces(nonces.to_vec())
+                    .await?
+                    .len();
+            }
+            if num_spent != 0 {
+                bail!("{num_spent} of the notes were already spent");
+            }
+        }
+
+        Ok(notes.total_amount())
+    }
+
+    /// Removes notes of exactly `amount` from the wallet to be spent out of
+    /// band. Returns `None` without touching the wallet if the notes held
+    /// can't make exact change.
+    async fn take_exact_notes(
+        dbtx: &mut ModuleDatabaseTransaction<'_>,
+        amount: Amount,
+    ) -> anyhow::Result<Option<TieredMulti<SpendableNote>>> {
+        let notes = Self::select_notes(dbtx, amount).await?;
+        if notes.total_amount() != amount {
+            return Ok(None);
+        }
+
+        for (amount, note) in notes.iter_items() {
+            dbtx.remove_entry(&NoteKey {
+                amount,
+                nonce: note.note.0,
+            })
+            .await;
+        }
+
+        Ok(Some(notes))
+    }
+
+    /// Waits for the split reissuance of an exact out-of-band spend to either
+    /// hand out the notes or fail
+    async fn await_spend_oob_split(&self, operation_id: OperationId) -> SpendOOBSplit {
+        Box::pin(
+            self.notifier
+                .subscribe(operation_id)
+                .await
+                .filter_map(|state| async move {
+                    let MintClientStateMachines::OOB(state) = state else { return None };
+
+                    match state.state {
+                        MintOOBStates::Created(_) => Some(SpendOOBSplit::Done),
+                        MintOOBStates::SplitCanceled(_) => Some(SpendOOBSplit::Canceled),
+                        MintOOBStates::SplitFailed(failed) => {
+                            Some(SpendOOBSplit::Failed(failed.error))
+                        }
+                        _ => None,
+                    }
+                }),
+        )
+        .next_or_pending()
+        .await
+    }
+
+    /// Waits for the notes of an out-of-band spend to be handed out, which for
+    /// exact spends may require splitting larger notes first
+    async fn await_spend_oob_notes(
+        &self,
+        operation_id: OperationId,
+    ) -> anyhow::Result<TieredMulti<SpendableNote>> {
+        Box::pin(
+            self.notifier
+                .subscribe(operation_id)
+                .await
+                .filter_map(|state| async move {
+                    let MintClientStateMachines::OOB(state) = state else { return None };
+
+                    match state.state {
+                        MintOOBStates::Created(created) => Some(Ok(created.notes)),
+                        MintOOBStates::SplitCanceled(_) => {
+                            Some(Err(anyhow!("The spend was canceled")))
+                        }
+                        MintOOBStates::SplitFailed(failed) => {
+                            Some(Err(anyhow!("Failed to split notes: {}", failed.error)))
+                        }
+                        _ => None,
+                    }
+                }),
+        )
+        .next_or_pending()
+        .await
+    }
+
     pub async fn await_spend_oob_refund(&self, operation_id: OperationId) -> SpendOOBRefund {
         Box::pin(
             self.notifier
@@ -944,7 +1710,10 @@ impl MintClientModule {
                             user_triggered: true,
                             transaction_id: refund.refund_txid,
                         }),
-                        MintOOBStates::Created(_) => None,
+                        MintOOBStates::Created(_)
+                        | MintOOBStates::Splitting(_)
+                        | MintOOBStates::SplitFailed(_)
+                        | MintOOBStates::SplitCanceled(_) => None,
                     }
                 }),
         )
@@ -1070,6 +1839,49 @@ pub struct SpendOOBRefund {
     pub transaction_id: TransactionId,
 }
 
+/// Outcome of the split reissuance of an exact out-of-band spend, see
+/// [`MintClientExt::spend_notes_exact`]
+enum SpendOOBSplit {
+    /// The notes were split and handed out
+    Done,
+    /// The user canceled the spend before the notes were handed out
+    Canceled,
+    /// Splitting the notes failed
+    Failed(String),
+}
+
+/// See [`MintClientModule::await_output_finalized`], usable by state machines
+/// which only have access to the notifier
+pub(crate) async fn await_output_finalized(
+    notifier: ModuleNotifier<DynGlobalClientContext, MintClientStateMachines>,
+    operation_id: OperationId,
+    out_point: OutPoint,
+) -> anyhow::Result<Amount> {
+    let stream = notifier
+        .subscribe(operation_id)
+        .await
+        .filter_map(|state| async move {
+            let MintClientStateMachines::Output(state) = state else { return None };
+
+            if state.common.out_point != out_point {
+                return None;
+            }
+
+            match state.state {
+                MintOutputStates::Succeeded(succeeded) => Some(Ok(succeeded.amount)),
+                MintOutputStates::Aborted(_) => Some(Err(anyhow!("Transaction was rejected"))),
+                MintOutputStates::Failed(failed) => Some(Err(anyhow!(
+                    "Failed to finalize transaction: {}",
+                    failed.error
+                ))),
+                _ => None,
+            }
+        });
+    pin_mut!(stream);
+
+    stream.next_or_pending().await
+}
+
 // We are using a greedy algorithm to select notes. We start with the largest
 // then proceed to the lowest tiers/denominations.
 // But there is a catch: we don't know if there are enough notes in the lowest
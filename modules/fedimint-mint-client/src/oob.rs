@@ -1,17 +1,22 @@
 use std::sync::Arc;
-use std::time::SystemTime;
+use std::time::{Duration, SystemTime};
 
+use anyhow::bail;
+use fedimint_client::event::StateSummary;
 use fedimint_client::sm::{ClientSMDatabaseTransaction, OperationId, State, StateTransition};
 use fedimint_client::transaction::ClientInput;
 use fedimint_client::DynGlobalClientContext;
 use fedimint_core::encoding::{Decodable, Encodable};
-use fedimint_core::{task, TieredMulti, TransactionId};
+use fedimint_core::{task, Amount, OutPoint, TieredMulti, TransactionId};
 use fedimint_mint_common::MintInput;
 
 use crate::input::{
     MintInputCommon, MintInputStateCreated, MintInputStateMachine, MintInputStates,
 };
-use crate::{MintClientContext, MintClientStateMachines, SpendableNote};
+use crate::{
+    await_output_finalized, MintClientContext, MintClientModule, MintClientStateMachines,
+    SpendableNote,
+};
 
 #[aquamarine::aquamarine]
 /// State machine managing e-cash that has been taken out of the wallet for
@@ -21,6 +26,9 @@ use crate::{MintClientContext, MintClientStateMachines, SpendableNote};
 /// graph LR
 ///     Created -- User triggered refund --> RefundU["User Refund"]
 ///     Created -- Timeout triggered refund --> RefundT["Timeout Refund"]
+///     Splitting -- Split notes issued --> Created
+///     Splitting -- Split failed --> SplitFailed["Split Failed"]
+///     Splitting -- User triggered cancel --> SplitCanceled["Split Canceled"]
 /// ```
 #[derive(Debug, Clone, Eq, PartialEq, Decodable, Encodable)]
 pub enum MintOOBStates {
@@ -33,6 +41,16 @@ pub enum MintOOBStates {
     /// refund. This refund *failing* is the expected behavior since the
     /// recipient is supposed to have already reissued it.
     TimeoutRefund(MintOOBStatesTimeoutRefund),
+    /// The notes held couldn't make exact change, so we are waiting for a
+    /// reissuance splitting larger notes before taking the e-cash out of the
+    /// wallet.
+    Splitting(MintOOBStatesSplitting),
+    /// Splitting the notes failed or they were spent concurrently, no e-cash
+    /// was taken out of the wallet.
+    SplitFailed(MintOOBStatesSplitFailed),
+    /// The user canceled the spend while the notes were being split, no e-cash
+    /// was taken out of the wallet.
+    SplitCanceled(MintOOBStatesSplitCanceled),
 }
 
 #[derive(Debug, Clone, Eq, PartialEq, Decodable, Encodable)]
@@ -57,6 +75,51 @@ pub struct MintOOBStatesTimeoutRefund {
     pub(crate) refund_txid: TransactionId,
 }
 
+#[derive(Debug, Clone, Eq, PartialEq, Decodable, Encodable)]
+pub struct MintOOBStatesSplitting {
+    /// Exact amount to take out of the wallet once the notes were split
+    pub(crate) amount: Amount,
+    /// Output of the reissuance issuing the split notes
+    pub(crate) out_point: OutPoint,
+    /// Timeout of the spend, starting once the e-cash was taken out of the
+    /// wallet
+    pub(crate) try_cancel_after: Duration,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Decodable, Encodable)]
+pub struct MintOOBStatesSplitFailed {
+    pub(crate) error: String,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Decodable, Encodable)]
+pub struct MintOOBStatesSplitCanceled;
+
+impl MintOOBStateMachine {
+    /// Refunds are tracked by the input state machines of the refund
+    /// transactions, so the refund states aren't final from the user's
+    /// perspective.
+    pub(crate) fn summary(&self) -> StateSummary {
+        match &self.state {
+            MintOOBStates::Created(_) => {
+                StateSummary::in_progress("Waiting for the recipient to reissue the e-cash")
+            }
+            MintOOBStates::UserRefund(_) => {
+                StateSummary::in_progress("Waiting for the user-requested refund")
+            }
+            MintOOBStates::TimeoutRefund(_) => {
+                StateSummary::in_progress("Waiting for the refund after the timeout")
+            }
+            MintOOBStates::Splitting(_) => {
+                StateSummary::in_progress("Waiting for the notes to be split")
+            }
+            MintOOBStates::SplitFailed(failed) => StateSummary::failed(&failed.error),
+            MintOOBStates::SplitCanceled(_) => {
+                StateSummary::failed("Canceled by the user before the e-cash was handed out")
+            }
+        }
+    }
+}
+
 impl State for MintOOBStateMachine {
     type ModuleContext = MintClientContext;
     type GlobalContext = DynGlobalClientContext;
@@ -76,6 +139,15 @@ impl State for MintOOBStateMachine {
             MintOOBStates::TimeoutRefund(_) => {
                 vec![]
             }
+            MintOOBStates::Splitting(splitting) => {
+                splitting.transitions(self.operation_id, context, global_context)
+            }
+            MintOOBStates::SplitFailed(_) => {
+                vec![]
+            }
+            MintOOBStates::SplitCanceled(_) => {
+                vec![]
+            }
         }
     }
 
@@ -115,6 +187,107 @@ impl MintOOBStatesCreated {
     }
 }
 
+impl MintOOBStatesSplitting {
+    fn transitions(
+        &self,
+        operation_id: OperationId,
+        context: &MintClientContext,
+        global_context: &DynGlobalClientContext,
+    ) -> Vec<StateTransition<MintOOBStateMachine>> {
+        let global_context = global_context.clone();
+        vec![
+            StateTransition::new(
+                await_split_finalized(context.clone(), operation_id, self.out_point),
+                move |dbtx, split_res, state| {
+                    Box::pin(transition_split_finalized(
+                        state,
+                        dbtx,
+                        split_res,
+                        global_context.clone(),
+                    ))
+                },
+            ),
+            StateTransition::new(
+                await_user_cancels(operation_id, context.subscribe_cancel_oob_payment()),
+                |_dbtx, (), state| {
+                    Box::pin(async move {
+                        MintOOBStateMachine {
+                            operation_id: state.operation_id,
+                            state: MintOOBStates::SplitCanceled(MintOOBStatesSplitCanceled),
+                        }
+                    })
+                },
+            ),
+        ]
+    }
+}
+
+async fn await_split_finalized(
+    context: MintClientContext,
+    operation_id: OperationId,
+    out_point: OutPoint,
+) -> Result<Amount, String> {
+    await_output_finalized(context.notifier, operation_id, out_point)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// Takes the exact amount out of the wallet once the split notes were issued.
+/// Usually these are the split notes themselves, but any notes adding up to the
+/// amount will do. Only then the spend is checked against the spending
+/// policies and recorded.
+async fn transition_split_finalized(
+    prev_state: MintOOBStateMachine,
+    dbtx: &mut ClientSMDatabaseTransaction<'_, '_>,
+    split_res: Result<Amount, String>,
+    global_context: DynGlobalClientContext,
+) -> MintOOBStateMachine {
+    let splitting = match prev_state.state {
+        MintOOBStates::Splitting(splitting) => splitting,
+        _ => panic!("Invalid previous state: {prev_state:?}"),
+    };
+
+    let notes_res = match split_res {
+        Ok(_) => take_split_notes(dbtx, splitting.amount, &global_context)
+            .await
+            .map_err(|e| e.to_string()),
+        Err(error) => Err(error),
+    };
+
+    let state = match notes_res {
+        Ok(notes) => MintOOBStates::Created(MintOOBStatesCreated {
+            notes,
+            timeout: fedimint_core::time::now() + splitting.try_cancel_after,
+        }),
+        Err(error) => MintOOBStates::SplitFailed(MintOOBStatesSplitFailed { error }),
+    };
+    MintOOBStateMachine {
+        operation_id: prev_state.operation_id,
+        state,
+    }
+}
+
+async fn take_split_notes(
+    dbtx: &mut ClientSMDatabaseTransaction<'_, '_>,
+    amount: Amount,
+    global_context: &DynGlobalClientContext,
+) -> anyhow::Result<TieredMulti<SpendableNote>> {
+    // Nothing may be written if the notes are gone, so check for them before
+    // recording the spend
+    let notes = MintClientModule::select_notes(&mut dbtx.module_tx(), amount).await?;
+    if notes.total_amount() != amount {
+        bail!("Split notes were spent concurrently");
+    }
+
+    global_context
+        .enforce_out_of_band_spend(dbtx, amount)
+        .await?;
+    let notes = MintClientModule::take_exact_notes(&mut dbtx.module_tx(), amount)
+        .await?
+        .expect("Notes were selected in the same transaction");
+    Ok(notes)
+}
+
 async fn await_user_cancels(
     operation_id: OperationId,
     mut oob_cancel_receiver: tokio::sync::broadcast::Receiver<OperationId>,
@@ -1,5 +1,6 @@
 use std::time::Duration;
 
+use fedimint_client::event::StateSummary;
 use fedimint_client::sm::{ClientSMDatabaseTransaction, OperationId, State, StateTransition};
 use fedimint_client::DynGlobalClientContext;
 use fedimint_core::api::GlobalFederationApi;
@@ -66,6 +67,19 @@ pub struct MintOutputStateMachine {
     pub(crate) state: MintOutputStates,
 }
 
+impl MintOutputStateMachine {
+    pub(crate) fn summary(&self) -> StateSummary {
+        match &self.state {
+            MintOutputStates::Created(_) => {
+                StateSummary::in_progress("Waiting for the e-cash notes to be issued")
+            }
+            MintOutputStates::Aborted(_) => StateSummary::failed("Transaction was rejected"),
+            MintOutputStates::Failed(failed) => StateSummary::failed(&failed.error),
+            MintOutputStates::Succeeded(_) => StateSummary::Succeeded,
+        }
+    }
+}
+
 impl State for MintOutputStateMachine {
     type ModuleContext = MintClientContext;
     type GlobalContext = DynGlobalClientContext;
@@ -1,12 +1,14 @@
 use std::collections::BTreeMap;
 
+use anyhow::ensure;
 use fedimint_core::config::EmptyGenParams;
 use fedimint_core::core::ModuleKind;
 use fedimint_core::encoding::{Decodable, Encodable};
 use fedimint_core::module::__reexports::serde_json;
-use fedimint_core::{plugin_types_trait_impl_config, Amount, PeerId, Tiered};
+use fedimint_core::{plugin_types_trait_impl_config, Amount, PeerId, Tiered, TieredSummary};
 use serde::{Deserialize, Serialize};
 use tbs::{AggregatePublicKey, PublicKeyShare};
+use thiserror::Error;
 
 use crate::MintCommonGen;
 
@@ -19,6 +21,9 @@ pub struct MintGenParams {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MintGenParamsConsensus {
     pub mint_amounts: Vec<Amount>,
+    /// Fees the federation charges for issuing and spending notes
+    #[serde(default)]
+    pub fee_consensus: FeeConsensus,
 }
 
 const TEN_BTC_IN_SATS: u64 = 10 * 100_000_000;
@@ -31,6 +36,7 @@ impl Default for MintGenParams {
                     .tiers()
                     .cloned()
                     .collect(),
+                fee_consensus: FeeConsensus::default(),
             },
             local: EmptyGenParams {},
         }
@@ -95,17 +101,262 @@ plugin_types_trait_impl_config!(
     MintClientConfig
 );
 
+/// Fees charged per note, versioned along with the mint's consensus version.
+///
+/// [`FeeConsensus::V1`] introduced proportional fees with consensus version
+/// `1`, federations without them keep using [`FeeConsensus::V0`].
 #[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize, Encodable, Decodable)]
-pub struct FeeConsensus {
-    pub note_issuance_abs: fedimint_core::Amount,
-    pub note_spend_abs: fedimint_core::Amount,
+pub enum FeeConsensus {
+    /// Absolute fees charged per note
+    V0 {
+        note_issuance_abs: Amount,
+        note_spend_abs: Amount,
+    },
+    /// Fees consisting of an absolute part and a part proportional to the
+    /// note's denomination.
+    ///
+    /// The proportional part is calculated for every note separately and
+    /// rounded down to the next msat, so all peers arrive at the same fee and
+    /// notes smaller than `1_000_000 / ppm` msat are free of proportional
+    /// fees. As long as there are no absolute fees this lets clients always
+    /// make exact change.
+    V1 {
+        note_issuance_abs: Amount,
+        note_spend_abs: Amount,
+        /// Fee for issuing a note in parts per million of its denomination
+        note_issuance_ppm: u64,
+        /// Fee for spending a note in parts per million of its denomination
+        note_spend_ppm: u64,
+    },
 }
 
 impl Default for FeeConsensus {
     fn default() -> Self {
-        Self {
-            note_issuance_abs: fedimint_core::Amount::ZERO,
-            note_spend_abs: fedimint_core::Amount::ZERO,
+        Self::V0 {
+            note_issuance_abs: Amount::ZERO,
+            note_spend_abs: Amount::ZERO,
         }
     }
 }
+
+/// Largest proportional fee, charging a note's full denomination
+pub const MAX_FEE_PPM: u64 = 1_000_000;
+
+/// A fee too large to be represented as an [`Amount`]
+#[derive(Debug, Clone, Copy, Error, PartialEq, Eq)]
+#[error("Fee overflows the maximum amount")]
+pub struct FeeOverflow;
+
+impl FeeConsensus {
+    /// Fails if a proportional fee exceeds [`MAX_FEE_PPM`]
+    pub fn validate(&self) -> anyhow::Result<()> {
+        let (_, issuance_ppm) = self.issuance_rates();
+        let (_, spend_ppm) = self.spend_rates();
+        ensure!(
+            issuance_ppm <= MAX_FEE_PPM,
+            "Note issuance fee of {issuance_ppm} ppm exceeds {MAX_FEE_PPM} ppm"
+        );
+        ensure!(
+            spend_ppm <= MAX_FEE_PPM,
+            "Note spend fee of {spend_ppm} ppm exceeds {MAX_FEE_PPM} ppm"
+        );
+        Ok(())
+    }
+
+    /// Fee for issuing a single note of denomination `amount`
+    pub fn note_issuance_fee(&self, amount: Amount) -> Result<Amount, FeeOverflow> {
+        let (abs, ppm) = self.issuance_rates();
+        note_fee(amount, abs, ppm)
+    }
+
+    /// Fee for spending a single note of denomination `amount`
+    pub fn note_spend_fee(&self, amount: Amount) -> Result<Amount, FeeOverflow> {
+        let (abs, ppm) = self.spend_rates();
+        note_fee(amount, abs, ppm)
+    }
+
+    /// Fee for issuing all `notes`
+    pub fn issuance_fee(&self, notes: &TieredSummary) -> Result<Amount, FeeOverflow> {
+        total_fee(notes, |amount| self.note_issuance_fee(amount))
+    }
+
+    /// Fee for spending all `notes`
+    pub fn spend_fee(&self, notes: &TieredSummary) -> Result<Amount, FeeOverflow> {
+        total_fee(notes, |amount| self.note_spend_fee(amount))
+    }
+
+    /// Absolute and proportional fee for issuing a note
+    fn issuance_rates(&self) -> (Amount, u64) {
+        match *self {
+            FeeConsensus::V0 {
+                note_issuance_abs, ..
+            } => (note_issuance_abs, 0),
+            FeeConsensus::V1 {
+                note_issuance_abs,
+                note_issuance_ppm,
+                ..
+            } => (note_issuance_abs, note_issuance_ppm),
+        }
+    }
+
+    /// Absolute and proportional fee for spending a note
+    fn spend_rates(&self) -> (Amount, u64) {
+        match *self {
+            FeeConsensus::V0 { note_spend_abs, .. } => (note_spend_abs, 0),
+            FeeConsensus::V1 {
+                note_spend_abs,
+                note_spend_ppm,
+                ..
+            } => (note_spend_abs, note_spend_ppm),
+        }
+    }
+}
+
+/// Returns `abs` plus `ppm` parts per million of `amount`, rounded down
+fn note_fee(amount: Amount, abs: Amount, ppm: u64) -> Result<Amount, FeeOverflow> {
+    let ppm_fee = u128::from(amount.msats) * u128::from(ppm) / 1_000_000;
+    let fee = u64::try_from(ppm_fee).map_err(|_| FeeOverflow)?;
+    abs.msats
+        .checked_add(fee)
+        .map(Amount::from_msats)
+        .ok_or(FeeOverflow)
+}
+
+/// Sums `note_fee` over all `notes`
+fn total_fee(
+    notes: &TieredSummary,
+    note_fee: impl Fn(Amount) -> Result<Amount, FeeOverflow>,
+) -> Result<Amount, FeeOverflow> {
+    notes
+        .iter()
+        .try_fold(Amount::ZERO, |total, (amount, count)| {
+            let fee = note_fee(amount)?
+                .msats
+                .checked_mul(count as u64)
+                .ok_or(FeeOverflow)?;
+            total
+                .msats
+                .checked_add(fee)
+                .map(Amount::from_msats)
+                .ok_or(FeeOverflow)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use fedimint_core::encoding::{Decodable, Encodable};
+    use fedimint_core::module::registry::ModuleDecoderRegistry;
+    use fedimint_core::{Amount, TieredSummary};
+
+    use super::{FeeConsensus, FeeOverflow, MAX_FEE_PPM};
+
+    fn ppm_fees(note_issuance_ppm: u64, note_spend_ppm: u64) -> FeeConsensus {
+        FeeConsensus::V1 {
+            note_issuance_abs: Amount::ZERO,
+            note_spend_abs: Amount::ZERO,
+            note_issuance_ppm,
+            note_spend_ppm,
+        }
+    }
+
+    #[test]
+    fn fee_consensus_versions_roundtrip() {
+        for fees in [
+            FeeConsensus::V0 {
+                note_issuance_abs: Amount::from_msats(10),
+                note_spend_abs: Amount::from_msats(3),
+            },
+            FeeConsensus::V1 {
+                note_issuance_abs: Amount::from_msats(10),
+                note_spend_abs: Amount::ZERO,
+                note_issuance_ppm: 1_000,
+                note_spend_ppm: 500,
+            },
+        ] {
+            let decoded = FeeConsensus::consensus_decode(
+                &mut Cursor::new(fees.consensus_encode_to_vec().unwrap()),
+                &ModuleDecoderRegistry::default(),
+            )
+            .unwrap();
+            assert_eq!(decoded, fees);
+        }
+    }
+
+    #[test]
+    fn ppm_fees_are_rounded_down_per_note() {
+        let fees = ppm_fees(1_000, 500);
+
+        assert_eq!(
+            fees.note_issuance_fee(Amount::from_msats(999)),
+            Ok(Amount::ZERO)
+        );
+        assert_eq!(
+            fees.note_issuance_fee(Amount::from_msats(1_999)),
+            Ok(Amount::from_msats(1))
+        );
+        assert_eq!(fees.note_spend_fee(Amount::from_sats(1)), Ok(Amount::ZERO));
+        assert_eq!(
+            fees.note_spend_fee(Amount::from_msats(u64::MAX)),
+            Ok(Amount::from_msats(u64::MAX / 2_000))
+        );
+
+        // The 512 msat note is free, while both 1024 msat notes are charged 1 msat
+        let notes =
+            TieredSummary::from_iter([(Amount::from_msats(512), 1), (Amount::from_msats(1024), 2)]);
+        assert_eq!(fees.issuance_fee(&notes), Ok(Amount::from_msats(2)));
+    }
+
+    #[test]
+    fn absolute_fees_are_charged_per_note() {
+        let notes =
+            TieredSummary::from_iter([(Amount::from_msats(1), 2), (Amount::from_msats(4096), 1)]);
+
+        let fees = FeeConsensus::V0 {
+            note_issuance_abs: Amount::from_msats(10),
+            note_spend_abs: Amount::from_msats(3),
+        };
+        assert_eq!(fees.issuance_fee(&notes), Ok(Amount::from_msats(30)));
+        assert_eq!(fees.spend_fee(&notes), Ok(Amount::from_msats(9)));
+
+        let fees = FeeConsensus::V1 {
+            note_issuance_abs: Amount::from_msats(10),
+            note_spend_abs: Amount::from_msats(3),
+            note_issuance_ppm: 1_000,
+            note_spend_ppm: 0,
+        };
+        assert_eq!(fees.issuance_fee(&notes), Ok(Amount::from_msats(34)));
+        assert_eq!(fees.spend_fee(&notes), Ok(Amount::from_msats(9)));
+    }
+
+    #[test]
+    fn ppm_fees_are_bounded() {
+        assert!(ppm_fees(MAX_FEE_PPM, MAX_FEE_PPM).validate().is_ok());
+        assert!(ppm_fees(MAX_FEE_PPM + 1, 0).validate().is_err());
+        assert!(ppm_fees(0, MAX_FEE_PPM + 1).validate().is_err());
+    }
+
+    #[test]
+    fn overflowing_fees_are_errors() {
+        let fees = FeeConsensus::V1 {
+            note_issuance_abs: Amount::from_msats(u64::MAX),
+            note_spend_abs: Amount::from_msats(1),
+            note_issuance_ppm: 0,
+            note_spend_ppm: MAX_FEE_PPM,
+        };
+
+        assert_eq!(
+            fees.note_issuance_fee(Amount::from_msats(1)),
+            Ok(Amount::from_msats(u64::MAX))
+        );
+        assert_eq!(
+            fees.issuance_fee(&TieredSummary::from_iter([(Amount::from_msats(1), 2)])),
+            Err(FeeOverflow)
+        );
+        assert_eq!(
+            fees.note_spend_fee(Amount::from_msats(u64::MAX)),
+            Err(FeeOverflow)
+        );
+    }
+}
@@ -18,11 +18,17 @@ pub mod common;
 pub mod db;
 
 pub const KIND: ModuleKind = ModuleKind::from_static_str("mint");
-const CONSENSUS_VERSION: ModuleConsensusVersion = ModuleConsensusVersion(0);
+/// Version `1` versioned the fee consensus to add proportional fees, see
+/// [`config::FeeConsensus`]
+pub const CONSENSUS_VERSION: ModuleConsensusVersion = ModuleConsensusVersion(1);
 
 /// By default, the maximum notes per denomination when change-making for users
 pub const DEFAULT_MAX_NOTES_PER_DENOMINATION: u16 = 3;
 
+/// Maximum number of nonces checked by a single `spent_nonces` API request,
+/// larger requests are rejected
+pub const MAX_SPENT_NONCES_PER_REQUEST: usize = 1_000;
+
 /// Data structures taking into account different amount tiers
 
 /// A consenus item from one of the federation members contributing partials
@@ -23,8 +23,8 @@ use fedimint_core::{
 };
 pub use fedimint_mint_common as common;
 use fedimint_mint_common::config::{
-    FeeConsensus, MintClientConfig, MintConfig, MintConfigConsensus, MintConfigLocal,
-    MintConfigPrivate, MintGenParams,
+    MintClientConfig, MintConfig, MintConfigConsensus, MintConfigLocal, MintConfigPrivate,
+    MintGenParams,
 };
 use fedimint_mint_common::db::{
     DbKeyPrefix, ECashUserBackupSnapshot, EcashBackupKey, EcashBackupKeyPrefix, MintAuditItemKey,
@@ -35,8 +35,8 @@ use fedimint_mint_common::db::{
 pub use fedimint_mint_common::{BackupRequest, SignedBackupRequest};
 use fedimint_mint_common::{
     BlindNonce, MintCommonGen, MintConsensusItem, MintError, MintInput, MintModuleTypes,
-    MintOutput, MintOutputBlindSignatures, MintOutputOutcome, MintOutputSignatureShare,
-    DEFAULT_MAX_NOTES_PER_DENOMINATION,
+    MintOutput, MintOutputBlindSignatures, MintOutputOutcome, MintOutputSignatureShare, Nonce,
+    CONSENSUS_VERSION, DEFAULT_MAX_NOTES_PER_DENOMINATION, MAX_SPENT_NONCES_PER_REQUEST,
 };
 use fedimint_server::config::distributedgen::{scalar, PeerHandleOps};
 use futures::StreamExt;
@@ -65,11 +65,17 @@ impl ServerModuleGen for MintGen {
     const DATABASE_VERSION: DatabaseVersion = DatabaseVersion(0);
 
     fn versions(&self, _core: CoreConsensusVersion) -> &[ModuleConsensusVersion] {
-        &[ModuleConsensusVersion(0)]
+        &[CONSENSUS_VERSION]
     }
 
     fn supported_api_versions(&self) -> SupportedModuleApiVersions {
-        SupportedModuleApiVersions::from_raw(0, 0, &[(0, 0)])
+        SupportedModuleApiVersions::from_raw(0, 1, &[(0, 1)])
+    }
+
+    fn parse_params(&self, params: &ConfigGenModuleParams) -> anyhow::Result<MintGenParams> {
+        let params = params.to_typed::<MintGenParams>()?;
+        params.consensus.fee_consensus.validate()?;
+        Ok(params)
     }
 
     async fn init(
@@ -118,7 +124,7 @@ impl ServerModuleGen for MintGen {
                                 (key_peer, keys)
                             })
                             .collect(),
-                        fee_consensus: FeeConsensus::default(),
+                        fee_consensus: params.consensus.fee_consensus.clone(),
                         max_notes_per_denomination: DEFAULT_MAX_NOTES_PER_DENOMINATION,
                     },
                     private: MintConfigPrivate {
@@ -180,7 +186,7 @@ impl ServerModuleGen for MintGen {
                         (*peer, pks)
                     })
                     .collect(),
-                fee_consensus: Default::default(),
+                fee_consensus: params.consensus.fee_consensus.clone(),
                 max_notes_per_denomination: DEFAULT_MAX_NOTES_PER_DENOMINATION,
             },
         };
@@ -211,6 +217,7 @@ impl ServerModuleGen for MintGen {
         if !sks.keys().contains(&Amount::from_msats(1)) {
             bail!("No msat 1 denomination");
         }
+        config.consensus.fee_consensus.validate()?;
 
         Ok(())
     }
@@ -500,16 +507,32 @@ impl ServerModule for Mint {
         _verification_cache: &Self::VerificationCache,
         input: &'a MintInput,
     ) -> Result<InputMeta, ModuleError> {
-        let iter = input.iter_items();
+        let mut notes = Vec::with_capacity(input.count_items());
+        for (amount, note) in input.iter_items() {
+            let Some(amount_key) = self.pub_key.get(&amount) else {
+                return Err(MintError::InvalidSignature).into_module_error_other();
+            };
+            notes.push((note, *amount_key));
+        }
 
+        // Hashing nonces to curve points is expensive too, so it's parallelized
+        let iter = notes.iter();
         #[cfg(not(target_family = "wasm"))]
         let iter = iter.par_bridge();
-
-        if !iter.all(|(amount, note)| match self.pub_key.get(&amount) {
-            Some(amount_key) => note.verify(*amount_key),
-            None => false,
-        }) {
-            return Err(MintError::InvalidSignature).into_module_error_other();
+        let signed_messages = iter
+            .map(|(note, amount_key)| (note.0.to_message(), note.1, *amount_key))
+            .collect::<Vec<_>>();
+
+        // Verifying all notes at once is much faster, but a failed batch doesn't tell
+        // which note is invalid, so only then they are checked one by one
+        if !tbs::verify_batch(signed_messages) {
+            let iter = notes.iter();
+            #[cfg(not(target_family = "wasm"))]
+            let iter = iter.par_bridge();
+
+            if !iter.all(|(note, amount_key)| note.verify(*amount_key)) {
+                return Err(MintError::InvalidSignature).into_module_error_other();
+            }
         }
 
         for (.., note) in input.iter_items() {
@@ -521,7 +544,12 @@ impl ServerModule for Mint {
         Ok(InputMeta {
             amount: TransactionItemAmount {
                 amount: input.total_amount(),
-                fee: self.cfg.consensus.fee_consensus.note_spend_abs * (input.count_items() as u64),
+                fee: self
+                    .cfg
+                    .consensus
+                    .fee_consensus
+                    .spend_fee(&input.summary())
+                    .into_module_error_other()?,
             },
             pub_keys: input
                 .iter_items()
@@ -579,8 +607,12 @@ impl ServerModule for Mint {
         } else {
             Ok(TransactionItemAmount {
                 amount: output.total_amount(),
-                fee: self.cfg.consensus.fee_consensus.note_issuance_abs
-                    * (output.count_items() as u64),
+                fee: self
+                    .cfg
+                    .consensus
+                    .fee_consensus
+                    .issuance_fee(&output.summary())
+                    .into_module_error_other()?,
             })
         }
     }
@@ -665,6 +697,18 @@ impl ServerModule for Mint {
                         .handle_recover_request(&mut context.dbtx(), id).await)
                 }
             },
+            api_endpoint! {
+                "spent_nonces",
+                async |module: &Mint, context, nonces: Vec<Nonce>| -> Vec<Nonce> {
+                    // The endpoint is unauthenticated, so the work per request is capped
+                    if nonces.len() > MAX_SPENT_NONCES_PER_REQUEST {
+                        return Err(ApiError::bad_request(format!(
+                            "Can check at most {MAX_SPENT_NONCES_PER_REQUEST} nonces per request"
+                        )));
+                    }
+                    Ok(module.spent_nonces(&mut context.dbtx(), nonces).await)
+                }
+            },
         ]
     }
 }
@@ -707,6 +751,21 @@ impl Mint {
     ) -> Option<ECashUserBackupSnapshot> {
         dbtx.get_value(&EcashBackupKey(id)).await
     }
+
+    /// Returns the nonces of `nonces` that were already spent
+    async fn spent_nonces(
+        &self,
+        dbtx: &mut ModuleDatabaseTransaction<'_>,
+        nonces: Vec<Nonce>,
+    ) -> Vec<Nonce> {
+        let mut spent = vec![];
+        for nonce in nonces {
+            if dbtx.get_value(&NonceKey(nonce)).await.is_some() {
+                spent.push(nonce);
+            }
+        }
+        spent
+    }
 }
 
 impl Mint {
@@ -811,6 +870,7 @@ mod test {
                 local: Default::default(),
                 consensus: MintGenParamsConsensus {
                     mint_amounts: vec![Amount::from_sats(1)],
+                    fee_consensus: FeeConsensus::default(),
                 },
             })
             .unwrap(),
@@ -818,7 +878,7 @@ mod test {
         let client_cfg = ClientModuleConfig::from_typed(
             0,
             MintGen::kind(),
-            ModuleConsensusVersion(0),
+            ModuleConsensusVersion(1),
             MintGen
                 .get_client_config(&mint_cfg[&PeerId::from(0)].consensus)
                 .unwrap(),
@@ -1,12 +1,27 @@
-use fedimint_core::sats;
+use std::time::Duration;
+
+use fedimint_client::batch::BatchingConfig;
+use fedimint_client::ledger::{LedgerDirection, LedgerFormat, LedgerRow, LedgerStatus};
+use fedimint_client::manager::ClientManager;
+use fedimint_client::oplog::OperationLogQuery;
+use fedimint_client::policy::{
+    MaxPerOperation, RollingLimit, SpendingPolicyViolation, SpendingRules,
+};
+use fedimint_client::secret::PlainRootSecretStrategy;
+use fedimint_core::db::mem_impl::MemDatabase;
+use fedimint_core::task::{sleep, TaskGroup};
 use fedimint_core::util::NextOrPending;
+use fedimint_core::{sats, Amount, TieredMulti};
 use fedimint_dummy_client::{DummyClientExt, DummyClientGen};
 use fedimint_dummy_common::config::DummyGenParams;
 use fedimint_dummy_server::DummyGen;
+use fedimint_mint_client::api::MintFederationApi;
 use fedimint_mint_client::{
-    MintClientExt, MintClientGen, ReissueExternalNotesState, SpendOOBState,
+    MintClientBuilderExt, MintClientExt, MintClientGen, NoteConsolidation,
+    ReissueExternalNotesState, SpendOOBState,
 };
 use fedimint_mint_common::config::MintGenParams;
+use fedimint_mint_common::MAX_SPENT_NONCES_PER_REQUEST;
 use fedimint_mint_server::MintGen;
 use fedimint_testing::fixtures::{Fixtures, TIMEOUT};
 
@@ -40,3 +55,351 @@ async fn sends_ecash_out_of_band() -> anyhow::Result<()> {
     assert_eq!(client2.get_balance().await, sats(750));
     Ok(())
 }
+
+#[tokio::test(flavor = "multi_thread")]
+async fn validates_notes() -> anyhow::Result<()> {
+    let fed = fixtures().new_fed().await;
+    let (client1, client2) = fed.two_clients().await;
+    let (op, outpoint) = client1.print_money(sats(1000)).await?;
+    client1.await_primary_module_output(op, outpoint).await?;
+
+    let (_, notes) = client1.spend_notes(sats(750), TIMEOUT, ()).await?;
+    let amount = notes.total_amount();
+    assert_eq!(client2.validate_notes(&notes, true).await?, amount);
+
+    // Notes claiming a different denomination than they were signed for
+    let forged_notes = notes
+        .iter_items()
+        .map(|(amount, note)| (amount * 2, note.clone()))
+        .collect::<TieredMulti<_>>();
+    assert!(client2.validate_notes(&forged_notes, false).await.is_err());
+    assert!(client2
+        .reissue_external_notes(forged_notes, ())
+        .await
+        .is_err());
+
+    // Denominations the federation doesn't issue
+    let unknown_tier_notes = notes
+        .iter_items()
+        .map(|(_, note)| (Amount::from_msats(3), note.clone()))
+        .collect::<TieredMulti<_>>();
+    assert!(client2
+        .validate_notes(&unknown_tier_notes, false)
+        .await
+        .is_err());
+
+    let op = client2.reissue_external_notes(notes.clone(), ()).await?;
+    let mut sub = client2
+        .subscribe_reissue_external_notes(op)
+        .await?
+        .into_stream();
+    assert_eq!(sub.ok().await?, ReissueExternalNotesState::Created);
+    assert_eq!(sub.ok().await?, ReissueExternalNotesState::Issuing);
+    assert_eq!(sub.ok().await?, ReissueExternalNotesState::Done);
+
+    // The signatures stay valid, but only the federation knows they were spent
+    assert_eq!(client2.validate_notes(&notes, false).await?, amount);
+    assert!(client2.validate_notes(&notes, true).await.is_err());
+
+    // The unauthenticated spent nonce check only accepts a bounded request size
+    let nonce = notes.iter_items().next().expect("has notes").1.note.0;
+    let module_api = client2.api().with_module(0);
+    assert!(module_api
+        .fetch_spent_nonces(vec![nonce; MAX_SPENT_NONCES_PER_REQUEST])
+        .await
+        .is_ok());
+    assert!(module_api
+        .fetch_spent_nonces(vec![nonce; MAX_SPENT_NONCES_PER_REQUEST + 1])
+        .await
+        .is_err());
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn sends_exact_ecash_out_of_band() -> anyhow::Result<()> {
+    let fed = fixtures().new_fed().await;
+    let (client1, client2) = fed.two_clients().await;
+    let (op, outpoint) = client1.print_money(sats(1000)).await?;
+    client1.await_primary_module_output(op, outpoint).await?;
+
+    // Leave client1 with as few notes as possible, so exact change for 750 sats
+    // can only be made by splitting a larger note
+    let ops = client1.consolidate_notes(0).await?;
+    let mut sub = client1
+        .subscribe_consolidate_notes(ops[0])
+        .await?
+        .into_stream();
+    assert_eq!(sub.ok().await?, ReissueExternalNotesState::Created);
+    assert_eq!(sub.ok().await?, ReissueExternalNotesState::Issuing);
+    assert_eq!(sub.ok().await?, ReissueExternalNotesState::Done);
+
+    let op = client1.spend_notes_exact(sats(750), TIMEOUT, ()).await?;
+    let sub1 = &mut client1.subscribe_spend_notes(op).await?.into_stream();
+    assert_eq!(sub1.ok().await?, SpendOOBState::Splitting);
+    assert_eq!(sub1.ok().await?, SpendOOBState::Created);
+    let notes = client1.await_spend_notes_exact(op).await?;
+    assert_eq!(notes.total_amount(), sats(750));
+
+    let op = client2.reissue_external_notes(notes, ()).await?;
+    let sub2 = client2.subscribe_reissue_external_notes(op).await?;
+    let mut sub2 = sub2.into_stream();
+    assert_eq!(sub2.ok().await?, ReissueExternalNotesState::Created);
+    assert_eq!(sub2.ok().await?, ReissueExternalNotesState::Issuing);
+    assert_eq!(sub2.ok().await?, ReissueExternalNotesState::Done);
+    assert_eq!(sub1.ok().await?, SpendOOBState::Success);
+
+    assert_eq!(client1.get_balance().await, sats(250));
+    assert_eq!(client2.get_balance().await, sats(750));
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn spending_policy_counts_out_of_band_spends() -> anyhow::Result<()> {
+    let fed = fixtures().new_fed().await;
+    let (client1, client2) = fed.two_clients().await;
+    let (op, outpoint) = client1.print_money(sats(1000)).await?;
+    client1.await_primary_module_output(op, outpoint).await?;
+
+    let rules = SpendingRules {
+        rolling_limits: vec![RollingLimit::daily(sats(800))],
+        ..Default::default()
+    };
+    client1.set_spending_rules(rules).await?;
+
+    // Only the e-cash handed out counts, not splitting notes to make exact change
+    let op = client1.spend_notes_exact(sats(600), TIMEOUT, ()).await?;
+    let notes = client1.await_spend_notes_exact(op).await?;
+    assert_eq!(
+        client1.spent_within(Duration::from_secs(60)).await,
+        sats(600)
+    );
+
+    let error = client1
+        .spend_notes(sats(300), TIMEOUT, ())
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        error.downcast_ref::<SpendingPolicyViolation>(),
+        Some(SpendingPolicyViolation::RollingLimitExceeded { .. })
+    ));
+    assert_eq!(client1.get_balance().await, sats(400));
+
+    // Reissuing notes only pays the client itself
+    let rules = SpendingRules {
+        max_per_operation: Some(MaxPerOperation(Amount::ZERO)),
+        ..Default::default()
+    };
+    client2.set_spending_rules(rules).await?;
+    let op = client2.reissue_external_notes(notes, ()).await?;
+    let mut sub = client2
+        .subscribe_reissue_external_notes(op)
+        .await?
+        .into_stream();
+    assert_eq!(sub.ok().await?, ReissueExternalNotesState::Created);
+    assert_eq!(sub.ok().await?, ReissueExternalNotesState::Issuing);
+    assert_eq!(sub.ok().await?, ReissueExternalNotesState::Done);
+    assert_eq!(
+        client2.spent_within(Duration::from_secs(60)).await,
+        Amount::ZERO
+    );
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn client_manager_refuses_to_leave_with_pending_operations() -> anyhow::Result<()> {
+    let fed = fixtures().new_fed().await;
+    let manager = ClientManager::<PlainRootSecretStrategy>::new(
+        Box::new(MemDatabase::new()),
+        fed.client_module_gens(),
+        fedimint_mint_common::KIND,
+        &mut TaskGroup::new(),
+    )
+    .await?;
+    let client = manager.join(fed.client_config()).await?;
+    let (op, outpoint) = client.print_money(sats(1000)).await?;
+    client.await_primary_module_output(op, outpoint).await?;
+
+    // The notes left the balance, but are refunded if they aren't reissued
+    let balance = client.get_balance().await;
+    client
+        .spend_notes(balance, Duration::from_secs(3600), ())
+        .await?;
+    assert_eq!(client.get_balance().await, Amount::ZERO);
+    assert!(manager.leave(fed.id(), false).await.is_err());
+    assert!(manager.get(&fed.id()).await.is_some());
+
+    manager.leave(fed.id(), true).await?;
+    assert!(manager.get(&fed.id()).await.is_none());
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn consolidates_notes() -> anyhow::Result<()> {
+    let fed = fixtures().new_fed().await;
+    let client = fed.new_client().await;
+    let (op, outpoint) = client.print_money(sats(1000)).await?;
+    client.await_primary_module_output(op, outpoint).await?;
+    let num_notes = client.get_note_summary().await.count_items();
+
+    // Without notes to keep per denomination everything is reissued into as few
+    // notes as possible
+    let ops = client.consolidate_notes(0).await?;
+    assert_eq!(ops.len(), 1);
+    let mut sub = client
+        .subscribe_consolidate_notes(ops[0])
+        .await?
+        .into_stream();
+    assert_eq!(sub.ok().await?, ReissueExternalNotesState::Created);
+    assert_eq!(sub.ok().await?, ReissueExternalNotesState::Issuing);
+    assert_eq!(sub.ok().await?, ReissueExternalNotesState::Done);
+
+    assert_eq!(client.get_balance().await, sats(1000));
+    assert!(client.get_note_summary().await.count_items() < num_notes);
+
+    // Consolidating again wouldn't reduce the number of notes any further
+    assert!(client.consolidate_notes(0).await?.is_empty());
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn consolidates_notes_in_the_background() -> anyhow::Result<()> {
+    let fed = fixtures().new_fed().await;
+    let client = fed.new_client().await;
+    let consolidating_client = fed
+        .new_client_with(|builder| {
+            builder.with_note_consolidation(NoteConsolidation {
+                max_notes: 1,
+                target_per_tier: 0,
+            })
+        })
+        .await;
+
+    for client in [&client, &consolidating_client] {
+        let (op, outpoint) = client.print_money(sats(1000)).await?;
+        client.await_primary_module_output(op, outpoint).await?;
+    }
+    let num_notes = client.get_note_summary().await.count_items();
+
+    fedimint_core::task::timeout(TIMEOUT, async {
+        while consolidating_client.get_note_summary().await.count_items() >= num_notes {
+            sleep(Duration::from_millis(100)).await;
+        }
+    })
+    .await?;
+    assert_eq!(consolidating_client.get_balance().await, sats(1000));
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn exports_ledger() -> anyhow::Result<()> {
+    let fed = fixtures().new_fed().await;
+    let (client1, client2) = fed.two_clients().await;
+    let (op, outpoint) = client1.print_money(sats(1000)).await?;
+    client1.await_primary_module_output(op, outpoint).await?;
+
+    let (spend_op, notes) = client1.spend_notes(sats(750), TIMEOUT, ()).await?;
+    let amount = notes.total_amount();
+    let reissue_op = client2.reissue_external_notes(notes, ()).await?;
+
+    // Nobody follows the updates of the operations, so no outcome is cached and
+    // the ledger has to derive the status from the state machines
+    while client2.get_active_operations().await.contains(&reissue_op) {
+        sleep(Duration::from_millis(100)).await;
+    }
+
+    let ledger = client2.export_ledger(LedgerFormat::Json, ..).await?;
+    let rows = serde_json::from_str::<Vec<LedgerRow>>(&ledger)?;
+    assert_eq!(rows.len(), 1);
+    assert_eq!(rows[0].operation_id, reissue_op);
+    assert_eq!(rows[0].module, "mint");
+    assert_eq!(rows[0].direction, LedgerDirection::Incoming);
+    assert_eq!(rows[0].amount_msat, Some(amount));
+    // The test federation charges no fees, but the reissuance paid them
+    assert_eq!(rows[0].fee_msat, Some(Amount::ZERO));
+    assert_eq!(rows[0].status, LedgerStatus::Succeeded);
+
+    // The spend stays pending until the notes are refunded after the timeout,
+    // the dummy module's operations don't show up in the ledger
+    let ledger = client1.export_ledger(LedgerFormat::Csv, ..).await?;
+    let lines = ledger.lines().collect::<Vec<_>>();
+    assert_eq!(lines.len(), 2);
+    assert_eq!(
+        lines[1].split(',').skip(1).collect::<Vec<_>>(),
+        [
+            spend_op.to_string().as_str(),
+            "mint",
+            "outgoing",
+            amount.msats.to_string().as_str(),
+            "",
+            "",
+            "pending"
+        ]
+    );
+
+    let query = OperationLogQuery {
+        min_amount: Some(sats(700)),
+        ..Default::default()
+    };
+    let operations = client1.query_operations(&query).await;
+    assert_eq!(operations.len(), 1);
+    assert_eq!(operations[0].0.operation_id, spend_op);
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn rejected_batch_fails_all_operations() -> anyhow::Result<()> {
+    let fed = fixtures().new_fed().await;
+    let (client1, client2) = fed.two_clients().await;
+    let (op, outpoint) = client1.print_money(sats(1000)).await?;
+    client1.await_primary_module_output(op, outpoint).await?;
+
+    let batching_client = fed
+        .new_client_with(|builder| {
+            builder.with_transaction_batching(BatchingConfig {
+                window: Duration::from_secs(1),
+                ..Default::default()
+            })
+        })
+        .await;
+
+    let (_, spent_notes) = client1.spend_notes(sats(250), TIMEOUT, ()).await?;
+    let (_, valid_notes) = client1.spend_notes(sats(500), TIMEOUT, ()).await?;
+    let valid_amount = valid_notes.total_amount();
+    let op = client2
+        .reissue_external_notes(spent_notes.clone(), ())
+        .await?;
+    let mut sub = client2
+        .subscribe_reissue_external_notes(op)
+        .await?
+        .into_stream();
+    assert_eq!(sub.ok().await?, ReissueExternalNotesState::Created);
+    assert_eq!(sub.ok().await?, ReissueExternalNotesState::Issuing);
+    assert_eq!(sub.ok().await?, ReissueExternalNotesState::Done);
+
+    // Both reissuances end up in one transaction, which is rejected because of the
+    // already spent notes
+    let (valid_op, spent_op) = futures::join!(
+        batching_client.reissue_external_notes(valid_notes, ()),
+        batching_client.reissue_external_notes(spent_notes, ())
+    );
+    for op in [valid_op?, spent_op?] {
+        let mut sub = batching_client
+            .subscribe_reissue_external_notes(op)
+            .await?
+            .into_stream();
+        assert_eq!(sub.ok().await?, ReissueExternalNotesState::Created);
+        assert!(matches!(
+            sub.ok().await?,
+            ReissueExternalNotesState::Failed(_)
+        ));
+    }
+
+    // The valid notes are reclaimed by a refund of their own
+    fedimint_core::task::timeout(TIMEOUT, async {
+        while batching_client.get_balance().await != valid_amount {
+            sleep(Duration::from_millis(100)).await;
+        }
+    })
+    .await?;
+    Ok(())
+}
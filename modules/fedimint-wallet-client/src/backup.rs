@@ -0,0 +1,162 @@
+use std::time::{Duration, SystemTime};
+
+use fedimint_client::backup::restore_operations;
+use fedimint_client::oplog::OperationLog;
+use fedimint_client::sm::Executor;
+use fedimint_client::DynGlobalClientContext;
+use fedimint_core::core::ModuleInstanceId;
+use fedimint_core::db::{DatabaseTransaction, ModuleDatabaseTransaction};
+use fedimint_core::encoding::{Decodable, Encodable};
+use fedimint_core::module::CommonModuleGen;
+use fedimint_core::time::now;
+use fedimint_wallet_common::WalletCommonGen;
+use tracing::warn;
+
+use crate::db::{DepositIndexKey, NextDepositIndexKey};
+use crate::deposit::{DepositStateMachine, DepositStates};
+use crate::{WalletClientModule, WalletClientStates, WalletOperationMeta};
+
+/// Restored deposit addresses are watched for at least this long, since the
+/// deposit may have been made while the client was gone
+const RESTORED_DEPOSIT_GRACE_PERIOD: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Snapshot of the deposit addresses that may still receive or hold funds
+///
+/// Only derivation indices are stored instead of tweak keys, the keys are
+/// derived from the module secret again on restore.
+#[derive(Debug, Clone, PartialEq, Eq, Encodable, Decodable)]
+pub struct WalletBackup {
+    next_deposit_idx: u64,
+    pending_deposits: Vec<PendingDeposit>,
+}
+
+/// Deposit address that wasn't claimed yet
+#[derive(Debug, Clone, PartialEq, Eq, Encodable, Decodable)]
+struct PendingDeposit {
+    deposit_idx: u64,
+    timeout_at: SystemTime,
+}
+
+impl WalletBackup {
+    /// An empty backup, like one created by a newly created client
+    pub fn new_empty() -> Self {
+        Self {
+            next_deposit_idx: 0,
+            pending_deposits: vec![],
+        }
+    }
+}
+
+impl WalletClientModule {
+    pub async fn prepare_wallet_backup(
+        &self,
+        dbtx: &mut ModuleDatabaseTransaction<'_>,
+        executor: Executor<DynGlobalClientContext>,
+        module_instance_id: ModuleInstanceId,
+    ) -> WalletBackup {
+        let mut backup = WalletBackup {
+            next_deposit_idx: dbtx.get_value(&NextDepositIndexKey).await.unwrap_or(0),
+            pending_deposits: vec![],
+        };
+
+        for (dyn_state, _active_state) in executor.get_active_states().await {
+            if dyn_state.module_instance_id() != module_instance_id {
+                continue;
+            }
+
+            let state: WalletClientStates = dyn_state
+                .as_any()
+                .downcast_ref()
+                .cloned()
+                .expect("Can't downcast wallet client state machine state");
+
+            let WalletClientStates::Deposit(DepositStateMachine {
+                operation_id,
+                state,
+            }) = state
+            else {
+                continue;
+            };
+            let timeout_at = match state {
+                DepositStates::Created(created) => created.timeout_at,
+                // The transaction is found again right after restoring
+                DepositStates::WaitingForConfirmations(_) => now(),
+                // Claimed funds are backed up by the primary module
+                DepositStates::Claiming(_) | DepositStates::TimedOut(_) => continue,
+            };
+
+            match dbtx.get_value(&DepositIndexKey(operation_id)).await {
+                Some(deposit_idx) => backup.pending_deposits.push(PendingDeposit {
+                    deposit_idx,
+                    timeout_at,
+                }),
+                None => warn!(
+                    %operation_id,
+                    "Deposit address doesn't use a derived key, can't back it up"
+                ),
+            }
+        }
+
+        backup
+    }
+
+    /// Re-creates the state machines watching the pending deposit addresses
+    /// in `backup` and adds their operations to `operation_log`
+    pub async fn restore_wallet_backup(
+        &self,
+        dbtx: &mut DatabaseTransaction<'_>,
+        module_instance_id: ModuleInstanceId,
+        executor: Executor<DynGlobalClientContext>,
+        operation_log: &OperationLog,
+        backup: WalletBackup,
+    ) -> anyhow::Result<()> {
+        let mut operations = vec![];
+        let mut module_dbtx = dbtx.with_module_prefix(module_instance_id);
+
+        // Never go back to an index that could already have been used
+        let next_deposit_idx = module_dbtx
+            .get_value(&NextDepositIndexKey)
+            .await
+            .unwrap_or(0)
+            .max(backup.next_deposit_idx);
+        module_dbtx
+            .insert_entry(&NextDepositIndexKey, &next_deposit_idx)
+            .await;
+
+        let min_timeout_at = now() + RESTORED_DEPOSIT_GRACE_PERIOD;
+        for PendingDeposit {
+            deposit_idx,
+            timeout_at,
+        } in backup.pending_deposits
+        {
+            let expires_at = timeout_at.max(min_timeout_at);
+            let (operation_id, state, address) =
+                self.deposit_state_machine(deposit_idx, expires_at);
+            self.rpc
+                .watch_script_history(&address.script_pubkey())
+                .await?;
+            module_dbtx
+                .insert_entry(&DepositIndexKey(operation_id), &deposit_idx)
+                .await;
+            operations.push((
+                operation_id,
+                WalletOperationMeta::Deposit {
+                    address,
+                    expires_at,
+                },
+                state,
+            ));
+        }
+        drop(module_dbtx);
+
+        restore_operations(
+            dbtx,
+            module_instance_id,
+            WalletCommonGen::KIND.as_str(),
+            &executor,
+            operation_log,
+            operations,
+        )
+        .await
+    }
+}
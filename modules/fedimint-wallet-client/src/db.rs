@@ -0,0 +1,47 @@
+use fedimint_client::sm::OperationId;
+use fedimint_core::encoding::{Decodable, Encodable};
+use fedimint_core::{impl_db_lookup, impl_db_record, Amount};
+use serde::Serialize;
+use strum_macros::EnumIter;
+
+#[repr(u8)]
+#[derive(Clone, EnumIter, Debug)]
+pub enum DbKeyPrefix {
+    NextDepositIndex = 0x2f,
+    DepositIndex = 0x30,
+    ClaimedDeposit = 0x31,
+}
+
+/// Index of the next tweak key derived for a deposit address
+#[derive(Debug, Encodable, Decodable, Serialize)]
+pub struct NextDepositIndexKey;
+
+impl_db_record!(
+    key = NextDepositIndexKey,
+    value = u64,
+    db_prefix = DbKeyPrefix::NextDepositIndex,
+);
+
+/// Index of the tweak key of the deposit address of an operation
+#[derive(Debug, Encodable, Decodable, Serialize)]
+pub struct DepositIndexKey(pub OperationId);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct DepositIndexKeyPrefix;
+
+impl_db_record!(
+    key = DepositIndexKey,
+    value = u64,
+    db_prefix = DbKeyPrefix::DepositIndex,
+);
+impl_db_lookup!(key = DepositIndexKey, query_prefix = DepositIndexKeyPrefix);
+
+/// Amount of the on-chain deposit claimed by a deposit operation
+#[derive(Debug, Encodable, Decodable, Serialize)]
+pub struct ClaimedDepositKey(pub OperationId);
+
+impl_db_record!(
+    key = ClaimedDepositKey,
+    value = Amount,
+    db_prefix = DbKeyPrefix::ClaimedDeposit,
+);
@@ -1,13 +1,14 @@
 use std::sync::Arc;
 use std::time::{Duration, SystemTime};
 
+use fedimint_client::event::StateSummary;
 use fedimint_client::sm::{ClientSMDatabaseTransaction, OperationId, State, StateTransition};
 use fedimint_client::transaction::ClientInput;
 use fedimint_client::DynGlobalClientContext;
 use fedimint_core::encoding::{Decodable, Encodable};
 use fedimint_core::task::sleep;
 use fedimint_core::txoproof::TxOutProof;
-use fedimint_core::{OutPoint, TransactionId};
+use fedimint_core::{Amount, OutPoint, TransactionId};
 use fedimint_wallet_common::tweakable::Tweakable;
 use fedimint_wallet_common::txoproof::PegInProof;
 use fedimint_wallet_common::WalletInput;
@@ -16,6 +17,7 @@ use secp256k1::KeyPair;
 use tracing::{trace, warn};
 
 use crate::api::WalletFederationApi;
+use crate::db::ClaimedDepositKey;
 use crate::{WalletClientContext, WalletClientStates};
 
 const TRANSACTION_STATUS_FETCH_INTERVAL: Duration = Duration::from_secs(1);
@@ -38,6 +40,25 @@ pub struct DepositStateMachine {
     pub(crate) state: DepositStates,
 }
 
+impl DepositStateMachine {
+    /// The claim transaction is tracked by the transaction submission state
+    /// machine, so reaching the claiming state counts as success.
+    pub(crate) fn summary(&self) -> StateSummary {
+        match &self.state {
+            DepositStates::Created(_) => {
+                StateSummary::in_progress("Waiting for a transaction to the deposit address")
+            }
+            DepositStates::WaitingForConfirmations(_) => {
+                StateSummary::in_progress("Waiting for the deposit to be confirmed")
+            }
+            DepositStates::Claiming(_) => StateSummary::Succeeded,
+            DepositStates::TimedOut(_) => {
+                StateSummary::failed("No deposit was made before the address expired")
+            }
+        }
+    }
+}
+
 impl State for DepositStateMachine {
     type ModuleContext = WalletClientContext;
     type GlobalContext = DynGlobalClientContext;
@@ -254,6 +275,16 @@ async fn transition_btc_tx_confirmed(
         _ => panic!("Invalid previous state"),
     };
 
+    let deposit_amount = awaiting_confirmation_state.btc_transaction.output
+        [awaiting_confirmation_state.out_idx as usize]
+        .value;
+    dbtx.module_tx()
+        .insert_entry(
+            &ClaimedDepositKey(old_state.operation_id),
+            &Amount::from_sats(deposit_amount),
+        )
+        .await;
+
     let wallet_input = WalletInput(Box::new(
         PegInProof::new(
             txout_proof,
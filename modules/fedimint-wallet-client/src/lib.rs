@@ -1,5 +1,7 @@
 pub mod api;
+pub mod backup;
 
+mod db;
 mod deposit;
 mod withdraw;
 
@@ -10,18 +12,26 @@ use anyhow::{anyhow, bail, ensure};
 use async_stream::stream;
 use bitcoin::{Address, Network};
 use fedimint_bitcoind::{create_bitcoind, DynBitcoindRpc};
-use fedimint_client::derivable_secret::DerivableSecret;
+use fedimint_client::batch::BatchedTransaction;
+use fedimint_client::derivable_secret::{ChildId, DerivableSecret};
+use fedimint_client::event::StateSummary;
+use fedimint_client::ledger::{LedgerDirection, LedgerEntry, LedgerStatus};
 use fedimint_client::module::gen::ClientModuleGen;
 use fedimint_client::module::{ClientModule, IClientModule};
-use fedimint_client::oplog::UpdateStreamOrOutcome;
+use fedimint_client::oplog::{OperationLog, OperationLogEntry, UpdateStreamOrOutcome};
+use fedimint_client::policy::SpendDestination;
 use fedimint_client::sm::util::MapStateTransitions;
-use fedimint_client::sm::{Context, DynState, ModuleNotifier, OperationId, State, StateTransition};
-use fedimint_client::transaction::{ClientOutput, TransactionBuilder};
+use fedimint_client::sm::{
+    Context, DynState, Executor, ModuleNotifier, OperationId, State, StateTransition,
+};
+use fedimint_client::transaction::{ClientOutput, TransactionBuilder, TransactionPreview};
 use fedimint_client::{sm_enum_variant_translation, Client, DynGlobalClientContext};
 use fedimint_core::api::{DynGlobalApi, DynModuleApi};
 use fedimint_core::bitcoinrpc::BitcoinRpcConfig;
 use fedimint_core::core::{Decoder, IntoDynInstance, ModuleInstanceId};
-use fedimint_core::db::{AutocommitError, Database};
+use fedimint_core::db::{
+    AutocommitError, Database, DatabaseTransaction, ModuleDatabaseTransaction,
+};
 use fedimint_core::encoding::{Decodable, Encodable};
 use fedimint_core::module::{
     ApiVersion, CommonModuleGen, ExtendsCommonModuleGen, ModuleCommon, MultiApiVersion,
@@ -35,14 +45,20 @@ pub use fedimint_wallet_common::*;
 use futures::{Stream, StreamExt};
 use miniscript::ToPublicKey;
 use rand::{thread_rng, Rng};
-use secp256k1::{All, KeyPair, Secp256k1};
+use secp256k1::{All, Secp256k1};
 use serde::{Deserialize, Serialize};
 use url::Url;
 
 use crate::api::WalletFederationApi;
+use crate::backup::WalletBackup;
+use crate::db::{ClaimedDepositKey, DepositIndexKey, DepositIndexKeyPrefix, NextDepositIndexKey};
 use crate::deposit::{CreatedDepositState, DepositStateMachine, DepositStates};
 use crate::withdraw::{CreatedWithdrawState, WithdrawStateMachine, WithdrawStates};
 
+/// Child of the module secret the tweak keys of deposit addresses are derived
+/// from
+const DEPOSIT_KEY_CHILD_ID: ChildId = ChildId(0);
+
 #[apply(async_trait_maybe_send!)]
 pub trait WalletClientExt {
     async fn get_deposit_address(
@@ -67,6 +83,16 @@ pub trait WalletClientExt {
         amount: bitcoin::Amount,
     ) -> anyhow::Result<PegOutFees>;
 
+    /// Calculates what withdrawing `amount` to `address` using
+    /// [`WalletClientExt::withdraw`] would cost *right now*, including the
+    /// on-chain fees from [`WalletClientExt::get_withdraw_fee`] and the
+    /// federation's fees, without withdrawing anything.
+    async fn quote_withdraw(
+        &self,
+        address: bitcoin::Address,
+        amount: bitcoin::Amount,
+    ) -> anyhow::Result<WithdrawQuote>;
+
     /// Attempt to withdraw a given `amount` of Bitcoin to a destination
     /// `address`. The caller has to supply the fee rate to be used which can be
     /// fetched using [`WalletClientExt::get_withdraw_fee`] and should be
@@ -84,6 +110,23 @@ pub trait WalletClientExt {
     ) -> anyhow::Result<UpdateStreamOrOutcome<WithdrawState>>;
 }
 
+/// The costs of a withdrawal, see [`WalletClientExt::quote_withdraw`]
+#[derive(Debug, Clone)]
+pub struct WithdrawQuote {
+    /// On-chain fees, to be passed to [`WalletClientExt::withdraw`]
+    pub fees: PegOutFees,
+    /// The transaction that would fund the withdrawal, including the
+    /// federation's fees
+    pub transaction: TransactionPreview,
+}
+
+impl WithdrawQuote {
+    /// Total amount the withdrawal would cost including all fees
+    pub fn total_cost(&self) -> Amount {
+        self.transaction.total_cost()
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
 pub enum DepositState {
     WaitingForTransaction,
@@ -118,8 +161,12 @@ impl WalletClientExt for Client {
             .autocommit(
                 |dbtx| {
                     Box::pin(async move {
-                        let (operation_id, sm, address) =
-                            wallet_client.get_deposit_address(valid_until);
+                        let (operation_id, sm, address) = wallet_client
+                            .get_deposit_address(
+                                &mut dbtx.with_module_prefix(instance.id),
+                                valid_until,
+                            )
+                            .await;
                         // Begin watching the script address
                         wallet_client
                             .rpc
@@ -246,6 +293,29 @@ impl WalletClientExt for Client {
         wallet_client.get_withdraw_fees(address, amount).await
     }
 
+    async fn quote_withdraw(
+        &self,
+        address: Address,
+        amount: bitcoin::Amount,
+    ) -> anyhow::Result<WithdrawQuote> {
+        let (wallet_client, instance) =
+            self.get_first_module::<WalletClientModule>(&WalletCommonGen::KIND);
+
+        let fees = wallet_client
+            .get_withdraw_fees(address.clone(), amount)
+            .await?;
+        let withdraw_output = wallet_client
+            .create_withdraw_output(OperationId::new_random(), address, amount, fees.clone())
+            .await?;
+        let tx_builder =
+            TransactionBuilder::new().with_output(withdraw_output.into_dyn(instance.id));
+
+        Ok(WithdrawQuote {
+            fees,
+            transaction: self.preview_transaction(tx_builder).await?,
+        })
+    }
+
     async fn withdraw(
         &self,
         address: Address,
@@ -263,14 +333,14 @@ impl WalletClientExt for Client {
         let tx_builder =
             TransactionBuilder::new().with_output(withdraw_output.into_dyn(instance.id));
 
-        self.finalize_and_submit_transaction(
+        self.submit_batched_transaction(
             operation_id,
             WalletCommonGen::KIND.as_str(),
-            move |_, change| WalletOperationMeta::Withdraw {
+            move |batched_tx: BatchedTransaction| WalletOperationMeta::Withdraw {
                 address: address.clone(),
                 amount,
                 fee: fee.clone(),
-                change,
+                change: batched_tx.change_outpoint,
             },
             tx_builder,
         )
@@ -396,7 +466,7 @@ impl ClientModuleGen for WalletClientGen {
         cfg: WalletClientConfig,
         _db: Database,
         _api_version: ApiVersion,
-        _module_root_secret: DerivableSecret,
+        module_root_secret: DerivableSecret,
         notifier: ModuleNotifier<DynGlobalClientContext, <Self::Module as ClientModule>::States>,
         _api: DynGlobalApi,
         module_api: DynModuleApi,
@@ -409,6 +479,7 @@ impl ClientModuleGen for WalletClientGen {
             cfg,
             module_api,
             notifier,
+            secret: module_root_secret,
             rpc: create_bitcoind(&rpc_config, TaskGroup::new().make_handle())?,
         })
     }
@@ -452,9 +523,11 @@ pub struct WalletClientModule {
     cfg: WalletClientConfig,
     module_api: DynModuleApi,
     notifier: ModuleNotifier<DynGlobalClientContext, WalletClientStates>,
+    secret: DerivableSecret,
     rpc: DynBitcoindRpc,
 }
 
+#[apply(async_trait_maybe_send!)]
 impl ClientModule for WalletClientModule {
     type Common = WalletModuleTypes;
     type ModuleStateMachineContext = WalletClientContext;
@@ -485,6 +558,119 @@ impl ClientModule for WalletClientModule {
             fee: self.cfg.fee_consensus.peg_out_abs,
         }
     }
+
+    fn output_destination(
+        &self,
+        output: &<Self::Common as ModuleCommon>::Output,
+    ) -> Option<SpendDestination> {
+        match output {
+            WalletOutput::PegOut(peg_out) => {
+                Some(SpendDestination::BitcoinAddress(peg_out.recipient.clone()))
+            }
+            WalletOutput::Rbf(_) => None,
+        }
+    }
+
+    async fn ledger_entry(
+        &self,
+        dbtx: &mut ModuleDatabaseTransaction<'_>,
+        operation_id: OperationId,
+        operation: &OperationLogEntry,
+    ) -> Option<LedgerEntry> {
+        Some(match operation.meta::<WalletOperationMeta>() {
+            WalletOperationMeta::Deposit { address, .. } => LedgerEntry {
+                direction: LedgerDirection::Incoming,
+                // Only known once the deposit was confirmed and is being claimed
+                amount: dbtx.get_value(&ClaimedDepositKey(operation_id)).await,
+                fee: None,
+                counterparty: Some(address.to_string()),
+                status: match operation.outcome::<DepositState>() {
+                    Some(DepositState::Claimed) => LedgerStatus::Succeeded,
+                    Some(DepositState::Failed(_)) => LedgerStatus::Failed,
+                    _ => LedgerStatus::Pending,
+                },
+            },
+            WalletOperationMeta::Withdraw {
+                address,
+                amount,
+                fee,
+                ..
+            } => LedgerEntry {
+                direction: LedgerDirection::Outgoing,
+                amount: Some(amount.into()),
+                fee: Some(fee.amount().into()),
+                counterparty: Some(address.to_string()),
+                status: match operation.outcome::<WithdrawState>() {
+                    Some(WithdrawState::Succeeded(_)) => LedgerStatus::Succeeded,
+                    Some(WithdrawState::Failed(_)) => LedgerStatus::Failed,
+                    _ => LedgerStatus::Pending,
+                },
+            },
+        })
+    }
+
+    fn summarize_state(&self, state: &Self::States) -> StateSummary {
+        match state {
+            WalletClientStates::Deposit(state) => state.summary(),
+            WalletClientStates::Withdraw(state) => state.summary(),
+        }
+    }
+
+    fn supports_backup(&self) -> bool {
+        true
+    }
+
+    async fn backup(
+        &self,
+        dbtx: &mut ModuleDatabaseTransaction<'_>,
+        executor: Executor<DynGlobalClientContext>,
+        _api: DynGlobalApi,
+        module_instance_id: ModuleInstanceId,
+    ) -> anyhow::Result<Vec<u8>> {
+        let backup = self
+            .prepare_wallet_backup(dbtx, executor, module_instance_id)
+            .await;
+
+        Ok(backup.consensus_encode_to_vec()?)
+    }
+
+    async fn restore(
+        &self,
+        dbtx: &mut DatabaseTransaction<'_>,
+        module_instance_id: ModuleInstanceId,
+        executor: Executor<DynGlobalClientContext>,
+        operation_log: &OperationLog,
+        _api: DynGlobalApi,
+        snapshot: Option<&[u8]>,
+    ) -> anyhow::Result<()> {
+        if executor
+            .get_active_states()
+            .await
+            .into_iter()
+            .any(|s| s.0.module_instance_id() == module_instance_id)
+        {
+            bail!("Found existing active state machines. Wallet module recovery must be started on an empty state.")
+        }
+
+        let backup = snapshot
+            .map(|mut s| WalletBackup::consensus_decode(&mut s, &Default::default()))
+            .transpose()?
+            .unwrap_or(WalletBackup::new_empty());
+
+        self.restore_wallet_backup(dbtx, module_instance_id, executor, operation_log, backup)
+            .await
+    }
+
+    async fn wipe(
+        &self,
+        dbtx: &mut ModuleDatabaseTransaction<'_>,
+        _module_instance_id: ModuleInstanceId,
+        _executor: Executor<DynGlobalClientContext>,
+    ) -> anyhow::Result<()> {
+        // The next deposit index is kept so no address is used twice
+        dbtx.remove_by_prefix(&DepositIndexKeyPrefix).await;
+        Ok(())
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -502,13 +688,40 @@ impl WalletClientModule {
         self.cfg.network
     }
 
-    pub fn get_deposit_address(
+    /// Creates a new deposit address valid until `valid_until` and the state
+    /// machine claiming deposits to it.
+    ///
+    /// The tweak key of the address is derived from the module secret, its
+    /// index is reserved in `dbtx`.
+    pub async fn get_deposit_address(
+        &self,
+        dbtx: &mut ModuleDatabaseTransaction<'_>,
+        valid_until: SystemTime,
+    ) -> (OperationId, WalletClientStates, Address) {
+        let deposit_idx = dbtx.get_value(&NextDepositIndexKey).await.unwrap_or(0);
+        dbtx.insert_entry(&NextDepositIndexKey, &(deposit_idx + 1))
+            .await;
+
+        let (operation_id, deposit_sm, address) =
+            self.deposit_state_machine(deposit_idx, valid_until);
+        dbtx.insert_entry(&DepositIndexKey(operation_id), &deposit_idx)
+            .await;
+
+        (operation_id, deposit_sm, address)
+    }
+
+    /// Derives the deposit address with index `deposit_idx`
+    fn deposit_state_machine(
         &self,
+        deposit_idx: u64,
         valid_until: SystemTime,
     ) -> (OperationId, WalletClientStates, Address) {
-        // TODO: derive from root secret
         // TODO: don't use global secp context
-        let tweak_key = KeyPair::new(secp256k1::SECP256K1, &mut thread_rng());
+        let tweak_key = self
+            .secret
+            .child_key(DEPOSIT_KEY_CHILD_ID)
+            .child_key(ChildId(deposit_idx))
+            .to_secp_key(secp256k1::SECP256K1);
         let x_only_pk = tweak_key.public_key().to_x_only_pubkey();
         let operation_id = OperationId(x_only_pk.serialize());
 
@@ -1,6 +1,7 @@
 use std::time::Duration;
 
 use bitcoin::Txid;
+use fedimint_client::event::StateSummary;
 use fedimint_client::sm::{OperationId, State, StateTransition};
 use fedimint_client::DynGlobalClientContext;
 use fedimint_core::api::GlobalFederationApi;
@@ -21,6 +22,18 @@ pub struct WithdrawStateMachine {
     pub(crate) state: WithdrawStates,
 }
 
+impl WithdrawStateMachine {
+    pub(crate) fn summary(&self) -> StateSummary {
+        match &self.state {
+            WithdrawStates::Created(_) => {
+                StateSummary::in_progress("Waiting for the federation to send the transaction")
+            }
+            WithdrawStates::Success(_) => StateSummary::Succeeded,
+            WithdrawStates::Aborted(aborted) => StateSummary::failed(&aborted.error),
+        }
+    }
+}
+
 impl State for WithdrawStateMachine {
     type ModuleContext = WalletClientContext;
     type GlobalContext = DynGlobalClientContext;
@@ -1,10 +1,16 @@
 use std::time::SystemTime;
 
+use fedimint_client::backup::Metadata;
+use fedimint_client::secret::PlainRootSecretStrategy;
+use fedimint_client::{ClientBuilder, ClientSecret};
+use fedimint_core::db::mem_impl::MemDatabase;
 use fedimint_core::sats;
+use fedimint_core::task::TaskGroup;
 use fedimint_core::util::NextOrPending;
 use fedimint_dummy_client::DummyClientGen;
 use fedimint_dummy_common::config::DummyGenParams;
 use fedimint_dummy_server::DummyGen;
+use fedimint_testing::federation::FederationTest;
 use fedimint_testing::fixtures::{Fixtures, TIMEOUT};
 use fedimint_wallet_client::{DepositState, WalletClientExt, WalletClientGen, WithdrawState};
 use fedimint_wallet_common::config::WalletGenParams;
@@ -63,3 +69,54 @@ async fn on_chain_peg_in_and_peg_out() -> anyhow::Result<()> {
     assert_eq!(received, peg_out.into());
     Ok(())
 }
+
+fn client_builder(fed: &FederationTest) -> ClientBuilder {
+    let mut client_builder = ClientBuilder::default();
+    client_builder.with_module_gens(fed.client_module_gens());
+    client_builder.with_primary_module(0);
+    client_builder.with_config(fed.client_config());
+    client_builder.with_database(MemDatabase::new());
+    client_builder
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn restores_deposit_address_from_backup() -> anyhow::Result<()> {
+    let fixtures = fixtures();
+    let fed = fixtures.new_fed().await;
+    let bitcoin = fixtures.bitcoin();
+    let finality_delay = 10;
+    bitcoin.mine_blocks(finality_delay).await;
+    let mut client_tasks = TaskGroup::new();
+    let client = client_builder(&fed)
+        .build::<PlainRootSecretStrategy>(&mut client_tasks)
+        .await?;
+
+    let valid_until = SystemTime::now() + TIMEOUT;
+    let (op, address) = client.get_deposit_address(valid_until).await?;
+    client.backup_to_federation(Metadata::empty()).await?;
+
+    // Lose the client and restore it from its secret
+    let secret = client
+        .root_secret_encoding::<PlainRootSecretStrategy>()
+        .await;
+    client_tasks.shutdown_join_all(None).await?;
+    let (restored, _) = client_builder(&fed)
+        .build_restoring_from_backup(
+            &mut TaskGroup::new(),
+            ClientSecret::<PlainRootSecretStrategy>::new(secret),
+        )
+        .await?;
+
+    // The restored client still claims deposits to the address
+    bitcoin.send_and_mine_block(&address, bsats(5000)).await;
+    let sub = restored.subscribe_deposit_updates(op).await?;
+    let mut sub = sub.into_stream();
+    assert_eq!(sub.ok().await?, DepositState::WaitingForTransaction);
+    assert_eq!(sub.ok().await?, DepositState::WaitingForConfirmation);
+
+    bitcoin.mine_blocks(finality_delay).await;
+    assert_eq!(sub.ok().await?, DepositState::Confirmed);
+    assert_eq!(sub.ok().await?, DepositState::Claimed);
+    assert_eq!(restored.get_balance().await, sats(5000));
+    Ok(())
+}